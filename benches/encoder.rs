@@ -0,0 +1,69 @@
+//! Encodes synthetic frames and reports throughput and bitrate.
+//!
+//! ```sh
+//! cargo bench --bench encoder --no-default-features --features web
+//! ```
+//!
+//! Arguments after `--` are `<width>x<height> [frames] [speed]`, e.g. `-- 1280x720 300 9`.
+
+use std::time::Instant;
+
+use flumph::camera::{CameraSource, FormatRequest, Resolution, TestPattern};
+use flumph::encoder::{Av1Encoder, EncoderSettings};
+
+fn main() {
+    let args: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|a| !a.starts_with("--"))
+        .collect();
+    let (width, height) = args
+        .first()
+        .and_then(|s| s.split_once('x'))
+        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+        .unwrap_or((640, 480));
+    let frames: u64 = args.get(1).and_then(|s| s.parse().ok()).unwrap_or(150);
+    let speed: u8 = args.get(2).and_then(|s| s.parse().ok()).unwrap_or(10);
+
+    let mut camera = TestPattern::open(FormatRequest::HighestResolution(Resolution::new(
+        width, height,
+    )))
+    .expect("the test pattern supports 320x240, 640x480 and 1280x720");
+    let settings = EncoderSettings {
+        width,
+        height,
+        fps: camera.format().fps,
+        speed,
+        ..EncoderSettings::default()
+    };
+    let fps = settings.fps as i64;
+    let mut encoder = Av1Encoder::new(settings).expect("valid settings");
+
+    let started = Instant::now();
+    let mut bytes = 0;
+    let mut packets = 0;
+    let mut keyframes = 0;
+    for i in 0..frames as i64 {
+        let frame = camera
+            .frame(i * 1_000_000 / fps)
+            .expect("test pattern never fails")
+            .expect("a new frame every period");
+        for packet in encoder.encode(&frame).expect("encoding succeeds") {
+            bytes += packet.data.len();
+            packets += 1;
+            keyframes += packet.keyframe as u64;
+        }
+    }
+    for packet in encoder.flush().expect("flushing succeeds") {
+        bytes += packet.data.len();
+        packets += 1;
+        keyframes += packet.keyframe as u64;
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+    let duration = frames as f64 / fps as f64;
+    println!(
+        "{width}x{height} speed {speed}: {frames} frames in {elapsed:.2} s ({:.1} fps), {packets} packets, \
+         {keyframes} keyframes, {:.0} kbps",
+        frames as f64 / elapsed,
+        bytes as f64 * 8.0 / duration / 1000.0,
+    );
+}
@@ -0,0 +1,360 @@
+//! Object classification of stored footage, for compute nodes.
+//!
+//! [`analyse`] decodes a [`VideoBlob`], hands a sample of its frames to a [`Detector`] and collects what it finds
+//! into a [`DetectionBlob`]: a derived dataset that refers back to the footage by hash, so it can be stored, synced
+//! and thrown away independently of it. Everything runs on the CPU; [`OnnxDetector`] runs ONNX models with tract.
+
+#[cfg(not(target_arch = "wasm32"))]
+mod onnx;
+
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Frame;
+use crate::encoder::EncoderError;
+use crate::node::NodeId;
+use crate::sensors::{ChannelId, Micros};
+use crate::storage::ivf::IvfError;
+use crate::storage::BlobHash;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::storage::VideoBlob;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use onnx::{OnnxConfig, OnnxDetector, OutputLayout};
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum AnalyticsError {
+    #[error(transparent)]
+    Decode(#[from] EncoderError),
+    #[error("corrupt video segment: {0}")]
+    Video(#[from] IvfError),
+    #[error("model failed: {0}")]
+    Model(String),
+}
+
+/// A box around an object, in fractions of the frame's width and height from its top left corner.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BoundingBox {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl BoundingBox {
+    pub fn area(&self) -> f32 {
+        self.width.max(0.0) * self.height.max(0.0)
+    }
+
+    /// Intersection over union, `0.0` for disjoint boxes and `1.0` for identical ones.
+    pub fn iou(&self, other: &BoundingBox) -> f32 {
+        let w = (self.x + self.width).min(other.x + other.width) - self.x.max(other.x);
+        let h = (self.y + self.height).min(other.y + other.height) - self.y.max(other.y);
+        let intersection = w.max(0.0) * h.max(0.0);
+        let union = self.area() + other.area() - intersection;
+        if union > 0.0 {
+            intersection / union
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Something a detector found in a frame.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Object {
+    pub class: String,
+    /// `0.0..=1.0`.
+    pub confidence: f32,
+    pub bbox: BoundingBox,
+}
+
+pub trait Detector {
+    /// Identifies the model in the datasets it produces.
+    fn name(&self) -> String;
+
+    fn detect(&mut self, frame: &Frame) -> Result<Vec<Object>, AnalyticsError>;
+}
+
+impl<D: Detector + ?Sized> Detector for Box<D> {
+    fn name(&self) -> String {
+        (**self).name()
+    }
+
+    fn detect(&mut self, frame: &Frame) -> Result<Vec<Object>, AnalyticsError> {
+        (**self).detect(frame)
+    }
+}
+
+/// An object in stored footage.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Detection {
+    /// Network time of the frame.
+    pub time: Micros,
+    /// Hash of the IVF data of the segment the frame is in.
+    pub segment: BlobHash,
+    pub class: String,
+    pub confidence: f32,
+    pub bbox: BoundingBox,
+}
+
+/// Everything a model found in an hour of one camera's footage.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DetectionBlob {
+    /// The [`VideoBlob`] the detections were made in.
+    pub video: BlobHash,
+    pub node: NodeId,
+    pub camera: ChannelId,
+    pub hour: i64,
+    pub model: String,
+    /// Time between analysed frames.
+    pub interval: Micros,
+    /// Sorted by time.
+    pub detections: Vec<Detection>,
+}
+
+impl DetectionBlob {
+    pub fn encode(&self) -> Vec<u8> {
+        postcard::to_stdvec(self).expect("detection blobs always serialize")
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, postcard::Error> {
+        postcard::from_bytes(bytes)
+    }
+
+    pub fn hash(&self) -> BlobHash {
+        BlobHash::of(&self.encode())
+    }
+}
+
+/// Runs `detector` on one frame every `interval` of `video`. Every frame is decoded, since AV1 frames depend on the
+/// ones before them, but only the sampled ones are classified, which is where nearly all the time goes.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn analyse(
+    video: &VideoBlob,
+    detector: &mut dyn Detector,
+    interval: Micros,
+) -> Result<DetectionBlob, AnalyticsError> {
+    use crate::encoder::Av1Decoder;
+
+    let mut detections = Vec::new();
+    let mut next = Micros::MIN;
+    for segment in &video.segments {
+        let hash = BlobHash::of(&segment.ivf);
+        // Segments start at a keyframe, so each gets a fresh decoder and a corrupt one does not spoil the rest.
+        let mut decoder = Av1Decoder::new(0)?;
+        for frame in segment.frames()? {
+            let (time, data) = frame?;
+            for frame in decoder.decode(data, time)? {
+                if frame.captured < next {
+                    continue;
+                }
+                next = frame.captured + interval;
+                for object in detector.detect(&frame)? {
+                    detections.push(Detection {
+                        time: frame.captured,
+                        segment: hash,
+                        class: object.class,
+                        confidence: object.confidence,
+                        bbox: object.bbox,
+                    });
+                }
+            }
+        }
+    }
+    detections.sort_by_key(|d| d.time);
+    Ok(DetectionBlob {
+        video: video.hash(),
+        node: video.node,
+        camera: video.camera.clone(),
+        hour: video.hour,
+        model: detector.name(),
+        interval,
+        detections,
+    })
+}
+
+/// Which detections to return from a [`DetectionIndex`]. The default matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DetectionQuery {
+    pub class: Option<String>,
+    pub min_confidence: f32,
+    pub camera: Option<ChannelId>,
+    pub node: Option<NodeId>,
+    /// Network time, inclusive.
+    pub from: Option<Micros>,
+    /// Network time, exclusive.
+    pub to: Option<Micros>,
+}
+
+impl DetectionQuery {
+    fn matches_blob(&self, blob: &DetectionBlob) -> bool {
+        self.camera.as_ref().is_none_or(|c| *c == blob.camera)
+            && self.node.is_none_or(|n| n == blob.node)
+    }
+
+    fn matches(&self, detection: &Detection) -> bool {
+        self.class.as_ref().is_none_or(|c| *c == detection.class)
+            && detection.confidence >= self.min_confidence
+            && self.from.is_none_or(|t| detection.time >= t)
+            && self.to.is_none_or(|t| detection.time < t)
+    }
+}
+
+/// The detection datasets a compute node holds, for searching footage by what is in it.
+#[derive(Debug, Clone, Default)]
+pub struct DetectionIndex {
+    blobs: Vec<DetectionBlob>,
+}
+
+impl DetectionIndex {
+    /// Adds a dataset, replacing an earlier one for the same footage and model.
+    pub fn insert(&mut self, blob: DetectionBlob) {
+        self.blobs
+            .retain(|b| !(b.video == blob.video && b.model == blob.model));
+        self.blobs.push(blob);
+    }
+
+    pub fn blobs(&self) -> &[DetectionBlob] {
+        &self.blobs
+    }
+
+    /// Drops every dataset made from `video`.
+    pub fn remove(&mut self, video: &BlobHash) {
+        self.blobs.retain(|b| b.video != *video);
+    }
+
+    /// Whether `video` has been analysed with `model` already.
+    pub fn contains(&self, video: &BlobHash, model: &str) -> bool {
+        self.blobs
+            .iter()
+            .any(|b| b.video == *video && b.model == model)
+    }
+
+    /// Matching detections with the dataset each came from, sorted by time.
+    pub fn query(&self, query: &DetectionQuery) -> Vec<(&DetectionBlob, &Detection)> {
+        let mut found: Vec<_> = self
+            .blobs
+            .iter()
+            .filter(|b| query.matches_blob(b))
+            .flat_map(|b| b.detections.iter().map(move |d| (b, d)))
+            .filter(|(_, d)| query.matches(d))
+            .collect();
+        found.sort_by_key(|(_, d)| d.time);
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sees a bird in the middle of every frame.
+    struct Birds {
+        frames: usize,
+    }
+
+    impl Detector for Birds {
+        fn name(&self) -> String {
+            "birds".to_string()
+        }
+
+        fn detect(&mut self, _frame: &Frame) -> Result<Vec<Object>, AnalyticsError> {
+            self.frames += 1;
+            Ok(vec![Object {
+                class: "bird".to_string(),
+                confidence: 0.9,
+                bbox: BoundingBox {
+                    x: 0.4,
+                    y: 0.4,
+                    width: 0.2,
+                    height: 0.2,
+                },
+            }])
+        }
+    }
+
+    #[test]
+    fn recorded_footage_is_sampled_and_its_detections_refer_back_to_it() {
+        let camera = ChannelId::new("camera");
+        let (videos, _) = crate::node::sim::footage(NodeId(1), &camera, 0..1).unwrap();
+        let video = &videos[0];
+        let mut birds = Birds { frames: 0 };
+        let blob = analyse(video, &mut birds, 1_000_000).unwrap();
+
+        assert_eq!(blob.video, video.hash());
+        assert_eq!(
+            (blob.node, &blob.camera, blob.hour),
+            (NodeId(1), &camera, 0)
+        );
+        assert_eq!(blob.model, "birds");
+        assert_eq!(blob.detections.len(), birds.frames);
+        assert!(!blob.detections.is_empty());
+        assert!(blob.detections.windows(2).all(|d| d[0].time < d[1].time));
+        // One frame a second at most.
+        assert!(blob
+            .detections
+            .windows(2)
+            .all(|d| d[1].time - d[0].time >= 1_000_000));
+        for detection in &blob.detections {
+            let segment = video
+                .segments
+                .iter()
+                .find(|s| BlobHash::of(&s.ivf) == detection.segment)
+                .expect("detections name a segment of the footage");
+            assert!((segment.start..=segment.end).contains(&detection.time));
+        }
+        let span = video.segments.last().unwrap().end - video.segments[0].start;
+        assert!(birds.frames as i64 <= span / 1_000_000 + 1);
+    }
+
+    #[test]
+    fn the_index_keeps_one_dataset_per_footage_and_model() {
+        let blob = |video: &str, model: &str, times: &[Micros]| DetectionBlob {
+            video: BlobHash::of(video.as_bytes()),
+            node: NodeId(1),
+            camera: ChannelId::new("camera"),
+            hour: 0,
+            model: model.to_string(),
+            interval: 1_000_000,
+            detections: times
+                .iter()
+                .map(|&time| Detection {
+                    time,
+                    segment: BlobHash::of(video.as_bytes()),
+                    class: "bird".to_string(),
+                    confidence: 0.5,
+                    bbox: BoundingBox {
+                        x: 0.0,
+                        y: 0.0,
+                        width: 1.0,
+                        height: 1.0,
+                    },
+                })
+                .collect(),
+        };
+        let (morning, evening) = (BlobHash::of(b"morning"), BlobHash::of(b"evening"));
+        let mut index = DetectionIndex::default();
+        index.insert(blob("morning", "yolo", &[1, 5]));
+        index.insert(blob("morning", "yolo", &[3]));
+        index.insert(blob("morning", "owls", &[2]));
+        index.insert(blob("evening", "yolo", &[4]));
+        assert_eq!(index.blobs().len(), 3);
+        assert!(index.contains(&morning, "yolo") && index.contains(&evening, "yolo"));
+        assert!(!index.contains(&evening, "owls"));
+
+        let times = |index: &DetectionIndex, query: &DetectionQuery| -> Vec<Micros> {
+            index.query(query).iter().map(|(_, d)| d.time).collect()
+        };
+        assert_eq!(times(&index, &DetectionQuery::default()), vec![2, 3, 4]);
+        let late = DetectionQuery {
+            from: Some(3),
+            to: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(times(&index, &late), vec![3]);
+
+        index.remove(&morning);
+        assert_eq!(times(&index, &DetectionQuery::default()), vec![4]);
+        assert!(!index.contains(&morning, "owls"));
+    }
+}
@@ -0,0 +1,227 @@
+//! Object detection models in ONNX format, run on the CPU by tract.
+//!
+//! Models are expected to take a `[1, 3, height, width]` RGB tensor scaled to `0.0..=1.0` and to produce YOLO style
+//! boxes, which is what the common exports of YOLOv5 and YOLOv8 do.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tract_onnx::prelude::*;
+
+use super::{AnalyticsError, BoundingBox, Detector, Object};
+use crate::camera::Frame;
+
+/// How a model lays out its boxes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OutputLayout {
+    /// `[1, 4 + classes, boxes]`: centre x, centre y, width and height in input pixels, then a score per class.
+    #[default]
+    YoloV8,
+    /// `[1, boxes, 5 + classes]`: like [`OutputLayout::YoloV8`] transposed, with an objectness score after the box
+    /// that multiplies every class score.
+    YoloV5,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OnnxConfig {
+    pub model: PathBuf,
+    /// Class names by index. Classes without one are called `class<index>`.
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Width and height the model takes. Frames are scaled to it, ignoring their aspect ratio.
+    pub input: (u32, u32),
+    #[serde(default)]
+    pub layout: OutputLayout,
+    pub min_confidence: f32,
+    /// Overlap above which the weaker of two boxes of the same class is dropped.
+    pub iou_threshold: f32,
+}
+
+impl OnnxConfig {
+    pub fn new(model: impl Into<PathBuf>) -> Self {
+        OnnxConfig {
+            model: model.into(),
+            labels: Vec::new(),
+            input: (640, 640),
+            layout: OutputLayout::YoloV8,
+            min_confidence: 0.4,
+            iou_threshold: 0.45,
+        }
+    }
+}
+
+pub struct OnnxDetector {
+    pub config: OnnxConfig,
+    model: Arc<TypedRunnableModel>,
+}
+
+impl OnnxDetector {
+    pub fn load(config: OnnxConfig) -> Result<Self, AnalyticsError> {
+        let model = tract_onnx::onnx()
+            .model_for_path(&config.model)
+            .map_err(model_error)?;
+        Self::with_model(config, model)
+    }
+
+    /// Loads a model from memory; `config.model` only names it.
+    pub fn from_bytes(config: OnnxConfig, mut bytes: &[u8]) -> Result<Self, AnalyticsError> {
+        let model = tract_onnx::onnx()
+            .model_for_read(&mut bytes)
+            .map_err(model_error)?;
+        Self::with_model(config, model)
+    }
+
+    fn with_model(config: OnnxConfig, model: InferenceModel) -> Result<Self, AnalyticsError> {
+        let (width, height) = config.input;
+        let model = model
+            .with_input_fact(0, f32::fact([1, 3, height as usize, width as usize]).into())
+            .and_then(|m| m.into_optimized())
+            .and_then(|m| m.into_runnable())
+            .map_err(model_error)?;
+        Ok(OnnxDetector { config, model })
+    }
+
+    fn label(&self, class: usize) -> String {
+        self.config
+            .labels
+            .get(class)
+            .cloned()
+            .unwrap_or_else(|| format!("class{class}"))
+    }
+
+    /// Reads the candidate boxes out of the model's output, keeping each box's best class.
+    fn candidates(&self, output: &Tensor) -> Result<Vec<(usize, Object)>, AnalyticsError> {
+        let output = output.to_plain_array_view::<f32>().map_err(model_error)?;
+        let shape = output.shape();
+        if shape.len() != 3 || shape[0] != 1 {
+            return Err(AnalyticsError::Model(format!(
+                "expected a [1, _, _] output, got {shape:?}"
+            )));
+        }
+        let layout = self.config.layout;
+        let (boxes, fields, first_class) = match layout {
+            OutputLayout::YoloV8 => (shape[2], shape[1], 4),
+            OutputLayout::YoloV5 => (shape[1], shape[2], 5),
+        };
+        let value = |b: usize, f: usize| match layout {
+            OutputLayout::YoloV8 => output[[0, f, b]],
+            OutputLayout::YoloV5 => output[[0, b, f]],
+        };
+        if fields <= first_class {
+            return Err(AnalyticsError::Model(format!(
+                "{fields} values per box leave no room for class scores"
+            )));
+        }
+
+        let (width, height) = (self.config.input.0 as f32, self.config.input.1 as f32);
+        let mut found = Vec::new();
+        for b in 0..boxes {
+            let objectness = match layout {
+                OutputLayout::YoloV8 => 1.0,
+                OutputLayout::YoloV5 => value(b, 4),
+            };
+            let (class, score) = (first_class..fields)
+                .map(|f| (f - first_class, value(b, f) * objectness))
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .expect("there is at least one class");
+            if score < self.config.min_confidence {
+                continue;
+            }
+            let centred = (value(b, 0), value(b, 1), value(b, 2), value(b, 3));
+            let bbox = bbox(centred, (width, height));
+            found.push((
+                class,
+                Object {
+                    class: self.label(class),
+                    confidence: score,
+                    bbox,
+                },
+            ));
+        }
+        Ok(found)
+    }
+}
+
+impl Detector for OnnxDetector {
+    fn name(&self) -> String {
+        self.config
+            .model
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "onnx".to_string())
+    }
+
+    fn detect(&mut self, frame: &Frame) -> Result<Vec<Object>, AnalyticsError> {
+        let (width, height) = self.config.input;
+        let scaled = frame.resized(width, height);
+        let (w, h) = (width as usize, height as usize);
+        let input = tract_ndarray::Array4::from_shape_fn((1, 3, h, w), |(_, c, y, x)| {
+            scaled.rgb[(y * w + x) * 3 + c] as f32 / 255.0
+        });
+        let outputs = self
+            .model
+            .run(tvec!(Tensor::from(input).into()))
+            .map_err(model_error)?;
+        let output = outputs
+            .first()
+            .ok_or_else(|| AnalyticsError::Model("model has no outputs".to_string()))?;
+        let mut candidates = self.candidates(output)?;
+
+        // Non-maximum suppression, per class.
+        candidates.sort_by(|a, b| b.1.confidence.total_cmp(&a.1.confidence));
+        let mut kept: Vec<(usize, Object)> = Vec::new();
+        for (class, object) in candidates {
+            let overlapping = kept.iter().any(|(k, kept)| {
+                *k == class && kept.bbox.iou(&object.bbox) > self.config.iou_threshold
+            });
+            if !overlapping {
+                kept.push((class, object));
+            }
+        }
+        Ok(kept.into_iter().map(|(_, object)| object).collect())
+    }
+}
+
+/// The part inside the frame of a box given by its centre and size in pixels of a `width` × `height` input, as
+/// fractions of the frame.
+fn bbox((cx, cy, w, h): (f32, f32, f32, f32), (width, height): (f32, f32)) -> BoundingBox {
+    let left = ((cx - w / 2.0) / width).clamp(0.0, 1.0);
+    let top = ((cy - h / 2.0) / height).clamp(0.0, 1.0);
+    let right = ((cx + w / 2.0) / width).clamp(0.0, 1.0);
+    let bottom = ((cy + h / 2.0) / height).clamp(0.0, 1.0);
+    BoundingBox {
+        x: left,
+        y: top,
+        width: right - left,
+        height: bottom - top,
+    }
+}
+
+/// tract's errors are `anyhow` chains; the alternate format includes the causes, which say what actually went wrong.
+fn model_error(e: impl std::fmt::Display) -> AnalyticsError {
+    AnalyticsError::Model(format!("{e:#}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boxes_hanging_off_the_frame_are_cut_at_its_edges() {
+        let input = (640.0, 480.0);
+        let close = |a: BoundingBox, b: [f32; 4]| {
+            let a = [a.x, a.y, a.width, a.height];
+            a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-6)
+        };
+        let inside = bbox((320.0, 240.0, 64.0, 48.0), input);
+        assert!(close(inside, [0.45, 0.45, 0.1, 0.1]), "{inside:?}");
+
+        // Half of it off the left edge, and a quarter off the bottom.
+        let cut = bbox((0.0, 456.0, 128.0, 96.0), input);
+        assert!(close(cut, [0.0, 0.85, 0.1, 0.15]), "{cut:?}");
+
+        let beyond = bbox((700.0, 240.0, 20.0, 20.0), input);
+        assert_eq!((beyond.x, beyond.width), (1.0, 0.0));
+    }
+}
@@ -0,0 +1,109 @@
+use crate::math::{self, Vec3};
+
+/// An axis aligned ellipsoid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ellipsoid {
+    pub center: Vec3,
+    pub radii: Vec3,
+}
+
+/// Least squares fit of `a x² + b y² + c z² + d x + e y + f z = 1` through `points`.
+///
+/// Axis aligned is enough to remove hard iron offsets and the dominant part of soft iron distortion on a phone; a
+/// full rotated ellipsoid needs many more, and better spread, samples than users are willing to wave a phone for.
+pub fn fit_ellipsoid(points: &[Vec3]) -> Option<Ellipsoid> {
+    if points.len() < 6 {
+        return None;
+    }
+    let mut ata = [[0.0; 6]; 6];
+    let mut atb = [0.0; 6];
+    for &[x, y, z] in points {
+        let row = [x * x, y * y, z * z, x, y, z];
+        for i in 0..6 {
+            for j in 0..6 {
+                ata[i][j] += row[i] * row[j];
+            }
+            atb[i] += row[i];
+        }
+    }
+    let [a, b, c, d, e, f] = math::solve(ata, atb)?;
+    if a <= 0.0 || b <= 0.0 || c <= 0.0 {
+        return None;
+    }
+    let center = [-d / (2.0 * a), -e / (2.0 * b), -f / (2.0 * c)];
+    let g = 1.0 + d * d / (4.0 * a) + e * e / (4.0 * b) + f * f / (4.0 * c);
+    Some(Ellipsoid {
+        center,
+        radii: [(g / a).sqrt(), (g / b).sqrt(), (g / c).sqrt()],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `count` points spread evenly over the ellipsoid, along a golden angle spiral.
+    fn surface(ellipsoid: Ellipsoid, count: usize) -> Vec<Vec3> {
+        let golden = std::f64::consts::PI * (3.0 - 5f64.sqrt());
+        (0..count)
+            .map(|i| {
+                let z = 1.0 - 2.0 * (i as f64 + 0.5) / count as f64;
+                let r = (1.0 - z * z).sqrt();
+                let theta = golden * i as f64;
+                let unit = [r * theta.cos(), r * theta.sin(), z];
+                std::array::from_fn(|axis| {
+                    ellipsoid.center[axis] + ellipsoid.radii[axis] * unit[axis]
+                })
+            })
+            .collect()
+    }
+
+    fn assert_close(a: Vec3, b: Vec3, tolerance: f64) {
+        assert!(
+            (0..3).all(|i| (a[i] - b[i]).abs() < tolerance),
+            "{a:?} is not {b:?}"
+        );
+    }
+
+    #[test]
+    fn hard_and_soft_iron_are_recovered() {
+        let distorted = Ellipsoid {
+            center: [12.0, -30.0, 4.5],
+            radii: [38.0, 52.0, 47.0],
+        };
+        let fitted = fit_ellipsoid(&surface(distorted, 200)).unwrap();
+        assert_close(fitted.center, distorted.center, 1e-6);
+        assert_close(fitted.radii, distorted.radii, 1e-6);
+
+        // Noise of a few tenths of a µT moves the fit about as much.
+        let noisy: Vec<Vec3> = surface(distorted, 500)
+            .into_iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let noise = 0.3 * ((i as f64 * 12.9898).sin() * 43_758.545).fract();
+                math::add(p, [noise, -noise, noise])
+            })
+            .collect();
+        let fitted = fit_ellipsoid(&noisy).unwrap();
+        assert_close(fitted.center, distorted.center, 0.5);
+        assert_close(fitted.radii, distorted.radii, 0.5);
+    }
+
+    #[test]
+    fn too_few_or_flat_readings_are_rejected() {
+        let sphere = Ellipsoid {
+            center: [0.0; 3],
+            radii: [50.0; 3],
+        };
+        assert_eq!(fit_ellipsoid(&surface(sphere, 5)), None);
+        // A phone only ever turned about one axis traces a circle, which fits no ellipsoid.
+        let circle: Vec<Vec3> = (0..100)
+            .map(|i| {
+                let angle = i as f64 * 0.1;
+                [50.0 * angle.cos(), 50.0 * angle.sin(), 20.0]
+            })
+            .collect();
+        assert_eq!(fit_ellipsoid(&circle), None);
+        assert_eq!(fit_ellipsoid(&[[1.0, 2.0, 3.0]; 50]), None);
+    }
+}
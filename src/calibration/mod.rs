@@ -0,0 +1,234 @@
+//! Sensor calibration.
+//!
+//! Raw phone magnetometer and accelerometer readings are off by biases and per axis gains that differ from phone to
+//! phone, so every node keeps a [`CalibrationStore`] of profiles per node and channel. Profiles never overwrite the
+//! raw data: they are either applied at capture time, producing an extra `<channel>/calibrated` channel next to the
+//! raw one, or lazily at query time against stored blobs using whichever profile was in effect when each sample was
+//! taken.
+
+mod fit;
+mod procedure;
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::math::{self, Mat3, Vec3};
+use crate::node::NodeId;
+use crate::sensors::{
+    ChannelId, ChannelInfo, Micros, Sample, SensorError, SensorKind, SensorSource,
+};
+use crate::storage::{SealedBlob, TimedSample};
+
+pub use fit::{fit_ellipsoid, Ellipsoid};
+pub use procedure::{
+    AccelerometerProcedure, Face, MagnetometerProcedure, Procedure, ProcedureResult,
+    ProcedureStatus, STANDARD_GRAVITY,
+};
+
+/// Suffix of the derived channel calibrated readings are written to.
+pub const CALIBRATED: &str = "calibrated";
+
+#[derive(Debug, thiserror::Error)]
+pub enum CalibrationError {
+    #[error("not enough data to calibrate: {0}")]
+    NotEnoughData(String),
+    #[error("the readings do not fit a calibration model")]
+    Degenerate,
+    #[error("could not read or write calibration profiles: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("calibration profiles are malformed: {0}")]
+    Format(#[from] serde_json::Error),
+}
+
+/// A correction applied to raw readings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Calibration {
+    /// `calibrated = scale * (raw - offset)`, for single valued channels like the barometer.
+    Linear { offset: f64, scale: f64 },
+    /// `calibrated = matrix * (raw - bias)`, for three axis sensors.
+    Affine { bias: Vec3, matrix: Mat3 },
+}
+
+impl Calibration {
+    pub fn apply(&self, raw: &[f64]) -> Vec<f64> {
+        match self {
+            Calibration::Linear { offset, scale } => {
+                raw.iter().map(|v| scale * (v - offset)).collect()
+            }
+            Calibration::Affine { bias, matrix } => match math::vec3(raw) {
+                Some(v) => math::mat_vec(matrix, math::sub(v, *bias)).to_vec(),
+                None => raw.to_vec(),
+            },
+        }
+    }
+}
+
+/// How a calibration was obtained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CalibrationMethod {
+    FigureEight,
+    SixOrientation,
+    /// Entered by hand, e.g. a barometer offset against a reference station.
+    Manual,
+}
+
+/// A calibration for one channel on one node, in effect from `valid_from` until a newer profile replaces it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CalibrationProfile {
+    pub node: NodeId,
+    pub channel: ChannelId,
+    pub kind: SensorKind,
+    /// Network time from which the profile applies.
+    pub valid_from: Micros,
+    pub method: CalibrationMethod,
+    pub residual: f64,
+    pub calibration: Calibration,
+}
+
+impl CalibrationProfile {
+    pub fn from_procedure(
+        node: NodeId,
+        kind: SensorKind,
+        valid_from: Micros,
+        result: ProcedureResult,
+    ) -> Self {
+        CalibrationProfile {
+            node,
+            channel: kind.channel(),
+            kind,
+            valid_from,
+            method: result.method,
+            residual: result.residual,
+            calibration: result.calibration,
+        }
+    }
+}
+
+/// Where calibration is applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ApplyAt {
+    /// Calibrated channels are recorded next to the raw ones as samples come in.
+    Capture,
+    /// Only raw channels are recorded; calibration happens when blobs are read.
+    #[default]
+    Query,
+}
+
+impl ApplyAt {
+    /// The profiles a [`CalibratedSource`] should hold: all of `store` at capture time, none otherwise.
+    pub fn capture_profiles(self, store: &CalibrationStore) -> CalibrationStore {
+        match self {
+            ApplyAt::Capture => store.clone(),
+            ApplyAt::Query => CalibrationStore::default(),
+        }
+    }
+}
+
+/// Every calibration profile a node knows about, including superseded ones so old data can still be corrected
+/// with the profile that was in effect at the time.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CalibrationStore {
+    profiles: Vec<CalibrationProfile>,
+}
+
+impl CalibrationStore {
+    pub fn load(path: &Path) -> Result<Self, CalibrationError> {
+        match std::fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(CalibrationStore::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), CalibrationError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn insert(&mut self, profile: CalibrationProfile) {
+        self.profiles.push(profile);
+        self.profiles.sort_by_key(|p| p.valid_from);
+    }
+
+    pub fn profiles(&self) -> &[CalibrationProfile] {
+        &self.profiles
+    }
+
+    /// The newest profile for a channel.
+    pub fn current(&self, node: NodeId, channel: &ChannelId) -> Option<&CalibrationProfile> {
+        self.profiles
+            .iter()
+            .rev()
+            .find(|p| p.node == node && &p.channel == channel)
+    }
+
+    /// The profile that was in effect for a channel at network time `at`.
+    pub fn at(&self, node: NodeId, channel: &ChannelId, at: Micros) -> Option<&CalibrationProfile> {
+        self.profiles
+            .iter()
+            .rev()
+            .find(|p| p.node == node && &p.channel == channel && p.valid_from <= at)
+    }
+
+    /// Calibrated copies of every sample in a blob that has a profile, on `<channel>/calibrated` channels. The blob
+    /// itself is left untouched.
+    pub fn calibrate_blob(&self, blob: &SealedBlob) -> Vec<TimedSample> {
+        blob.samples
+            .iter()
+            .filter_map(|timed| {
+                let profile = self.at(blob.node, &timed.sample.channel, timed.time.network)?;
+                Some(TimedSample {
+                    sample: calibrated(profile, &timed.sample),
+                    time: timed.time,
+                })
+            })
+            .collect()
+    }
+}
+
+fn calibrated(profile: &CalibrationProfile, raw: &Sample) -> Sample {
+    Sample {
+        channel: raw.channel.derived(CALIBRATED),
+        local_time: raw.local_time,
+        values: profile.calibration.apply(&raw.values),
+    }
+}
+
+/// Wraps a source and emits a calibrated copy of every sample that has a profile, for [`ApplyAt::Capture`].
+pub struct CalibratedSource<S> {
+    pub inner: S,
+    pub node: NodeId,
+    pub store: CalibrationStore,
+}
+
+impl<S: SensorSource> SensorSource for CalibratedSource<S> {
+    fn channels(&self) -> Vec<ChannelInfo> {
+        let mut channels = self.inner.channels();
+        let derived: Vec<ChannelInfo> = channels
+            .iter()
+            .filter(|c| self.store.current(self.node, &c.id).is_some())
+            .map(|c| ChannelInfo {
+                id: c.id.derived(CALIBRATED),
+                ..c.clone()
+            })
+            .collect();
+        channels.extend(derived);
+        channels
+    }
+
+    fn poll(&mut self, now: Micros) -> Result<Vec<Sample>, SensorError> {
+        let raw = self.inner.poll(now)?;
+        let mut samples = Vec::with_capacity(raw.len() * 2);
+        for sample in raw {
+            if let Some(profile) = self.store.current(self.node, &sample.channel) {
+                samples.push(calibrated(profile, &sample));
+            }
+            samples.push(sample);
+        }
+        Ok(samples)
+    }
+}
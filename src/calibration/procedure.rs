@@ -0,0 +1,534 @@
+use std::collections::VecDeque;
+
+use crate::math::{self, Vec3, IDENTITY3};
+use crate::sensors::{Sample, SensorKind};
+
+use super::fit::fit_ellipsoid;
+use super::{Calibration, CalibrationError, CalibrationMethod};
+
+/// Standard gravity, which a calibrated accelerometer at rest should read.
+pub const STANDARD_GRAVITY: f64 = 9.80665;
+
+/// What the user should be told while a procedure runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcedureStatus {
+    /// `0.0..=1.0`
+    pub progress: f64,
+    pub instruction: String,
+    pub ready: bool,
+}
+
+/// The result of a finished procedure, before it is attached to a node and stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcedureResult {
+    pub calibration: Calibration,
+    pub method: CalibrationMethod,
+    /// RMS relative error of the calibrated readings against the expected magnitude.
+    pub residual: f64,
+}
+
+/// An interactive calibration in progress.
+#[derive(Debug, Clone)]
+pub enum Procedure {
+    Magnetometer(MagnetometerProcedure),
+    Accelerometer(AccelerometerProcedure),
+}
+
+impl Procedure {
+    /// Starts the procedure for `kind`, if there is one.
+    pub fn start(kind: SensorKind) -> Option<Self> {
+        match kind {
+            SensorKind::Magnetometer => {
+                Some(Procedure::Magnetometer(MagnetometerProcedure::default()))
+            }
+            SensorKind::Accelerometer => {
+                Some(Procedure::Accelerometer(AccelerometerProcedure::default()))
+            }
+            SensorKind::Barometer | SensorKind::Gyroscope => None,
+        }
+    }
+
+    pub fn kind(&self) -> SensorKind {
+        match self {
+            Procedure::Magnetometer(_) => SensorKind::Magnetometer,
+            Procedure::Accelerometer(_) => SensorKind::Accelerometer,
+        }
+    }
+
+    /// Feeds a sample. Samples from other channels are ignored.
+    pub fn push(&mut self, sample: &Sample) {
+        if sample.channel != self.kind().channel() {
+            return;
+        }
+        let Some(v) = math::vec3(&sample.values) else {
+            return;
+        };
+        match self {
+            Procedure::Magnetometer(p) => p.push(v),
+            Procedure::Accelerometer(p) => p.push(v),
+        }
+    }
+
+    pub fn status(&self) -> ProcedureStatus {
+        match self {
+            Procedure::Magnetometer(p) => p.status(),
+            Procedure::Accelerometer(p) => p.status(),
+        }
+    }
+
+    pub fn finish(&self) -> Result<ProcedureResult, CalibrationError> {
+        match self {
+            Procedure::Magnetometer(p) => p.finish(),
+            Procedure::Accelerometer(p) => p.finish(),
+        }
+    }
+}
+
+/// Figure-eight calibration: the user waves the phone through as many orientations as possible while we collect
+/// raw field vectors, then an ellipsoid is fitted through them.
+#[derive(Debug, Clone, Default)]
+pub struct MagnetometerProcedure {
+    points: Vec<Vec3>,
+    min: Option<Vec3>,
+    max: Option<Vec3>,
+}
+
+impl MagnetometerProcedure {
+    /// Samples needed before a fit is attempted.
+    pub const MIN_SAMPLES: usize = 300;
+    /// Samples needed in each octant around the estimated center.
+    const PER_OCTANT: usize = 10;
+
+    pub fn push(&mut self, v: Vec3) {
+        self.min = Some(
+            self.min
+                .map_or(v, |m| [m[0].min(v[0]), m[1].min(v[1]), m[2].min(v[2])]),
+        );
+        self.max = Some(
+            self.max
+                .map_or(v, |m| [m[0].max(v[0]), m[1].max(v[1]), m[2].max(v[2])]),
+        );
+        self.points.push(v);
+    }
+
+    /// How many samples fall in each octant around the midpoint of the readings seen so far.
+    fn octants(&self) -> [usize; 8] {
+        let mut counts = [0; 8];
+        let (Some(min), Some(max)) = (self.min, self.max) else {
+            return counts;
+        };
+        let center = math::scale(math::add(min, max), 0.5);
+        for p in &self.points {
+            let d = math::sub(*p, center);
+            let index =
+                (d[0] > 0.0) as usize | ((d[1] > 0.0) as usize) << 1 | ((d[2] > 0.0) as usize) << 2;
+            counts[index] += 1;
+        }
+        counts
+    }
+
+    pub fn status(&self) -> ProcedureStatus {
+        let covered = self
+            .octants()
+            .iter()
+            .filter(|&&c| c >= Self::PER_OCTANT)
+            .count();
+        let progress =
+            (covered as f64 / 8.0).min(self.points.len() as f64 / Self::MIN_SAMPLES as f64);
+        let ready = progress >= 1.0;
+        let instruction = if ready {
+            "Enough orientations collected. Save to apply the calibration.".to_string()
+        } else {
+            format!(
+                "Move the phone in a slow figure-eight, rolling it as you go. {covered} of 8 directions covered."
+            )
+        };
+        ProcedureStatus {
+            progress,
+            instruction,
+            ready,
+        }
+    }
+
+    pub fn finish(&self) -> Result<ProcedureResult, CalibrationError> {
+        if !self.status().ready {
+            return Err(CalibrationError::NotEnoughData(
+                "the phone has not been rotated through enough orientations".into(),
+            ));
+        }
+        let ellipsoid = fit_ellipsoid(&self.points).ok_or(CalibrationError::Degenerate)?;
+        // Scale every axis to the mean radius, which keeps readings in µT rather than normalising them to 1.
+        let mean = (ellipsoid.radii[0] + ellipsoid.radii[1] + ellipsoid.radii[2]) / 3.0;
+        let mut matrix = IDENTITY3;
+        for (i, row) in matrix.iter_mut().enumerate() {
+            row[i] = mean / ellipsoid.radii[i];
+        }
+        let calibration = Calibration::Affine {
+            bias: ellipsoid.center,
+            matrix,
+        };
+        let residual = rms_relative(&calibration, &self.points, mean);
+        Ok(ProcedureResult {
+            calibration,
+            method: CalibrationMethod::FigureEight,
+            residual,
+        })
+    }
+}
+
+/// One of the six ways a phone can lie still for the accelerometer calibration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Face {
+    ScreenUp,
+    ScreenDown,
+    TopUp,
+    TopDown,
+    RightEdgeUp,
+    LeftEdgeUp,
+}
+
+impl Face {
+    pub const ALL: [Face; 6] = [
+        Face::ScreenUp,
+        Face::ScreenDown,
+        Face::TopUp,
+        Face::TopDown,
+        Face::RightEdgeUp,
+        Face::LeftEdgeUp,
+    ];
+
+    /// The body axis pointing up, and its sign.
+    pub fn axis(self) -> (usize, f64) {
+        match self {
+            Face::RightEdgeUp => (0, 1.0),
+            Face::LeftEdgeUp => (0, -1.0),
+            Face::TopUp => (1, 1.0),
+            Face::TopDown => (1, -1.0),
+            Face::ScreenUp => (2, 1.0),
+            Face::ScreenDown => (2, -1.0),
+        }
+    }
+
+    /// The face pointing up given an accelerometer reading at rest.
+    fn of(v: Vec3) -> Face {
+        let axis = (0..3)
+            .max_by(|&a, &b| v[a].abs().total_cmp(&v[b].abs()))
+            .unwrap_or(2);
+        let up = v[axis] > 0.0;
+        *Face::ALL
+            .iter()
+            .find(|f| f.axis() == (axis, if up { 1.0 } else { -1.0 }))
+            .expect("every axis and sign has a face")
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Face::ScreenUp => "flat, screen up",
+            Face::ScreenDown => "flat, screen down",
+            Face::TopUp => "standing on its bottom edge",
+            Face::TopDown => "standing on its top edge",
+            Face::RightEdgeUp => "on its left edge",
+            Face::LeftEdgeUp => "on its right edge",
+        }
+    }
+}
+
+/// Six orientation calibration: the user rests the phone on each face in turn, and the average reading on each face
+/// gives a per axis bias and scale.
+#[derive(Debug, Clone, Default)]
+pub struct AccelerometerProcedure {
+    window: VecDeque<Vec3>,
+    faces: Vec<(Face, Vec3)>,
+}
+
+impl AccelerometerProcedure {
+    /// Consecutive samples that must agree before the phone counts as resting.
+    const WINDOW: usize = 50;
+    /// Largest standard deviation on any axis (m/s²) that still counts as resting.
+    const STILL: f64 = 0.08;
+
+    pub fn push(&mut self, v: Vec3) {
+        if self.window.len() == Self::WINDOW {
+            self.window.pop_front();
+        }
+        self.window.push_back(v);
+        if self.window.len() < Self::WINDOW {
+            return;
+        }
+
+        let mean = math::scale(
+            self.window
+                .iter()
+                .fold([0.0; 3], |acc, v| math::add(acc, *v)),
+            1.0 / self.window.len() as f64,
+        );
+        // A slowly rotating phone still reads 1 g, so stillness has to be judged per axis, not on the magnitude.
+        let deviation = (0..3)
+            .map(|axis| {
+                let variance = self
+                    .window
+                    .iter()
+                    .map(|v| (v[axis] - mean[axis]).powi(2))
+                    .sum::<f64>();
+                (variance / self.window.len() as f64).sqrt()
+            })
+            .fold(0.0, f64::max);
+        if deviation > Self::STILL {
+            return;
+        }
+        // Only accept readings close to one of the six faces, not a phone propped at an angle.
+        let dominant = mean.iter().fold(0.0f64, |acc, v| acc.max(v.abs()));
+        if dominant < 0.9 * math::norm(mean) {
+            return;
+        }
+        let face = Face::of(mean);
+        if self.faces.iter().all(|(f, _)| *f != face) {
+            self.faces.push((face, mean));
+            self.window.clear();
+        }
+    }
+
+    pub fn remaining(&self) -> Vec<Face> {
+        Face::ALL
+            .into_iter()
+            .filter(|face| self.faces.iter().all(|(f, _)| f != face))
+            .collect()
+    }
+
+    pub fn status(&self) -> ProcedureStatus {
+        let remaining = self.remaining();
+        let ready = remaining.is_empty();
+        let instruction = match remaining.first() {
+            Some(face) => format!(
+                "Rest the phone {} and hold it still. {} of 6 orientations done.",
+                face.describe(),
+                6 - remaining.len()
+            ),
+            None => "All six orientations collected. Save to apply the calibration.".to_string(),
+        };
+        ProcedureStatus {
+            progress: self.faces.len() as f64 / 6.0,
+            instruction,
+            ready,
+        }
+    }
+
+    pub fn finish(&self) -> Result<ProcedureResult, CalibrationError> {
+        let reading = |face: Face| {
+            self.faces
+                .iter()
+                .find(|(f, _)| *f == face)
+                .map(|(_, v)| *v)
+                .ok_or_else(|| {
+                    CalibrationError::NotEnoughData(format!("missing reading {}", face.describe()))
+                })
+        };
+        let mut bias = [0.0; 3];
+        let mut matrix = IDENTITY3;
+        for (axis, (up, down)) in [
+            (Face::RightEdgeUp, Face::LeftEdgeUp),
+            (Face::TopUp, Face::TopDown),
+            (Face::ScreenUp, Face::ScreenDown),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let (up, down) = (reading(up)?[axis], reading(down)?[axis]);
+            let half_span = (up - down) / 2.0;
+            if half_span <= 0.0 {
+                return Err(CalibrationError::Degenerate);
+            }
+            bias[axis] = (up + down) / 2.0;
+            matrix[axis][axis] = STANDARD_GRAVITY / half_span;
+        }
+        let calibration = Calibration::Affine { bias, matrix };
+        let rests: Vec<Vec3> = self.faces.iter().map(|(_, v)| *v).collect();
+        let residual = rms_relative(&calibration, &rests, STANDARD_GRAVITY);
+        Ok(ProcedureResult {
+            calibration,
+            method: CalibrationMethod::SixOrientation,
+            residual,
+        })
+    }
+}
+
+fn rms_relative(calibration: &Calibration, points: &[Vec3], expected: f64) -> f64 {
+    let sum: f64 = points
+        .iter()
+        .map(|p| {
+            let corrected = calibration.apply(p);
+            ((math::norm(math::vec3(&corrected).unwrap_or_default()) - expected) / expected).powi(2)
+        })
+        .sum();
+    (sum / points.len().max(1) as f64).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Mat3;
+
+    fn sample(kind: SensorKind, v: Vec3) -> Sample {
+        Sample {
+            channel: kind.channel(),
+            local_time: 0,
+            values: v.to_vec(),
+        }
+    }
+
+    fn affine(result: &ProcedureResult) -> (Vec3, Mat3) {
+        match result.calibration {
+            Calibration::Affine { bias, matrix } => (bias, matrix),
+            ref other => panic!("expected an affine calibration, got {other:?}"),
+        }
+    }
+
+    /// Raw readings of a phone waved through every direction of a 50 µT field, with hard iron `bias` and soft iron
+    /// `radii`.
+    fn waved(bias: Vec3, radii: Vec3, count: usize) -> Vec<Vec3> {
+        let golden = std::f64::consts::PI * (3.0 - 5f64.sqrt());
+        (0..count)
+            .map(|i| {
+                let z = 1.0 - 2.0 * (i as f64 + 0.5) / count as f64;
+                let r = (1.0 - z * z).sqrt();
+                let theta = golden * i as f64;
+                let unit = [r * theta.cos(), r * theta.sin(), z];
+                std::array::from_fn(|axis| bias[axis] + radii[axis] * unit[axis])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_figure_eight_gives_the_offset_and_scale() {
+        let (bias, radii) = ([20.0, -8.0, 35.0], [45.0, 50.0, 55.0]);
+        let mut procedure = Procedure::start(SensorKind::Magnetometer).unwrap();
+        for v in waved(bias, radii, MagnetometerProcedure::MIN_SAMPLES) {
+            assert!(!procedure.status().ready);
+            procedure.push(&sample(SensorKind::Magnetometer, v));
+            // Other sensors are ignored.
+            procedure.push(&sample(SensorKind::Accelerometer, [0.0, 0.0, 9.8]));
+        }
+        assert!(procedure.status().ready);
+
+        let result = procedure.finish().unwrap();
+        assert_eq!(result.method, CalibrationMethod::FigureEight);
+        assert!(result.residual < 1e-6, "{}", result.residual);
+        let (fitted, matrix) = affine(&result);
+        for axis in 0..3 {
+            assert!((fitted[axis] - bias[axis]).abs() < 1e-6, "{fitted:?}");
+            assert!(
+                (matrix[axis][axis] - 50.0 / radii[axis]).abs() < 1e-6,
+                "{matrix:?}"
+            );
+        }
+        let corrected = result
+            .calibration
+            .apply(&[bias[0] + radii[0], bias[1], bias[2]]);
+        assert!((math::norm(math::vec3(&corrected).unwrap()) - 50.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn a_figure_eight_needs_every_direction() {
+        // Turned flat on a table, the phone never points up or down.
+        let mut flat = MagnetometerProcedure::default();
+        for i in 0..1_000 {
+            let angle = i as f64 * 0.05;
+            flat.push([50.0 * angle.cos(), 50.0 * angle.sin(), -20.0]);
+        }
+        let status = flat.status();
+        assert!(!status.ready);
+        assert!(
+            status.instruction.contains("4 of 8"),
+            "{}",
+            status.instruction
+        );
+        assert!(matches!(
+            flat.finish(),
+            Err(CalibrationError::NotEnoughData(_))
+        ));
+
+        let mut brief = MagnetometerProcedure::default();
+        for v in waved([0.0; 3], [50.0; 3], MagnetometerProcedure::MIN_SAMPLES / 2) {
+            brief.push(v);
+        }
+        assert!((brief.status().progress - 0.5).abs() < 0.01);
+        assert!(matches!(
+            brief.finish(),
+            Err(CalibrationError::NotEnoughData(_))
+        ));
+    }
+
+    /// What an accelerometer with `bias` and `gain` reads at rest on `face`.
+    fn resting(face: Face, bias: Vec3, gain: Vec3) -> Vec3 {
+        let (axis, sign) = face.axis();
+        let mut v = bias;
+        v[axis] += sign * STANDARD_GRAVITY / gain[axis];
+        v
+    }
+
+    #[test]
+    fn six_orientations_give_the_bias_and_gain() {
+        let (bias, gain) = ([0.2, -0.15, 0.35], [1.02, 0.97, 1.01]);
+        let mut procedure = Procedure::start(SensorKind::Accelerometer).unwrap();
+        for (done, face) in [
+            Face::TopDown,
+            Face::ScreenUp,
+            Face::LeftEdgeUp,
+            Face::ScreenDown,
+            Face::RightEdgeUp,
+            Face::TopUp,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            assert_eq!(procedure.status().progress, done as f64 / 6.0);
+            for _ in 0..AccelerometerProcedure::WINDOW {
+                procedure.push(&sample(
+                    SensorKind::Accelerometer,
+                    resting(face, bias, gain),
+                ));
+            }
+        }
+        assert!(procedure.status().ready);
+
+        let result = procedure.finish().unwrap();
+        assert_eq!(result.method, CalibrationMethod::SixOrientation);
+        assert!(result.residual < 1e-9, "{}", result.residual);
+        let (fitted, matrix) = affine(&result);
+        for axis in 0..3 {
+            assert!((fitted[axis] - bias[axis]).abs() < 1e-9, "{fitted:?}");
+            assert!((matrix[axis][axis] - gain[axis]).abs() < 1e-9, "{matrix:?}");
+        }
+    }
+
+    #[test]
+    fn moving_or_tilted_phones_do_not_count() {
+        let mut procedure = AccelerometerProcedure::default();
+        // Shaken.
+        for i in 0..200 {
+            let shake = if i % 2 == 0 { 1.0 } else { -1.0 };
+            procedure.push([shake, 0.0, STANDARD_GRAVITY]);
+        }
+        // Propped at 45°.
+        let tilted = STANDARD_GRAVITY / 2f64.sqrt();
+        for _ in 0..200 {
+            procedure.push([tilted, 0.0, tilted]);
+        }
+        assert_eq!(procedure.remaining(), Face::ALL);
+
+        // Resting on the same face twice only counts once.
+        for _ in 0..2 * AccelerometerProcedure::WINDOW {
+            procedure.push([0.0, 0.0, STANDARD_GRAVITY]);
+        }
+        assert_eq!(procedure.remaining().len(), 5);
+        let status = procedure.status();
+        assert!(!status.ready);
+        assert!(status
+            .instruction
+            .starts_with("Rest the phone flat, screen down"));
+        assert!(matches!(
+            procedure.finish(),
+            Err(CalibrationError::NotEnoughData(_))
+        ));
+    }
+}
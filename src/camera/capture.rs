@@ -0,0 +1,113 @@
+//! Cameras on a thread of their own.
+//!
+//! Webcam handles are not always allowed to leave the thread that opened them, and a camera polled only when the node
+//! gets round to it loses frames. [`CaptureThread`] opens the camera on a new thread, takes frames there as they come
+//! and hands over the newest one whenever it is asked, like any other [`CameraSource`].
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{CameraError, CameraFormat, CameraSource, DeviceInfo, FormatRequest, Frame};
+use crate::sensors::Micros;
+use crate::time_sync::local_now;
+
+/// Frames held for the caller. The camera's contract is to hand out the newest frame and drop what was missed, so
+/// there is no point keeping more than a couple.
+const BACKLOG: usize = 2;
+
+pub struct CaptureThread {
+    info: DeviceInfo,
+    format: CameraFormat,
+    frames: Receiver<Result<Frame, CameraError>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl CaptureThread {
+    /// Opens camera `id` (see [`super::open`]) on a new thread. Fails if the camera cannot be opened.
+    pub fn open(id: &str, request: FormatRequest) -> Result<Self, CameraError> {
+        let (opened, opening) = mpsc::channel();
+        let (sender, frames) = mpsc::sync_channel(BACKLOG);
+        let stop = Arc::new(AtomicBool::new(false));
+        let id = id.to_string();
+        let stopped = stop.clone();
+        std::thread::Builder::new()
+            .name(format!("camera {id}"))
+            .spawn(move || {
+                let mut camera = match super::open(&id, request) {
+                    Ok(camera) => camera,
+                    Err(e) => {
+                        let _ = opened.send(Err(e));
+                        return;
+                    }
+                };
+                let format = camera.format();
+                if opened.send(Ok((camera.info().clone(), format))).is_err() {
+                    return;
+                }
+                // Polling at four times the frame rate keeps the delay a frame waits for pickup small.
+                let idle = Duration::from_micros(250_000 / format.fps.max(1) as u64);
+                while !stopped.load(Ordering::Relaxed) {
+                    match camera.frame(local_now()) {
+                        Ok(Some(frame)) => match sender.try_send(Ok(frame)) {
+                            Ok(()) | Err(mpsc::TrySendError::Full(_)) => {}
+                            Err(mpsc::TrySendError::Disconnected(_)) => return,
+                        },
+                        Ok(None) => std::thread::sleep(idle),
+                        Err(e) => {
+                            let _ = sender.send(Err(e));
+                            return;
+                        }
+                    }
+                }
+            })
+            .map_err(|e| CameraError::Backend(format!("cannot start capture thread: {e}")))?;
+        let (info, format) = opening
+            .recv()
+            .map_err(|_| CameraError::Backend("capture thread died while opening".to_string()))??;
+        Ok(CaptureThread {
+            info,
+            format,
+            frames,
+            stop,
+        })
+    }
+}
+
+impl CameraSource for CaptureThread {
+    fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    fn format(&self) -> CameraFormat {
+        self.format
+    }
+
+    /// The newest frame the thread has taken since the last call. Fails once the camera has failed, with its error
+    /// the first time.
+    fn frame(&mut self, _now: Micros) -> Result<Option<Frame>, CameraError> {
+        let mut newest = None;
+        loop {
+            match self.frames.try_recv() {
+                Ok(frame) => newest = Some(frame?),
+                Err(TryRecvError::Empty) => return Ok(newest),
+                Err(TryRecvError::Disconnected) => {
+                    return match newest {
+                        Some(frame) => Ok(Some(frame)),
+                        None => Err(CameraError::Backend(format!(
+                            "capture thread of {} stopped",
+                            self.info.id
+                        ))),
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for CaptureThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
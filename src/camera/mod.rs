@@ -0,0 +1,562 @@
+//! Cameras as another kind of sensor.
+//!
+//! A [`CameraSource`] delivers RGB frames. Webcams go through nokhwa (behind the `webcam` feature), and a synthetic
+//! [`TestPattern`] stands in for them on machines without one. [`CameraSensor`] puts a camera into the sensor pipeline:
+//! it reports a small summary of every frame on a `camera/<device>` channel and keeps the frames themselves for the
+//! video path to pick up. [`MotionGate`] sits between the two and keeps a static scene from being recorded. On links
+//! too slow for any video, [`SnapshotCamera`] takes its place and keeps a still every few minutes instead. A node runs
+//! its camera as a [`CaptureThread`], so that capture keeps its own pace.
+
+#[cfg(not(target_arch = "wasm32"))]
+mod capture;
+mod motion;
+mod pattern;
+mod snapshot;
+
+#[cfg(feature = "webcam")]
+mod native;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::sensors::{ChannelId, ChannelInfo, Micros, Sample, SensorError, SensorSource};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use capture::CaptureThread;
+pub use motion::{Gated, MotionConfig, MotionDetector, MotionEvent, MotionGate};
+pub use pattern::{TestPattern, TEST_PATTERN};
+#[cfg(not(target_arch = "wasm32"))]
+pub use snapshot::snapshot;
+pub use snapshot::{SnapshotCamera, SnapshotConfig};
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum CameraError {
+    #[error("no camera {0:?}")]
+    NoDevice(String),
+    #[error("camera {device} supports no format matching {request}")]
+    UnsupportedFormat {
+        device: String,
+        request: FormatRequest,
+    },
+    #[error("invalid format request {request:?}: {reason}")]
+    BadRequest { request: String, reason: String },
+    #[error("camera backend failed: {0}")]
+    Backend(String),
+}
+
+impl From<CameraError> for SensorError {
+    fn from(e: CameraError) -> Self {
+        SensorError::Unavailable(e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Resolution {
+    pub const fn new(width: u32, height: u32) -> Self {
+        Resolution { width, height }
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/// How the camera encodes frames on the wire. Frames are always handed out as RGB regardless.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum PixelFormat {
+    Mjpeg,
+    Yuyv,
+    Nv12,
+    Gray,
+    Rgb,
+    Bgr,
+}
+
+impl fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PixelFormat::Mjpeg => "MJPEG",
+            PixelFormat::Yuyv => "YUYV",
+            PixelFormat::Nv12 => "NV12",
+            PixelFormat::Gray => "GRAY",
+            PixelFormat::Rgb => "RAWRGB",
+            PixelFormat::Bgr => "RAWBGR",
+        })
+    }
+}
+
+impl FromStr for PixelFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "MJPEG" | "MJPG" => Ok(PixelFormat::Mjpeg),
+            "YUYV" | "YUY2" => Ok(PixelFormat::Yuyv),
+            "NV12" => Ok(PixelFormat::Nv12),
+            "GRAY" | "GREY" => Ok(PixelFormat::Gray),
+            "RAWRGB" | "RGB" => Ok(PixelFormat::Rgb),
+            "RAWBGR" | "BGR" => Ok(PixelFormat::Bgr),
+            _ => Err(format!("unknown pixel format {s:?}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CameraFormat {
+    pub resolution: Resolution,
+    pub pixel_format: PixelFormat,
+    pub fps: u32,
+}
+
+impl fmt::Display for CameraFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} @ {} fps",
+            self.resolution, self.pixel_format, self.fps
+        )
+    }
+}
+
+/// Which of a camera's formats to use. These mirror nokhwa's `RequestedFormatType`, including its naming.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum FormatRequest {
+    /// The largest resolution, then the highest frame rate at it.
+    AbsoluteHighestResolution,
+    /// The highest frame rate, then the largest resolution at it.
+    AbsoluteHighestFrameRate,
+    /// Exactly this resolution, at the highest frame rate it supports.
+    HighestResolution(Resolution),
+    /// Exactly this frame rate, at the largest resolution it supports.
+    HighestFrameRate(u32),
+    Exact(CameraFormat),
+    /// The nearest resolution and then frame rate in the same pixel format.
+    Closest(CameraFormat),
+    /// Whatever the camera lists first.
+    #[default]
+    Any,
+}
+
+impl FormatRequest {
+    /// Picks a format from those a camera supports, or `None` if none satisfy the request.
+    pub fn fulfill(&self, formats: &[CameraFormat]) -> Option<CameraFormat> {
+        let area = |f: &CameraFormat| f.resolution.width as u64 * f.resolution.height as u64;
+        match *self {
+            FormatRequest::AbsoluteHighestResolution => {
+                formats.iter().max_by_key(|f| (area(f), f.fps)).copied()
+            }
+            FormatRequest::AbsoluteHighestFrameRate => {
+                formats.iter().max_by_key(|f| (f.fps, area(f))).copied()
+            }
+            FormatRequest::HighestResolution(resolution) => formats
+                .iter()
+                .filter(|f| f.resolution == resolution)
+                .max_by_key(|f| f.fps)
+                .copied(),
+            FormatRequest::HighestFrameRate(fps) => formats
+                .iter()
+                .filter(|f| f.fps == fps)
+                .max_by_key(|f| area(f))
+                .copied(),
+            FormatRequest::Exact(format) => formats.contains(&format).then_some(format),
+            FormatRequest::Closest(target) => {
+                let distance = |r: Resolution| {
+                    let dx = r.width as i64 - target.resolution.width as i64;
+                    let dy = r.height as i64 - target.resolution.height as i64;
+                    dx * dx + dy * dy
+                };
+                let same_format = formats
+                    .iter()
+                    .filter(|f| f.pixel_format == target.pixel_format);
+                let resolution = same_format
+                    .clone()
+                    .map(|f| f.resolution)
+                    .min_by_key(|r| distance(*r))?;
+                same_format
+                    .filter(|f| f.resolution == resolution)
+                    .min_by_key(|f| f.fps.abs_diff(target.fps))
+                    .copied()
+            }
+            FormatRequest::Any => formats.first().copied(),
+        }
+    }
+}
+
+/// Formats a request the way [`FormatRequest::from_str`] reads it, e.g. `Closest:640,480,15,MJPEG`.
+impl fmt::Display for FormatRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = |c: &CameraFormat| {
+            let r = c.resolution;
+            format!("{},{},{},{}", r.width, r.height, c.fps, c.pixel_format)
+        };
+        match self {
+            FormatRequest::AbsoluteHighestResolution => write!(f, "AbsoluteHighestResolution"),
+            FormatRequest::AbsoluteHighestFrameRate => write!(f, "AbsoluteHighestFrameRate"),
+            FormatRequest::HighestResolution(r) => {
+                write!(f, "HighestResolution:{},{}", r.width, r.height)
+            }
+            FormatRequest::HighestFrameRate(fps) => write!(f, "HighestFrameRate:{fps}"),
+            FormatRequest::Exact(c) => write!(f, "Exact:{}", format(c)),
+            FormatRequest::Closest(c) => write!(f, "Closest:{}", format(c)),
+            FormatRequest::Any => write!(f, "Any"),
+        }
+    }
+}
+
+/// Parses `<kind>[:<options>]`, the syntax of the old capture example's command line:
+///
+/// - `AbsoluteHighestResolution`, `AbsoluteHighestFrameRate`, `Any` (or `None`)
+/// - `HighestResolution:<width>,<height>`
+/// - `HighestFrameRate:<fps>`
+/// - `Exact:<width>,<height>,<fps>,<pixel format>` and likewise `Closest:...`
+impl FromStr for FormatRequest {
+    type Err = CameraError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = |reason: &str| CameraError::BadRequest {
+            request: s.to_string(),
+            reason: reason.to_string(),
+        };
+        let (kind, options) = match s.split_once(':') {
+            Some((kind, options)) => (kind, Some(options)),
+            None => (s, None),
+        };
+        let values: Vec<&str> = options
+            .map(|o| o.split(',').map(str::trim).collect())
+            .unwrap_or_default();
+        let number = |i: usize, what: &str| -> Result<u32, CameraError> {
+            let value = values
+                .get(i)
+                .ok_or_else(|| bad(&format!("missing {what}")))?;
+            value
+                .parse()
+                .map_err(|_| bad(&format!("{what} {value:?} is not a number")))
+        };
+        let camera_format = || -> Result<CameraFormat, CameraError> {
+            let pixel_format = values.get(3).ok_or_else(|| bad("missing pixel format"))?;
+            Ok(CameraFormat {
+                resolution: Resolution::new(number(0, "width")?, number(1, "height")?),
+                fps: number(2, "frame rate")?,
+                pixel_format: pixel_format.parse().map_err(|e: String| bad(&e))?,
+            })
+        };
+        match kind {
+            "AbsoluteHighestResolution" => Ok(FormatRequest::AbsoluteHighestResolution),
+            "AbsoluteHighestFrameRate" => Ok(FormatRequest::AbsoluteHighestFrameRate),
+            "HighestResolution" => Ok(FormatRequest::HighestResolution(Resolution::new(
+                number(0, "width")?,
+                number(1, "height")?,
+            ))),
+            "HighestFrameRate" => Ok(FormatRequest::HighestFrameRate(number(0, "frame rate")?)),
+            "Exact" => Ok(FormatRequest::Exact(camera_format()?)),
+            "Closest" => Ok(FormatRequest::Closest(camera_format()?)),
+            "Any" | "None" => Ok(FormatRequest::Any),
+            _ => Err(bad(
+                "expected AbsoluteHighestResolution, AbsoluteHighestFrameRate, HighestResolution, \
+                 HighestFrameRate, Exact, Closest or Any",
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    /// Stable within one machine; what [`open`] takes.
+    pub id: String,
+    pub name: String,
+    pub description: String,
+}
+
+/// One captured image, as tightly packed 8 bit RGB rows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// Local time the frame was captured.
+    pub captured: Micros,
+    pub width: u32,
+    pub height: u32,
+    pub rgb: Vec<u8>,
+}
+
+impl Frame {
+    /// Mean luma (BT.601) in `0.0..=1.0`.
+    pub fn mean_luma(&self) -> f64 {
+        let pixels = self.rgb.len() / 3;
+        if pixels == 0 {
+            return 0.0;
+        }
+        let sum: f64 = self
+            .rgb
+            .chunks_exact(3)
+            .map(|p| 0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64)
+            .sum();
+        sum / pixels as f64 / 255.0
+    }
+
+    /// A copy scaled to `width` x `height`, averaging the source pixels that fall into each destination pixel. Meant
+    /// for scaling down; scaling up repeats pixels.
+    pub fn resized(&self, width: u32, height: u32) -> Frame {
+        if (width, height) == (self.width, self.height) {
+            return self.clone();
+        }
+        let (sw, sh) = (self.width as usize, self.height as usize);
+        let (dw, dh) = (width as usize, height as usize);
+        let mut rgb = Vec::with_capacity(dw * dh * 3);
+        for y in 0..dh {
+            let (y0, y1) = (y * sh / dh, ((y + 1) * sh / dh).max(y * sh / dh + 1));
+            for x in 0..dw {
+                let (x0, x1) = (x * sw / dw, ((x + 1) * sw / dw).max(x * sw / dw + 1));
+                let mut sum = [0u32; 3];
+                for row in y0..y1 {
+                    for col in x0..x1 {
+                        let i = (row * sw + col) * 3;
+                        for (s, v) in sum.iter_mut().zip(&self.rgb[i..i + 3]) {
+                            *s += *v as u32;
+                        }
+                    }
+                }
+                let n = ((y1 - y0) * (x1 - x0)) as u32;
+                rgb.extend(sum.map(|v| (v / n) as u8));
+            }
+        }
+        Frame {
+            captured: self.captured,
+            width,
+            height,
+            rgb,
+        }
+    }
+}
+
+pub trait CameraSource {
+    fn info(&self) -> &DeviceInfo;
+
+    /// The format negotiated when the camera was opened.
+    fn format(&self) -> CameraFormat;
+
+    /// The newest frame captured since the last call, if any. Cameras keep running between calls; frames the caller
+    /// was too slow to take are dropped rather than queued.
+    fn frame(&mut self, now: Micros) -> Result<Option<Frame>, CameraError>;
+}
+
+/// Every camera on this machine, followed by the test pattern.
+pub fn list_devices() -> Result<Vec<DeviceInfo>, CameraError> {
+    #[cfg(feature = "webcam")]
+    let native = native::list_devices()?;
+    #[cfg(not(feature = "webcam"))]
+    let native = Vec::new();
+    Ok(native.into_iter().chain([TestPattern::info()]).collect())
+}
+
+/// Opens a camera by the id [`list_devices`] gave it.
+pub fn open(id: &str, request: FormatRequest) -> Result<Box<dyn CameraSource>, CameraError> {
+    if id == TEST_PATTERN {
+        return Ok(Box::new(TestPattern::open(request)?));
+    }
+    #[cfg(feature = "webcam")]
+    return Ok(Box::new(native::NativeCamera::open(id, request)?));
+    #[cfg(not(feature = "webcam"))]
+    Err(CameraError::NoDevice(id.to_string()))
+}
+
+/// Puts a camera into the sensor pipeline.
+///
+/// Every frame becomes a sample of `[width, height, mean luma]` on `camera/<device>`, which is enough to chart
+/// exposure and spot a dead camera; the frames themselves wait in [`CameraSensor::take_frames`] for the encoder.
+pub struct CameraSensor<C> {
+    pub camera: C,
+    /// Frames beyond this many are dropped, oldest first, if nobody takes them.
+    pub max_queued: usize,
+    frames: VecDeque<Frame>,
+}
+
+impl<C: CameraSource> CameraSensor<C> {
+    pub fn new(camera: C) -> Self {
+        CameraSensor {
+            camera,
+            max_queued: 64,
+            frames: VecDeque::new(),
+        }
+    }
+
+    pub fn channel(&self) -> ChannelId {
+        ChannelId::new(format!("camera/{}", self.camera.info().id))
+    }
+
+    /// Frames captured since the last call, oldest first.
+    pub fn take_frames(&mut self) -> Vec<Frame> {
+        self.frames.drain(..).collect()
+    }
+}
+
+impl<C: CameraSource> SensorSource for CameraSensor<C> {
+    fn channels(&self) -> Vec<ChannelInfo> {
+        vec![ChannelInfo {
+            id: self.channel(),
+            kind: None,
+            unit: "px, px, luma".to_string(),
+            dims: 3,
+        }]
+    }
+
+    fn poll(&mut self, now: Micros) -> Result<Vec<Sample>, SensorError> {
+        let Some(frame) = self.camera.frame(now)? else {
+            return Ok(Vec::new());
+        };
+        let sample = Sample {
+            channel: self.channel(),
+            local_time: frame.captured,
+            values: vec![frame.width as f64, frame.height as f64, frame.mean_luma()],
+        };
+        if self.frames.len() >= self.max_queued {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+        Ok(vec![sample])
+    }
+}
+
+impl<C: CameraSource + ?Sized> CameraSource for Box<C> {
+    fn info(&self) -> &DeviceInfo {
+        (**self).info()
+    }
+
+    fn format(&self) -> CameraFormat {
+        (**self).format()
+    }
+
+    fn frame(&mut self, now: Micros) -> Result<Option<Frame>, CameraError> {
+        (**self).frame(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(width: u32, height: u32, fps: u32, pixel_format: PixelFormat) -> CameraFormat {
+        CameraFormat {
+            resolution: Resolution::new(width, height),
+            pixel_format,
+            fps,
+        }
+    }
+
+    #[test]
+    fn format_requests_pick_from_what_the_camera_supports() {
+        let formats = [
+            format(640, 480, 30, PixelFormat::Yuyv),
+            format(1280, 720, 10, PixelFormat::Mjpeg),
+            format(1280, 720, 30, PixelFormat::Mjpeg),
+            format(320, 240, 60, PixelFormat::Yuyv),
+        ];
+        let pick = |request: &str| request.parse::<FormatRequest>().unwrap().fulfill(&formats);
+        assert_eq!(pick("AbsoluteHighestResolution"), Some(formats[2]));
+        assert_eq!(pick("AbsoluteHighestFrameRate"), Some(formats[3]));
+        assert_eq!(pick("HighestResolution:1280,720"), Some(formats[2]));
+        assert_eq!(pick("HighestFrameRate:30"), Some(formats[2]));
+        assert_eq!(pick("Exact:640,480,30,YUYV"), Some(formats[0]));
+        assert_eq!(pick("Exact:640,480,15,YUYV"), None);
+        assert_eq!(pick("Closest:1000,700,12,MJPEG"), Some(formats[1]));
+        assert_eq!(pick("None"), Some(formats[0]));
+    }
+
+    #[test]
+    fn format_requests_read_back_what_they_print() {
+        for text in [
+            "AbsoluteHighestResolution",
+            "HighestResolution:640,480",
+            "HighestFrameRate:15",
+            "Exact:640,480,15,MJPEG",
+            "Closest:320,240,30,RAWRGB",
+            "Any",
+        ] {
+            assert_eq!(text.parse::<FormatRequest>().unwrap().to_string(), text);
+        }
+        for bad in ["Fastest", "Exact:640,480,15", "HighestFrameRate:fast"] {
+            assert!(matches!(
+                bad.parse::<FormatRequest>(),
+                Err(CameraError::BadRequest { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn the_test_pattern_opens_like_any_camera() {
+        assert!(list_devices().unwrap().iter().any(|d| d.id == TEST_PATTERN));
+        let request = "HighestResolution:320,240".parse().unwrap();
+        let camera = open(TEST_PATTERN, request).unwrap();
+        assert_eq!(camera.format(), TestPattern::FORMATS[0]);
+        assert!(matches!(
+            open(TEST_PATTERN, "HighestResolution:1920,1080".parse().unwrap()),
+            Err(CameraError::UnsupportedFormat { .. })
+        ));
+        #[cfg(not(feature = "webcam"))]
+        assert!(matches!(
+            open("/dev/video0", FormatRequest::Any),
+            Err(CameraError::NoDevice(_))
+        ));
+    }
+
+    #[test]
+    fn the_test_pattern_delivers_frames_at_its_frame_rate() {
+        let mut camera = TestPattern::open(FormatRequest::Any).unwrap();
+        let mut frames = Vec::new();
+        // Polled every 10 ms for a second, at 15 fps.
+        for t in 0..100 {
+            frames.extend(camera.frame(1_000_000 + t * 10_000).unwrap());
+        }
+        assert_eq!(frames.len(), 15);
+        assert_eq!(frames[1].captured - frames[0].captured, 1_000_000 / 15);
+        assert_eq!(frames[0].rgb.len(), 320 * 240 * 3);
+        assert_ne!(frames[0].rgb, frames[1].rgb, "the box moves");
+
+        camera.motion = false;
+        assert_eq!(camera.render(3), camera.render(40));
+    }
+
+    #[test]
+    fn the_camera_sensor_summarises_frames_and_keeps_them_for_the_encoder() {
+        let mut sensor = CameraSensor::new(TestPattern::open(FormatRequest::Any).unwrap());
+        sensor.max_queued = 3;
+        let mut samples = Vec::new();
+        for t in 0..5 {
+            samples.extend(sensor.poll(t * 100_000).unwrap());
+        }
+        assert_eq!(samples.len(), 5);
+        assert_eq!(
+            sensor.channels()[0].id,
+            ChannelId::new("camera/test-pattern")
+        );
+        assert_eq!(samples[0].channel, sensor.channel());
+        assert_eq!(&samples[0].values[..2], &[320.0, 240.0]);
+        assert!((0.2..0.8).contains(&samples[0].values[2]));
+
+        let frames = sensor.take_frames();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[2].captured, samples[4].local_time);
+        assert!(sensor.take_frames().is_empty());
+    }
+
+    #[test]
+    fn frames_scale_down_by_averaging() {
+        let frame = Frame {
+            captured: 0,
+            width: 2,
+            height: 2,
+            rgb: vec![0, 0, 0, 255, 255, 255, 255, 255, 255, 0, 0, 0],
+        };
+        let small = frame.resized(1, 1);
+        assert_eq!(small.rgb, vec![127, 127, 127]);
+        assert!((frame.mean_luma() - 0.5).abs() < 1e-9);
+    }
+}
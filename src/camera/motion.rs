@@ -0,0 +1,394 @@
+//! Recording only when something happens.
+//!
+//! [`MotionDetector`] compares a coarse luma grid of every frame against a slowly adapting background, which is
+//! cheap enough to run on every frame ahead of the encoder. [`MotionGate`] uses it to decide which frames get
+//! encoded: everything while there is motion, plus a pre-roll from before it started and a post-roll after it
+//! stopped, and otherwise only an occasional keepalive frame so the footage still shows the camera was alive.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use super::Frame;
+use crate::sensors::Micros;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MotionConfig {
+    /// Size of the luma grid frames are reduced to before comparing.
+    pub grid: (u32, u32),
+    /// Luma difference (out of 255) for a cell to count as changed.
+    pub threshold: u8,
+    /// Fraction of changed cells that counts as motion.
+    pub min_area: f64,
+    /// Fraction of changed cells above which the change is taken to be lighting (a cloud, the sun, the camera's
+    /// exposure) rather than motion, and the background starts over.
+    pub max_area: f64,
+    /// How fast the background follows the scene, per frame.
+    pub adaptation: f64,
+    /// How much footage from before motion was detected is kept. Pre-roll frames wait unencoded, so this costs
+    /// `width * height * 3 * fps` bytes per second of memory.
+    pub pre_roll: Micros,
+    /// How long recording carries on after the last motion.
+    pub post_roll: Micros,
+    /// Time between keepalive frames while nothing moves.
+    pub keepalive: Micros,
+}
+
+impl Default for MotionConfig {
+    fn default() -> Self {
+        MotionConfig {
+            grid: (64, 48),
+            threshold: 24,
+            min_area: 0.01,
+            max_area: 0.6,
+            adaptation: 0.05,
+            pre_roll: 2_000_000,
+            post_roll: 10_000_000,
+            keepalive: 10_000_000,
+        }
+    }
+}
+
+/// Frame differencing against a running background.
+#[derive(Debug, Clone)]
+pub struct MotionDetector {
+    pub config: MotionConfig,
+    background: Vec<f32>,
+}
+
+impl MotionDetector {
+    pub fn new(config: MotionConfig) -> Self {
+        MotionDetector {
+            config,
+            background: Vec::new(),
+        }
+    }
+
+    /// The fraction of the grid that changed. The first frame, and any that changes too much, only resets the
+    /// background and scores zero.
+    pub fn score(&mut self, frame: &Frame) -> f64 {
+        let (w, h) = self.config.grid;
+        let small = frame.resized(w, h);
+        let luma: Vec<f32> = small
+            .rgb
+            .chunks_exact(3)
+            .map(|p| 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32)
+            .collect();
+        if self.background.len() != luma.len() {
+            self.background = luma;
+            return 0.0;
+        }
+
+        let threshold = self.config.threshold as f32;
+        let changed = luma
+            .iter()
+            .zip(&self.background)
+            .filter(|(l, b)| (*l - *b).abs() > threshold)
+            .count();
+        let score = changed as f64 / luma.len() as f64;
+        if score > self.config.max_area {
+            self.background = luma;
+            return 0.0;
+        }
+        let alpha = self.config.adaptation as f32;
+        for (b, l) in self.background.iter_mut().zip(&luma) {
+            *b += alpha * (l - *b);
+        }
+        score
+    }
+
+    pub fn is_motion(&mut self, frame: &Frame) -> bool {
+        self.score(frame) >= self.config.min_area
+    }
+}
+
+/// A stretch of time with motion in it, in the capture times of the frames.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MotionEvent {
+    /// The first frame with motion.
+    pub start: Micros,
+    /// The last frame with motion.
+    pub end: Micros,
+    /// Highest fraction of the picture that changed.
+    pub peak: f32,
+}
+
+impl MotionEvent {
+    pub fn overlaps(&self, start: Micros, end: Micros) -> bool {
+        self.start <= end && start <= self.end
+    }
+}
+
+/// What the gate lets through for one frame.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Gated {
+    /// Frames to encode, oldest first: the pre-roll followed by the current frame when motion starts, the current
+    /// frame while recording or for a keepalive, and nothing otherwise.
+    pub frames: Vec<Frame>,
+    /// Whether the first of `frames` should be a keyframe, so the event can be played from its start.
+    pub keyframe: bool,
+    /// An event whose post-roll just ran out.
+    pub finished: Option<MotionEvent>,
+}
+
+/// Decides which frames are worth encoding.
+#[derive(Debug, Clone)]
+pub struct MotionGate {
+    pub detector: MotionDetector,
+    /// The event being recorded.
+    recording: Option<MotionEvent>,
+    pre_roll: VecDeque<Frame>,
+    last_keepalive: Option<Micros>,
+    /// Frames that were not encoded.
+    pub skipped: u64,
+}
+
+impl MotionGate {
+    pub fn new(config: MotionConfig) -> Self {
+        MotionGate {
+            detector: MotionDetector::new(config),
+            recording: None,
+            pre_roll: VecDeque::new(),
+            last_keepalive: None,
+            skipped: 0,
+        }
+    }
+
+    pub fn config(&self) -> &MotionConfig {
+        &self.detector.config
+    }
+
+    /// The event being recorded, if any.
+    pub fn current(&self) -> Option<MotionEvent> {
+        self.recording
+    }
+
+    pub fn push(&mut self, frame: Frame) -> Gated {
+        let score = self.detector.score(&frame);
+        let motion = score >= self.config().min_area;
+        let now = frame.captured;
+        let mut gated = Gated::default();
+
+        if let Some(event) = &mut self.recording {
+            if motion {
+                event.end = now;
+                event.peak = event.peak.max(score as f32);
+            }
+            if now - event.end <= self.detector.config.post_roll {
+                gated.frames.push(frame);
+                return gated;
+            }
+            gated.finished = Some(*event);
+            self.recording = None;
+            self.last_keepalive = Some(now);
+        }
+
+        if motion {
+            self.recording = Some(MotionEvent {
+                start: now,
+                end: now,
+                peak: score as f32,
+            });
+            gated.frames.extend(self.pre_roll.drain(..));
+            gated.frames.push(frame);
+            gated.keyframe = true;
+            return gated;
+        }
+
+        let keepalive_due = self
+            .last_keepalive
+            .is_none_or(|last| now - last >= self.config().keepalive);
+        if keepalive_due {
+            self.last_keepalive = Some(now);
+            // Frames before a keepalive would go to the encoder out of order if motion started now.
+            self.skipped += self.pre_roll.len() as u64;
+            self.pre_roll.clear();
+            gated.frames.push(frame);
+            return gated;
+        }
+
+        self.pre_roll.push_back(frame);
+        let pre_roll = self.config().pre_roll;
+        while self
+            .pre_roll
+            .front()
+            .is_some_and(|oldest| now - oldest.captured > pre_roll)
+        {
+            self.pre_roll.pop_front();
+            self.skipped += 1;
+        }
+        gated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Micros = 1_000_000;
+    const FPS: Micros = 10;
+    const WIDTH: u32 = 80;
+    const HEIGHT: u32 = 60;
+
+    /// A grey yard with a little sensor noise and, when `object` is given, a dark 10 pixel square at that x.
+    fn scene(captured: Micros, brightness: u8, object: Option<u32>) -> Frame {
+        let mut rng = captured as u64 | 1;
+        let mut rgb = Vec::with_capacity((WIDTH * HEIGHT * 3) as usize);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                rng ^= rng << 13;
+                rng ^= rng >> 7;
+                rng ^= rng << 17;
+                let inside =
+                    object.is_some_and(|ox| (ox..ox + 10).contains(&x) && (25..35).contains(&y));
+                let base = if inside { 20 } else { brightness as i16 };
+                let v = (base + (rng % 9) as i16 - 4).clamp(0, 255) as u8;
+                rgb.extend([v, v, v]);
+            }
+        }
+        Frame {
+            captured,
+            width: WIDTH,
+            height: HEIGHT,
+            rgb,
+        }
+    }
+
+    /// A minute of footage in which something crosses the yard between 25 and 28 seconds.
+    fn crossing(frame: Micros) -> Frame {
+        let t = frame * SECOND / FPS;
+        let object = (25 * FPS..28 * FPS)
+            .contains(&frame)
+            .then(|| (frame - 25 * FPS) as u32 * 2);
+        scene(t, 100, object)
+    }
+
+    #[test]
+    fn a_static_noisy_scene_only_records_keepalives() {
+        let mut gate = MotionGate::new(MotionConfig::default());
+        let mut encoded = Vec::new();
+        for frame in 0..60 * FPS {
+            let gated = gate.push(scene(frame * SECOND / FPS, 100, None));
+            assert_eq!(gated.finished, None);
+            encoded.extend(gated.frames.iter().map(|f| f.captured / SECOND));
+        }
+        assert_eq!(encoded, vec![0, 10, 20, 30, 40, 50]);
+        // The rest was dropped, except the last two seconds still waiting as pre-roll.
+        assert_eq!(gate.skipped, 600 - 6 - 21);
+    }
+
+    #[test]
+    fn motion_is_recorded_with_its_pre_roll_and_post_roll() {
+        let mut gate = MotionGate::new(MotionConfig::default());
+        let mut encoded = Vec::new();
+        let mut keyframes = Vec::new();
+        let mut events = Vec::new();
+        for frame in 0..60 * FPS {
+            let gated = gate.push(crossing(frame));
+            if gated.keyframe {
+                keyframes.push(gated.frames[0].captured);
+            }
+            encoded.extend(gated.frames.iter().map(|f| f.captured));
+            events.extend(gated.finished);
+        }
+
+        // Two seconds of pre-roll before the last still frame, handed over with the first frame that moved and
+        // starting on a keyframe.
+        let pre_roll = 25 * SECOND - SECOND / FPS - 2 * SECOND;
+        assert_eq!(keyframes, vec![pre_roll]);
+        let event = events[0];
+        assert_eq!(events.len(), 1);
+        assert_eq!(event.start, 25 * SECOND);
+        assert!((27 * SECOND..29 * SECOND).contains(&event.end), "{event:?}");
+        assert!(event.peak > 0.01);
+
+        // Everything from the pre-roll to the end of the post-roll, once each and in order.
+        let recorded: Vec<_> = encoded
+            .iter()
+            .copied()
+            .filter(|t| (pre_roll..=event.end + 10 * SECOND).contains(t))
+            .collect();
+        let expected: Vec<_> = (pre_roll * FPS / SECOND..)
+            .map(|f| f * SECOND / FPS)
+            .take_while(|t| *t <= event.end + 10 * SECOND)
+            .collect();
+        assert_eq!(recorded, expected);
+        assert!(encoded.windows(2).all(|w| w[0] < w[1]));
+        // And then back to keepalives.
+        let after: Vec<_> = encoded
+            .iter()
+            .filter(|t| **t > event.end + 10 * SECOND)
+            .collect();
+        assert!(after.len() <= 3, "{after:?}");
+    }
+
+    #[test]
+    fn lighting_changes_are_not_motion() {
+        let mut detector = MotionDetector::new(MotionConfig::default());
+        assert_eq!(detector.score(&scene(0, 100, None)), 0.0);
+        assert!(!detector.is_motion(&scene(100_000, 100, None)));
+        // A cloud moving off the sun brightens everything at once.
+        assert_eq!(detector.score(&scene(200_000, 170, None)), 0.0);
+        assert!(!detector.is_motion(&scene(300_000, 170, None)));
+        assert!(detector.is_motion(&scene(400_000, 170, Some(30))));
+    }
+
+    #[test]
+    fn recorded_segments_are_tagged_with_the_motion_in_them() {
+        use crate::encoder::Packet;
+        use crate::node::NodeId;
+        use crate::sensors::ChannelId;
+        use crate::storage::ivf::FourCc;
+        use crate::storage::{VideoFormat, VideoRecorder};
+        use crate::time_sync::NetworkTime;
+
+        let format = VideoFormat {
+            codec: FourCc::AV1,
+            width: WIDTH as u16,
+            height: HEIGHT as u16,
+        };
+        let mut recorder = VideoRecorder::new(NodeId(1), ChannelId::new("camera0"), format);
+        recorder.max_segment = 5 * SECOND;
+        for frame in 0..20 * FPS {
+            let t = frame * SECOND / FPS;
+            let packet = Packet {
+                captured: t,
+                frame: frame as u64,
+                keyframe: frame % (2 * FPS) == 0,
+                data: vec![0; 16],
+            };
+            let time = NetworkTime {
+                local: t,
+                network: t,
+                error: 0,
+            };
+            assert_eq!(recorder.push(&packet, time), None);
+        }
+        let event = MotionEvent {
+            start: 5 * SECOND,
+            end: 9 * SECOND,
+            peak: 0.1,
+        };
+        recorder.mark_motion(event);
+        // Marked again with a later end, as happens when a recorder is told before the post-roll ran out.
+        recorder.mark_motion(MotionEvent {
+            end: 11 * SECOND,
+            ..event
+        });
+        let blob = recorder.seal().unwrap();
+        let tagged: Vec<_> = blob
+            .segments
+            .iter()
+            .filter(|s| !s.motion.is_empty())
+            .map(|s| s.start / SECOND)
+            .collect();
+        assert_eq!(tagged, vec![0, 6]);
+        assert_eq!(
+            blob.motion(),
+            vec![MotionEvent {
+                end: 11 * SECOND,
+                ..event
+            }]
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! Webcams, through nokhwa's platform backends (V4L2, AVFoundation, Media Foundation).
+
+use std::sync::{Arc, Mutex};
+
+use nokhwa::pixel_format::RgbFormat;
+use nokhwa::utils::{self, CameraIndex, RequestedFormat, RequestedFormatType};
+use nokhwa::{Buffer, CallbackCamera, NokhwaError};
+
+use super::{
+    CameraError, CameraFormat, CameraSource, DeviceInfo, FormatRequest, Frame, PixelFormat,
+    Resolution,
+};
+use crate::sensors::Micros;
+
+impl From<NokhwaError> for CameraError {
+    fn from(e: NokhwaError) -> Self {
+        CameraError::Backend(e.to_string())
+    }
+}
+
+pub fn list_devices() -> Result<Vec<DeviceInfo>, CameraError> {
+    let Some(backend) = nokhwa::native_api_backend() else {
+        return Ok(Vec::new());
+    };
+    Ok(nokhwa::query(backend)?
+        .into_iter()
+        .map(|camera| DeviceInfo {
+            id: camera.index().as_string(),
+            name: camera.human_name(),
+            description: camera.description().to_string(),
+        })
+        .collect())
+}
+
+pub struct NativeCamera {
+    info: DeviceInfo,
+    format: CameraFormat,
+    camera: CallbackCamera,
+    latest: Arc<Mutex<Option<Buffer>>>,
+}
+
+impl NativeCamera {
+    pub fn open(id: &str, request: FormatRequest) -> Result<Self, CameraError> {
+        let index = match id.parse() {
+            Ok(i) => CameraIndex::Index(i),
+            Err(_) => CameraIndex::String(id.to_string()),
+        };
+        let requested = RequestedFormat::new::<RgbFormat>(requested_format(request));
+        let latest = Arc::new(Mutex::new(None));
+        let mut camera = CallbackCamera::new(index, requested, {
+            let latest = latest.clone();
+            move |buffer| {
+                if let Ok(mut latest) = latest.lock() {
+                    *latest = Some(buffer);
+                }
+            }
+        })
+        .map_err(|e| match e {
+            NokhwaError::OpenDeviceError(..) => CameraError::NoDevice(id.to_string()),
+            NokhwaError::GetPropertyError { .. } | NokhwaError::SetPropertyError { .. } => {
+                CameraError::UnsupportedFormat {
+                    device: id.to_string(),
+                    request,
+                }
+            }
+            e => e.into(),
+        })?;
+        let format = from_nokhwa(camera.camera_format()?);
+        camera.open_stream()?;
+        let info = camera.info();
+        Ok(NativeCamera {
+            info: DeviceInfo {
+                id: id.to_string(),
+                name: info.human_name(),
+                description: info.description().to_string(),
+            },
+            format,
+            camera,
+            latest,
+        })
+    }
+}
+
+impl CameraSource for NativeCamera {
+    fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    fn format(&self) -> CameraFormat {
+        self.format
+    }
+
+    /// Frames are stamped when they are collected rather than when the backend captured them; nokhwa's capture
+    /// timestamps use a different clock on every platform.
+    fn frame(&mut self, now: Micros) -> Result<Option<Frame>, CameraError> {
+        let Some(buffer) = self.latest.lock().ok().and_then(|mut latest| latest.take()) else {
+            return Ok(None);
+        };
+        let image = buffer.decode_image::<RgbFormat>()?;
+        Ok(Some(Frame {
+            captured: now,
+            width: image.width(),
+            height: image.height(),
+            rgb: image.into_raw(),
+        }))
+    }
+}
+
+impl Drop for NativeCamera {
+    fn drop(&mut self) {
+        if let Err(e) = self.camera.stop_stream() {
+            tracing::warn!("could not stop camera {}: {e}", self.info.id);
+        }
+    }
+}
+
+fn requested_format(request: FormatRequest) -> RequestedFormatType {
+    match request {
+        FormatRequest::AbsoluteHighestResolution => RequestedFormatType::AbsoluteHighestResolution,
+        FormatRequest::AbsoluteHighestFrameRate => RequestedFormatType::AbsoluteHighestFrameRate,
+        FormatRequest::HighestResolution(r) => {
+            RequestedFormatType::HighestResolution(utils::Resolution::new(r.width, r.height))
+        }
+        FormatRequest::HighestFrameRate(fps) => RequestedFormatType::HighestFrameRate(fps),
+        FormatRequest::Exact(format) => RequestedFormatType::Exact(to_nokhwa(format)),
+        FormatRequest::Closest(format) => RequestedFormatType::Closest(to_nokhwa(format)),
+        FormatRequest::Any => RequestedFormatType::None,
+    }
+}
+
+fn to_nokhwa(format: CameraFormat) -> utils::CameraFormat {
+    let pixel_format = match format.pixel_format {
+        PixelFormat::Mjpeg => utils::FrameFormat::MJPEG,
+        PixelFormat::Yuyv => utils::FrameFormat::YUYV,
+        PixelFormat::Nv12 => utils::FrameFormat::NV12,
+        PixelFormat::Gray => utils::FrameFormat::GRAY,
+        PixelFormat::Rgb => utils::FrameFormat::RAWRGB,
+        PixelFormat::Bgr => utils::FrameFormat::RAWBGR,
+    };
+    let resolution = utils::Resolution::new(format.resolution.width, format.resolution.height);
+    utils::CameraFormat::new(resolution, pixel_format, format.fps)
+}
+
+fn from_nokhwa(format: utils::CameraFormat) -> CameraFormat {
+    let pixel_format = match format.format() {
+        utils::FrameFormat::MJPEG => PixelFormat::Mjpeg,
+        utils::FrameFormat::YUYV => PixelFormat::Yuyv,
+        utils::FrameFormat::NV12 => PixelFormat::Nv12,
+        utils::FrameFormat::GRAY => PixelFormat::Gray,
+        utils::FrameFormat::RAWRGB => PixelFormat::Rgb,
+        utils::FrameFormat::RAWBGR => PixelFormat::Bgr,
+    };
+    CameraFormat {
+        resolution: Resolution::new(format.width(), format.height()),
+        pixel_format,
+        fps: format.frame_rate(),
+    }
+}
@@ -0,0 +1,160 @@
+//! A synthetic camera: colour bars with a box sliding across them, so the video path can run without a webcam and
+//! motion detection has something to find.
+
+use super::{
+    CameraError, CameraFormat, CameraSource, DeviceInfo, FormatRequest, Frame, PixelFormat,
+    Resolution,
+};
+use crate::sensors::Micros;
+
+/// Device id of the test pattern.
+pub const TEST_PATTERN: &str = "test-pattern";
+
+const BARS: [[u8; 3]; 7] = [
+    [192, 192, 192],
+    [192, 192, 0],
+    [0, 192, 192],
+    [0, 192, 0],
+    [192, 0, 192],
+    [192, 0, 0],
+    [0, 0, 192],
+];
+
+pub struct TestPattern {
+    info: DeviceInfo,
+    format: CameraFormat,
+    /// Whether the box moves. Turn it off to simulate a static scene.
+    pub motion: bool,
+    /// Amplitude of per pixel sensor noise, which changes from frame to frame even in a static scene.
+    pub noise: u8,
+    start: Option<Micros>,
+    last_frame: Option<u64>,
+}
+
+impl TestPattern {
+    /// The formats the pattern pretends to support.
+    pub const FORMATS: [CameraFormat; 5] = [
+        CameraFormat {
+            resolution: Resolution::new(320, 240),
+            pixel_format: PixelFormat::Rgb,
+            fps: 15,
+        },
+        CameraFormat {
+            resolution: Resolution::new(640, 480),
+            pixel_format: PixelFormat::Rgb,
+            fps: 15,
+        },
+        CameraFormat {
+            resolution: Resolution::new(640, 480),
+            pixel_format: PixelFormat::Rgb,
+            fps: 30,
+        },
+        CameraFormat {
+            resolution: Resolution::new(1280, 720),
+            pixel_format: PixelFormat::Rgb,
+            fps: 15,
+        },
+        CameraFormat {
+            resolution: Resolution::new(1280, 720),
+            pixel_format: PixelFormat::Rgb,
+            fps: 30,
+        },
+    ];
+
+    pub fn info() -> DeviceInfo {
+        DeviceInfo {
+            id: TEST_PATTERN.to_string(),
+            name: "Test pattern".to_string(),
+            description: "Synthetic colour bars".to_string(),
+        }
+    }
+
+    pub fn open(request: FormatRequest) -> Result<Self, CameraError> {
+        let format =
+            request
+                .fulfill(&Self::FORMATS)
+                .ok_or_else(|| CameraError::UnsupportedFormat {
+                    device: TEST_PATTERN.to_string(),
+                    request,
+                })?;
+        Ok(TestPattern {
+            info: Self::info(),
+            format,
+            motion: true,
+            noise: 0,
+            start: None,
+            last_frame: None,
+        })
+    }
+
+    /// Renders frame number `n`.
+    pub fn render(&self, n: u64) -> Vec<u8> {
+        let Resolution { width, height } = self.format.resolution;
+        let (w, h) = (width as usize, height as usize);
+        let mut rgb = Vec::with_capacity(w * h * 3);
+        let bars_end = h * 2 / 3;
+        for y in 0..h {
+            for x in 0..w {
+                let pixel = if y < bars_end {
+                    BARS[x * BARS.len() / w]
+                } else {
+                    let v = (x * 255 / w.max(2).saturating_sub(1)) as u8;
+                    [v, v, v]
+                };
+                rgb.extend_from_slice(&pixel);
+            }
+        }
+        // A white box crossing the frame every four seconds.
+        let size = (h / 6).max(1);
+        let span = w.saturating_sub(size).max(1) as u64;
+        let frames_per_pass = 4 * self.format.fps.max(1) as u64;
+        let offset = if self.motion {
+            n % frames_per_pass * span / frames_per_pass
+        } else {
+            0
+        } as usize;
+        let top = (h - size) / 2;
+        for y in top..top + size {
+            let row = (y * w + offset) * 3;
+            rgb[row..row + size * 3].fill(255);
+        }
+        if self.noise > 0 {
+            let span = 2 * self.noise as u64 + 1;
+            let mut state = n.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+            for v in &mut rgb {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                let delta = (state % span) as i16 - self.noise as i16;
+                *v = (*v as i16 + delta).clamp(0, 255) as u8;
+            }
+        }
+        rgb
+    }
+}
+
+impl CameraSource for TestPattern {
+    fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    fn format(&self) -> CameraFormat {
+        self.format
+    }
+
+    fn frame(&mut self, now: Micros) -> Result<Option<Frame>, CameraError> {
+        let start = *self.start.get_or_insert(now);
+        let period = 1_000_000 / self.format.fps.max(1) as i64;
+        let n = ((now - start) / period) as u64;
+        if self.last_frame.is_some_and(|last| last >= n) {
+            return Ok(None);
+        }
+        self.last_frame = Some(n);
+        Ok(Some(Frame {
+            captured: start + n as i64 * period,
+            width: self.format.resolution.width,
+            height: self.format.resolution.height,
+            rgb: self.render(n),
+        }))
+    }
+}
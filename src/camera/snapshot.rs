@@ -0,0 +1,166 @@
+//! Snapshot mode, for links too slow for video.
+//!
+//! Instead of streaming, [`SnapshotCamera`] keeps one frame every few minutes, and [`snapshot`] turns it into a
+//! [`Still`] with a thumbnail for the hour blob plus an [`ImageBlob`] holding the full image. The thumbnail goes
+//! out at [`Priority::High`] and the full image at [`Priority::Low`], so a satellite link always shows the latest
+//! sky even when it is days behind on full images.
+//!
+//! [`Priority::High`]: crate::storage::Priority::High
+//! [`Priority::Low`]: crate::storage::Priority::Low
+
+use serde::{Deserialize, Serialize};
+
+use super::{CameraSource, Frame};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::encoder::{encode_image, EncoderError};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::node::NodeId;
+use crate::sensors::{ChannelId, ChannelInfo, Micros, Sample, SensorError, SensorSource};
+use crate::storage::ImageFormat;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::storage::{Image, ImageBlob, Still};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::time_sync::NetworkTime;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotConfig {
+    /// Time between stills.
+    pub interval: Micros,
+    pub format: ImageFormat,
+    /// 1 (smallest) to 100 (best).
+    pub quality: u8,
+    /// Thumbnails are scaled to this width, keeping the aspect ratio.
+    pub thumbnail_width: u32,
+    pub thumbnail_quality: u8,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        SnapshotConfig {
+            interval: 10 * 60 * 1_000_000,
+            format: ImageFormat::Avif,
+            quality: 70,
+            thumbnail_width: 160,
+            thumbnail_quality: 40,
+        }
+    }
+}
+
+/// A camera that keeps one frame per [`SnapshotConfig::interval`].
+///
+/// Like [`super::CameraSensor`] it reports `[width, height, mean luma]` on `camera/<device>`, but only for the frames
+/// it keeps, which wait in [`SnapshotCamera::take_frames`].
+pub struct SnapshotCamera<C> {
+    pub camera: C,
+    pub config: SnapshotConfig,
+    /// Local time the next still is due; the first poll takes one straight away.
+    next: Option<Micros>,
+    frames: Vec<Frame>,
+}
+
+impl<C: CameraSource> SnapshotCamera<C> {
+    pub fn new(camera: C, config: SnapshotConfig) -> Self {
+        SnapshotCamera {
+            camera,
+            config,
+            next: None,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn channel(&self) -> ChannelId {
+        ChannelId::new(format!("camera/{}", self.camera.info().id))
+    }
+
+    /// Whether a still is due at `now`.
+    pub fn due(&self, now: Micros) -> bool {
+        self.next.is_none_or(|next| now >= next)
+    }
+
+    /// Local time the next still is due, or `None` before the first.
+    pub fn next_due(&self) -> Option<Micros> {
+        self.next
+    }
+
+    /// Frames kept since the last call, oldest first.
+    pub fn take_frames(&mut self) -> Vec<Frame> {
+        std::mem::take(&mut self.frames)
+    }
+}
+
+impl<C: CameraSource> SensorSource for SnapshotCamera<C> {
+    fn channels(&self) -> Vec<ChannelInfo> {
+        vec![ChannelInfo {
+            id: self.channel(),
+            kind: None,
+            unit: "px, px, luma".to_string(),
+            dims: 3,
+        }]
+    }
+
+    fn poll(&mut self, now: Micros) -> Result<Vec<Sample>, SensorError> {
+        // The camera is still read between stills so that the frame kept is a fresh one rather than one left over
+        // from minutes ago.
+        let frame = self.camera.frame(now)?;
+        if !self.due(now) {
+            return Ok(Vec::new());
+        }
+        // Try again on the next poll if the camera has nothing yet.
+        let Some(frame) = frame else {
+            return Ok(Vec::new());
+        };
+        // Stay on the original schedule, skipping any stills missed while the node was asleep.
+        let interval = self.config.interval.max(1);
+        let mut next = self.next.unwrap_or(now);
+        while next <= now {
+            next += interval;
+        }
+        self.next = Some(next);
+
+        let sample = Sample {
+            channel: self.channel(),
+            local_time: frame.captured,
+            values: vec![frame.width as f64, frame.height as f64, frame.mean_luma()],
+        };
+        self.frames.push(frame);
+        Ok(vec![sample])
+    }
+}
+
+/// Encodes a kept frame into the [`Still`] for the hour blob and the [`ImageBlob`] with the full image. `time` is
+/// the frame's capture time on the network clock.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn snapshot(
+    node: NodeId,
+    camera: ChannelId,
+    frame: &Frame,
+    time: NetworkTime,
+    config: &SnapshotConfig,
+) -> Result<(Still, ImageBlob), EncoderError> {
+    let full = ImageBlob {
+        node,
+        camera: camera.clone(),
+        time,
+        image: Image {
+            format: config.format,
+            width: frame.width,
+            height: frame.height,
+            data: encode_image(frame, config.format, config.quality)?,
+        },
+    };
+    let width = config.thumbnail_width.clamp(1, frame.width.max(1));
+    let height = (width * frame.height / frame.width.max(1)).max(1);
+    let small = frame.resized(width, height);
+    let still = Still {
+        camera,
+        time,
+        thumbnail: Image {
+            format: config.format,
+            width,
+            height,
+            data: encode_image(&small, config.format, config.thumbnail_quality)?,
+        },
+        full: full.reference(),
+    };
+    Ok((still, full))
+}
@@ -0,0 +1,54 @@
+use dioxus::prelude::*;
+use flumph::node::NodeId;
+use flumph::storage::{BlobInfo, HOUR};
+
+use super::format;
+
+/// Stored blobs, one row each with their hour, size, how well they compress, how many other nodes hold them and
+/// whether they are encrypted. Rows can be picked.
+#[component]
+pub fn BlobTable(blobs: Vec<BlobInfo>, on_select: EventHandler<BlobInfo>) -> Element {
+    rsx! {
+        table { class: "overview-table blob-table",
+            tr { class: "muted",
+                td { "Hour" }
+                td { "Kind" }
+                td { "Size" }
+                td { "Ratio" }
+                td { "Copies" }
+                td { "Encrypted" }
+            }
+            for blob in blobs.iter() {
+                tr {
+                    key: "{blob.hash}",
+                    class: "selectable",
+                    onclick: {
+                        let blob = blob.clone();
+                        move |_| on_select.call(blob.clone())
+                    },
+                    td { class: "overview-label", "{format::date_time(blob.hour * HOUR)}" }
+                    td { "{blob.kind}" }
+                    td { "{format::bytes(blob.size)}" }
+                    td { "{blob.compression_ratio():.1}×" }
+                    td { class: if blob.replicas.is_empty() { "error" }, "{blob.replicas.len()}" }
+                    td { if blob.encrypted { "yes" } else { "no" } }
+                }
+            }
+        }
+        if blobs.is_empty() {
+            p { class: "muted", "no blobs" }
+        }
+    }
+}
+
+/// Where copies of a blob are, beyond this node.
+#[component]
+pub fn ReplicaList(replicas: Vec<NodeId>) -> Element {
+    rsx! {
+        if replicas.is_empty() {
+            span { class: "error", "only here" }
+        } else {
+            span { "{replicas.iter().map(|id| format::short_id(*id)).collect::<Vec<_>>().join(\", \")}" }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use dioxus::prelude::*;
+use flumph::calibration::{CalibrationProfile, CalibrationStore, Procedure};
+use flumph::node::NodeId;
+use flumph::sensors::{Sample, SensorKind};
+use flumph::time_sync::local_now;
+
+/// Sensors that have an interactive calibration procedure.
+const CALIBRATABLE: [SensorKind; 2] = [SensorKind::Magnetometer, SensorKind::Accelerometer];
+
+/// Lists the calibration of each sensor and walks the user through recalibrating one.
+#[component]
+pub fn CalibrationPanel(
+    node: NodeId,
+    samples: ReadOnlySignal<Vec<Sample>>,
+    store: Signal<CalibrationStore>,
+) -> Element {
+    let mut procedure = use_signal(|| None::<Procedure>);
+    let mut error = use_signal(|| None::<String>);
+
+    // Feed every new batch of samples to the running procedure, if there is one.
+    use_effect(move || {
+        let samples = samples.read();
+        if procedure.peek().is_none() {
+            return;
+        }
+        if let Some(procedure) = procedure.write().as_mut() {
+            for sample in samples.iter() {
+                procedure.push(sample);
+            }
+        }
+    });
+
+    let save = move |_| {
+        let Some(running) = procedure.take() else {
+            return;
+        };
+        match running.finish() {
+            Ok(result) => {
+                // The app does not run clock sync yet, so the local clock stands in for network time.
+                let profile =
+                    CalibrationProfile::from_procedure(node, running.kind(), local_now(), result);
+                store.write().insert(profile);
+                error.set(None);
+            }
+            Err(e) => {
+                error.set(Some(e.to_string()));
+                procedure.set(Some(running));
+            }
+        }
+    };
+
+    rsx! {
+        div { id: "calibration",
+            h2 { "Calibration" }
+            for kind in CALIBRATABLE {
+                div { class: "calibration-row",
+                    span { class: "calibration-sensor", "{kind.name()}" }
+                    match store.read().current(node, &kind.channel()) {
+                        Some(profile) => rsx! {
+                            span { "{profile.method:?}, residual {profile.residual * 100.0:.2}%" }
+                        },
+                        None => rsx! {
+                            span { class: "muted", "uncalibrated" }
+                        },
+                    }
+                    button {
+                        disabled: procedure.read().is_some(),
+                        onclick: move |_| {
+                            error.set(None);
+                            procedure.set(Procedure::start(kind));
+                        },
+                        "Calibrate"
+                    }
+                }
+            }
+            if let Some(running) = procedure.read().as_ref() {
+                ProcedureView {
+                    kind: running.kind(),
+                    instruction: running.status().instruction,
+                    progress: running.status().progress,
+                    ready: running.status().ready,
+                    on_save: save,
+                    on_cancel: move |_| procedure.set(None),
+                }
+            }
+            if let Some(error) = error() {
+                p { class: "error", "{error}" }
+            }
+        }
+    }
+}
+
+#[component]
+fn ProcedureView(
+    kind: SensorKind,
+    instruction: String,
+    progress: f64,
+    ready: bool,
+    on_save: EventHandler<()>,
+    on_cancel: EventHandler<()>,
+) -> Element {
+    let percent = (progress * 100.0).round();
+    rsx! {
+        div { class: "procedure",
+            h3 { "Calibrating {kind.name()}" }
+            p { class: "instruction", "{instruction}" }
+            div { class: "progress",
+                div { class: "progress-bar", style: "width: {percent}%" }
+            }
+            div { class: "procedure-actions",
+                button { disabled: !ready, onclick: move |_| on_save.call(()), "Save" }
+                button { onclick: move |_| on_cancel.call(()), "Cancel" }
+            }
+        }
+    }
+}
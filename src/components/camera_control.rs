@@ -0,0 +1,135 @@
+use dioxus::prelude::*;
+use flumph::camera::Resolution;
+use flumph::control::{CameraCommand, CommandStatus};
+use flumph::sensors::ChannelId;
+
+use crate::hooks::CameraLink;
+
+/// Resolutions offered in the picker. Cameras scale to whatever they are asked for, these are just common ones.
+const RESOLUTIONS: [Resolution; 4] = [
+    Resolution::new(320, 240),
+    Resolution::new(640, 480),
+    Resolution::new(1280, 720),
+    Resolution::new(1920, 1080),
+];
+
+/// Sends commands to a remote camera and shows how they went.
+#[component]
+pub fn CameraControlPanel(link: CameraLink, camera: ChannelId) -> Element {
+    let mut resolution = use_signal(|| 1usize);
+    let mut fps = use_signal(|| 15u32);
+    let mut kbps = use_signal(|| 500u32);
+    let mut threshold = use_signal(|| 24u8);
+    let mut min_area = use_signal(|| 1.0f64);
+
+    let target = link
+        .target(&camera)
+        .map_or_else(|| "an unknown node".to_string(), |node| node.to_string());
+    let send = {
+        let camera = camera.clone();
+        move |command: CameraCommand| link.send(camera.clone(), command)
+    };
+
+    rsx! {
+        div { id: "camera-control",
+            h2 { "Camera {camera} on {target}" }
+            div { class: "control-row",
+                button { onclick: { let send = send.clone(); move |_| send(CameraCommand::StartRecording) }, "Start recording" }
+                button { onclick: { let send = send.clone(); move |_| send(CameraCommand::StopRecording) }, "Stop recording" }
+                button { onclick: { let send = send.clone(); move |_| send(CameraCommand::Snapshot) }, "Snapshot" }
+            }
+            div { class: "control-row",
+                label { "Resolution" }
+                select {
+                    onchange: move |e| resolution.set(e.value().parse().unwrap_or(1)),
+                    for (i, r) in RESOLUTIONS.iter().enumerate() {
+                        option { value: "{i}", selected: i == resolution(), "{r}" }
+                    }
+                }
+                button {
+                    onclick: { let send = send.clone(); move |_| send(CameraCommand::SetResolution(RESOLUTIONS[resolution()])) },
+                    "Set"
+                }
+            }
+            div { class: "control-row",
+                label { "Frame rate" }
+                input {
+                    r#type: "number",
+                    min: 1,
+                    max: 60,
+                    value: "{fps}",
+                    oninput: move |e| fps.set(e.value().parse().unwrap_or(fps())),
+                }
+                button { onclick: { let send = send.clone(); move |_| send(CameraCommand::SetFps(fps())) }, "Set" }
+            }
+            div { class: "control-row",
+                label { "Bitrate (kbps)" }
+                input {
+                    r#type: "number",
+                    min: 16,
+                    value: "{kbps}",
+                    oninput: move |e| kbps.set(e.value().parse().unwrap_or(kbps())),
+                }
+                button { onclick: { let send = send.clone(); move |_| send(CameraCommand::SetBitrate { kbps: kbps() }) }, "Set" }
+            }
+            div { class: "control-row",
+                label { "Motion" }
+                input {
+                    r#type: "range",
+                    min: 1,
+                    max: 128,
+                    value: "{threshold}",
+                    oninput: move |e| threshold.set(e.value().parse().unwrap_or(threshold())),
+                }
+                span { class: "muted", "difference {threshold}" }
+                input {
+                    r#type: "number",
+                    min: 0.1,
+                    max: 50,
+                    step: 0.1,
+                    value: "{min_area}",
+                    oninput: move |e| min_area.set(e.value().parse().unwrap_or(min_area())),
+                }
+                span { class: "muted", "% of frame" }
+                button {
+                    onclick: {
+                        let send = send.clone();
+                        move |_| send(CameraCommand::SetMotionSensitivity {
+                            threshold: threshold(),
+                            min_area: min_area() / 100.0,
+                        })
+                    },
+                    "Set"
+                }
+            }
+            if let Some(snapshot) = link.snapshot.read().as_ref() {
+                p { class: "muted", "Last snapshot: {snapshot.width}x{snapshot.height}, taken at {snapshot.captured}" }
+            }
+            h3 { "Commands" }
+            table { class: "command-log",
+                for entry in link.client.read().log().entries().rev().take(20) {
+                    tr { key: "{entry.id}",
+                        td { "{entry.command}" }
+                        match &entry.status {
+                            CommandStatus::Queued => rsx! {
+                                td { class: "warning", "queued until {entry.peer} is back" }
+                            },
+                            CommandStatus::Pending { attempts } => rsx! {
+                                td { class: "muted", "waiting (sent {attempts}x)" }
+                            },
+                            CommandStatus::Done => rsx! {
+                                td { "done" }
+                            },
+                            CommandStatus::Failed(e) => rsx! {
+                                td { class: "error", "{e}" }
+                            },
+                            CommandStatus::TimedOut => rsx! {
+                                td { class: "error", "no answer" }
+                            },
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use dioxus::prelude::*;
+use flumph::sensors::history::{History, Series, Window};
+use flumph::sensors::{ChannelId, Micros};
+use flumph::time_sync::local_now;
+
+use super::format;
+
+/// Size of a chart in pixels. Charts are drawn at a fixed size so that pointer positions map straight to times.
+const WIDTH: f64 = 560.0;
+const HEIGHT: f64 = 160.0;
+/// Line colours, one per value in a sample.
+const COLORS: [&str; 3] = ["#4caf50", "#2196f3", "#ff9800"];
+const AXES: [&str; 3] = ["x", "y", "z"];
+
+/// A scrolling chart for every channel in `history`, or just `only`, over a selectable window, that can be paused to
+/// look closer.
+#[component]
+pub fn SensorCharts(history: ReadOnlySignal<History>, only: Option<ChannelId>) -> Element {
+    let mut window = use_signal(|| Window::Minute);
+    // What was on screen when pausing, and the time it was paused at.
+    let mut paused = use_signal(|| None::<(History, Micros)>);
+
+    let (charts, end) = {
+        let live = history.read();
+        let frozen = paused.read();
+        let (source, end) = match frozen.as_ref() {
+            Some((history, end)) => (history, *end),
+            None => (&*live, local_now()),
+        };
+        let charts: Vec<(ChannelId, Series)> = source
+            .channels()
+            .filter(|channel| only.as_ref().is_none_or(|only| only == *channel))
+            .filter_map(|channel| Some((channel.clone(), source.series(channel, window())?)))
+            .collect();
+        (charts, end)
+    };
+    let is_paused = paused.read().is_some();
+
+    rsx! {
+        div { id: "charts",
+            div { class: "control-row",
+                for w in Window::ALL {
+                    button {
+                        class: if w == window() { "selected" },
+                        onclick: move |_| window.set(w),
+                        "{w.label()}"
+                    }
+                }
+                button {
+                    class: "chart-pause",
+                    onclick: move |_| {
+                        if paused.peek().is_some() {
+                            paused.set(None);
+                        } else {
+                            paused.set(Some((history.peek().clone(), local_now())));
+                        }
+                    },
+                    if is_paused { "Resume" } else { "Pause" }
+                }
+            }
+            for (channel, series) in charts {
+                SeriesChart {
+                    key: "{channel}",
+                    channel,
+                    series,
+                    end,
+                    live: true,
+                    paused: is_paused,
+                }
+            }
+        }
+    }
+}
+
+/// One channel's series as a line per value, with the spread of the selected value as a band and its minimum,
+/// maximum and mean over the window as dashed lines. Pointing at the chart shows the readings at that time.
+///
+/// A `live` chart ends now, or when it was `paused`, and labels times by how long ago they were. Otherwise it shows
+/// a stretch of the past ending at `end` and labels times by the clock.
+#[component]
+pub fn SeriesChart(
+    channel: ChannelId,
+    series: Series,
+    end: Micros,
+    live: bool,
+    #[props(default)] paused: bool,
+) -> Element {
+    let mut selected = use_signal(|| 0usize);
+    let mut inspect = use_signal(|| None::<Micros>);
+    let dim = selected().min(series.dims.saturating_sub(1));
+
+    let span = series.window.span();
+    let start = end - span;
+    let half = series.window.resolution() / 2;
+    let (lo, hi) = match series.range() {
+        Some((lo, hi)) if hi - lo > 1e-9 => (lo - (hi - lo) * 0.05, hi + (hi - lo) * 0.05),
+        Some((lo, _)) => (lo - 1.0, lo + 1.0),
+        None => (0.0, 1.0),
+    };
+    let x = move |t: Micros| (t - start) as f64 / span as f64 * WIDTH;
+    let y = move |v: f64| HEIGHT - (v - lo) / (hi - lo) * HEIGHT;
+
+    let lines: Vec<String> = (0..series.dims)
+        .map(|d| {
+            series
+                .buckets
+                .iter()
+                .map(|b| format!("{:.1},{:.1}", x(b.start + half), y(b.mean(d))))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect();
+    let band = series
+        .buckets
+        .iter()
+        .map(|b| format!("{:.1},{:.1}", x(b.start + half), y(b.max[dim])))
+        .chain(
+            series
+                .buckets
+                .iter()
+                .rev()
+                .map(|b| format!("{:.1},{:.1}", x(b.start + half), y(b.min[dim]))),
+        )
+        .collect::<Vec<_>>()
+        .join(" ");
+    let summary = series.summary(dim);
+    let unit = format::unit(&channel);
+    let color = COLORS[dim % COLORS.len()];
+    let pointed = inspect().and_then(|t| series.at(t));
+
+    let point = move |e: PointerEvent| {
+        let px = e.element_coordinates().x.clamp(0.0, WIDTH);
+        inspect.set(Some(start + (px / WIDTH * span as f64) as Micros));
+    };
+
+    rsx! {
+        div { class: "chart",
+            div { class: "chart-header",
+                span { class: "chart-title", "{channel}" }
+                if series.dims > 1 {
+                    for d in 0..series.dims {
+                        button {
+                            class: if d == dim { "selected" },
+                            style: "color: {COLORS[d % COLORS.len()]}",
+                            onclick: move |_| selected.set(d),
+                            "{AXES.get(d).copied().unwrap_or(\"?\")}"
+                        }
+                    }
+                }
+            }
+            svg {
+                width: "{WIDTH}",
+                height: "{HEIGHT}",
+                view_box: "0 0 {WIDTH} {HEIGHT}",
+                onpointerdown: point,
+                onpointermove: point,
+                onpointerleave: move |_| inspect.set(None),
+                rect { class: "plot-background", width: "{WIDTH}", height: "{HEIGHT}" }
+                polygon { points: "{band}", fill: color, fill_opacity: "0.15" }
+                if let Some(s) = summary {
+                    for v in [s.min, s.max, s.mean] {
+                        line {
+                            x1: "0",
+                            x2: "{WIDTH}",
+                            y1: "{y(v):.1}",
+                            y2: "{y(v):.1}",
+                            stroke: color,
+                            stroke_opacity: "0.6",
+                            stroke_dasharray: "4 4",
+                        }
+                    }
+                }
+                for (d, points) in lines.iter().enumerate() {
+                    polyline {
+                        points: "{points}",
+                        fill: "none",
+                        stroke: COLORS[d % COLORS.len()],
+                        stroke_width: "1.5",
+                    }
+                }
+                if let Some(bucket) = pointed {
+                    line {
+                        x1: "{x(bucket.start + half):.1}",
+                        x2: "{x(bucket.start + half):.1}",
+                        y1: "0",
+                        y2: "{HEIGHT}",
+                        class: "plot-cursor",
+                    }
+                }
+            }
+            div { class: "chart-footer muted",
+                if live {
+                    span { "-{series.window.label()}" }
+                } else {
+                    span { "{format::clock(start)}" }
+                }
+                if let Some(s) = summary {
+                    span { "min {s.min:.2} · avg {s.mean:.2} · max {s.max:.2} {unit}" }
+                }
+                span {
+                    if !live {
+                        "{format::clock(end)}"
+                    } else if paused {
+                        "paused"
+                    } else {
+                        "now"
+                    }
+                }
+            }
+            if let Some(bucket) = pointed {
+                p { class: "chart-inspect",
+                    if live {
+                        "{format::age(end - bucket.start - half)} ago: "
+                    } else {
+                        "{format::clock(bucket.start + half)}: "
+                    }
+                    "{format::values(&(0..series.dims).map(|d| bucket.mean(d)).collect::<Vec<_>>())} {unit}"
+                    span { class: "muted", " ({bucket.min[dim]:.2} to {bucket.max[dim]:.2}, {bucket.count} samples)" }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use std::time::Duration;
+
+use dioxus::prelude::*;
+use flumph::sensors::Micros;
+use flumph::server::PreviewFrame;
+use flumph::storage::{CameraTimeline, MarkerKind, HOUR};
+
+use super::format;
+use crate::hooks::NodeFeed;
+
+const WIDTH: f64 = 560.0;
+const HEIGHT: f64 = 48.0;
+/// Frames are tiny, so the player scales them up.
+const PLAYER_WIDTH: u32 = 384;
+/// Longest wait between frames, so a gap in the footage does not stall playback.
+const MAX_FRAME_GAP: Micros = 500_000;
+
+/// One camera's footage over `start..end` as a bar: recordings in green, motion in orange and detections in red,
+/// with a list of the markers below. Clicking the bar or a marker reports the time to play from.
+#[component]
+pub fn FootageTimeline(
+    timeline: CameraTimeline,
+    start: Micros,
+    end: Micros,
+    on_seek: EventHandler<Micros>,
+) -> Element {
+    let span = (end - start).max(1);
+    let x = move |t: Micros| ((t - start) as f64 / span as f64 * WIDTH).clamp(0.0, WIDTH);
+    // Clips are seconds long on a bar a day wide, so everything gets at least a couple of pixels.
+    let width = move |from: Micros, to: Micros| (x(to) - x(from)).max(2.0);
+
+    rsx! {
+        svg {
+            class: "footage-timeline",
+            width: "{WIDTH}",
+            height: "{HEIGHT}",
+            view_box: "0 0 {WIDTH} {HEIGHT}",
+            onclick: move |e| {
+                let px = e.element_coordinates().x.clamp(0.0, WIDTH);
+                on_seek.call(start + (px / WIDTH * span as f64) as Micros);
+            },
+            rect { class: "plot-background", width: "{WIDTH}", height: "{HEIGHT}" }
+            for hour in (0..span / HOUR).step_by(3) {
+                line {
+                    key: "{hour}",
+                    x1: "{x(start + hour * HOUR):.1}",
+                    x2: "{x(start + hour * HOUR):.1}",
+                    y1: "0",
+                    y2: "{HEIGHT}",
+                    class: "plot-grid",
+                }
+                text {
+                    key: "{hour}-label",
+                    x: "{x(start + hour * HOUR) + 2.0:.1}",
+                    y: "{HEIGHT - 2.0}",
+                    class: "topology-label muted",
+                    "{hour:02}:00"
+                }
+            }
+            for recording in timeline.recordings.iter() {
+                rect {
+                    key: "{recording.video}-{recording.segment}",
+                    x: "{x(recording.start):.1}",
+                    y: "10",
+                    width: "{width(recording.start, recording.end):.1}",
+                    height: "20",
+                    fill: "#4caf50",
+                }
+            }
+            for (i, marker) in timeline.markers.iter().enumerate() {
+                rect {
+                    key: "{i}",
+                    x: "{x(marker.start):.1}",
+                    y: if matches!(marker.kind, MarkerKind::Motion { .. }) { "30" } else { "2" },
+                    width: "{width(marker.start, marker.end):.1}",
+                    height: "8",
+                    fill: if matches!(marker.kind, MarkerKind::Motion { .. }) { "#ff9800" } else { "#ff6b6b" },
+                }
+            }
+        }
+        table { class: "overview-table",
+            for (i, marker) in timeline.markers.iter().enumerate() {
+                tr {
+                    key: "{i}",
+                    class: "selectable",
+                    onclick: {
+                        let time = marker.start;
+                        move |_| on_seek.call(time)
+                    },
+                    td { class: "overview-label", "{format::clock(marker.start)}" }
+                    match &marker.kind {
+                        MarkerKind::Motion { peak } => rsx! {
+                            td { "motion" }
+                            td { class: "muted", "{peak * 100.0:.0}% of the picture" }
+                        },
+                        MarkerKind::Detection { class, confidence } => rsx! {
+                            td { "{class}" }
+                            td { class: "muted", "{confidence * 100.0:.0}% sure" }
+                        },
+                    }
+                    td { class: "muted", "{format::age(marker.end - marker.start)}" }
+                }
+            }
+        }
+        if timeline.markers.is_empty() {
+            p { class: "muted", "nothing detected" }
+        }
+    }
+}
+
+/// Frames decoded from one keyframe to the end of its segment, and the keyframe playback carries on from after them.
+#[derive(Debug, Clone, PartialEq)]
+struct Clip {
+    frames: Vec<PreviewFrame>,
+    next: Option<usize>,
+}
+
+/// Plays one camera's footage from the keyframe at index `position` of its timeline, carrying on into the following
+/// segments. The slider scrubs from keyframe to keyframe, since those are the only places decoding can start.
+#[component]
+pub fn FootagePlayer(timeline: CameraTimeline, position: Signal<usize>) -> Element {
+    let node = use_context::<NodeFeed>();
+    let mut playing = use_signal(|| true);
+    let mut shown = use_signal(|| 0usize);
+    let clip = use_resource(use_reactive!(|timeline| {
+        let index = position();
+        let point = timeline.keyframes.get(index).copied();
+        async move {
+            let point = point?;
+            Some(node.preview(point).await.map(|frames| {
+                let last = frames
+                    .last()
+                    .map_or(point.keyframe.time, |frame| frame.time);
+                let next = timeline
+                    .keyframes
+                    .iter()
+                    .position(|k| k.keyframe.time > last)
+                    .filter(|&next| next > index);
+                Clip { frames, next }
+            }))
+        }
+    }));
+    use_effect(move || {
+        position();
+        shown.set(0);
+    });
+    use_future(move || async move {
+        loop {
+            let wait = {
+                let clip = clip.peek();
+                let frames = match clip.as_ref() {
+                    Some(Some(Ok(clip))) => &clip.frames,
+                    _ => &Vec::new(),
+                };
+                let i = *shown.peek();
+                match (frames.get(i), frames.get(i + 1)) {
+                    (Some(a), Some(b)) => (b.time - a.time).clamp(10_000, MAX_FRAME_GAP),
+                    _ => MAX_FRAME_GAP,
+                }
+            };
+            futures_timer::Delay::new(Duration::from_micros(wait as u64)).await;
+            if !*playing.peek() {
+                continue;
+            }
+            let (len, next) = match clip.peek().as_ref() {
+                Some(Some(Ok(clip))) => (clip.frames.len(), clip.next),
+                _ => (0, None),
+            };
+            let i = *shown.peek();
+            if i + 1 < len {
+                shown.set(i + 1);
+            } else if let Some(next) = next {
+                position.set(next);
+            } else {
+                playing.set(false);
+            }
+        }
+    });
+
+    let keyframes = timeline.keyframes.len();
+    let clip = clip.read();
+    rsx! {
+        div { class: "footage-player",
+            match clip.as_ref() {
+                Some(Some(Ok(clip))) => match clip.frames.get(shown().min(clip.frames.len().saturating_sub(1))) {
+                    Some(frame) => rsx! {
+                        img { src: "{frame.image}", width: "{PLAYER_WIDTH}" }
+                        p { class: "muted", "{format::date_time(frame.time)} · {format::clock(frame.time)}" }
+                    },
+                    None => rsx! {
+                        p { class: "muted", "no frames from this keyframe" }
+                    },
+                },
+                Some(Some(Err(e))) => rsx! {
+                    p { class: "error", "{e}" }
+                },
+                Some(None) => rsx! {
+                    p { class: "muted", "nothing to play" }
+                },
+                None => rsx! {
+                    p { class: "muted", "decoding…" }
+                },
+            }
+            div { class: "control-row",
+                button {
+                    onclick: move |_| playing.toggle(),
+                    if playing() { "Pause" } else { "Play" }
+                }
+                input {
+                    r#type: "range",
+                    min: 0,
+                    max: "{keyframes.saturating_sub(1)}",
+                    value: "{position}",
+                    oninput: move |e| {
+                        if let Ok(index) = e.value().parse() {
+                            position.set(index);
+                        }
+                    },
+                }
+                span { class: "muted", "keyframe {position() + 1} of {keyframes}" }
+            }
+        }
+    }
+}
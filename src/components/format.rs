@@ -0,0 +1,98 @@
+//! Formatting shared by the components.
+
+use flumph::node::NodeId;
+use flumph::sensors::{ChannelId, SensorKind};
+use flumph::time_sync::civil_date;
+
+/// Unit of a channel's values, or nothing for channels that are not built in sensors.
+pub fn unit(channel: &ChannelId) -> &'static str {
+    SensorKind::of_channel(channel).map_or("", |kind| kind.unit())
+}
+
+pub fn values(values: &[f64]) -> String {
+    values
+        .iter()
+        .map(|v| format!("{v:.2}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub fn bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "kB", "MB", "GB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// A duration in µs, rounded down to its largest unit.
+pub fn age(micros: i64) -> String {
+    let seconds = micros.max(0) / 1_000_000;
+    match seconds {
+        0..60 => format!("{seconds} s"),
+        60..3600 => format!("{} min", seconds / 60),
+        _ => format!("{} h", seconds / 3600),
+    }
+}
+
+/// A round trip time in µs, in milliseconds while that is readable.
+pub fn rtt(micros: i64) -> String {
+    if micros < 10_000_000 {
+        format!("{} ms", micros / 1_000)
+    } else {
+        format!("{} s", micros / 1_000_000)
+    }
+}
+
+/// The last four hex digits of a node id, which tell apart the handful of nodes on one screen.
+pub fn short_id(id: NodeId) -> String {
+    format!("…{:04x}", id.0 & 0xffff)
+}
+
+/// A network time in µs as a UTC date and time to the minute.
+pub fn date_time(micros: i64) -> String {
+    let minutes = micros.div_euclid(60_000_000).rem_euclid(24 * 60);
+    format!(
+        "{} {:02}:{:02} UTC",
+        date(micros),
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+/// The UTC date of a network time in µs.
+pub fn date(micros: i64) -> String {
+    let (year, month, day) = civil_date(micros.div_euclid(1_000_000).div_euclid(86_400));
+    format!("{year}-{month:02}-{day:02}")
+}
+
+/// The UTC time of day of a network time in µs, to the second.
+pub fn clock(micros: i64) -> String {
+    let seconds = micros.div_euclid(1_000_000).rem_euclid(86_400);
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// A `data:` URL holding `bytes`, with everything but unreserved characters escaped.
+pub fn data_url(mime: &str, bytes: &[u8]) -> String {
+    let mut url = format!("data:{mime},");
+    for &byte in bytes {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            url.push(byte as char);
+        } else {
+            url.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    url
+}
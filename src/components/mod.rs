@@ -1,6 +1,31 @@
 //! The components module contains all shared components for our app. Components are the building blocks of dioxus apps.
-//! They can be used to defined common UI elements like buttons, forms, and modals. In this template, we define a Hero
-//! component  to be used in our app.
+//! They can be used to defined common UI elements like buttons, forms, and modals.
 
-mod hero;
-pub use hero::Hero;
+mod blobs;
+pub use blobs::{BlobTable, ReplicaList};
+
+mod calibration;
+pub use calibration::CalibrationPanel;
+
+mod charts;
+pub use charts::{SensorCharts, SeriesChart};
+
+mod camera_control;
+pub use camera_control::CameraControlPanel;
+
+mod footage;
+pub use footage::{FootagePlayer, FootageTimeline};
+
+pub mod format;
+
+mod overview;
+pub use overview::{NodeOverview, PeerList, StorageSummary};
+
+mod status_bar;
+pub use status_bar::StatusBar;
+
+mod topology;
+pub use topology::{LinkTable, PeerDetail, TopologyMap};
+
+#[cfg(test)]
+pub mod testing;
@@ -0,0 +1,116 @@
+use dioxus::prelude::*;
+use flumph::node::{NodeId, NodeStatus, PeerStatus, StoreUsage, SyncProgress};
+use flumph::storage::HOUR;
+use flumph::time_sync::local_now;
+
+use super::format;
+
+/// The node at a glance: who it is, what its sensors read, what it holds and how far behind its peers are.
+#[component]
+pub fn NodeOverview(status: ReadOnlySignal<NodeStatus>) -> Element {
+    let status = status.read();
+    let now = local_now();
+
+    rsx! {
+        div { id: "overview",
+            h2 { "Node {status.id}" }
+            p { class: "muted", "{status.role} node" }
+
+            h3 { "Sensors" }
+            table { class: "overview-table",
+                for (channel, sample) in status.readings.iter() {
+                    tr { key: "{channel}",
+                        td { class: "overview-label", "{channel}" }
+                        td { "{format::values(&sample.values)} {format::unit(channel)}" }
+                        td { class: "muted", "{format::age(now - sample.local_time)}" }
+                    }
+                }
+            }
+            if status.readings.is_empty() {
+                p { class: "muted", "no readings yet" }
+            }
+
+            h3 { "Storage" }
+            StorageSummary {
+                hour: status.hour,
+                hour_samples: status.hour_samples,
+                store: status.store,
+                sync: status.sync,
+            }
+
+            h3 { "Peers" }
+            PeerList { peers: status.peers.clone() }
+        }
+    }
+}
+
+/// The hour being filled, the blob store and how far sending it along has got.
+#[component]
+pub fn StorageSummary(
+    hour: i64,
+    hour_samples: usize,
+    store: StoreUsage,
+    sync: SyncProgress,
+) -> Element {
+    let now = local_now();
+    let percent = (sync.fraction() * 100.0).round();
+
+    rsx! {
+        div { class: "overview-row",
+            span { class: "overview-label", "This hour" }
+            span { "{hour_samples} samples since {format::age(now - hour * HOUR)} ago" }
+        }
+        div { class: "overview-row",
+            span { class: "overview-label", "Blob store" }
+            span { "{store.blobs} blobs, {format::bytes(store.bytes)}" }
+            if let Some(capacity) = store.capacity {
+                span { class: "muted", "of {format::bytes(capacity)}" }
+            }
+        }
+        div { class: "overview-row",
+            span { class: "overview-label", "Sync" }
+            div { class: "progress overview-progress",
+                div { class: "progress-bar", style: "width: {percent}%" }
+            }
+        }
+        p { class: "muted",
+            "{sync.sent_blobs} blobs sent, {sync.queued_blobs} waiting ({format::bytes(sync.queued_bytes)})"
+        }
+    }
+}
+
+/// The node's peers and whether they can be reached. With `on_select`, rows can be picked.
+#[component]
+pub fn PeerList(peers: Vec<PeerStatus>, on_select: Option<EventHandler<NodeId>>) -> Element {
+    let now = local_now();
+
+    rsx! {
+        table { class: "overview-table",
+            for peer in peers.iter() {
+                tr {
+                    key: "{peer.id}",
+                    class: if on_select.is_some() { "selectable" },
+                    onclick: {
+                        let id = peer.id;
+                        move |_| {
+                            if let Some(on_select) = on_select {
+                                on_select.call(id);
+                            }
+                        }
+                    },
+                    td { class: "overview-label", "{format::short_id(peer.id)}" }
+                    td { "{peer.role}" }
+                    td { "{peer.link}" }
+                    if peer.connected {
+                        td { "connected" }
+                    } else {
+                        td { class: "error", "last seen {format::age(now - peer.last_seen)} ago" }
+                    }
+                }
+            }
+        }
+        if peers.is_empty() {
+            p { class: "muted", "no peers" }
+        }
+    }
+}
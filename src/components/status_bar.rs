@@ -0,0 +1,98 @@
+use dioxus::prelude::*;
+use flumph::node::NodeStatus;
+use flumph::sensors::Micros;
+use flumph::time_sync::local_now;
+
+/// Readings older than this mean the sensors have stopped.
+const STALE_READING: Micros = 10_000_000;
+
+/// What the node is mostly doing, which colours the bar. Each state is also spelled out, so the bar does not rely
+/// on colour alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Activity {
+    /// No peer can be reached, so blobs and commands wait on the phone.
+    Offline,
+    /// Blobs are being sent to a peer.
+    Syncing,
+    /// Everything is sent and the sensors are sampling.
+    Collecting,
+    Idle,
+}
+
+impl Activity {
+    fn of(status: &NodeStatus, collecting: bool) -> Self {
+        if !status.peers.iter().any(|peer| peer.connected) {
+            Activity::Offline
+        } else if status.sync.queued_blobs > 0 {
+            Activity::Syncing
+        } else if collecting {
+            Activity::Collecting
+        } else {
+            Activity::Idle
+        }
+    }
+
+    fn class(self) -> &'static str {
+        match self {
+            Activity::Offline => "status-bar status-offline",
+            Activity::Syncing => "status-bar status-syncing",
+            Activity::Collecting => "status-bar status-collecting",
+            Activity::Idle => "status-bar",
+        }
+    }
+}
+
+/// A bar across the top of every screen saying whether the node can reach its peers, whether its sensors are
+/// collecting and how much is still waiting to be sent, including `queued` commands held back while offline.
+#[component]
+pub fn StatusBar(status: ReadOnlySignal<NodeStatus>, queued: usize) -> Element {
+    let status = status.read();
+    let newest = status.readings.values().map(|s| s.local_time).max();
+    let collecting = newest.is_some_and(|newest| local_now() - newest < STALE_READING);
+    let activity = Activity::of(&status, collecting);
+    let reachable = status.peers.iter().filter(|peer| peer.connected).count();
+    let sync = status.sync;
+
+    rsx! {
+        div { class: activity.class(), role: "status", aria_live: "polite",
+            span { class: "status-item",
+                if activity == Activity::Offline {
+                    strong { "Offline" }
+                    " · no peer reachable"
+                } else {
+                    strong { "Online" }
+                    " · {reachable} of {status.peers.len()} peers"
+                }
+            }
+            span { class: "status-item",
+                if collecting {
+                    strong { "Collecting" }
+                    " · {status.hour_samples} samples this hour"
+                } else {
+                    strong { "Not collecting" }
+                }
+            }
+            span { class: "status-item",
+                match (activity, sync.queued_blobs) {
+                    (_, 0) => rsx! {
+                        strong { "Synced" }
+                    },
+                    (Activity::Offline, waiting) => rsx! {
+                        strong { "Waiting" }
+                        " · {waiting} blobs to send"
+                    },
+                    (_, waiting) => rsx! {
+                        strong { "Syncing" }
+                        " · {waiting} blobs left, {sync.fraction() * 100.0:.0}% sent"
+                    },
+                }
+            }
+            if queued > 0 {
+                span { class: "status-item",
+                    strong { "Queued" }
+                    " · {queued} commands"
+                }
+            }
+        }
+    }
+}
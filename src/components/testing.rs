@@ -0,0 +1,135 @@
+//! Rendering components to HTML in unit tests, without a webview.
+//!
+//! The mounted tree of a [`VirtualDom`] is walked the way a server side renderer would and written out as HTML,
+//! leaving out event listeners. It is only as faithful as tests need: no escaping, and void elements get closing tags.
+//! [`wait_until`] drives the dom's futures and effects for components that update in the background.
+
+use std::fmt::Write;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::{Duration, Instant};
+
+use dioxus::dioxus_core::{AttributeValue, DynamicNode, TemplateAttribute, TemplateNode, VNode};
+use dioxus::prelude::*;
+
+/// Builds `app` with `props`, runs its first render and returns the HTML.
+pub fn render<P: Clone + 'static, M: 'static>(
+    app: impl ComponentFunction<P, M>,
+    props: P,
+) -> String {
+    let mut dom = VirtualDom::new_with_props(app, props);
+    dom.rebuild_in_place();
+    html(&dom)
+}
+
+/// Runs the dom's work until its HTML satisfies `done`, and returns that HTML. Panics with the last HTML after
+/// `timeout`.
+pub fn wait_until(dom: &mut VirtualDom, timeout: Duration, done: impl Fn(&str) -> bool) -> String {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let out = html(dom);
+        if done(&out) {
+            return out;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        assert!(!left.is_zero(), "timed out waiting on:\n{out}");
+        {
+            let mut work = pin!(dom.wait_for_work());
+            let mut delay = pin!(futures_timer::Delay::new(left));
+            block_on(std::future::poll_fn(|cx| {
+                if work.as_mut().poll(cx).is_ready() || delay.as_mut().poll(cx).is_ready() {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            }));
+        }
+        dom.render_immediate_to_vec();
+    }
+}
+
+/// Runs `future` to completion on this thread.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    struct Unpark(std::thread::Thread);
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+    let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(out) = future.as_mut().poll(&mut cx) {
+            return out;
+        }
+        std::thread::park();
+    }
+}
+
+/// The HTML of everything currently mounted in `dom`.
+pub fn html(dom: &VirtualDom) -> String {
+    let mut out = String::new();
+    write_vnode(&mut out, dom, dom.base_scope().root_node());
+    out
+}
+
+fn write_vnode(out: &mut String, dom: &VirtualDom, vnode: &VNode) {
+    for root in vnode.template.roots {
+        write_template(out, dom, vnode, root);
+    }
+}
+
+fn write_template(out: &mut String, dom: &VirtualDom, vnode: &VNode, node: &TemplateNode) {
+    match node {
+        TemplateNode::Element {
+            tag,
+            attrs,
+            children,
+            ..
+        } => {
+            write!(out, "<{tag}").unwrap();
+            for attr in attrs.iter() {
+                match attr {
+                    TemplateAttribute::Static { name, value, .. } => {
+                        write!(out, " {name}=\"{value}\"").unwrap()
+                    }
+                    TemplateAttribute::Dynamic { id } => {
+                        for attr in vnode.dynamic_attrs[*id].iter() {
+                            let value = match &attr.value {
+                                AttributeValue::Text(text) => text.clone(),
+                                AttributeValue::Float(f) => f.to_string(),
+                                AttributeValue::Int(i) => i.to_string(),
+                                AttributeValue::Bool(b) => b.to_string(),
+                                _ => continue,
+                            };
+                            write!(out, " {}=\"{value}\"", attr.name).unwrap();
+                        }
+                    }
+                }
+            }
+            out.push('>');
+            for child in children.iter() {
+                write_template(out, dom, vnode, child);
+            }
+            write!(out, "</{tag}>").unwrap();
+        }
+        TemplateNode::Text { text } => out.push_str(text),
+        TemplateNode::Dynamic { id } => match &vnode.dynamic_nodes[*id] {
+            DynamicNode::Component(component) => {
+                if let Some(scope) = component.mounted_scope(*id, vnode, dom) {
+                    write_vnode(out, dom, scope.root_node());
+                }
+            }
+            DynamicNode::Text(text) => out.push_str(&text.value),
+            DynamicNode::Placeholder(_) => {}
+            DynamicNode::Fragment(nodes) => {
+                for node in nodes {
+                    write_vnode(out, dom, node);
+                }
+            }
+        },
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::BTreeMap;
+
+use dioxus::prelude::*;
+use flumph::node::{Link, LinkKind, NodeId, NodeStatus, PeerStatus};
+use flumph::sensors::Micros;
+use flumph::time_sync::local_now;
+
+use super::format;
+
+const WIDTH: f64 = 560.0;
+const HEIGHT: f64 = 400.0;
+/// Distance from the centre of the node's peers, and of the nodes only its peers reach.
+const PEER_RING: f64 = 110.0;
+const OUTER_RING: f64 = 175.0;
+const NODE_RADIUS: f64 = 18.0;
+/// Links not known to work for this long are drawn as stale.
+const STALE: Micros = 30_000_000;
+
+/// The mesh as seen from this node: itself in the middle, its peers around it and the nodes they reach further out.
+/// Selecting a node reports its id.
+#[component]
+pub fn TopologyMap(status: NodeStatus, on_select: EventHandler<NodeId>) -> Element {
+    let now = local_now();
+    let links = status.topology();
+    let positions = layout(&status, &links);
+    let peers: BTreeMap<NodeId, &PeerStatus> = status.peers.iter().map(|p| (p.id, p)).collect();
+
+    rsx! {
+        svg {
+            class: "topology",
+            width: "{WIDTH}",
+            height: "{HEIGHT}",
+            view_box: "0 0 {WIDTH} {HEIGHT}",
+            for link in links.iter() {
+                if let (Some(&(x1, y1)), Some(&(x2, y2))) = (positions.get(&link.a), positions.get(&link.b)) {
+                    g { key: "{link.a}-{link.b}",
+                        line {
+                            x1: "{x1:.1}",
+                            y1: "{y1:.1}",
+                            x2: "{x2:.1}",
+                            y2: "{y2:.1}",
+                            stroke: link_color(link, now),
+                            stroke_width: "2",
+                            stroke_dasharray: if link.kind == LinkKind::Wan { "8 4" },
+                        }
+                        text {
+                            x: "{(x1 + x2) / 2.0:.1}",
+                            y: "{(y1 + y2) / 2.0 - 4.0:.1}",
+                            class: "topology-label",
+                            text_anchor: "middle",
+                            "{link.kind} {link.rtt.map(format::rtt).unwrap_or_default()}"
+                        }
+                    }
+                }
+            }
+            for (&id, &(x, y)) in positions.iter() {
+                g {
+                    key: "{id}",
+                    class: "topology-node",
+                    onclick: move |_| on_select.call(id),
+                    circle {
+                        cx: "{x:.1}",
+                        cy: "{y:.1}",
+                        r: "{NODE_RADIUS}",
+                        fill: node_fill(id == status.id, peers.get(&id).copied()),
+                        stroke_dasharray: if peers.get(&id).is_some_and(|p| !p.connected) { "3 3" },
+                    }
+                    text {
+                        x: "{x:.1}",
+                        y: "{y + NODE_RADIUS + 14.0:.1}",
+                        class: "topology-label",
+                        text_anchor: "middle",
+                        "{format::short_id(id)}"
+                    }
+                    text {
+                        x: "{x:.1}",
+                        y: "{y + NODE_RADIUS + 28.0:.1}",
+                        class: "topology-label muted",
+                        text_anchor: "middle",
+                        match (id == status.id, peers.get(&id)) {
+                            (true, _) => format!("this {}", status.role),
+                            (false, Some(peer)) => peer.role.to_string(),
+                            (false, None) => "via peer".to_string(),
+                        }
+                    }
+                }
+            }
+        }
+        p { class: "muted topology-legend", "solid: LAN · dashed: WAN · red: not heard from for 30 s" }
+    }
+}
+
+/// Everything known about one peer.
+#[component]
+pub fn PeerDetail(peer: PeerStatus) -> Element {
+    let now = local_now();
+    let percent = (peer.sync.fraction() * 100.0).round();
+
+    rsx! {
+        div { class: "overview-row",
+            span { class: "overview-label", "Role" }
+            span { "{peer.role}" }
+        }
+        div { class: "overview-row",
+            span { class: "overview-label", "Address" }
+            span { "{peer.address}" }
+        }
+        div { class: "overview-row",
+            span { class: "overview-label", "Link" }
+            span { "{peer.link}" }
+            if let Some(rtt) = peer.rtt {
+                span { class: "muted", "round trip {format::rtt(rtt)}" }
+            }
+        }
+        div { class: "overview-row",
+            span { class: "overview-label", "Last seen" }
+            if peer.connected {
+                span { "connected" }
+            } else {
+                span { class: "error", "{format::age(now - peer.last_seen)} ago" }
+            }
+        }
+        div { class: "overview-row",
+            span { class: "overview-label", "Held here" }
+            span { "{peer.held.blobs} blobs, {format::bytes(peer.held.bytes)}" }
+        }
+        div { class: "overview-row",
+            span { class: "overview-label", "Sync to it" }
+            div { class: "progress overview-progress",
+                div { class: "progress-bar", style: "width: {percent}%" }
+            }
+        }
+        p { class: "muted",
+            "{peer.sync.sent_blobs} blobs sent, {peer.sync.queued_blobs} waiting ({format::bytes(peer.sync.queued_bytes)})"
+        }
+    }
+}
+
+/// The links of node `from`, and who is at their other end.
+#[component]
+pub fn LinkTable(from: NodeId, links: Vec<Link>) -> Element {
+    let now = local_now();
+
+    rsx! {
+        table { class: "overview-table",
+            for link in links.iter() {
+                tr { key: "{link.a}-{link.b}",
+                    td { class: "overview-label", "{format::short_id(other_end(link, from))}" }
+                    td { "{link.kind}" }
+                    td { "{link.rtt.map(format::rtt).unwrap_or_default()}" }
+                    td { class: "muted", "{format::age(now - link.last_seen)} ago" }
+                }
+            }
+        }
+    }
+}
+
+/// Puts this node in the middle, its peers evenly around it and every other node outside the first peer that
+/// reaches it.
+fn layout(status: &NodeStatus, links: &[Link]) -> BTreeMap<NodeId, (f64, f64)> {
+    let centre = (WIDTH / 2.0, HEIGHT / 2.0);
+    let at = |angle: f64, radius: f64| {
+        (
+            centre.0 + radius * angle.cos(),
+            centre.1 + radius * angle.sin(),
+        )
+    };
+    let mut angles = BTreeMap::new();
+    let mut positions = BTreeMap::from([(status.id, centre)]);
+    let count = status.peers.len().max(1) as f64;
+    for (i, peer) in status.peers.iter().enumerate() {
+        let angle = std::f64::consts::TAU * i as f64 / count - std::f64::consts::FRAC_PI_2;
+        angles.insert(peer.id, angle);
+        positions.insert(peer.id, at(angle, PEER_RING));
+    }
+    // Nodes further out fan around their peer so that several behind the same one do not overlap.
+    let mut behind: BTreeMap<NodeId, usize> = BTreeMap::new();
+    for link in links {
+        for (near, far) in [(link.a, link.b), (link.b, link.a)] {
+            if positions.contains_key(&far) {
+                continue;
+            }
+            let Some(&angle) = angles.get(&near) else {
+                continue;
+            };
+            let n = behind.entry(near).or_default();
+            // 0, +1, -1, +2, -2... steps either side of the peer.
+            let step = (*n).div_ceil(2) as f64 * if (*n).is_multiple_of(2) { -0.35 } else { 0.35 };
+            *n += 1;
+            positions.insert(far, at(angle + step, OUTER_RING));
+        }
+    }
+    positions
+}
+
+fn other_end(link: &Link, id: NodeId) -> NodeId {
+    if link.a == id {
+        link.b
+    } else {
+        link.a
+    }
+}
+
+fn link_color(link: &Link, now: Micros) -> &'static str {
+    if now - link.last_seen >= STALE {
+        "#ff6b6b"
+    } else if link.kind == LinkKind::Lan {
+        "#4caf50"
+    } else {
+        "#2196f3"
+    }
+}
+
+fn node_fill(local: bool, peer: Option<&PeerStatus>) -> &'static str {
+    match (local, peer) {
+        (true, _) => "#4caf50",
+        (false, Some(peer)) if peer.connected => "#2a2d35",
+        _ => "#0f1116",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flumph::node::{NodeRole, StoreUsage, SyncProgress};
+
+    use super::*;
+    use crate::components::testing::render;
+
+    const HERE: NodeId = NodeId(0xa000);
+    const SHED: NodeId = NodeId(0xb001);
+    const MAST: NodeId = NodeId(0xb002);
+
+    fn peer(id: NodeId, link: LinkKind, rtt: Micros, ago: Micros, now: Micros) -> PeerStatus {
+        PeerStatus {
+            id,
+            role: NodeRole::Sensor,
+            address: format!("10.0.0.{}", id.0 & 0xff),
+            link,
+            rtt: Some(rtt),
+            last_seen: now - ago,
+            connected: ago < STALE,
+            held: StoreUsage {
+                blobs: 12,
+                bytes: 3_400_000,
+                ..Default::default()
+            },
+            sync: SyncProgress {
+                queued_blobs: 1,
+                queued_bytes: 1_000,
+                sent_blobs: 9,
+                sent_bytes: 3_000,
+            },
+        }
+    }
+
+    fn link(a: u64, b: u64, kind: LinkKind, ago: Micros, now: Micros) -> Link {
+        Link {
+            a: NodeId(a),
+            b: NodeId(b),
+            kind,
+            rtt: Some(20_000),
+            last_seen: now - ago,
+        }
+    }
+
+    /// A compute node with a shed on the LAN and a mast over WAN that has gone quiet. Two more nodes hang off the
+    /// shed, one off the mast, and one link is between nodes nobody here can place.
+    fn fixture() -> NodeStatus {
+        let now = local_now();
+        NodeStatus {
+            id: HERE,
+            role: NodeRole::Compute,
+            readings: Default::default(),
+            hour: 0,
+            hour_samples: 0,
+            store: StoreUsage::default(),
+            peers: vec![
+                peer(SHED, LinkKind::Lan, 4_000, 1_000_000, now),
+                peer(MAST, LinkKind::Wan, 600_000, 150_000_000, now),
+            ],
+            links: vec![
+                link(0xb001, 0xc003, LinkKind::Lan, 0, now),
+                link(0xc004, 0xb001, LinkKind::Wan, 0, now),
+                link(0xb002, 0xc005, LinkKind::Lan, 200_000_000, now),
+                link(0xd006, 0xd007, LinkKind::Lan, 0, now),
+            ],
+            sync: SyncProgress::default(),
+            cameras: Vec::new(),
+        }
+    }
+
+    fn distance((x, y): (f64, f64)) -> f64 {
+        (x - WIDTH / 2.0).hypot(y - HEIGHT / 2.0)
+    }
+
+    #[test]
+    fn the_layout_rings_peers_and_the_nodes_behind_them() {
+        let status = fixture();
+        let positions = layout(&status, &status.topology());
+        assert_eq!(positions[&HERE], (WIDTH / 2.0, HEIGHT / 2.0));
+        for id in [SHED, MAST] {
+            assert!((distance(positions[&id]) - PEER_RING).abs() < 1e-9);
+        }
+        for id in [0xc003, 0xc004, 0xc005] {
+            assert!((distance(positions[&NodeId(id)]) - OUTER_RING).abs() < 1e-9);
+        }
+        let (a, b) = (positions[&NodeId(0xc003)], positions[&NodeId(0xc004)]);
+        assert!((a.0 - b.0).hypot(a.1 - b.1) > 2.0 * NODE_RADIUS);
+        assert!(!positions.contains_key(&NodeId(0xd006)));
+        assert_eq!(positions.len(), 6);
+    }
+
+    #[test]
+    fn the_map_shows_every_reachable_node_and_link() {
+        fn map(status: NodeStatus) -> Element {
+            rsx! {
+                TopologyMap { status, on_select: |_| {} }
+            }
+        }
+        let html = render(map, fixture());
+        for id in [
+            HERE,
+            SHED,
+            MAST,
+            NodeId(0xc003),
+            NodeId(0xc004),
+            NodeId(0xc005),
+        ] {
+            assert!(html.contains(&format::short_id(id)), "{id} missing");
+        }
+        assert!(!html.contains(&format::short_id(NodeId(0xd006))));
+        assert!(html.contains("this compute"));
+        assert_eq!(html.matches("via peer").count(), 3);
+        assert!(html.contains("LAN 4 ms"));
+        assert!(html.contains("WAN 600 ms"));
+        // Five drawable links, two of them WAN and two not heard from in over 30 s.
+        assert_eq!(html.matches("<line").count(), 5);
+        assert_eq!(html.matches("stroke-dasharray=\"8 4\"").count(), 2);
+        assert_eq!(html.matches("stroke=\"#ff6b6b\"").count(), 2);
+        // The quiet peer is drawn dashed.
+        assert_eq!(html.matches("stroke-dasharray=\"3 3\"").count(), 1);
+    }
+
+    #[test]
+    fn peer_details_show_link_sync_and_data_held() {
+        fn detail(peer: PeerStatus) -> Element {
+            rsx! {
+                PeerDetail { peer }
+            }
+        }
+        let status = fixture();
+        let html = render(detail, status.peers[1].clone());
+        assert!(html.contains("10.0.0.2"));
+        assert!(html.contains("WAN"));
+        assert!(html.contains("round trip 600 ms"));
+        assert!(html.contains("2 min ago"));
+        assert!(html.contains(&format!("12 blobs, {}", format::bytes(3_400_000))));
+        assert!(html.contains("width: 75%"));
+        assert!(html.contains("9 blobs sent, 1 waiting"));
+
+        let html = render(detail, status.peers[0].clone());
+        assert!(html.contains("connected"));
+        assert!(!html.contains("ago"));
+    }
+
+    #[test]
+    fn the_link_table_names_the_far_end() {
+        fn table(links: Vec<Link>) -> Element {
+            rsx! {
+                LinkTable { from: SHED, links }
+            }
+        }
+        let links = fixture().links[..2].to_vec();
+        let html = render(table, links);
+        assert!(html.contains(&format::short_id(NodeId(0xc003))));
+        assert!(html.contains(&format::short_id(NodeId(0xc004))));
+        assert!(!html.contains(&format::short_id(SHED)));
+    }
+}
@@ -0,0 +1,127 @@
+//! Bringing config files written by older versions of the app up to [`VERSION`].
+//!
+//! Each step rewrites the JSON of one version into the next, so a file from any version reaches the current one by
+//! running the steps after it in turn. Steps only move and rename fields; anything they do not recognise is left for
+//! deserialization to ignore.
+
+use serde_json::{json, Map, Value};
+
+use super::{ConfigError, VERSION};
+
+/// Rewrites a config of any version into the current layout. Files without a `version` are from version 1.
+pub fn migrate(mut config: Value) -> Result<Value, ConfigError> {
+    let version = config
+        .get("version")
+        .and_then(Value::as_u64)
+        .map_or(1, |v| v as u32);
+    if version > VERSION {
+        return Err(ConfigError::TooNew(version));
+    }
+    if version < 2 {
+        config = from_v1(config);
+    }
+    if let Some(config) = config.as_object_mut() {
+        config.insert("version".to_string(), json!(VERSION));
+    }
+    Ok(config)
+}
+
+/// Version 1 was the camera app's: a flat object with `server_ip`, `server_port` and an `encoding` object holding
+/// `width`, `height`, `fps` and `bitrate_kbps`. Version 2 groups settings by what they are for.
+fn from_v1(config: Value) -> Value {
+    let Value::Object(mut old) = config else {
+        return config;
+    };
+    let mut link = Map::new();
+    if let Some(ip) = old
+        .remove("server_ip")
+        .and_then(|ip| ip.as_str().map(str::to_string))
+    {
+        let port = old
+            .remove("server_port")
+            .and_then(|port| port.as_u64())
+            .unwrap_or(7310);
+        // IPv6 addresses need brackets so that the port can be told apart.
+        let server = if ip.contains(':') {
+            format!("[{ip}]:{port}")
+        } else {
+            format!("{ip}:{port}")
+        };
+        link.insert("server".to_string(), json!(server));
+    }
+    let mut camera = Map::new();
+    if let Some(Value::Object(mut encoding)) = old.remove("encoding") {
+        if let (Some(width), Some(height)) = (encoding.remove("width"), encoding.remove("height")) {
+            camera.insert(
+                "resolution".to_string(),
+                json!({ "width": width, "height": height }),
+            );
+        }
+        for field in ["fps", "bitrate_kbps", "keyframe_interval"] {
+            if let Some(value) = encoding.remove(field) {
+                camera.insert(field.to_string(), value);
+            }
+        }
+    }
+    if !link.is_empty() {
+        old.insert("link".to_string(), Value::Object(link));
+    }
+    if !camera.is_empty() {
+        old.insert("camera".to_string(), Value::Object(camera));
+    }
+    Value::Object(old)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+
+    #[test]
+    fn the_camera_apps_flat_layout_is_grouped() {
+        let v1 = json!({
+            "server_ip": "10.0.0.2",
+            "server_port": 9000,
+            "encoding": { "width": 320, "height": 240, "fps": 10, "bitrate_kbps": 200 },
+        });
+        let migrated = migrate(v1).unwrap();
+        assert_eq!(migrated["version"], json!(VERSION));
+        assert_eq!(migrated["link"], json!({ "server": "10.0.0.2:9000" }));
+        assert_eq!(
+            migrated["camera"],
+            json!({
+                "resolution": { "width": 320, "height": 240 },
+                "fps": 10,
+                "bitrate_kbps": 200,
+            })
+        );
+        assert!(migrated.get("server_ip").is_none() && migrated.get("encoding").is_none());
+
+        let config: AppConfig = serde_json::from_value(migrated).unwrap();
+        assert_eq!(config.link.server, "10.0.0.2:9000");
+        assert_eq!(
+            (config.camera.resolution.width, config.camera.fps),
+            (320, 10)
+        );
+        assert!(config.validate().is_empty());
+    }
+
+    #[test]
+    fn v1_ports_default_and_ipv6_addresses_are_bracketed() {
+        let migrated = migrate(json!({ "server_ip": "fe80::1" })).unwrap();
+        assert_eq!(migrated["link"]["server"], json!("[fe80::1]:7310"));
+        // Nothing to group leaves the groups to their defaults.
+        let migrated = migrate(json!({})).unwrap();
+        assert_eq!(migrated, json!({ "version": VERSION }));
+    }
+
+    #[test]
+    fn current_files_are_left_alone_and_newer_ones_refused() {
+        let v2 = json!({ "version": VERSION, "link": { "server": "a:1" }, "server_ip": "b" });
+        assert_eq!(migrate(v2.clone()).unwrap(), v2);
+        assert!(matches!(
+            migrate(json!({ "version": VERSION + 1 })),
+            Err(ConfigError::TooNew(v)) if v == VERSION + 1
+        ));
+    }
+}
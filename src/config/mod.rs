@@ -0,0 +1,644 @@
+//! The app's settings, kept in a JSON file.
+//!
+//! [`AppConfig`] holds everything a user can change about their node: its role, how fast it samples, how much it may
+//! store and for how long, what its link may carry, how its camera records, what it classifies, what it serves to
+//! browsers and how the app looks. Every field has a default, so a file only needs the ones that differ, and [`AppConfig::validate`] checks
+//! each field so a settings screen can show what is wrong next to it.
+//!
+//! Files carry a `version`. Older ones are brought up to date by [`migrate`] before they are read, starting with the
+//! flat `server_ip`/`encoding` layout of the first camera app.
+
+mod migrate;
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::calibration::ApplyAt;
+use crate::camera::{MotionConfig, Resolution, SnapshotConfig};
+use crate::encoder::adaptive::{AdaptiveConfig, ByteBudget};
+use crate::encoder::EncoderSettings;
+use crate::node::NodeRole;
+use crate::sensors::Micros;
+use crate::storage::{ImageFormat, DAY, HOUR};
+
+pub use migrate::migrate;
+
+/// Version of the config layout this build writes.
+pub const VERSION: u32 = 2;
+
+/// Least the camera may be given to encode with.
+const MIN_BITRATE_KBPS: u32 = 16;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("could not read or write the config: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("config is malformed: {0}")]
+    Format(#[from] serde_json::Error),
+    #[error("config version {0} is newer than this app, which understands up to {VERSION}")]
+    TooNew(u32),
+    #[error("config is invalid: {}", .0.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; "))]
+    Invalid(Vec<FieldError>),
+}
+
+/// A problem with one field, named by its path in the file, e.g. `camera.fps`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    pub version: u32,
+    pub node: NodeConfig,
+    pub sensors: SensorConfig,
+    pub storage: StorageConfig,
+    pub link: LinkConfig,
+    pub camera: CameraConfig,
+    pub analytics: AnalyticsConfig,
+    pub server: ServerConfig,
+    pub display: DisplayConfig,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            version: VERSION,
+            node: NodeConfig::default(),
+            sensors: SensorConfig::default(),
+            storage: StorageConfig::default(),
+            link: LinkConfig::default(),
+            camera: CameraConfig::default(),
+            analytics: AnalyticsConfig::default(),
+            server: ServerConfig::default(),
+            display: DisplayConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeConfig {
+    pub role: NodeRole,
+    /// Shown to other nodes and in the app, to tell nodes apart more easily than by id.
+    pub name: String,
+    /// Runs a simulated node, with made up sensors, peers and footage, in place of the real pipeline. For trying the
+    /// app without any instruments attached.
+    pub demo: bool,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            role: NodeRole::Sensor,
+            name: "flumph".to_string(),
+            demo: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SensorConfig {
+    /// Samples per second of every built in sensor.
+    pub rate_hz: u32,
+    /// Station description listing the instruments wired to the node (see [`crate::sensors::station`]). `None` for
+    /// a node without any.
+    pub station: Option<PathBuf>,
+    /// Whether calibrated channels are recorded as samples come in, or only worked out when stored hours are read.
+    pub calibrate: ApplyAt,
+}
+
+impl Default for SensorConfig {
+    fn default() -> Self {
+        SensorConfig {
+            rate_hz: 50,
+            station: None,
+            calibrate: ApplyAt::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// Most the blob store may hold, in MB. `None` lets it fill the disk.
+    pub quota_mb: Option<u64>,
+    /// Blobs older than this many days may be deleted once a peer holds a copy. `None` keeps everything.
+    pub retention_days: Option<u32>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            quota_mb: Some(512),
+            retention_days: None,
+        }
+    }
+}
+
+impl StorageConfig {
+    /// How many hours blobs are kept once a peer holds a copy.
+    pub fn retention_hours(&self) -> Option<i64> {
+        self.retention_days.map(|days| i64::from(days) * DAY / HOUR)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LinkConfig {
+    /// `host:port` of the node blobs are sent to.
+    pub server: String,
+    /// What the uplink carries, in kbps.
+    pub uplink_kbps: u32,
+    /// Most a metered link may send in a day, in MB. `None` for unmetered links.
+    pub daily_budget_mb: Option<u32>,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        LinkConfig {
+            server: "localhost:7310".to_string(),
+            uplink_kbps: 32,
+            daily_budget_mb: None,
+        }
+    }
+}
+
+impl LinkConfig {
+    /// A whole day's allowance of a metered link, from `now`.
+    pub fn daily_budget(&self, now: Micros) -> Option<ByteBudget> {
+        self.daily_budget_mb.map(|mb| ByteBudget {
+            remaining: u64::from(mb) * 1024 * 1024,
+            period_end: now + DAY,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraConfig {
+    /// Id of the camera to record from, as [`crate::camera::list_devices`] gives it. `None` for a node without one.
+    pub device: Option<String>,
+    pub resolution: Resolution,
+    pub fps: u32,
+    pub bitrate_kbps: u32,
+    /// Longest run of frames between keyframes.
+    pub keyframe_interval: u64,
+    /// Luma difference (out of 255) that counts as motion.
+    pub motion_threshold: u8,
+    /// Keep a still this often instead of recording video, for links too slow for footage. `None` records video.
+    pub snapshot_minutes: Option<u32>,
+    pub snapshot_format: ImageFormat,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        CameraConfig {
+            device: None,
+            resolution: Resolution::new(640, 480),
+            fps: 15,
+            bitrate_kbps: 500,
+            keyframe_interval: 60,
+            motion_threshold: 24,
+            snapshot_minutes: None,
+            snapshot_format: ImageFormat::Avif,
+        }
+    }
+}
+
+impl CameraConfig {
+    /// How the camera's footage is encoded. Frames are encoded at the size they are captured at, which the camera
+    /// picks as close to `resolution` as it can.
+    pub fn encoder(&self) -> EncoderSettings {
+        EncoderSettings {
+            width: self.resolution.width,
+            height: self.resolution.height,
+            fps: self.fps,
+            bitrate_kbps: self.bitrate_kbps,
+            keyframe_interval: self.keyframe_interval,
+            ..EncoderSettings::default()
+        }
+    }
+
+    pub fn motion(&self) -> MotionConfig {
+        MotionConfig {
+            threshold: self.motion_threshold,
+            ..MotionConfig::default()
+        }
+    }
+
+    /// How stills are kept, or `None` when the camera records video.
+    pub fn snapshot(&self) -> Option<SnapshotConfig> {
+        self.snapshot_minutes.map(|minutes| SnapshotConfig {
+            interval: Micros::from(minutes) * 60 * 1_000_000,
+            format: self.snapshot_format,
+            ..SnapshotConfig::default()
+        })
+    }
+}
+
+/// How a compute node classifies the footage it stores (see [`crate::analytics`]).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnalyticsConfig {
+    /// ONNX object detection model, in the YOLOv8 layout with a 640x640 input. `None` classifies nothing.
+    pub model: Option<PathBuf>,
+    /// Class names by index, as the model was trained with.
+    pub labels: Vec<String>,
+    /// Seconds between the frames classified.
+    pub interval_s: u32,
+    /// Weakest detection kept, `0.0..=1.0`.
+    pub min_confidence: f32,
+}
+
+impl Default for AnalyticsConfig {
+    fn default() -> Self {
+        AnalyticsConfig {
+            model: None,
+            labels: Vec::new(),
+            interval_s: 1,
+            min_confidence: 0.4,
+        }
+    }
+}
+
+impl AnalyticsConfig {
+    /// Time between the frames classified.
+    pub fn interval(&self) -> Micros {
+        Micros::from(self.interval_s) * 1_000_000
+    }
+}
+
+/// The HTTP server a compute node runs so that browsers on its network can look at what it stores.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Only compute nodes serve, and only once this is turned on.
+    pub enabled: bool,
+    /// Address and port to listen on. `0.0.0.0` listens on every network the node is on.
+    pub listen: String,
+    /// Directory holding the `web` build of the app, as `dx bundle --platform web` writes it. Without one only the
+    /// API is served.
+    pub web_root: Option<PathBuf>,
+    /// What browsers and scripts pair with. Made up the first time the server starts.
+    pub token: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            enabled: false,
+            listen: "0.0.0.0:7380".to_string(),
+            web_root: None,
+            token: None,
+        }
+    }
+}
+
+/// How the app is drawn, which matters most on a phone used outdoors.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplayConfig {
+    pub theme: Theme,
+    /// Bigger buttons and fields, for gloved fingers. On by default on phones.
+    pub large_targets: bool,
+}
+
+/// Whether the app is running on a phone, where large targets are the default.
+const MOBILE: bool = cfg!(any(target_os = "android", target_os = "ios"));
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        DisplayConfig {
+            theme: Theme::default(),
+            large_targets: MOBILE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Theme {
+    #[default]
+    Dark,
+    /// Black on white with heavy outlines, to stay readable in direct sunlight.
+    Outdoor,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 2] = [Theme::Dark, Theme::Outdoor];
+}
+
+impl fmt::Display for Theme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Theme::Dark => f.write_str("dark"),
+            Theme::Outdoor => f.write_str("outdoor"),
+        }
+    }
+}
+
+impl AppConfig {
+    /// How the camera's footage is encoded: as [`CameraConfig::encoder`] says, at no more than a metered link's
+    /// daily budget could carry if the camera recorded all day, leaving room for sensor data.
+    pub fn encoder(&self) -> EncoderSettings {
+        let mut settings = self.camera.encoder();
+        if let Some(budget) = self.link.daily_budget(0) {
+            let share = AdaptiveConfig::default().budget_share;
+            let affordable = (budget.sustainable_kbps(0) * share) as u32;
+            settings.bitrate_kbps = settings.bitrate_kbps.min(affordable.max(MIN_BITRATE_KBPS));
+        }
+        settings
+    }
+
+    /// Reads the config at `path`, migrating it from an older version if need be. A missing file gives the
+    /// defaults; an invalid one is an error listing every problem.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        match std::fs::read(path) {
+            Ok(bytes) => Self::from_json(&bytes),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(AppConfig::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn from_json(bytes: &[u8]) -> Result<Self, ConfigError> {
+        let value = migrate(serde_json::from_slice(bytes)?)?;
+        let config: AppConfig = serde_json::from_value(value)?;
+        let errors = config.validate();
+        if !errors.is_empty() {
+            return Err(ConfigError::Invalid(errors));
+        }
+        Ok(config)
+    }
+
+    /// Writes the config to `path`, through a temporary file so that a crash never leaves half of one behind.
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let temporary = path.with_extension("json.tmp");
+        std::fs::write(&temporary, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /// Every problem with the config, by field. An empty list means it is fine to use.
+    pub fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        let mut check = |ok: bool, field: &str, message: &str| {
+            if !ok {
+                errors.push(FieldError {
+                    field: field.to_string(),
+                    message: message.to_string(),
+                });
+            }
+        };
+
+        let name = self.node.name.trim();
+        check(!name.is_empty(), "node.name", "must not be empty");
+        check(name.len() <= 64, "node.name", "must be at most 64 bytes");
+
+        check(
+            (1..=1_000).contains(&self.sensors.rate_hz),
+            "sensors.rate_hz",
+            "must be between 1 and 1000",
+        );
+
+        check(
+            self.sensors
+                .station
+                .as_ref()
+                .is_none_or(|path| !path.as_os_str().is_empty()),
+            "sensors.station",
+            "must not be empty",
+        );
+
+        check(
+            self.storage.quota_mb.is_none_or(|mb| mb >= 16),
+            "storage.quota_mb",
+            "must be at least 16 MB",
+        );
+        check(
+            self.storage.retention_days.is_none_or(|days| days >= 1),
+            "storage.retention_days",
+            "must be at least a day",
+        );
+
+        let (host, port) = self
+            .link
+            .server
+            .rsplit_once(':')
+            .unwrap_or((&self.link.server, ""));
+        check(
+            !host.is_empty() && !host.contains(char::is_whitespace),
+            "link.server",
+            "needs a host, as in host:port",
+        );
+        check(
+            port.parse::<u16>().is_ok_and(|port| port > 0),
+            "link.server",
+            "needs a port between 1 and 65535, as in host:port",
+        );
+        check(
+            self.link.uplink_kbps >= 8,
+            "link.uplink_kbps",
+            "must be at least 8 kbps",
+        );
+        check(
+            self.link.daily_budget_mb.is_none_or(|mb| mb >= 1),
+            "link.daily_budget_mb",
+            "must be at least 1 MB",
+        );
+
+        check(
+            self.camera
+                .device
+                .as_ref()
+                .is_none_or(|device| !device.trim().is_empty()),
+            "camera.device",
+            "must not be empty",
+        );
+        let Resolution { width, height } = self.camera.resolution;
+        check(
+            (16..=3840).contains(&width) && (16..=2160).contains(&height),
+            "camera.resolution",
+            "must be between 16x16 and 3840x2160",
+        );
+        check(
+            width.is_multiple_of(2) && height.is_multiple_of(2),
+            "camera.resolution",
+            "width and height must be even for 4:2:0 video",
+        );
+        check(
+            (1..=60).contains(&self.camera.fps),
+            "camera.fps",
+            "must be between 1 and 60",
+        );
+        check(
+            self.camera.bitrate_kbps >= MIN_BITRATE_KBPS,
+            "camera.bitrate_kbps",
+            "must be at least 16 kbps",
+        );
+        check(
+            self.camera.keyframe_interval >= 1,
+            "camera.keyframe_interval",
+            "must be at least 1 frame",
+        );
+        check(
+            self.camera.motion_threshold >= 1,
+            "camera.motion_threshold",
+            "must be at least 1",
+        );
+        check(
+            self.camera.snapshot_minutes != Some(0),
+            "camera.snapshot_minutes",
+            "must be at least 1 minute",
+        );
+        check(
+            self.analytics
+                .model
+                .as_ref()
+                .is_none_or(|model| !model.as_os_str().is_empty()),
+            "analytics.model",
+            "must not be empty",
+        );
+        check(
+            self.analytics.interval_s >= 1,
+            "analytics.interval_s",
+            "must be at least 1 second",
+        );
+        check(
+            (0.0..=1.0).contains(&self.analytics.min_confidence),
+            "analytics.min_confidence",
+            "must be between 0 and 1",
+        );
+        check(
+            self.server.listen.parse::<std::net::SocketAddr>().is_ok(),
+            "server.listen",
+            "must be an address and port, as in 0.0.0.0:7380",
+        );
+        check(
+            self.server
+                .web_root
+                .as_ref()
+                .is_none_or(|root| !root.as_os_str().is_empty()),
+            "server.web_root",
+            "must not be empty",
+        );
+        check(
+            self.server.token.as_ref().is_none_or(|token| {
+                token.len() >= 16 && !token.contains(|c: char| c.is_whitespace() || c.is_control())
+            }),
+            "server.token",
+            "must be at least 16 characters without spaces",
+        );
+        errors
+    }
+}
+
+/// Where the app keeps its config: `$FLUMPH_CONFIG` if set, otherwise `flumph/config.json` in the platform's config
+/// directory, or the working directory when there is none.
+pub fn default_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("FLUMPH_CONFIG") {
+        return PathBuf::from(path);
+    }
+    let base = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    base.map_or_else(
+        || PathBuf::from("flumph.json"),
+        |base| base.join("flumph").join("config.json"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(config: &AppConfig) -> Vec<String> {
+        config.validate().into_iter().map(|e| e.field).collect()
+    }
+
+    #[test]
+    fn each_problem_is_reported_against_its_field() {
+        assert!(AppConfig::default().validate().is_empty());
+
+        let mut config = AppConfig::default();
+        config.node.name = "  ".to_string();
+        config.sensors.rate_hz = 0;
+        config.storage.retention_days = Some(0);
+        config.link.server = "no port".to_string();
+        config.camera.resolution = Resolution::new(641, 480);
+        config.camera.snapshot_minutes = Some(0);
+        config.analytics.min_confidence = 1.5;
+        assert_eq!(
+            fields(&config),
+            [
+                "node.name",
+                "sensors.rate_hz",
+                "storage.retention_days",
+                "link.server",
+                "link.server",
+                "camera.resolution",
+                "camera.snapshot_minutes",
+                "analytics.min_confidence",
+            ]
+        );
+
+        let json = serde_json::to_vec(&config).unwrap();
+        match AppConfig::from_json(&json) {
+            Err(ConfigError::Invalid(errors)) => assert_eq!(errors, config.validate()),
+            other => panic!("expected the config to be invalid, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn files_only_need_what_differs_and_are_read_back_as_saved() {
+        let config = AppConfig::from_json(br#"{ "camera": { "fps": 5 } }"#).unwrap();
+        assert_eq!(config.camera.fps, 5);
+        assert_eq!(config.link, LinkConfig::default());
+
+        let path = std::env::temp_dir().join(format!("flumph-config-{}.json", std::process::id()));
+        assert_eq!(AppConfig::load(&path).unwrap(), AppConfig::default());
+        config.save(&path).unwrap();
+        assert_eq!(AppConfig::load(&path).unwrap(), config);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn a_daily_budget_caps_the_cameras_bitrate() {
+        let mut config = AppConfig::default();
+        assert_eq!(config.encoder().bitrate_kbps, config.camera.bitrate_kbps);
+
+        // 1000 MB a day is about 97 kbps, of which video gets four fifths.
+        config.link.daily_budget_mb = Some(1000);
+        let capped = config.encoder().bitrate_kbps;
+        assert!((70..=80).contains(&capped), "{capped}");
+        config.link.daily_budget_mb = Some(1);
+        assert_eq!(config.encoder().bitrate_kbps, MIN_BITRATE_KBPS);
+    }
+
+    #[test]
+    fn retention_is_counted_in_hours() {
+        let mut storage = StorageConfig::default();
+        assert_eq!(storage.retention_hours(), None);
+        storage.retention_days = Some(2);
+        assert_eq!(storage.retention_hours(), Some(48));
+    }
+}
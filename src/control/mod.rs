@@ -0,0 +1,848 @@
+//! Remote control of camera nodes.
+//!
+//! A node that wants to change a camera issues a [`CameraCommand`] through its [`CommandClient`], which stamps it
+//! with a [`CommandId`] and resends it until the camera node acknowledges it. While the camera node cannot be
+//! reached, commands are queued instead, and sent once it is back. The camera node's [`CommandHandler`]
+//! executes each id once, however many times it arrives, and answers repeats with the acknowledgement it already
+//! sent. Both ends keep a [`CommandLog`] of what was asked and what came of it.
+//!
+//! The app hands its [`ControlMessage`]s to its node through [`crate::node::NodeBackend::control`], which handles
+//! those for its own cameras and passes the rest on like [`crate::live::LiveSignal`]s: over the node's own link, or
+//! over the data channel of a live view session when there is one.
+
+pub mod sim;
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::camera::{Frame, MotionConfig, Resolution};
+use crate::encoder::EncoderSettings;
+use crate::live::LiveAccess;
+use crate::node::NodeId;
+use crate::sensors::{ChannelId, Micros};
+
+/// Identifies a command for as long as either end remembers it. Sequence numbers only ever increase for an issuer,
+/// so a command that arrives after a newer one from the same issuer can be recognised as stale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct CommandId {
+    pub issuer: NodeId,
+    pub seq: u64,
+}
+
+impl fmt::Display for CommandId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.issuer, self.seq)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CameraCommand {
+    StartRecording,
+    StopRecording,
+    SetResolution(Resolution),
+    SetFps(u32),
+    SetBitrate {
+        kbps: u32,
+    },
+    /// Asks for a small still of what the camera sees right now.
+    Snapshot,
+    /// How different a part of the frame has to be, and how much of the frame has to differ, to count as motion.
+    /// See [`MotionConfig::threshold`] and [`MotionConfig::min_area`].
+    SetMotionSensitivity {
+        threshold: u8,
+        min_area: f64,
+    },
+}
+
+impl CameraCommand {
+    /// The setting the command changes, for telling which commands a newer one supersedes.
+    fn setting(&self) -> Option<&'static str> {
+        match self {
+            CameraCommand::StartRecording | CameraCommand::StopRecording => Some("recording"),
+            CameraCommand::SetResolution(_) => Some("resolution"),
+            CameraCommand::SetFps(_) => Some("fps"),
+            CameraCommand::SetBitrate { .. } => Some("bitrate"),
+            CameraCommand::Snapshot => None,
+            CameraCommand::SetMotionSensitivity { .. } => Some("motion"),
+        }
+    }
+}
+
+impl fmt::Display for CameraCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CameraCommand::StartRecording => f.write_str("start recording"),
+            CameraCommand::StopRecording => f.write_str("stop recording"),
+            CameraCommand::SetResolution(r) => write!(f, "set resolution to {r}"),
+            CameraCommand::SetFps(fps) => write!(f, "set frame rate to {fps} fps"),
+            CameraCommand::SetBitrate { kbps } => write!(f, "set bitrate to {kbps} kbps"),
+            CameraCommand::Snapshot => f.write_str("snapshot"),
+            CameraCommand::SetMotionSensitivity {
+                threshold,
+                min_area,
+            } => write!(
+                f,
+                "set motion sensitivity to {threshold} over {:.1}%",
+                min_area * 100.0
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error, Serialize, Deserialize)]
+pub enum CommandError {
+    #[error("not authorized to control this node")]
+    Unauthorized,
+    #[error("no such camera")]
+    UnknownCamera,
+    #[error("{0}")]
+    Invalid(String),
+    /// A newer command from the same issuer changing the same setting of the same camera was executed first.
+    #[error("superseded by a newer command")]
+    Superseded,
+    #[error("camera failed: {0}")]
+    Failed(String),
+}
+
+/// A still from a camera, as RGB rows.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub captured: Micros,
+    pub width: u32,
+    pub height: u32,
+    pub rgb: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Width snapshots are scaled down to, small enough for a single data channel message.
+    pub const WIDTH: u32 = 160;
+
+    /// `frame`, scaled down to [`Snapshot::WIDTH`].
+    pub fn of(frame: &Frame) -> Self {
+        let height = (Self::WIDTH * frame.height / frame.width.max(1)).max(1);
+        let frame = frame.resized(Self::WIDTH, height);
+        Snapshot {
+            captured: frame.captured,
+            width: frame.width,
+            height: frame.height,
+            rgb: frame.rgb,
+        }
+    }
+}
+
+/// What a successful command produced.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Reply {
+    Done,
+    Snapshot(Snapshot),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ControlMessage {
+    Command {
+        id: CommandId,
+        camera: ChannelId,
+        command: CameraCommand,
+    },
+    Ack {
+        id: CommandId,
+        result: Result<Reply, CommandError>,
+    },
+}
+
+impl ControlMessage {
+    pub fn encode(&self) -> Vec<u8> {
+        postcard::to_stdvec(self).expect("control messages always serialize")
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, postcard::Error> {
+        postcard::from_bytes(bytes)
+    }
+}
+
+/// What a camera node is told to do with one camera. The capture pipeline reads it and applies changes, for
+/// example by reconfiguring its encoder when [`CameraState::encoder`] changes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraState {
+    pub recording: bool,
+    pub encoder: EncoderSettings,
+    pub motion: MotionConfig,
+}
+
+impl CameraState {
+    pub fn new(encoder: EncoderSettings) -> Self {
+        CameraState {
+            recording: true,
+            encoder,
+            motion: MotionConfig::default(),
+        }
+    }
+
+    /// Applies a command that changes state. Snapshots leave it alone.
+    pub fn apply(&mut self, command: &CameraCommand) -> Result<(), CommandError> {
+        let invalid = |message: String| Err(CommandError::Invalid(message));
+        match *command {
+            CameraCommand::StartRecording => self.recording = true,
+            CameraCommand::StopRecording => self.recording = false,
+            CameraCommand::SetResolution(Resolution { width, height }) => {
+                // 4:2:0 chroma needs even sizes, and AV1 tops out well above any camera we use.
+                if !(16..=3840).contains(&width) || !(16..=2160).contains(&height) {
+                    return invalid(format!("{width}x{height} is out of range"));
+                }
+                if width % 2 != 0 || height % 2 != 0 {
+                    return invalid(format!("{width}x{height} is not even"));
+                }
+                self.encoder.width = width;
+                self.encoder.height = height;
+            }
+            CameraCommand::SetFps(fps) => {
+                if !(1..=60).contains(&fps) {
+                    return invalid(format!("{fps} fps is out of range"));
+                }
+                self.encoder.fps = fps;
+            }
+            CameraCommand::SetBitrate { kbps } => {
+                if !(16..=50_000).contains(&kbps) {
+                    return invalid(format!("{kbps} kbps is out of range"));
+                }
+                self.encoder.bitrate_kbps = kbps;
+            }
+            CameraCommand::Snapshot => {}
+            CameraCommand::SetMotionSensitivity {
+                threshold,
+                min_area,
+            } => {
+                if !(min_area > 0.0 && min_area < self.motion.max_area) {
+                    return invalid(format!(
+                        "motion area must be between 0 and {}",
+                        self.motion.max_area
+                    ));
+                }
+                self.motion.threshold = threshold;
+                self.motion.min_area = min_area;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The cameras of a camera node, as far as remote control is concerned.
+pub trait CameraControl {
+    fn execute(
+        &mut self,
+        camera: &ChannelId,
+        command: &CameraCommand,
+    ) -> Result<Reply, CommandError>;
+}
+
+/// How a command turned out, as far as one end knows.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CommandStatus {
+    /// Held back until the camera node can be reached.
+    Queued,
+    Pending {
+        attempts: u32,
+    },
+    Done,
+    Failed(CommandError),
+    /// The issuer gave up waiting for an acknowledgement. The command may still have been executed.
+    TimedOut,
+}
+
+impl CommandStatus {
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, CommandStatus::Queued | CommandStatus::Pending { .. })
+    }
+
+    fn of(result: &Result<Reply, CommandError>) -> Self {
+        match result {
+            Ok(_) => CommandStatus::Done,
+            Err(e) => CommandStatus::Failed(e.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub id: CommandId,
+    /// The camera node for the issuer's log, the issuer for the camera node's.
+    pub peer: NodeId,
+    pub camera: ChannelId,
+    pub command: CameraCommand,
+    /// Local time the command was issued or first received.
+    pub at: Micros,
+    pub finished: Option<Micros>,
+    pub status: CommandStatus,
+}
+
+impl From<&LogEntry> for QueuedCommand {
+    fn from(entry: &LogEntry) -> Self {
+        QueuedCommand {
+            target: entry.peer,
+            camera: entry.camera.clone(),
+            command: entry.command.clone(),
+            at: entry.at,
+        }
+    }
+}
+
+/// The most recent commands, oldest first.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandLog {
+    pub capacity: usize,
+    entries: VecDeque<LogEntry>,
+}
+
+impl Default for CommandLog {
+    fn default() -> Self {
+        CommandLog {
+            capacity: 256,
+            entries: VecDeque::new(),
+        }
+    }
+}
+
+impl CommandLog {
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &LogEntry> {
+        self.entries.iter()
+    }
+
+    pub fn get(&self, id: CommandId) -> Option<&LogEntry> {
+        self.entries.iter().find(|e| e.id == id)
+    }
+
+    fn push(&mut self, entry: LogEntry) {
+        while self.entries.len() >= self.capacity.max(1) {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    fn finish(&mut self, id: CommandId, status: CommandStatus, now: Micros) {
+        if let Some(entry) = self.entries.iter_mut().rev().find(|e| e.id == id) {
+            entry.status = status;
+            entry.finished = Some(now);
+        }
+    }
+}
+
+/// The camera node's end: executes commands once each and acknowledges every copy.
+#[derive(Debug, Clone, Default)]
+pub struct CommandHandler {
+    /// Who may send commands; the same policy as for live view is the usual choice.
+    pub access: LiveAccess,
+    log: CommandLog,
+    /// Acknowledgements of recent commands by sender, oldest first, to answer repeats with.
+    acks: VecDeque<((NodeId, CommandId), Result<Reply, CommandError>)>,
+    /// The newest sequence number executed per issuer, camera and setting.
+    latest: BTreeMap<(NodeId, ChannelId, &'static str), u64>,
+}
+
+impl CommandHandler {
+    pub fn new(access: LiveAccess) -> Self {
+        CommandHandler {
+            access,
+            ..Default::default()
+        }
+    }
+
+    pub fn log(&self) -> &CommandLog {
+        &self.log
+    }
+
+    /// Handles a message from `from`, returning the acknowledgement to send back, if any.
+    pub fn handle(
+        &mut self,
+        from: NodeId,
+        message: ControlMessage,
+        cameras: &mut dyn CameraControl,
+        now: Micros,
+    ) -> Option<ControlMessage> {
+        let ControlMessage::Command {
+            id,
+            camera,
+            command,
+        } = message
+        else {
+            return None;
+        };
+        // Whoever sends it, a command only counts as a repeat once the sender is known to be allowed to send it, so
+        // that nobody learns what others' commands did by replaying their ids.
+        let authorized = id.issuer == from && self.access.allows(from);
+        if authorized {
+            let cached = self.acks.iter().find(|(acked, _)| *acked == (from, id));
+            if let Some((_, result)) = cached {
+                return Some(ControlMessage::Ack {
+                    id,
+                    result: result.clone(),
+                });
+            }
+        }
+
+        let key = command.setting().map(|s| (from, camera.clone(), s));
+        let result = if !authorized {
+            Err(CommandError::Unauthorized)
+        } else if key
+            .as_ref()
+            .and_then(|k| self.latest.get(k))
+            .is_some_and(|&seq| seq > id.seq)
+        {
+            Err(CommandError::Superseded)
+        } else {
+            let result = cameras.execute(&camera, &command);
+            if let (Some(key), Ok(_)) = (key, &result) {
+                self.latest.insert(key, id.seq);
+            }
+            result
+        };
+        match &result {
+            Ok(_) => tracing::info!("{from} asked {camera} to {command}"),
+            Err(e) => tracing::info!("refused to {command} on {camera} for {from}: {e}"),
+        }
+
+        self.log.push(LogEntry {
+            id,
+            peer: from,
+            camera,
+            command,
+            at: now,
+            finished: Some(now),
+            status: CommandStatus::of(&result),
+        });
+        // Acknowledgements are only needed while the issuer may still be retrying, which is a lot less than the log
+        // covers; snapshots make them too big to keep as long.
+        if authorized {
+            if self.acks.len() >= 64 {
+                self.acks.pop_front();
+            }
+            self.acks.push_back(((from, id), result.clone()));
+        }
+        Some(ControlMessage::Ack { id, result })
+    }
+}
+
+struct Pending {
+    target: NodeId,
+    message: ControlMessage,
+    /// When the command was first sent, which is when the client starts waiting for it.
+    since: Micros,
+    sent: Micros,
+    attempts: u32,
+}
+
+/// A command waiting for its camera node to be reachable, in a form that can be kept across restarts of the app.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueuedCommand {
+    pub target: NodeId,
+    pub camera: ChannelId,
+    pub command: CameraCommand,
+    /// Local time it was issued.
+    pub at: Micros,
+}
+
+/// The issuing end: numbers commands, resends them until they are acknowledged and records how they went.
+pub struct CommandClient {
+    pub node: NodeId,
+    /// Time to wait for an acknowledgement before sending a command again.
+    pub retry: Micros,
+    /// Time after which a command that was sent but never acknowledged is given up on. Time spent queued does not
+    /// count.
+    pub give_up: Micros,
+    next_seq: u64,
+    pending: BTreeMap<CommandId, Pending>,
+    /// Commands for unreachable nodes, in the order they were issued.
+    queued: BTreeMap<CommandId, QueuedCommand>,
+    unreachable: BTreeSet<NodeId>,
+    log: CommandLog,
+}
+
+impl CommandClient {
+    /// `first_seq` should be higher than any sequence number `node` has used before, such as the current time in
+    /// microseconds, or camera nodes will take new commands for repeats of old ones.
+    pub fn new(node: NodeId, first_seq: u64) -> Self {
+        CommandClient {
+            node,
+            retry: 1_000_000,
+            give_up: 15_000_000,
+            next_seq: first_seq,
+            pending: BTreeMap::new(),
+            queued: BTreeMap::new(),
+            unreachable: BTreeSet::new(),
+            log: CommandLog::default(),
+        }
+    }
+
+    pub fn log(&self) -> &CommandLog {
+        &self.log
+    }
+
+    pub fn status(&self, id: CommandId) -> Option<&CommandStatus> {
+        self.log.get(id).map(|e| &e.status)
+    }
+
+    /// Issues a command, returning its id and the message to send to `target`. While `target` cannot be reached the
+    /// command is queued instead, and there is nothing to send.
+    pub fn issue(
+        &mut self,
+        target: NodeId,
+        camera: ChannelId,
+        command: CameraCommand,
+        now: Micros,
+    ) -> (CommandId, Option<ControlMessage>) {
+        let id = self.next_id();
+        self.log.push(LogEntry {
+            id,
+            peer: target,
+            camera: camera.clone(),
+            command: command.clone(),
+            at: now,
+            finished: None,
+            status: CommandStatus::Queued,
+        });
+        let queued = QueuedCommand {
+            target,
+            camera,
+            command,
+            at: now,
+        };
+        if self.unreachable.contains(&target) {
+            self.enqueue(id, queued, now);
+            return (id, None);
+        }
+        (id, Some(self.send(id, queued, now)))
+    }
+
+    /// Commands still waiting for their camera node to be reachable, oldest first.
+    pub fn queued(&self) -> Vec<QueuedCommand> {
+        self.queued.values().cloned().collect()
+    }
+
+    /// Queues commands kept from an earlier run of the app. They are numbered afresh, since none of them was sent.
+    pub fn restore(&mut self, queued: Vec<QueuedCommand>, now: Micros) {
+        for command in queued {
+            let id = self.next_id();
+            self.log.push(LogEntry {
+                id,
+                peer: command.target,
+                camera: command.camera.clone(),
+                command: command.command.clone(),
+                at: command.at,
+                finished: None,
+                status: CommandStatus::Queued,
+            });
+            self.enqueue(id, command, now);
+        }
+    }
+
+    /// Records whether `target` can be reached. Commands waiting on a node that was lost go back in the queue, so
+    /// they are not given up on while it is away, and queued commands for reachable nodes are returned for sending.
+    pub fn set_reachable(
+        &mut self,
+        target: NodeId,
+        reachable: bool,
+        now: Micros,
+    ) -> Vec<(NodeId, ControlMessage)> {
+        if !reachable {
+            if self.unreachable.insert(target) {
+                tracing::info!("queueing commands for {target} until it is back");
+                let waiting: Vec<CommandId> = self
+                    .pending
+                    .iter()
+                    .filter(|(_, pending)| pending.target == target)
+                    .map(|(id, _)| *id)
+                    .collect();
+                for id in waiting {
+                    self.pending.remove(&id);
+                    if let Some(queued) = self.log.get(id).map(QueuedCommand::from) {
+                        self.enqueue(id, queued, now);
+                    }
+                }
+            }
+            return Vec::new();
+        }
+        self.unreachable.remove(&target);
+        let ready: Vec<CommandId> = self
+            .queued
+            .iter()
+            .filter(|(_, queued)| !self.unreachable.contains(&queued.target))
+            .map(|(id, _)| *id)
+            .collect();
+        ready
+            .into_iter()
+            .filter_map(|id| {
+                let queued = self.queued.remove(&id)?;
+                let target = queued.target;
+                Some((target, self.send(id, queued, now)))
+            })
+            .collect()
+    }
+
+    fn next_id(&mut self) -> CommandId {
+        let id = CommandId {
+            issuer: self.node,
+            seq: self.next_seq,
+        };
+        self.next_seq += 1;
+        id
+    }
+
+    /// Holds a command back. A newer command for the same setting of the same camera replaces any still queued, since
+    /// the camera node would only refuse the older one as superseded.
+    fn enqueue(&mut self, id: CommandId, queued: QueuedCommand, now: Micros) {
+        if let Some(setting) = queued.command.setting() {
+            let superseded: Vec<CommandId> = self
+                .queued
+                .iter()
+                .filter(|(older, q)| {
+                    **older < id
+                        && q.target == queued.target
+                        && q.camera == queued.camera
+                        && q.command.setting() == Some(setting)
+                })
+                .map(|(older, _)| *older)
+                .collect();
+            for older in superseded {
+                self.queued.remove(&older);
+                self.log
+                    .finish(older, CommandStatus::Failed(CommandError::Superseded), now);
+            }
+        }
+        if let Some(entry) = self.log.entries.iter_mut().rev().find(|e| e.id == id) {
+            entry.status = CommandStatus::Queued;
+        }
+        self.queued.insert(id, queued);
+    }
+
+    fn send(&mut self, id: CommandId, queued: QueuedCommand, now: Micros) -> ControlMessage {
+        let message = ControlMessage::Command {
+            id,
+            camera: queued.camera,
+            command: queued.command,
+        };
+        if let Some(entry) = self.log.entries.iter_mut().rev().find(|e| e.id == id) {
+            entry.status = CommandStatus::Pending { attempts: 1 };
+        }
+        self.pending.insert(
+            id,
+            Pending {
+                target: queued.target,
+                message: message.clone(),
+                since: now,
+                sent: now,
+                attempts: 1,
+            },
+        );
+        message
+    }
+
+    /// Handles a message from a camera node. Returns the command's outcome when it is the first acknowledgement of
+    /// a command still waiting for one.
+    pub fn handle(
+        &mut self,
+        message: ControlMessage,
+        now: Micros,
+    ) -> Option<(CommandId, Result<Reply, CommandError>)> {
+        let ControlMessage::Ack { id, result } = message else {
+            return None;
+        };
+        self.pending.remove(&id)?;
+        self.log.finish(id, CommandStatus::of(&result), now);
+        Some((id, result))
+    }
+
+    /// Commands due to be sent again, with the node to send each to. Gives up on those that have waited too long.
+    pub fn retries(&mut self, now: Micros) -> Vec<(NodeId, ControlMessage)> {
+        let mut resend = Vec::new();
+        let mut expired = Vec::new();
+        for (id, pending) in self.pending.iter_mut() {
+            if now - pending.since >= self.give_up {
+                expired.push(*id);
+            } else if now - pending.sent >= self.retry {
+                pending.sent = now;
+                pending.attempts += 1;
+                resend.push((pending.target, pending.message.clone()));
+                if let Some(entry) = self.log.entries.iter_mut().rev().find(|e| e.id == *id) {
+                    entry.status = CommandStatus::Pending {
+                        attempts: pending.attempts,
+                    };
+                }
+            }
+        }
+        for id in expired {
+            tracing::warn!("gave up on command {id}");
+            self.pending.remove(&id);
+            self.log.finish(id, CommandStatus::TimedOut, now);
+        }
+        resend
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sim::SimulatedCameraNode;
+    use super::*;
+
+    const ISSUER: NodeId = NodeId(1);
+    const OTHER: NodeId = NodeId(2);
+    const CAMERA_NODE: NodeId = NodeId(3);
+
+    /// Cameras that do whatever they are told, remembering what that was.
+    #[derive(Default)]
+    struct Obliging {
+        executed: Vec<CameraCommand>,
+    }
+
+    impl CameraControl for Obliging {
+        fn execute(
+            &mut self,
+            _camera: &ChannelId,
+            command: &CameraCommand,
+        ) -> Result<Reply, CommandError> {
+            self.executed.push(command.clone());
+            Ok(Reply::Done)
+        }
+    }
+
+    fn command(issuer: NodeId, seq: u64, command: CameraCommand) -> ControlMessage {
+        ControlMessage::Command {
+            id: CommandId { issuer, seq },
+            camera: ChannelId::new("camera0"),
+            command,
+        }
+    }
+
+    fn result(ack: Option<ControlMessage>) -> Result<Reply, CommandError> {
+        match ack {
+            Some(ControlMessage::Ack { result, .. }) => result,
+            other => panic!("expected an acknowledgement, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn repeats_are_executed_once_and_answered_like_the_first() {
+        let mut handler = CommandHandler::new(LiveAccess::Anyone);
+        let mut cameras = Obliging::default();
+        let message = command(ISSUER, 7, CameraCommand::StopRecording);
+        let first = handler.handle(ISSUER, message.clone(), &mut cameras, 0);
+        let again = handler.handle(ISSUER, message, &mut cameras, 1);
+        assert_eq!(first, again);
+        assert_eq!(result(first), Ok(Reply::Done));
+        assert_eq!(cameras.executed, vec![CameraCommand::StopRecording]);
+        assert_eq!(handler.log().entries().count(), 1);
+    }
+
+    #[test]
+    fn senders_are_authorized_before_repeats_are_answered() {
+        let mut handler = CommandHandler::new(LiveAccess::Nodes([ISSUER].into()));
+        let mut cameras = Obliging::default();
+        let message = command(ISSUER, 1, CameraCommand::Snapshot);
+        assert_eq!(
+            result(handler.handle(ISSUER, message.clone(), &mut cameras, 0)),
+            Ok(Reply::Done)
+        );
+
+        // Replaying someone else's id gets nowhere near their acknowledgement.
+        assert_eq!(
+            result(handler.handle(OTHER, message, &mut cameras, 1)),
+            Err(CommandError::Unauthorized)
+        );
+        // Nor is a refusal remembered, so a sender let in later has its command carried out.
+        let own = command(OTHER, 1, CameraCommand::StartRecording);
+        assert_eq!(
+            result(handler.handle(OTHER, own.clone(), &mut cameras, 2)),
+            Err(CommandError::Unauthorized)
+        );
+        handler.access = LiveAccess::Anyone;
+        assert_eq!(
+            result(handler.handle(OTHER, own, &mut cameras, 3)),
+            Ok(Reply::Done)
+        );
+        assert_eq!(
+            cameras.executed,
+            vec![CameraCommand::Snapshot, CameraCommand::StartRecording]
+        );
+    }
+
+    #[test]
+    fn a_late_command_does_not_undo_a_newer_one() {
+        let mut handler = CommandHandler::new(LiveAccess::Anyone);
+        let mut cameras = Obliging::default();
+        handler.handle(
+            ISSUER,
+            command(ISSUER, 2, CameraCommand::SetFps(10)),
+            &mut cameras,
+            0,
+        );
+        let late = handler.handle(
+            ISSUER,
+            command(ISSUER, 1, CameraCommand::SetFps(20)),
+            &mut cameras,
+            1,
+        );
+        assert_eq!(result(late), Err(CommandError::Superseded));
+        // Other settings are not held up by it.
+        let other = command(ISSUER, 0, CameraCommand::SetBitrate { kbps: 200 });
+        assert_eq!(
+            result(handler.handle(ISSUER, other, &mut cameras, 2)),
+            Ok(Reply::Done)
+        );
+        assert_eq!(
+            cameras.executed,
+            vec![
+                CameraCommand::SetFps(10),
+                CameraCommand::SetBitrate { kbps: 200 }
+            ]
+        );
+    }
+
+    #[test]
+    fn commands_wait_for_their_node_and_then_get_through_a_lossy_link() {
+        let camera = ChannelId::new("camera0");
+        let mut node = SimulatedCameraNode::new(CAMERA_NODE, [camera.clone()], 0.5);
+        let mut client = CommandClient::new(ISSUER, 100);
+        client.set_reachable(CAMERA_NODE, false, 0);
+        let (fps, sent) = client.issue(CAMERA_NODE, camera.clone(), CameraCommand::SetFps(5), 0);
+        assert_eq!(sent, None);
+        // A newer command for the same setting replaces the one still waiting.
+        let (kept, _) = client.issue(CAMERA_NODE, camera.clone(), CameraCommand::SetFps(8), 0);
+        assert_eq!(client.queued().len(), 1);
+        assert_eq!(
+            client.status(fps),
+            Some(&CommandStatus::Failed(CommandError::Superseded))
+        );
+
+        let mut outgoing = client.set_reachable(CAMERA_NODE, true, 1_000_000);
+        assert_eq!(outgoing.len(), 1);
+        let mut now = 1_000_000;
+        while !client.status(kept).unwrap().is_finished() {
+            for (target, message) in outgoing.drain(..) {
+                assert_eq!(target, CAMERA_NODE);
+                if let Some(ack) = node.deliver(ISSUER, message, now) {
+                    client.handle(ack, now);
+                }
+            }
+            now += client.retry;
+            outgoing = client.retries(now);
+        }
+        assert_eq!(client.status(kept), Some(&CommandStatus::Done));
+        assert_eq!(node.cameras.state(&camera).unwrap().encoder.fps, 8);
+        assert_eq!(node.handler.log().entries().count(), 1);
+    }
+
+    #[test]
+    fn commands_that_are_never_answered_are_given_up_on() {
+        let mut client = CommandClient::new(ISSUER, 100);
+        let camera = ChannelId::new("camera0");
+        let (id, sent) = client.issue(CAMERA_NODE, camera, CameraCommand::StopRecording, 0);
+        assert!(sent.is_some());
+        assert_eq!(client.retries(client.retry).len(), 1);
+        assert_eq!(
+            client.status(id),
+            Some(&CommandStatus::Pending { attempts: 2 })
+        );
+        assert!(client.retries(client.give_up).is_empty());
+        assert_eq!(client.status(id), Some(&CommandStatus::TimedOut));
+    }
+}
@@ -0,0 +1,111 @@
+//! A simulated camera node for exercising remote control without cameras or a network.
+
+use std::collections::BTreeMap;
+
+use crate::camera::{CameraSource, FormatRequest, Frame, TestPattern};
+use crate::encoder::EncoderSettings;
+use crate::live::LiveAccess;
+use crate::node::NodeId;
+use crate::sensors::{ChannelId, Micros};
+use crate::time_sync::local_now;
+
+use super::{
+    CameraCommand, CameraControl, CameraState, CommandError, CommandHandler, ControlMessage, Reply,
+    Snapshot,
+};
+
+/// Test pattern cameras that keep the state commands give them.
+pub struct SimulatedCameras {
+    cameras: BTreeMap<ChannelId, (CameraState, TestPattern)>,
+}
+
+impl SimulatedCameras {
+    pub fn new(cameras: impl IntoIterator<Item = ChannelId>) -> Self {
+        let cameras = cameras
+            .into_iter()
+            .map(|camera| {
+                let pattern = TestPattern::open(FormatRequest::Any)
+                    .expect("the test pattern takes any format");
+                let settings = EncoderSettings::default();
+                (camera, (CameraState::new(settings), pattern))
+            })
+            .collect();
+        SimulatedCameras { cameras }
+    }
+
+    pub fn state(&self, camera: &ChannelId) -> Option<&CameraState> {
+        self.cameras.get(camera).map(|(state, _)| state)
+    }
+}
+
+impl CameraControl for SimulatedCameras {
+    fn execute(
+        &mut self,
+        camera: &ChannelId,
+        command: &CameraCommand,
+    ) -> Result<Reply, CommandError> {
+        let (state, pattern) = self
+            .cameras
+            .get_mut(camera)
+            .ok_or(CommandError::UnknownCamera)?;
+        state.apply(command)?;
+        if *command != CameraCommand::Snapshot {
+            return Ok(Reply::Done);
+        }
+        let now = local_now();
+        let format = pattern.format();
+        let period = 1_000_000 / format.fps.max(1) as Micros;
+        let frame = Frame {
+            captured: now,
+            width: format.resolution.width,
+            height: format.resolution.height,
+            rgb: pattern.render((now / period) as u64),
+        };
+        Ok(Reply::Snapshot(Snapshot::of(&frame)))
+    }
+}
+
+/// A camera node behind a link that loses a fraction of the messages in each direction, so that retries and
+/// duplicate deliveries happen.
+pub struct SimulatedCameraNode {
+    pub node: NodeId,
+    pub cameras: SimulatedCameras,
+    pub handler: CommandHandler,
+    /// Chance of losing each message, `0.0..1.0`.
+    pub loss: f64,
+    rng: u64,
+}
+
+impl SimulatedCameraNode {
+    pub fn new(node: NodeId, cameras: impl IntoIterator<Item = ChannelId>, loss: f64) -> Self {
+        SimulatedCameraNode {
+            node,
+            cameras: SimulatedCameras::new(cameras),
+            handler: CommandHandler::new(LiveAccess::Anyone),
+            loss,
+            rng: (0x2545_f491_4f6c_dd1d ^ node.0) | 1,
+        }
+    }
+
+    /// Delivers a message from `from`, returning the reply if neither it nor the reply was lost.
+    pub fn deliver(
+        &mut self,
+        from: NodeId,
+        message: ControlMessage,
+        now: Micros,
+    ) -> Option<ControlMessage> {
+        if self.lost() {
+            return None;
+        }
+        let reply = self.handler.handle(from, message, &mut self.cameras, now)?;
+        (!self.lost()).then_some(reply)
+    }
+
+    fn lost(&mut self) -> bool {
+        // xorshift64, which is plenty for deciding which messages to drop.
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        ((self.rng >> 11) as f64 / (1u64 << 53) as f64) < self.loss
+    }
+}
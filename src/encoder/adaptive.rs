@@ -0,0 +1,384 @@
+//! Adapting video quality to the link.
+//!
+//! Cameras sit on metered cellular links whose capacity changes with the weather and time of day. The
+//! [`BitrateController`] moves up and down a ladder of [`QualityStep`]s: it steps down as soon as the link shows
+//! congestion (loss or a growing round trip time) and steps up only after conditions have been good for a while. A
+//! step up that congests the link again makes the next attempt wait longer, so quality settles instead of
+//! oscillating around the link's capacity. A metered byte budget caps the ladder independently of link conditions.
+
+use serde::{Deserialize, Serialize};
+
+use super::EncoderSettings;
+use crate::sensors::Micros;
+
+/// One rung of the quality ladder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QualityStep {
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    pub bitrate_kbps: u32,
+}
+
+impl QualityStep {
+    /// Encoder settings for this step, keeping everything else from `base`.
+    pub fn apply(&self, base: &EncoderSettings) -> EncoderSettings {
+        EncoderSettings {
+            width: self.width,
+            height: self.height,
+            fps: self.fps,
+            bitrate_kbps: self.bitrate_kbps,
+            ..base.clone()
+        }
+    }
+}
+
+/// What the transport observed about the link recently.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LinkStats {
+    pub rtt: Micros,
+    /// Fraction of packets lost, `0.0..=1.0`.
+    pub loss: f64,
+}
+
+/// What is left of a metered data allowance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ByteBudget {
+    pub remaining: u64,
+    /// When the allowance renews.
+    pub period_end: Micros,
+}
+
+impl ByteBudget {
+    /// The bitrate that would use up the remaining bytes exactly at the end of the period.
+    pub fn sustainable_kbps(&self, now: Micros) -> f64 {
+        let seconds = ((self.period_end - now) as f64 / 1e6).max(1.0);
+        self.remaining as f64 * 8.0 / seconds / 1000.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdaptiveConfig {
+    /// From lowest to highest quality.
+    pub ladder: Vec<QualityStep>,
+    /// Index into the ladder to start at.
+    pub start: usize,
+    /// Loss above which the controller steps down.
+    pub loss_down: f64,
+    /// Loss below which the link counts as good. The gap to `loss_down` is the hysteresis band.
+    pub loss_up: f64,
+    pub rtt_down: Micros,
+    pub rtt_up: Micros,
+    /// How long the link has to stay good before stepping up.
+    pub up_after: Micros,
+    /// Minimum time between two steps down, so the link gets a chance to drain after the first.
+    pub down_interval: Micros,
+    /// Fraction of the budget's sustainable bitrate video may use; the rest is left for sensor data.
+    pub budget_share: f64,
+}
+
+impl Default for AdaptiveConfig {
+    /// A ladder topping out at the 500 kbps, 15 fps target for cellular cameras.
+    fn default() -> Self {
+        let step = |width, height, fps, bitrate_kbps| QualityStep {
+            width,
+            height,
+            fps,
+            bitrate_kbps,
+        };
+        AdaptiveConfig {
+            ladder: vec![
+                step(160, 120, 5, 40),
+                step(320, 240, 5, 80),
+                step(320, 240, 10, 150),
+                step(320, 240, 15, 250),
+                step(640, 480, 15, 500),
+            ],
+            start: 3,
+            loss_down: 0.05,
+            loss_up: 0.01,
+            rtt_down: 800_000,
+            rtt_up: 300_000,
+            up_after: 20_000_000,
+            down_interval: 2_000_000,
+            budget_share: 0.8,
+        }
+    }
+}
+
+/// Why the controller changed steps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Change {
+    Congestion,
+    Budget,
+    Probe,
+}
+
+#[derive(Debug, Clone)]
+pub struct BitrateController {
+    pub config: AdaptiveConfig,
+    level: usize,
+    good_since: Option<Micros>,
+    last_down: Option<Micros>,
+    last_up: Option<Micros>,
+    /// Consecutive steps up that were followed by congestion. Each one doubles the wait before the next.
+    failed_probes: u32,
+}
+
+impl BitrateController {
+    const MAX_BACKOFF: u32 = 4;
+
+    pub fn new(config: AdaptiveConfig) -> Self {
+        assert!(
+            !config.ladder.is_empty(),
+            "the quality ladder needs at least one step"
+        );
+        BitrateController {
+            level: config.start.min(config.ladder.len() - 1),
+            config,
+            good_since: None,
+            last_down: None,
+            last_up: None,
+            failed_probes: 0,
+        }
+    }
+
+    pub fn current(&self) -> QualityStep {
+        self.config.ladder[self.level]
+    }
+
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    /// How long the link currently has to stay good before a step up.
+    pub fn up_wait(&self) -> Micros {
+        self.config.up_after << self.failed_probes
+    }
+
+    /// Feeds the latest link statistics. Returns the new step and the reason when it changes.
+    pub fn update(
+        &mut self,
+        now: Micros,
+        link: LinkStats,
+        budget: Option<ByteBudget>,
+    ) -> Option<(QualityStep, Change)> {
+        let config = &self.config;
+        let ceiling = match budget {
+            Some(budget) => {
+                let allowed = budget.sustainable_kbps(now) * config.budget_share;
+                config
+                    .ladder
+                    .iter()
+                    .rposition(|s| s.bitrate_kbps as f64 <= allowed)
+                    .unwrap_or(0)
+            }
+            None => config.ladder.len() - 1,
+        };
+        if self.level > ceiling {
+            self.level = ceiling;
+            self.good_since = None;
+            return Some((self.current(), Change::Budget));
+        }
+
+        let congested = link.loss > config.loss_down || link.rtt > config.rtt_down;
+        let good = link.loss < config.loss_up && link.rtt < config.rtt_up;
+        if congested {
+            self.good_since = None;
+            let recently = self
+                .last_down
+                .is_some_and(|t| now - t < config.down_interval);
+            if self.level == 0 || recently {
+                return None;
+            }
+            // Congestion soon after probing upwards means the probe failed. Only the first step down counts.
+            if self
+                .last_up
+                .take()
+                .is_some_and(|t| now - t < 2 * self.up_wait())
+            {
+                self.failed_probes = (self.failed_probes + 1).min(Self::MAX_BACKOFF);
+            }
+            self.level -= 1;
+            self.last_down = Some(now);
+            return Some((self.current(), Change::Congestion));
+        }
+        // Between the thresholds the link is neither good nor bad. A lone lost packet lands here on a slow link, so
+        // it does not restart the wait for a step up either.
+        if !good {
+            return None;
+        }
+
+        let since = *self.good_since.get_or_insert(now);
+        if self.level >= ceiling || now - since < self.up_wait() {
+            return None;
+        }
+        // A probe that held for a full wait period means the link has recovered; forget earlier failures.
+        if self.last_up.is_some_and(|t| now - t >= 2 * self.up_wait()) {
+            self.failed_probes = self.failed_probes.saturating_sub(1);
+        }
+        self.level += 1;
+        self.last_up = Some(now);
+        self.good_since = Some(now);
+        Some((self.current(), Change::Probe))
+    }
+}
+
+/// Drops frames to bring a camera's frame rate down to a step's.
+#[derive(Debug, Clone, Default)]
+pub struct FrameRateLimiter {
+    next: Option<Micros>,
+}
+
+impl FrameRateLimiter {
+    /// Whether a frame captured at `captured` should be encoded at `fps`.
+    pub fn accept(&mut self, captured: Micros, fps: u32) -> bool {
+        let period = 1_000_000 / fps.max(1) as Micros;
+        match self.next {
+            // Allow a little jitter so a 15 fps camera is not decimated to 7.5 fps when asked for 15.
+            Some(next) if captured < next - period / 4 => false,
+            _ => {
+                self.next = Some(self.next.map_or(captured, |n| n.max(captured - period)) + period);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::sim::{simulate, SimulatedLink};
+
+    const SECOND: Micros = 1_000_000;
+
+    fn good() -> LinkStats {
+        LinkStats {
+            rtt: 150_000,
+            loss: 0.0,
+        }
+    }
+
+    fn congested() -> LinkStats {
+        LinkStats {
+            rtt: 150_000,
+            loss: 0.2,
+        }
+    }
+
+    #[test]
+    fn quality_follows_the_link_capacity_down_and_back_up() {
+        let mut controller = BitrateController::new(AdaptiveConfig::default());
+        let mut link = SimulatedLink::new(800.0)
+            .set_capacity(60 * SECOND, 200.0)
+            .set_capacity(600 * SECOND, 800.0);
+        let trace = simulate(&mut controller, &mut link, None, 0, 1500 * SECOND, SECOND);
+        let at = |t: Micros| trace.iter().rfind(|p| p.time <= t).unwrap().step;
+
+        assert_eq!(at(55 * SECOND).bitrate_kbps, 500, "probes up to the top");
+        assert_eq!(
+            at(590 * SECOND).bitrate_kbps,
+            150,
+            "settles under the narrow link"
+        );
+        assert_eq!(
+            at(1500 * SECOND).bitrate_kbps,
+            500,
+            "recovers with the link"
+        );
+
+        // While the link is narrow it keeps probing, but failed probes make each attempt wait longer.
+        let probes: Vec<_> = trace
+            .iter()
+            .filter(|p| (60 * SECOND..600 * SECOND).contains(&p.time))
+            .filter(|p| p.change == Some(Change::Probe))
+            .map(|p| p.time)
+            .collect();
+        assert!(probes.len() >= 3, "{probes:?}");
+        assert!(
+            probes.windows(3).all(|w| w[2] - w[1] > w[1] - w[0]),
+            "{probes:?}"
+        );
+    }
+
+    #[test]
+    fn losses_inside_the_hysteresis_band_change_nothing() {
+        let mut controller = BitrateController::new(AdaptiveConfig::default());
+        let middling = LinkStats {
+            rtt: 150_000,
+            loss: 0.03,
+        };
+        for t in 0..120 {
+            assert_eq!(controller.update(t * SECOND, middling, None), None);
+        }
+        assert_eq!(controller.level(), 3);
+    }
+
+    #[test]
+    fn steps_down_are_spaced_and_steps_up_wait_for_a_good_link() {
+        let config = AdaptiveConfig::default();
+        let mut controller = BitrateController::new(config.clone());
+        assert_eq!(
+            controller.update(0, congested(), None),
+            Some((config.ladder[2], Change::Congestion))
+        );
+        assert_eq!(controller.update(SECOND, congested(), None), None);
+        assert_eq!(controller.level(), 2);
+        assert!(controller.update(2 * SECOND, congested(), None).is_some());
+        assert_eq!(controller.level(), 1);
+
+        assert_eq!(controller.update(3 * SECOND, good(), None), None);
+        assert_eq!(controller.update(22 * SECOND, good(), None), None);
+        assert_eq!(
+            controller.update(23 * SECOND, good(), None),
+            Some((config.ladder[2], Change::Probe))
+        );
+        // Congested right after the probe: it failed, and the next one waits twice as long.
+        assert!(controller.update(24 * SECOND, congested(), None).is_some());
+        assert_eq!(controller.up_wait(), 2 * config.up_after);
+    }
+
+    #[test]
+    fn the_byte_budget_caps_quality_whatever_the_link() {
+        let mut controller = BitrateController::new(AdaptiveConfig::default());
+        // 100 MB left for a day is about 9 kbps, too little for anything but the bottom step.
+        let budget = ByteBudget {
+            remaining: 100_000_000,
+            period_end: 86_400 * SECOND,
+        };
+        assert_eq!(
+            controller.update(0, good(), Some(budget)),
+            Some((controller.config.ladder[0], Change::Budget))
+        );
+        for t in 1..100 {
+            assert_eq!(controller.update(t * SECOND, good(), Some(budget)), None);
+        }
+        // A fresh allowance lifts the cap, and good conditions climb to the top again.
+        let budget = ByteBudget {
+            remaining: 10_000_000_000,
+            ..budget
+        };
+        let mut t = 100;
+        while controller.level() < 4 {
+            controller.update(t * SECOND, good(), Some(budget));
+            t += 1;
+            assert!(t < 300, "stuck at level {}", controller.level());
+        }
+    }
+
+    #[test]
+    fn the_frame_rate_limiter_decimates_evenly() {
+        let mut limiter = FrameRateLimiter::default();
+        let kept = (0..150)
+            .filter(|i| limiter.accept(i * SECOND / 15, 5))
+            .count();
+        assert_eq!(kept, 50);
+
+        let mut limiter = FrameRateLimiter::default();
+        // A camera running slightly fast or with jittery timestamps is not halved.
+        let kept = (0..150)
+            .filter(|i| limiter.accept(i * SECOND / 15 + (i % 3) * 5_000, 15))
+            .count();
+        assert_eq!(kept, 150);
+    }
+}
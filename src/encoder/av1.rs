@@ -0,0 +1,253 @@
+//! The rav1e backed encoder.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use rav1e::prelude::{
+    ChromaSampling, Config, Context, EncoderConfig, EncoderStatus, FrameParameters, FrameType,
+    FrameTypeOverride, Opaque, Rational, SpeedSettings,
+};
+
+use super::{
+    rgb_to_yuv420, Capabilities, EncoderBackend, EncoderError, EncoderSettings, Packet, VideoCodec,
+    VideoEncoder,
+};
+use crate::camera::{Frame, Resolution};
+use crate::sensors::Micros;
+
+pub struct Av1Encoder {
+    settings: EncoderSettings,
+    ctx: Context<u8>,
+    frames_sent: u64,
+    force_keyframe: bool,
+    flushed: bool,
+    /// Packets produced while making room for a frame, handed out with the next batch.
+    ready: VecDeque<Packet>,
+}
+
+impl Av1Encoder {
+    pub fn new(settings: EncoderSettings) -> Result<Self, EncoderError> {
+        let ctx = rav1e_config(&settings)
+            .new_context()
+            .map_err(|e| EncoderError::Config(e.to_string()))?;
+        Ok(Av1Encoder {
+            settings,
+            ctx,
+            frames_sent: 0,
+            force_keyframe: false,
+            flushed: false,
+            ready: VecDeque::new(),
+        })
+    }
+
+    pub fn settings(&self) -> &EncoderSettings {
+        &self.settings
+    }
+
+    /// The AV1 sequence header in ISOBMFF `av1C` form, for containers that carry it out of band.
+    pub fn sequence_header(&self) -> Vec<u8> {
+        self.ctx.container_sequence_header()
+    }
+
+    /// Makes the next frame sent a keyframe, e.g. when a viewer joins a live stream.
+    pub fn force_keyframe(&mut self) {
+        self.force_keyframe = true;
+    }
+
+    /// Encodes one frame and returns whatever packets are ready, which are for earlier frames while the lookahead
+    /// fills up.
+    pub fn encode(&mut self, frame: &Frame) -> Result<Vec<Packet>, EncoderError> {
+        if self.flushed {
+            return Err(EncoderError::Finished);
+        }
+        if (frame.width, frame.height) != (self.settings.width, self.settings.height) {
+            return Err(EncoderError::FrameSize {
+                width: self.settings.width,
+                height: self.settings.height,
+                got_width: frame.width,
+                got_height: frame.height,
+            });
+        }
+
+        let yuv = rgb_to_yuv420(&frame.rgb, frame.width as usize, frame.height as usize);
+        let mut input = self.ctx.new_frame();
+        let strides = [yuv.width, yuv.chroma_width(), yuv.chroma_width()];
+        for ((plane, data), stride) in input
+            .planes
+            .iter_mut()
+            .zip([&yuv.y, &yuv.u, &yuv.v])
+            .zip(strides)
+        {
+            plane.copy_from_raw_u8(data, stride, 1);
+            plane.pad(yuv.width, yuv.height);
+        }
+        let input = Arc::new(input);
+        let keyframe = std::mem::take(&mut self.force_keyframe);
+
+        loop {
+            let params = FrameParameters {
+                frame_type_override: if keyframe {
+                    FrameTypeOverride::Key
+                } else {
+                    FrameTypeOverride::No
+                },
+                opaque: Some(Opaque::new(frame.captured)),
+                ..Default::default()
+            };
+            match self.ctx.send_frame((input.clone(), params)) {
+                Ok(()) => break,
+                // The lookahead queue is full: take packets out and try again.
+                Err(EncoderStatus::EnoughData) => {
+                    let drained = self.drain()?;
+                    if drained.is_empty() {
+                        return Err(EncoderError::Failure);
+                    }
+                    self.ready.extend(drained);
+                }
+                Err(_) => return Err(EncoderError::Failure),
+            }
+        }
+        self.frames_sent += 1;
+
+        let mut packets: Vec<Packet> = self.ready.drain(..).collect();
+        packets.extend(self.drain()?);
+        Ok(packets)
+    }
+
+    /// Ends the stream and returns the packets for every frame still inside the encoder.
+    pub fn flush(&mut self) -> Result<Vec<Packet>, EncoderError> {
+        if self.flushed {
+            return Ok(Vec::new());
+        }
+        self.ctx.flush();
+        self.flushed = true;
+        let mut packets: Vec<Packet> = self.ready.drain(..).collect();
+        packets.extend(self.drain()?);
+        Ok(packets)
+    }
+
+    /// Switches to new settings, e.g. a lower bitrate or resolution. rav1e cannot change either mid stream, so this
+    /// flushes the current stream, returning its last packets, and starts a new one; the first frame encoded after
+    /// it is a keyframe and frame numbers start over from zero.
+    pub fn reconfigure(&mut self, settings: EncoderSettings) -> Result<Vec<Packet>, EncoderError> {
+        let next = Av1Encoder::new(settings)?;
+        let packets = self.flush()?;
+        *self = next;
+        Ok(packets)
+    }
+
+    /// Receives packets until the encoder needs more input (or, once flushed, until it has none left).
+    fn drain(&mut self) -> Result<Vec<Packet>, EncoderError> {
+        let mut packets = Vec::new();
+        loop {
+            match self.ctx.receive_packet() {
+                Ok(packet) => {
+                    let captured = packet
+                        .opaque
+                        .and_then(|o| o.downcast::<Micros>().ok())
+                        .map_or(0, |captured| *captured);
+                    packets.push(Packet {
+                        captured,
+                        frame: packet.input_frameno,
+                        keyframe: packet.frame_type == FrameType::KEY,
+                        data: packet.data,
+                    });
+                }
+                // A frame was encoded internally without producing a packet yet; keep asking.
+                Err(EncoderStatus::Encoded) => continue,
+                Err(EncoderStatus::NeedMoreData) | Err(EncoderStatus::LimitReached) => {
+                    return Ok(packets)
+                }
+                Err(EncoderStatus::EnoughData) | Err(EncoderStatus::NotReady) => {
+                    return Ok(packets)
+                }
+                Err(EncoderStatus::Failure) => return Err(EncoderError::Failure),
+            }
+        }
+    }
+
+    /// Frames sent so far.
+    pub fn frames_sent(&self) -> u64 {
+        self.frames_sent
+    }
+}
+
+impl VideoEncoder for Av1Encoder {
+    fn codec(&self) -> VideoCodec {
+        VideoCodec::Av1
+    }
+
+    fn settings(&self) -> &EncoderSettings {
+        Av1Encoder::settings(self)
+    }
+
+    fn force_keyframe(&mut self) {
+        Av1Encoder::force_keyframe(self)
+    }
+
+    fn encode(&mut self, frame: &Frame) -> Result<Vec<Packet>, EncoderError> {
+        Av1Encoder::encode(self, frame)
+    }
+
+    fn flush(&mut self) -> Result<Vec<Packet>, EncoderError> {
+        Av1Encoder::flush(self)
+    }
+
+    fn reconfigure(&mut self, settings: EncoderSettings) -> Result<Vec<Packet>, EncoderError> {
+        Av1Encoder::reconfigure(self, settings)
+    }
+}
+
+/// rav1e on the CPU, which is always there.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rav1eBackend;
+
+impl EncoderBackend for Rav1eBackend {
+    fn name(&self) -> &str {
+        "rav1e"
+    }
+
+    fn probe(&self) -> Result<Capabilities, EncoderError> {
+        Ok(Capabilities {
+            codecs: vec![VideoCodec::Av1],
+            // AV1's level 6.3 limits; the CPU runs out long before.
+            max_resolution: Resolution::new(8192, 4352),
+            max_fps: 120,
+            hardware: false,
+        })
+    }
+
+    fn open(
+        &self,
+        codec: VideoCodec,
+        settings: &EncoderSettings,
+    ) -> Result<Box<dyn VideoEncoder>, EncoderError> {
+        if codec != VideoCodec::Av1 {
+            return Err(EncoderError::Unavailable(format!(
+                "rav1e cannot encode {codec}"
+            )));
+        }
+        Ok(Box::new(Av1Encoder::new(settings.clone())?))
+    }
+}
+
+fn rav1e_config(settings: &EncoderSettings) -> Config {
+    let mut speed_settings = SpeedSettings::from_preset(settings.speed);
+    speed_settings.rdo_lookahead_frames = settings.lookahead.max(1);
+    let enc = EncoderConfig {
+        width: settings.width as usize,
+        height: settings.height as usize,
+        time_base: Rational::new(1, settings.fps.max(1) as u64),
+        bit_depth: 8,
+        chroma_sampling: ChromaSampling::Cs420,
+        bitrate: (settings.bitrate_kbps as i32).saturating_mul(1000),
+        min_key_frame_interval: 1,
+        max_key_frame_interval: settings.keyframe_interval.max(1),
+        low_latency: true,
+        speed_settings,
+        ..Default::default()
+    };
+    Config::new()
+        .with_encoder_config(enc)
+        .with_threads(settings.threads)
+}
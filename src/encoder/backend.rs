@@ -0,0 +1,551 @@
+//! Choosing between encoders at runtime.
+//!
+//! Phones usually have a hardware H.265 (and sometimes AV1) encoder that costs far less power than rav1e, but which
+//! ones exist, what they accept and whether they keep working is only known on the device. Each way of encoding is
+//! an [`EncoderBackend`] that probes for itself; [`FallbackEncoder`] opens the first backend that can do the job
+//! and moves down the list when one fails, ending with rav1e, which always works.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::{EncoderError, EncoderSettings, Packet};
+use crate::camera::{Frame, Resolution};
+use crate::storage::ivf::FourCc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum VideoCodec {
+    Av1,
+    H265,
+}
+
+impl VideoCodec {
+    /// How IVF files name the codec.
+    pub fn fourcc(self) -> FourCc {
+        match self {
+            VideoCodec::Av1 => FourCc::AV1,
+            VideoCodec::H265 => FourCc::H265,
+        }
+    }
+}
+
+impl fmt::Display for VideoCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VideoCodec::Av1 => f.write_str("AV1"),
+            VideoCodec::H265 => f.write_str("H.265"),
+        }
+    }
+}
+
+/// Turns frames into packets of one codec.
+pub trait VideoEncoder: Send {
+    fn codec(&self) -> VideoCodec;
+
+    fn settings(&self) -> &EncoderSettings;
+
+    /// Makes the next frame sent a keyframe.
+    fn force_keyframe(&mut self);
+
+    /// Encodes one frame and returns whatever packets are ready, which may be for earlier frames.
+    fn encode(&mut self, frame: &Frame) -> Result<Vec<Packet>, EncoderError>;
+
+    /// Ends the stream and returns the packets for every frame still inside the encoder.
+    fn flush(&mut self) -> Result<Vec<Packet>, EncoderError>;
+
+    /// Switches to new settings, returning the last packets made with the old ones. The first frame encoded after
+    /// it is a keyframe.
+    fn reconfigure(&mut self, settings: EncoderSettings) -> Result<Vec<Packet>, EncoderError>;
+}
+
+/// What a backend can do on this device.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Codecs it can produce.
+    pub codecs: Vec<VideoCodec>,
+    pub max_resolution: Resolution,
+    pub max_fps: u32,
+    /// Whether encoding happens on dedicated hardware rather than the CPU.
+    pub hardware: bool,
+}
+
+impl Capabilities {
+    pub fn supports(&self, codec: VideoCodec, settings: &EncoderSettings) -> bool {
+        self.codecs.contains(&codec)
+            && settings.width <= self.max_resolution.width
+            && settings.height <= self.max_resolution.height
+            && settings.fps <= self.max_fps
+    }
+}
+
+/// A way of encoding video that may or may not be available on this device.
+pub trait EncoderBackend: Send {
+    fn name(&self) -> &str;
+
+    /// Finds out what the backend can do here. Fails with [`EncoderError::Unavailable`] when it cannot be used at all.
+    fn probe(&self) -> Result<Capabilities, EncoderError>;
+
+    fn open(
+        &self,
+        codec: VideoCodec,
+        settings: &EncoderSettings,
+    ) -> Result<Box<dyn VideoEncoder>, EncoderError>;
+}
+
+/// The backends this build knows about, most preferred first. rav1e comes last as the one that always works.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn default_backends() -> Vec<Box<dyn EncoderBackend>> {
+    vec![Box::new(super::Rav1eBackend)]
+}
+
+/// Browsers encode with WebCodecs, not through this crate.
+#[cfg(target_arch = "wasm32")]
+pub fn default_backends() -> Vec<Box<dyn EncoderBackend>> {
+    Vec::new()
+}
+
+/// An encoder that falls back to the next usable backend whenever the current one fails.
+pub struct FallbackEncoder {
+    backends: Vec<Box<dyn EncoderBackend>>,
+    /// Codecs the stream may use, most preferred first.
+    codecs: Vec<VideoCodec>,
+    settings: EncoderSettings,
+    current: Box<dyn VideoEncoder>,
+    /// Index of the backend `current` came from.
+    index: usize,
+}
+
+impl FallbackEncoder {
+    /// Opens the first of `backends` that can produce one of `codecs` with `settings`.
+    pub fn new(
+        backends: Vec<Box<dyn EncoderBackend>>,
+        codecs: Vec<VideoCodec>,
+        settings: EncoderSettings,
+    ) -> Result<Self, EncoderError> {
+        let (index, current) = open_first(&backends, 0, &codecs, &settings)?;
+        Ok(FallbackEncoder {
+            backends,
+            codecs,
+            settings,
+            current,
+            index,
+        })
+    }
+
+    /// Name of the backend in use.
+    pub fn backend(&self) -> &str {
+        self.backends[self.index].name()
+    }
+
+    /// Gives up on the current backend for the rest that follow it, returning the packets the failed encoder still
+    /// hands over when it is flushed.
+    fn fall_back(&mut self, error: &EncoderError) -> Result<Vec<Packet>, EncoderError> {
+        tracing::warn!("{} encoder failed: {error}", self.backend());
+        let (index, mut next) =
+            open_first(&self.backends, self.index + 1, &self.codecs, &self.settings)?;
+        let salvaged = self.current.flush().unwrap_or_else(|e| {
+            tracing::debug!("cannot flush the failed {} encoder: {e}", self.backend());
+            Vec::new()
+        });
+        next.force_keyframe();
+        self.current = next;
+        self.index = index;
+        tracing::info!("encoding with {} instead", self.backend());
+        Ok(salvaged)
+    }
+}
+
+impl VideoEncoder for FallbackEncoder {
+    /// The codec of the backend in use, which can change when it falls back.
+    fn codec(&self) -> VideoCodec {
+        self.current.codec()
+    }
+
+    fn settings(&self) -> &EncoderSettings {
+        &self.settings
+    }
+
+    fn force_keyframe(&mut self) {
+        self.current.force_keyframe();
+    }
+
+    /// Encodes with the current backend, or with the next one if it fails. Frames still inside the failed encoder
+    /// come out first if it can be flushed, and are lost otherwise; the stream starts over with a keyframe.
+    fn encode(&mut self, frame: &Frame) -> Result<Vec<Packet>, EncoderError> {
+        match self.current.encode(frame) {
+            Err(e) if e.is_backend_failure() => {
+                let mut packets = self.fall_back(&e)?;
+                packets.extend(self.current.encode(frame)?);
+                Ok(packets)
+            }
+            result => result,
+        }
+    }
+
+    fn flush(&mut self) -> Result<Vec<Packet>, EncoderError> {
+        self.current.flush()
+    }
+
+    fn reconfigure(&mut self, settings: EncoderSettings) -> Result<Vec<Packet>, EncoderError> {
+        self.settings = settings.clone();
+        match self.current.reconfigure(settings) {
+            Err(e) if e.is_backend_failure() => {
+                // The new settings may be beyond this backend; an earlier one will not take them either. The failed
+                // encoder still has the old settings, so flushing it ends the old stream.
+                self.fall_back(&e)
+            }
+            result => result,
+        }
+    }
+}
+
+/// Opens the first backend from `start` on that probes as able to encode one of `codecs` with `settings`.
+fn open_first(
+    backends: &[Box<dyn EncoderBackend>],
+    start: usize,
+    codecs: &[VideoCodec],
+    settings: &EncoderSettings,
+) -> Result<(usize, Box<dyn VideoEncoder>), EncoderError> {
+    let mut last_error = None;
+    for (index, backend) in backends.iter().enumerate().skip(start) {
+        let capabilities = match backend.probe() {
+            Ok(capabilities) => capabilities,
+            Err(e) => {
+                tracing::debug!("{} encoder is unavailable: {e}", backend.name());
+                continue;
+            }
+        };
+        for &codec in codecs {
+            if !capabilities.supports(codec, settings) {
+                continue;
+            }
+            match backend.open(codec, settings) {
+                Ok(encoder) => return Ok((index, encoder)),
+                Err(e) => {
+                    tracing::warn!("opening {} for {codec} failed: {e}", backend.name());
+                    last_error = Some(e);
+                }
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        EncoderError::Unavailable(format!(
+            "no encoder can produce {} at {}x{} and {} fps",
+            codecs
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .join(" or "),
+            settings.width,
+            settings.height,
+            settings.fps
+        ))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::sensors::Micros;
+
+    /// What the mock encoders were asked to do, as `<backend> <call>` lines.
+    type Calls = Arc<Mutex<Vec<String>>>;
+
+    /// A backend whose encoders hold one frame back, like a real encoder's lookahead, and fail on command.
+    struct MockBackend {
+        name: &'static str,
+        capabilities: Option<Capabilities>,
+        /// Encoders fail on the frame captured at this time.
+        fail_on: Option<Micros>,
+        calls: Calls,
+    }
+
+    struct MockEncoder {
+        name: &'static str,
+        codec: VideoCodec,
+        settings: EncoderSettings,
+        max_resolution: Resolution,
+        fail_on: Option<Micros>,
+        held: Option<Frame>,
+        frames: u64,
+        keyframe: bool,
+        calls: Calls,
+    }
+
+    impl MockBackend {
+        fn new(name: &'static str, codecs: Vec<VideoCodec>, max: u32, calls: &Calls) -> Self {
+            MockBackend {
+                name,
+                capabilities: Some(Capabilities {
+                    codecs,
+                    max_resolution: Resolution::new(max, max),
+                    max_fps: 30,
+                    hardware: true,
+                }),
+                fail_on: None,
+                calls: calls.clone(),
+            }
+        }
+    }
+
+    impl EncoderBackend for MockBackend {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn probe(&self) -> Result<Capabilities, EncoderError> {
+            self.capabilities
+                .clone()
+                .ok_or_else(|| EncoderError::Unavailable("not on this device".to_string()))
+        }
+
+        fn open(
+            &self,
+            codec: VideoCodec,
+            settings: &EncoderSettings,
+        ) -> Result<Box<dyn VideoEncoder>, EncoderError> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{} open {codec}", self.name));
+            Ok(Box::new(MockEncoder {
+                name: self.name,
+                codec,
+                settings: settings.clone(),
+                max_resolution: self.capabilities.as_ref().unwrap().max_resolution,
+                fail_on: self.fail_on,
+                held: None,
+                frames: 0,
+                keyframe: true,
+                calls: self.calls.clone(),
+            }))
+        }
+    }
+
+    impl MockEncoder {
+        fn packet(&mut self, frame: Frame) -> Packet {
+            let packet = Packet {
+                captured: frame.captured,
+                frame: self.frames,
+                keyframe: std::mem::take(&mut self.keyframe),
+                data: self.name.as_bytes().to_vec(),
+            };
+            self.frames += 1;
+            packet
+        }
+    }
+
+    impl VideoEncoder for MockEncoder {
+        fn codec(&self) -> VideoCodec {
+            self.codec
+        }
+
+        fn settings(&self) -> &EncoderSettings {
+            &self.settings
+        }
+
+        fn force_keyframe(&mut self) {
+            self.keyframe = true;
+        }
+
+        fn encode(&mut self, frame: &Frame) -> Result<Vec<Packet>, EncoderError> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{} encode {}", self.name, frame.captured));
+            if self.fail_on == Some(frame.captured) {
+                return Err(EncoderError::Failure);
+            }
+            let packets = self.held.take().map(|held| self.packet(held));
+            self.held = Some(frame.clone());
+            Ok(packets.into_iter().collect())
+        }
+
+        fn flush(&mut self) -> Result<Vec<Packet>, EncoderError> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{} flush", self.name));
+            let packets = self.held.take().map(|held| self.packet(held));
+            Ok(packets.into_iter().collect())
+        }
+
+        fn reconfigure(&mut self, settings: EncoderSettings) -> Result<Vec<Packet>, EncoderError> {
+            if settings.width > self.max_resolution.width {
+                return Err(EncoderError::Config("too wide".to_string()));
+            }
+            let packets = self.flush()?;
+            self.settings = settings;
+            self.keyframe = true;
+            Ok(packets)
+        }
+    }
+
+    fn frame(captured: Micros) -> Frame {
+        Frame {
+            captured,
+            width: 4,
+            height: 4,
+            rgb: vec![0; 4 * 4 * 3],
+        }
+    }
+
+    /// `(captured, keyframe, backend)` of each packet.
+    fn summary(packets: &[Packet]) -> Vec<(Micros, bool, String)> {
+        packets
+            .iter()
+            .map(|p| {
+                (
+                    p.captured,
+                    p.keyframe,
+                    String::from_utf8(p.data.clone()).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_first_backend_that_can_do_the_job_is_opened() {
+        let calls = Calls::default();
+        let mut missing = MockBackend::new("missing", vec![VideoCodec::Av1], 1920, &calls);
+        missing.capabilities = None;
+        let backends: Vec<Box<dyn EncoderBackend>> = vec![
+            Box::new(missing),
+            Box::new(MockBackend::new(
+                "h265 only",
+                vec![VideoCodec::H265],
+                1920,
+                &calls,
+            )),
+            Box::new(MockBackend::new(
+                "too small",
+                vec![VideoCodec::Av1],
+                320,
+                &calls,
+            )),
+            Box::new(MockBackend::new(
+                "fallback",
+                vec![VideoCodec::Av1],
+                1920,
+                &calls,
+            )),
+        ];
+        let encoder =
+            FallbackEncoder::new(backends, vec![VideoCodec::Av1], EncoderSettings::default())
+                .unwrap();
+        assert_eq!(encoder.backend(), "fallback");
+        assert_eq!(encoder.codec(), VideoCodec::Av1);
+        assert_eq!(*calls.lock().unwrap(), vec!["fallback open AV1"]);
+
+        let none = FallbackEncoder::new(
+            vec![Box::new(MockBackend::new(
+                "small",
+                vec![VideoCodec::Av1],
+                320,
+                &calls,
+            ))],
+            vec![VideoCodec::Av1, VideoCodec::H265],
+            EncoderSettings::default(),
+        );
+        assert!(matches!(none, Err(EncoderError::Unavailable(e)) if e.contains("AV1 or H.265")));
+    }
+
+    #[test]
+    fn a_failing_encoder_hands_over_to_the_next_backend_without_losing_frames() {
+        let calls = Calls::default();
+        let mut hardware = MockBackend::new("hardware", vec![VideoCodec::H265], 1920, &calls);
+        hardware.fail_on = Some(3);
+        let backends: Vec<Box<dyn EncoderBackend>> = vec![
+            Box::new(hardware),
+            Box::new(MockBackend::new(
+                "software",
+                vec![VideoCodec::Av1],
+                1920,
+                &calls,
+            )),
+        ];
+        let mut encoder = FallbackEncoder::new(
+            backends,
+            vec![VideoCodec::H265, VideoCodec::Av1],
+            EncoderSettings::default(),
+        )
+        .unwrap();
+        assert_eq!(encoder.codec(), VideoCodec::H265);
+
+        let mut packets = Vec::new();
+        for t in 1..=5 {
+            packets.extend(encoder.encode(&frame(t)).unwrap());
+        }
+        packets.extend(encoder.flush().unwrap());
+        assert_eq!(encoder.backend(), "software");
+        assert_eq!(encoder.codec(), VideoCodec::Av1);
+        // Frame 2 was still inside the hardware encoder when it failed, and the software stream starts on a keyframe.
+        assert_eq!(
+            summary(&packets),
+            vec![
+                (1, true, "hardware".to_string()),
+                (2, false, "hardware".to_string()),
+                (3, true, "software".to_string()),
+                (4, false, "software".to_string()),
+                (5, false, "software".to_string()),
+            ]
+        );
+        assert!(calls
+            .lock()
+            .unwrap()
+            .contains(&"hardware flush".to_string()));
+    }
+
+    #[test]
+    fn settings_beyond_a_backend_move_to_the_next_and_end_the_old_stream() {
+        let calls = Calls::default();
+        let backends: Vec<Box<dyn EncoderBackend>> = vec![
+            Box::new(MockBackend::new(
+                "hardware",
+                vec![VideoCodec::Av1],
+                640,
+                &calls,
+            )),
+            Box::new(MockBackend::new(
+                "software",
+                vec![VideoCodec::Av1],
+                1920,
+                &calls,
+            )),
+        ];
+        let mut encoder =
+            FallbackEncoder::new(backends, vec![VideoCodec::Av1], EncoderSettings::default())
+                .unwrap();
+        let mut packets = encoder.encode(&frame(1)).unwrap();
+        packets.extend(encoder.encode(&frame(2)).unwrap());
+
+        let wide = EncoderSettings {
+            width: 1280,
+            height: 720,
+            ..EncoderSettings::default()
+        };
+        packets.extend(encoder.reconfigure(wide.clone()).unwrap());
+        assert_eq!(encoder.backend(), "software");
+        assert_eq!(*encoder.settings(), wide);
+        packets.extend(encoder.encode(&frame(3)).unwrap());
+        packets.extend(encoder.flush().unwrap());
+        assert_eq!(
+            summary(&packets),
+            vec![
+                (1, true, "hardware".to_string()),
+                (2, false, "hardware".to_string()),
+                (3, true, "software".to_string()),
+            ]
+        );
+
+        // With nothing left to fall back to, the error comes through.
+        let huge = EncoderSettings {
+            width: 4096,
+            height: 4096,
+            ..EncoderSettings::default()
+        };
+        assert!(encoder.reconfigure(huge).is_err());
+    }
+}
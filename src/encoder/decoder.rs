@@ -0,0 +1,169 @@
+//! AV1 decoding with rav1d, the Rust port of dav1d.
+//!
+//! rav1d only exposes dav1d's C API, so this module is the one place in the crate that needs `unsafe`; everything
+//! it hands out is owned, safe data.
+
+use std::ptr::{self, NonNull};
+
+use rav1d::include::dav1d::data::Dav1dData;
+use rav1d::include::dav1d::dav1d::{Dav1dContext, Dav1dSettings};
+use rav1d::include::dav1d::headers::DAV1D_PIXEL_LAYOUT_I420;
+use rav1d::include::dav1d::picture::Dav1dPicture;
+use rav1d::src::lib::{
+    dav1d_close, dav1d_data_create, dav1d_data_unref, dav1d_default_settings, dav1d_get_picture,
+    dav1d_open, dav1d_picture_unref, dav1d_send_data,
+};
+
+use super::{yuv420_to_rgb, EncoderError, Yuv420};
+use crate::camera::Frame;
+use crate::sensors::Micros;
+
+/// rav1d reports errors as negative errno values, and this one as "send or receive again later".
+#[cfg(unix)]
+const EAGAIN: i32 = -nix::libc::EAGAIN;
+#[cfg(not(unix))]
+const EAGAIN: i32 = -11;
+
+/// Decodes a stream of AV1 temporal units, like the data of [`super::Packet`]s or IVF frames, into RGB frames.
+pub struct Av1Decoder {
+    context: Option<Dav1dContext>,
+}
+
+// rav1d contexts are reference counted and synchronise internally.
+unsafe impl Send for Av1Decoder {}
+
+impl Av1Decoder {
+    /// `threads` of 0 uses one per core.
+    pub fn new(threads: usize) -> Result<Self, EncoderError> {
+        let mut settings = std::mem::MaybeUninit::<Dav1dSettings>::uninit();
+        // SAFETY: `dav1d_default_settings` initialises all of `settings` without reading it.
+        let mut settings = unsafe {
+            dav1d_default_settings(NonNull::new_unchecked(settings.as_mut_ptr()));
+            settings.assume_init()
+        };
+        settings.n_threads = threads.min(256) as i32;
+        // Pictures come out as soon as they are decoded instead of being held back for frame threading.
+        settings.max_frame_delay = 1;
+        let mut context = None;
+        // SAFETY: both pointers come from live references.
+        let result = unsafe {
+            dav1d_open(
+                Some(NonNull::from(&mut context)),
+                Some(NonNull::from(&mut settings)),
+            )
+        };
+        check(result.0)?;
+        Ok(Av1Decoder { context })
+    }
+
+    /// Decodes one temporal unit stamped with `time`, returning the frames it completes with that time as their
+    /// capture time.
+    pub fn decode(&mut self, data: &[u8], time: Micros) -> Result<Vec<Frame>, EncoderError> {
+        if data.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut input = Dav1dData::default();
+        // SAFETY: `input` is a live, default initialised `Dav1dData`.
+        let buffer = unsafe { dav1d_data_create(Some(NonNull::from(&mut input)), data.len()) };
+        if buffer.is_null() {
+            return Err(EncoderError::Decode("out of memory".to_string()));
+        }
+        // SAFETY: `dav1d_data_create` allocated `data.len()` bytes at `buffer`.
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), buffer, data.len()) };
+        input.m.timestamp = time;
+
+        let mut frames = Vec::new();
+        let result = loop {
+            // SAFETY: the context is open and `input` is live. On success rav1d takes the data and leaves `input`
+            // empty; on EAGAIN it leaves it alone, to be sent again once pictures have been taken out.
+            let result =
+                unsafe { dav1d_send_data(self.context, Some(NonNull::from(&mut input))) }.0;
+            if result != EAGAIN {
+                break result;
+            }
+            let before = frames.len();
+            self.pictures(&mut frames)?;
+            if frames.len() == before {
+                break EAGAIN;
+            }
+        };
+        if input.sz > 0 {
+            // SAFETY: `input` still owns the buffer from `dav1d_data_create`.
+            unsafe { dav1d_data_unref(Some(NonNull::from(&mut input))) };
+        }
+        check(result)?;
+        self.pictures(&mut frames)?;
+        Ok(frames)
+    }
+
+    /// Takes out whatever pictures are ready.
+    fn pictures(&mut self, frames: &mut Vec<Frame>) -> Result<(), EncoderError> {
+        loop {
+            let mut picture = Dav1dPicture::default();
+            // SAFETY: the context is open and `picture` is live.
+            let result =
+                unsafe { dav1d_get_picture(self.context, Some(NonNull::from(&mut picture))) }.0;
+            if result == EAGAIN {
+                return Ok(());
+            }
+            check(result)?;
+            let frame = to_frame(&picture);
+            // SAFETY: `picture` was filled in by `dav1d_get_picture` and is released exactly once.
+            unsafe { dav1d_picture_unref(Some(NonNull::from(&mut picture))) };
+            frames.push(frame?);
+        }
+    }
+}
+
+impl Drop for Av1Decoder {
+    fn drop(&mut self) {
+        // SAFETY: the context came from `dav1d_open`; `dav1d_close` sets it to `None`.
+        unsafe { dav1d_close(Some(NonNull::from(&mut self.context))) };
+    }
+}
+
+fn check(result: i32) -> Result<(), EncoderError> {
+    match result {
+        0 => Ok(()),
+        e => Err(EncoderError::Decode(format!("rav1d returned error {}", -e))),
+    }
+}
+
+/// Copies an 8 bit 4:2:0 picture out of rav1d's buffers.
+fn to_frame(picture: &Dav1dPicture) -> Result<Frame, EncoderError> {
+    if picture.p.layout != DAV1D_PIXEL_LAYOUT_I420 || picture.p.bpc != 8 {
+        return Err(EncoderError::Decode(format!(
+            "only 8 bit 4:2:0 is supported, got {} bit layout {}",
+            picture.p.bpc, picture.p.layout
+        )));
+    }
+    let (width, height) = (picture.p.w as usize, picture.p.h as usize);
+    let (cw, ch) = (width.div_ceil(2), height.div_ceil(2));
+    let plane =
+        |index: usize, w: usize, h: usize, stride: isize| -> Result<Vec<u8>, EncoderError> {
+            let base = picture.data[index]
+                .ok_or_else(|| EncoderError::Decode("picture without data".to_string()))?
+                .as_ptr() as *const u8;
+            let mut out = Vec::with_capacity(w * h);
+            for row in 0..h {
+                // SAFETY: rav1d planes hold `h` rows of at least `w` bytes, `stride` bytes apart.
+                let line =
+                    unsafe { std::slice::from_raw_parts(base.offset(row as isize * stride), w) };
+                out.extend_from_slice(line);
+            }
+            Ok(out)
+        };
+    let yuv = Yuv420 {
+        width,
+        height,
+        y: plane(0, width, height, picture.stride[0])?,
+        u: plane(1, cw, ch, picture.stride[1])?,
+        v: plane(2, cw, ch, picture.stride[1])?,
+    };
+    Ok(Frame {
+        captured: picture.m.timestamp,
+        width: width as u32,
+        height: height as u32,
+        rgb: yuv420_to_rgb(&yuv),
+    })
+}
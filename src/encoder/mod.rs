@@ -0,0 +1,232 @@
+//! Video encoding of camera frames.
+//!
+//! Camera nodes have little bandwidth and usually no hardware AV1 encoder, so [`Av1Encoder`] runs rav1e at its
+//! fastest preset in low latency mode and lets the rate controller aim for a fixed bitrate (500 kbps at 15 fps by
+//! default). Frames go in as RGB and come out as [`Packet`]s stamped with the capture time of the frame they hold.
+//!
+//! Where a device has a hardware encoder, an [`EncoderBackend`] for it can be put ahead of [`Rav1eBackend`] and
+//! [`FallbackEncoder`] picks whichever works, behind the same [`VideoEncoder`] trait.
+//!
+//! [`adaptive`] steps bitrate, resolution and frame rate down and up with the link. [`Av1Decoder`] turns recorded
+//! packets back into frames for analysis on the compute node, and [`preview`] into JPEGs for people to watch.
+//! [`encode_image`] makes single AVIF or JPEG stills for links too slow for video.
+//!
+//! rav1e and rav1d do not build for the browser, so only the settings and packet types exist there.
+
+pub mod adaptive;
+#[cfg(not(target_arch = "wasm32"))]
+mod av1;
+mod backend;
+#[cfg(not(target_arch = "wasm32"))]
+mod decoder;
+#[cfg(not(target_arch = "wasm32"))]
+mod preview;
+pub mod sim;
+#[cfg(not(target_arch = "wasm32"))]
+mod still;
+mod yuv;
+
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Frame;
+use crate::sensors::Micros;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use av1::{Av1Encoder, Rav1eBackend};
+pub use backend::{
+    default_backends, Capabilities, EncoderBackend, FallbackEncoder, VideoCodec, VideoEncoder,
+};
+#[cfg(not(target_arch = "wasm32"))]
+pub use decoder::Av1Decoder;
+#[cfg(not(target_arch = "wasm32"))]
+pub use preview::preview;
+#[cfg(not(target_arch = "wasm32"))]
+pub use still::encode_image;
+pub use yuv::{rgb_to_yuv420, yuv420_to_rgb, Yuv420};
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum EncoderError {
+    #[error("invalid encoder settings: {0}")]
+    Config(String),
+    #[error("frame is {got_width}x{got_height}, encoder expects {width}x{height}")]
+    FrameSize {
+        width: u32,
+        height: u32,
+        got_width: u32,
+        got_height: u32,
+    },
+    #[error("encoder failed")]
+    Failure,
+    #[error("encoder was already flushed")]
+    Finished,
+    #[error("decoder failed: {0}")]
+    Decode(String),
+    #[error("encoder unavailable: {0}")]
+    Unavailable(String),
+}
+
+impl EncoderError {
+    /// Whether the error is the encoder's fault rather than the caller's, so another encoder may do better.
+    pub fn is_backend_failure(&self) -> bool {
+        matches!(
+            self,
+            EncoderError::Config(_) | EncoderError::Failure | EncoderError::Unavailable(_)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EncoderSettings {
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    pub bitrate_kbps: u32,
+    /// Longest run of frames between keyframes. Recordings can only be cut and seeked at keyframes.
+    pub keyframe_interval: u64,
+    /// rav1e speed preset, 0 (slowest, smallest) to 10 (fastest).
+    pub speed: u8,
+    /// Frames rav1e looks ahead to plan rate and keyframes. Packets come out this many frames (plus about three)
+    /// after their input, which is the bulk of a live stream's latency.
+    pub lookahead: usize,
+    /// Worker threads; 0 uses rayon's global pool.
+    pub threads: usize,
+}
+
+impl Default for EncoderSettings {
+    fn default() -> Self {
+        EncoderSettings {
+            width: 640,
+            height: 480,
+            fps: 15,
+            bitrate_kbps: 500,
+            keyframe_interval: 60,
+            speed: 10,
+            lookahead: 5,
+            threads: 0,
+        }
+    }
+}
+
+impl EncoderSettings {
+    /// Settings for frames of a camera's size, keeping everything else.
+    pub fn for_frame(self, frame: &Frame) -> Self {
+        EncoderSettings {
+            width: frame.width,
+            height: frame.height,
+            ..self
+        }
+    }
+}
+
+/// One encoded temporal unit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Packet {
+    /// Local time the frame in this packet was captured.
+    pub captured: Micros,
+    /// Index of the frame among those sent to the encoder.
+    pub frame: u64,
+    pub keyframe: bool,
+    pub data: Vec<u8>,
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    fn settings() -> EncoderSettings {
+        EncoderSettings {
+            width: 64,
+            height: 48,
+            bitrate_kbps: 100,
+            keyframe_interval: 1_000,
+            ..EncoderSettings::default()
+        }
+    }
+
+    /// Frame `n` of a bar sweeping across the picture, captured at `n` fifteenths of a second past a second.
+    fn frame(n: u64, width: u32, height: u32) -> Frame {
+        let bar = (n as u32 * 2) % width;
+        let rgb = (0..width * height)
+            .flat_map(|i| {
+                let lit = (i % width).abs_diff(bar) < 4;
+                if lit {
+                    [230, 200, 40]
+                } else {
+                    [20, 40, 90]
+                }
+            })
+            .collect();
+        Frame {
+            captured: 1_000_000 + n as Micros * 1_000_000 / 15,
+            width,
+            height,
+            rgb,
+        }
+    }
+
+    fn encode(encoder: &mut Av1Encoder, frames: std::ops::Range<u64>) -> Vec<Packet> {
+        let (width, height) = (encoder.settings().width, encoder.settings().height);
+        frames
+            .flat_map(|n| encoder.encode(&frame(n, width, height)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn a_stream_starts_with_a_keyframe_and_keeps_its_frames_times() {
+        let mut encoder = Av1Encoder::new(settings()).unwrap();
+        let mut packets = encode(&mut encoder, 0..20);
+        packets.extend(encoder.flush().unwrap());
+        assert_eq!(encoder.frames_sent(), 20);
+        assert_eq!(
+            encoder.encode(&frame(20, 64, 48)),
+            Err(EncoderError::Finished)
+        );
+
+        assert!(packets[0].keyframe);
+        assert!(packets[1..].iter().all(|p| !p.keyframe));
+        let numbers: Vec<u64> = packets.iter().map(|p| p.frame).collect();
+        assert_eq!(numbers, (0..20).collect::<Vec<_>>());
+        for packet in &packets {
+            assert_eq!(packet.captured, frame(packet.frame, 64, 48).captured);
+            assert!(!packet.data.is_empty());
+        }
+    }
+
+    #[test]
+    fn keyframes_come_when_forced_and_after_reconfiguring() {
+        let mut encoder = Av1Encoder::new(settings()).unwrap();
+        let mut packets = encode(&mut encoder, 0..10);
+        encoder.force_keyframe();
+        packets.extend(encode(&mut encoder, 10..15));
+        packets.extend(encoder.flush().unwrap());
+        let keyframes: Vec<u64> = packets
+            .iter()
+            .filter(|p| p.keyframe)
+            .map(|p| p.frame)
+            .collect();
+        assert_eq!(keyframes, [0, 10]);
+
+        let mut encoder = Av1Encoder::new(settings()).unwrap();
+        let before = encode(&mut encoder, 0..10);
+        let smaller = EncoderSettings {
+            width: 32,
+            height: 24,
+            ..settings()
+        };
+        let rest = encoder.reconfigure(smaller.clone()).unwrap();
+        assert_eq!(before.len() + rest.len(), 10);
+        assert_eq!(rest.last().unwrap().frame, 9);
+        assert_eq!(encoder.settings(), &smaller);
+        assert!(matches!(
+            encoder.encode(&frame(10, 64, 48)),
+            Err(EncoderError::FrameSize { got_width: 64, .. })
+        ));
+
+        let mut after = encode(&mut encoder, 10..15);
+        after.extend(encoder.flush().unwrap());
+        assert_eq!(after.len(), 5);
+        assert!(after[0].keyframe);
+        assert_eq!(after[0].frame, 0);
+        assert_eq!(after[0].captured, frame(10, 32, 24).captured);
+    }
+}
@@ -0,0 +1,38 @@
+//! Recorded footage turned back into pictures a screen can show.
+
+use super::{encode_image, Av1Decoder, EncoderError};
+use crate::sensors::Micros;
+use crate::storage::{ImageFormat, Keyframe, VideoBlob};
+
+/// JPEG quality of preview frames: small enough to send a few seconds of them to a browser at once.
+const PREVIEW_QUALITY: u8 = 85;
+
+/// Decodes the segment `keyframe` is in, from `keyframe` to the end of the segment, into JPEGs stamped with their
+/// network times.
+pub fn preview(
+    video: &VideoBlob,
+    keyframe: &Keyframe,
+) -> Result<Vec<(Micros, Vec<u8>)>, EncoderError> {
+    let segment = video
+        .segments
+        .get(keyframe.segment as usize)
+        .ok_or_else(|| {
+            EncoderError::Decode(format!(
+                "{} on {} has no segment {}",
+                video.camera, video.node, keyframe.segment
+            ))
+        })?;
+    let mut decoder = Av1Decoder::new(1)?;
+    let mut frames = Vec::new();
+    let packets = segment
+        .frames_from(keyframe.offset)
+        .map_err(|e| EncoderError::Decode(e.to_string()))?;
+    for packet in packets {
+        let (time, data) = packet.map_err(|e| EncoderError::Decode(e.to_string()))?;
+        for frame in decoder.decode(data, time)? {
+            let jpeg = encode_image(&frame, ImageFormat::Jpeg, PREVIEW_QUALITY)?;
+            frames.push((frame.captured, jpeg));
+        }
+    }
+    Ok(frames)
+}
@@ -0,0 +1,312 @@
+//! Stand-ins for the parts of encoding that only exist on a phone in a field.
+//!
+//! [`SimulatedLink`] is a cellular link, so the bitrate control loop can be exercised without one. It has a capacity
+//! that changes over time, a queue in front of it (cellular modems buffer generously, so sending too fast shows up
+//! as a growing round trip time before anything is lost) and some random loss.
+//!
+//! [`MockBackend`] is a hardware encoder that can be missing, refuse to open or die mid stream, so choosing and
+//! falling back between backends can be exercised on a desktop.
+
+use std::collections::VecDeque;
+
+use super::adaptive::{BitrateController, ByteBudget, Change, LinkStats, QualityStep};
+use super::{
+    Capabilities, EncoderBackend, EncoderError, EncoderSettings, Packet, VideoCodec, VideoEncoder,
+};
+use crate::camera::{Frame, Resolution};
+use crate::sensors::Micros;
+
+/// Payload bytes per packet on the wire.
+const MTU: usize = 1200;
+/// Link statistics cover this much recent history.
+const WINDOW: Micros = 2_000_000;
+
+pub struct SimulatedLink {
+    /// Capacity in kbps from each time on, sorted by time.
+    pub capacity: Vec<(Micros, f64)>,
+    pub base_rtt: Micros,
+    /// Probability of losing a packet regardless of load.
+    pub random_loss: f64,
+    /// Queueing delay beyond which the modem drops packets.
+    pub max_queue: Micros,
+    pub sent_bytes: u64,
+    queued_bits: f64,
+    last: Option<Micros>,
+    history: VecDeque<(Micros, bool)>,
+    rng: u64,
+}
+
+impl SimulatedLink {
+    pub fn new(capacity_kbps: f64) -> Self {
+        SimulatedLink {
+            capacity: vec![(Micros::MIN, capacity_kbps)],
+            base_rtt: 120_000,
+            random_loss: 0.002,
+            max_queue: 1_500_000,
+            sent_bytes: 0,
+            queued_bits: 0.0,
+            last: None,
+            history: VecDeque::new(),
+            rng: 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+
+    /// Changes the capacity to `kbps` from time `at` on.
+    pub fn set_capacity(mut self, at: Micros, kbps: f64) -> Self {
+        self.capacity.push((at, kbps));
+        self.capacity.sort_by_key(|(t, _)| *t);
+        self
+    }
+
+    pub fn capacity_at(&self, now: Micros) -> f64 {
+        self.capacity
+            .iter()
+            .rev()
+            .find(|(t, _)| *t <= now)
+            .map_or(0.0, |(_, kbps)| *kbps)
+    }
+
+    fn queue_delay(&self, now: Micros) -> Micros {
+        let bps = self.capacity_at(now).max(1.0) * 1000.0;
+        (self.queued_bits / bps * 1e6) as Micros
+    }
+
+    fn advance(&mut self, now: Micros) {
+        if let Some(last) = self.last {
+            let drained = self.capacity_at(last) * 1000.0 * (now - last).max(0) as f64 / 1e6;
+            self.queued_bits = (self.queued_bits - drained).max(0.0);
+        }
+        self.last = Some(now);
+        while self.history.front().is_some_and(|(t, _)| now - t > WINDOW) {
+            self.history.pop_front();
+        }
+    }
+
+    /// Sends `bytes` at `now`, split into packets. Returns how many bytes were delivered.
+    pub fn send(&mut self, now: Micros, bytes: usize) -> usize {
+        self.advance(now);
+        let mut delivered = 0;
+        let mut left = bytes;
+        while left > 0 {
+            let packet = left.min(MTU);
+            left -= packet;
+            self.sent_bytes += packet as u64;
+            let lost = self.queue_delay(now) > self.max_queue || self.random() < self.random_loss;
+            if !lost {
+                self.queued_bits += packet as f64 * 8.0;
+                delivered += packet;
+            }
+            self.history.push_back((now, lost));
+        }
+        delivered
+    }
+
+    /// What a transport would report: the round trip including queueing, and the loss over the last two seconds.
+    pub fn stats(&mut self, now: Micros) -> LinkStats {
+        self.advance(now);
+        let lost = self.history.iter().filter(|(_, lost)| *lost).count();
+        LinkStats {
+            rtt: self.base_rtt + self.queue_delay(now),
+            loss: lost as f64 / self.history.len().max(1) as f64,
+        }
+    }
+
+    fn random(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TracePoint {
+    pub time: Micros,
+    pub step: QualityStep,
+    pub change: Option<Change>,
+    pub stats: LinkStats,
+    pub capacity_kbps: f64,
+}
+
+/// Runs a camera through `link` for `duration`, sending frames of the size the current step's bitrate implies and
+/// updating the controller every `interval`. Returns the controller's view at every update.
+pub fn simulate(
+    controller: &mut BitrateController,
+    link: &mut SimulatedLink,
+    mut budget: Option<ByteBudget>,
+    start: Micros,
+    duration: Micros,
+    interval: Micros,
+) -> Vec<TracePoint> {
+    let mut trace = Vec::new();
+    let mut now = start;
+    let mut next_update = start + interval;
+    while now < start + duration {
+        let step = controller.current();
+        let frame_bytes = (step.bitrate_kbps as usize * 1000 / 8) / step.fps.max(1) as usize;
+        link.send(now, frame_bytes);
+        if let Some(budget) = &mut budget {
+            budget.remaining = budget.remaining.saturating_sub(frame_bytes as u64);
+        }
+        now += 1_000_000 / step.fps.max(1) as Micros;
+
+        if now >= next_update {
+            next_update += interval;
+            let stats = link.stats(now);
+            let change = controller
+                .update(now, stats, budget)
+                .map(|(_, change)| change);
+            trace.push(TracePoint {
+                time: now,
+                step: controller.current(),
+                change,
+                stats,
+                capacity_kbps: link.capacity_at(now),
+            });
+        }
+    }
+    trace
+}
+
+/// A pretend hardware encoder backend.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockBackend {
+    pub name: String,
+    /// What probing finds, or `None` for a device without the encoder.
+    pub capabilities: Option<Capabilities>,
+    /// Whether opening an encoder fails even though probing succeeded, as vendor encoders are prone to.
+    pub fail_open: bool,
+    /// Encoders fail on this frame and every one after it, like a hardware encoder that dies mid stream.
+    pub fail_after: Option<u64>,
+}
+
+impl MockBackend {
+    /// A working hardware encoder for `codecs` up to 1080p30.
+    pub fn hardware(codecs: Vec<VideoCodec>) -> Self {
+        MockBackend {
+            name: "mock hardware".to_string(),
+            capabilities: Some(Capabilities {
+                codecs,
+                max_resolution: Resolution::new(1920, 1080),
+                max_fps: 30,
+                hardware: true,
+            }),
+            fail_open: false,
+            fail_after: None,
+        }
+    }
+
+    /// A device without the encoder.
+    pub fn absent() -> Self {
+        MockBackend {
+            capabilities: None,
+            ..Self::hardware(Vec::new())
+        }
+    }
+}
+
+impl EncoderBackend for MockBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn probe(&self) -> Result<Capabilities, EncoderError> {
+        self.capabilities
+            .clone()
+            .ok_or_else(|| EncoderError::Unavailable(format!("no {} on this device", self.name)))
+    }
+
+    fn open(
+        &self,
+        codec: VideoCodec,
+        settings: &EncoderSettings,
+    ) -> Result<Box<dyn VideoEncoder>, EncoderError> {
+        if self.fail_open {
+            return Err(EncoderError::Unavailable(format!(
+                "{} refused to open",
+                self.name
+            )));
+        }
+        Ok(Box::new(MockEncoder {
+            codec,
+            settings: settings.clone(),
+            fail_after: self.fail_after,
+            frames: 0,
+            force_keyframe: true,
+            flushed: false,
+        }))
+    }
+}
+
+/// Encodes nothing, but hands out a packet per frame of the size the bitrate allows, with keyframes four times as
+/// big, straight away.
+#[derive(Debug, Clone)]
+pub struct MockEncoder {
+    codec: VideoCodec,
+    settings: EncoderSettings,
+    fail_after: Option<u64>,
+    frames: u64,
+    force_keyframe: bool,
+    flushed: bool,
+}
+
+impl VideoEncoder for MockEncoder {
+    fn codec(&self) -> VideoCodec {
+        self.codec
+    }
+
+    fn settings(&self) -> &EncoderSettings {
+        &self.settings
+    }
+
+    fn force_keyframe(&mut self) {
+        self.force_keyframe = true;
+    }
+
+    fn encode(&mut self, frame: &Frame) -> Result<Vec<Packet>, EncoderError> {
+        if self.flushed {
+            return Err(EncoderError::Finished);
+        }
+        if (frame.width, frame.height) != (self.settings.width, self.settings.height) {
+            return Err(EncoderError::FrameSize {
+                width: self.settings.width,
+                height: self.settings.height,
+                got_width: frame.width,
+                got_height: frame.height,
+            });
+        }
+        if self.fail_after.is_some_and(|n| self.frames >= n) {
+            return Err(EncoderError::Failure);
+        }
+        let keyframe = std::mem::take(&mut self.force_keyframe)
+            || self
+                .frames
+                .is_multiple_of(self.settings.keyframe_interval.max(1));
+        let size =
+            self.settings.bitrate_kbps as usize * 1000 / 8 / self.settings.fps.max(1) as usize;
+        let mut data = vec![0; if keyframe { size * 4 } else { size }.max(16)];
+        data[..4].copy_from_slice(b"MOCK");
+        data[8..16].copy_from_slice(&self.frames.to_le_bytes());
+        let packet = Packet {
+            captured: frame.captured,
+            frame: self.frames,
+            keyframe,
+            data,
+        };
+        self.frames += 1;
+        Ok(vec![packet])
+    }
+
+    fn flush(&mut self) -> Result<Vec<Packet>, EncoderError> {
+        self.flushed = true;
+        Ok(Vec::new())
+    }
+
+    fn reconfigure(&mut self, settings: EncoderSettings) -> Result<Vec<Packet>, EncoderError> {
+        self.settings = settings;
+        self.frames = 0;
+        self.force_keyframe = true;
+        self.flushed = false;
+        Ok(Vec::new())
+    }
+}
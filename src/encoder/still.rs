@@ -0,0 +1,88 @@
+//! Encoding single frames as still images.
+
+use std::sync::Arc;
+
+use jpeg_encoder::ColorType;
+use rav1e::prelude::{
+    ChromaSampling, Config, Context, EncoderConfig, EncoderStatus, SpeedSettings,
+};
+
+use super::{rgb_to_yuv420, EncoderError};
+use crate::camera::Frame;
+use crate::storage::ImageFormat;
+
+/// rav1e preset for stills, slower than video gets since there is only one frame every few minutes.
+const STILL_SPEED: u8 = 6;
+
+/// Encodes `frame` as a complete image file. `quality` runs from 1 (smallest) to 100 (best).
+pub fn encode_image(
+    frame: &Frame,
+    format: ImageFormat,
+    quality: u8,
+) -> Result<Vec<u8>, EncoderError> {
+    let quality = quality.clamp(1, 100);
+    match format {
+        ImageFormat::Avif => encode_avif(frame, quality),
+        ImageFormat::Jpeg => encode_jpeg(frame, quality),
+    }
+}
+
+fn encode_avif(frame: &Frame, quality: u8) -> Result<Vec<u8>, EncoderError> {
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let enc = EncoderConfig {
+        width,
+        height,
+        bit_depth: 8,
+        chroma_sampling: ChromaSampling::Cs420,
+        still_picture: true,
+        // Quality 100 is quantizer 0 (lossless); 1 is the coarsest rav1e allows.
+        quantizer: (100 - quality as usize) * 255 / 99,
+        speed_settings: SpeedSettings::from_preset(STILL_SPEED),
+        ..Default::default()
+    };
+    let mut ctx: Context<u8> = Config::new()
+        .with_encoder_config(enc)
+        .new_context()
+        .map_err(|e| EncoderError::Config(e.to_string()))?;
+
+    let yuv = rgb_to_yuv420(&frame.rgb, width, height);
+    let mut input = ctx.new_frame();
+    let strides = [yuv.width, yuv.chroma_width(), yuv.chroma_width()];
+    for ((plane, data), stride) in input
+        .planes
+        .iter_mut()
+        .zip([&yuv.y, &yuv.u, &yuv.v])
+        .zip(strides)
+    {
+        plane.copy_from_raw_u8(data, stride, 1);
+        plane.pad(width, height);
+    }
+    ctx.send_frame(Arc::new(input))
+        .map_err(|_| EncoderError::Failure)?;
+    ctx.flush();
+
+    let av1 = loop {
+        match ctx.receive_packet() {
+            Ok(packet) => break packet.data,
+            Err(EncoderStatus::Encoded) => continue,
+            Err(_) => return Err(EncoderError::Failure),
+        }
+    };
+    Ok(avif_serialize::Aviffy::new()
+        .set_chroma_subsampling((true, true))
+        .to_vec(&av1, None, frame.width, frame.height, 8))
+}
+
+fn encode_jpeg(frame: &Frame, quality: u8) -> Result<Vec<u8>, EncoderError> {
+    let (Ok(width), Ok(height)) = (u16::try_from(frame.width), u16::try_from(frame.height)) else {
+        return Err(EncoderError::Config(format!(
+            "JPEG cannot hold a {}x{} image",
+            frame.width, frame.height
+        )));
+    };
+    let mut jpeg = Vec::new();
+    jpeg_encoder::Encoder::new(&mut jpeg, quality)
+        .encode(&frame.rgb, width, height, ColorType::Rgb)
+        .map_err(|e| EncoderError::Config(e.to_string()))?;
+    Ok(jpeg)
+}
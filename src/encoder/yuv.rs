@@ -0,0 +1,85 @@
+//! Colour conversion for the encoder.
+
+/// An 8 bit, 4:2:0 image with BT.601 limited range values. Chroma planes are half the luma size, rounded up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Yuv420 {
+    pub width: usize,
+    pub height: usize,
+    pub y: Vec<u8>,
+    pub u: Vec<u8>,
+    pub v: Vec<u8>,
+}
+
+impl Yuv420 {
+    pub fn chroma_width(&self) -> usize {
+        self.width.div_ceil(2)
+    }
+}
+
+/// Converts packed RGB rows to 4:2:0, averaging each 2x2 block for chroma.
+pub fn rgb_to_yuv420(rgb: &[u8], width: usize, height: usize) -> Yuv420 {
+    assert_eq!(
+        rgb.len(),
+        width * height * 3,
+        "RGB buffer does not match its size"
+    );
+    let (cw, ch) = (width.div_ceil(2), height.div_ceil(2));
+    let mut y = vec![0u8; width * height];
+    let mut u = vec![0u8; cw * ch];
+    let mut v = vec![0u8; cw * ch];
+    let pixel = |x: usize, row: usize| {
+        let i = (row * width + x) * 3;
+        (rgb[i] as i32, rgb[i + 1] as i32, rgb[i + 2] as i32)
+    };
+
+    for row in 0..height {
+        for x in 0..width {
+            let (r, g, b) = pixel(x, row);
+            y[row * width + x] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+        }
+    }
+    for cy in 0..ch {
+        for cx in 0..cw {
+            let (mut r, mut g, mut b, mut n) = (0, 0, 0, 0);
+            for row in 2 * cy..(2 * cy + 2).min(height) {
+                for x in 2 * cx..(2 * cx + 2).min(width) {
+                    let p = pixel(x, row);
+                    r += p.0;
+                    g += p.1;
+                    b += p.2;
+                    n += 1;
+                }
+            }
+            let (r, g, b) = (r / n, g / n, b / n);
+            u[cy * cw + cx] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+            v[cy * cw + cx] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+        }
+    }
+    Yuv420 {
+        width,
+        height,
+        y,
+        u,
+        v,
+    }
+}
+
+/// Converts 4:2:0 back to packed RGB rows, the inverse of [`rgb_to_yuv420`].
+pub fn yuv420_to_rgb(yuv: &Yuv420) -> Vec<u8> {
+    let cw = yuv.chroma_width();
+    let mut rgb = Vec::with_capacity(yuv.width * yuv.height * 3);
+    for row in 0..yuv.height {
+        for x in 0..yuv.width {
+            let c = (row / 2) * cw + x / 2;
+            let y = 298 * (yuv.y[row * yuv.width + x] as i32 - 16);
+            let (u, v) = (yuv.u[c] as i32 - 128, yuv.v[c] as i32 - 128);
+            let channel = |value: i32| ((value + 128) >> 8).clamp(0, 255) as u8;
+            rgb.extend([
+                channel(y + 409 * v),
+                channel(y - 100 * u - 208 * v),
+                channel(y + 516 * u),
+            ]);
+        }
+    }
+    rgb
+}
@@ -0,0 +1,525 @@
+//! Sensor fusion: derives orientation and altitude channels from the raw gyroscope, accelerometer, magnetometer and
+//! barometer streams.
+//!
+//! The same [`Fusion`] state machine runs in two places. On a sensor node [`FusedSource`] wraps the node's sensors
+//! and records the derived channels live alongside the raw ones. On a compute node [`fuse_blob`] replays a stored
+//! hour blob through it, so derived channels can be recomputed (e.g. with a newer calibration) without touching the
+//! raw data.
+//!
+//! Orientation comes from a Mahony filter: gyroscope rates are integrated, and a PI controller pulls the estimate
+//! towards the attitude implied by gravity and the horizontal component of the magnetic field. The integral term
+//! also tracks gyroscope bias.
+
+use serde::{Deserialize, Serialize};
+
+use crate::math::{self, Quat, Vec3};
+use crate::sensors::{
+    ChannelId, ChannelInfo, Micros, Sample, SensorError, SensorKind, SensorSource,
+};
+use crate::storage::{SealedBlob, TimedSample};
+
+/// Channel orientation quaternions are written to, as `[w, x, y, z]` rotating the body frame into a north-west-up
+/// frame: x points to magnetic north, y west and z up.
+pub const ORIENTATION: &str = "orientation";
+/// Channel barometric altitude is written to, in metres.
+pub const ALTITUDE: &str = "altitude";
+
+/// Pressure at sea level in the standard atmosphere, in hPa.
+pub const STANDARD_PRESSURE: f64 = 1013.25;
+
+/// Altitude in metres for a pressure reading, using the international barometric formula.
+pub fn pressure_altitude(pressure: f64, sea_level: f64) -> f64 {
+    44_330.0 * (1.0 - (pressure / sea_level).powf(1.0 / 5.255))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FusionConfig {
+    pub accelerometer: ChannelId,
+    pub gyroscope: ChannelId,
+    pub magnetometer: ChannelId,
+    pub barometer: ChannelId,
+    /// Proportional gain of the attitude correction.
+    pub kp: f64,
+    /// Integral gain, which sets how quickly gyroscope bias is learned.
+    pub ki: f64,
+    /// Reference pressure for altitude, in hPa. Set it to the local QNH for true altitude, or leave it at the
+    /// standard atmosphere for altitude that is only meaningful relative to other readings.
+    pub sea_level_pressure: f64,
+    /// Minimum time between orientation samples. The filter still runs on every gyroscope sample.
+    pub orientation_period: Micros,
+}
+
+impl Default for FusionConfig {
+    fn default() -> Self {
+        FusionConfig {
+            accelerometer: SensorKind::Accelerometer.channel(),
+            gyroscope: SensorKind::Gyroscope.channel(),
+            magnetometer: SensorKind::Magnetometer.channel(),
+            barometer: SensorKind::Barometer.channel(),
+            kp: 1.0,
+            ki: 0.05,
+            sea_level_pressure: STANDARD_PRESSURE,
+            orientation_period: 100_000,
+        }
+    }
+}
+
+impl FusionConfig {
+    /// Reads the `<channel>/calibrated` accelerometer and magnetometer channels instead of the raw ones. Fusing an
+    /// uncalibrated magnetometer gives headings that are off by tens of degrees.
+    pub fn calibrated() -> Self {
+        use crate::calibration::CALIBRATED;
+        FusionConfig {
+            accelerometer: SensorKind::Accelerometer.channel().derived(CALIBRATED),
+            magnetometer: SensorKind::Magnetometer.channel().derived(CALIBRATED),
+            ..FusionConfig::default()
+        }
+    }
+
+    /// Reads the `<channel>/calibrated` accelerometer and magnetometer channels of the sensors `calibrated` says have
+    /// one, and the raw channels of the others.
+    pub fn prefer_calibrated(calibrated: impl Fn(&ChannelId) -> bool) -> Self {
+        use crate::calibration::CALIBRATED;
+        let channel = |kind: SensorKind| {
+            let raw = kind.channel();
+            if calibrated(&raw) {
+                raw.derived(CALIBRATED)
+            } else {
+                raw
+            }
+        };
+        FusionConfig {
+            accelerometer: channel(SensorKind::Accelerometer),
+            magnetometer: channel(SensorKind::Magnetometer),
+            ..FusionConfig::default()
+        }
+    }
+}
+
+/// Mahony's nonlinear complementary filter on SO(3).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mahony {
+    pub q: Quat,
+    /// Integrated error, which converges to minus the gyroscope bias.
+    pub integral: Vec3,
+    pub kp: f64,
+    pub ki: f64,
+}
+
+impl Mahony {
+    /// Starts from the attitude implied by a single accelerometer and magnetometer reading, so the filter does not
+    /// have to slew in from the identity.
+    pub fn from_vectors(accel: Vec3, mag: Vec3, kp: f64, ki: f64) -> Self {
+        let up = math::normalize(accel);
+        let north = math::normalize(math::sub(mag, math::scale(up, math::dot(mag, up))));
+        let west = math::cross(up, north);
+        Mahony {
+            q: Quat::from_rows(&[north, west, up]),
+            integral: [0.0; 3],
+            kp,
+            ki,
+        }
+    }
+
+    /// Advances the filter by `dt` seconds. `gyro` is in rad/s; `accel` and `mag` only need the right direction.
+    pub fn update(&mut self, gyro: Vec3, accel: Vec3, mag: Option<Vec3>, dt: f64) {
+        let mut error = [0.0; 3];
+        if math::norm(accel) > 0.0 {
+            let measured = math::normalize(accel);
+            let estimated = self.q.conjugate().rotate([0.0, 0.0, 1.0]);
+            error = math::add(error, math::cross(measured, estimated));
+        }
+        if let Some(mag) = mag.filter(|m| math::norm(*m) > 0.0) {
+            let measured = math::normalize(mag);
+            // Only the horizontal direction of the field is trusted; its inclination varies with location.
+            let h = self.q.rotate(measured);
+            let reference = [(h[0] * h[0] + h[1] * h[1]).sqrt(), 0.0, h[2]];
+            let estimated = self.q.conjugate().rotate(reference);
+            error = math::add(error, math::cross(measured, estimated));
+        }
+
+        if self.ki > 0.0 {
+            self.integral = math::add(self.integral, math::scale(error, self.ki * dt));
+        }
+        let rate = math::add(math::add(gyro, math::scale(error, self.kp)), self.integral);
+        let omega = Quat {
+            w: 0.0,
+            x: rate[0],
+            y: rate[1],
+            z: rate[2],
+        };
+        let dq = self.q * omega;
+        self.q = Quat {
+            w: self.q.w + 0.5 * dq.w * dt,
+            x: self.q.x + 0.5 * dq.x * dt,
+            y: self.q.y + 0.5 * dq.y * dt,
+            z: self.q.z + 0.5 * dq.z * dt,
+        }
+        .normalize();
+    }
+}
+
+/// Turns a time ordered stream of raw samples into derived orientation and altitude samples.
+#[derive(Debug, Clone)]
+pub struct Fusion {
+    pub config: FusionConfig,
+    filter: Option<Mahony>,
+    accel: Option<Vec3>,
+    mag: Option<Vec3>,
+    last_gyro: Option<Micros>,
+    last_output: Option<Micros>,
+}
+
+impl Fusion {
+    /// Gaps between gyroscope samples longer than this reset the filter instead of integrating across them.
+    const MAX_GAP: Micros = 1_000_000;
+
+    pub fn new(config: FusionConfig) -> Self {
+        Fusion {
+            config,
+            filter: None,
+            accel: None,
+            mag: None,
+            last_gyro: None,
+            last_output: None,
+        }
+    }
+
+    pub fn channels(&self) -> Vec<ChannelInfo> {
+        vec![
+            ChannelInfo {
+                id: ChannelId::new(ORIENTATION),
+                kind: None,
+                unit: "quaternion".to_string(),
+                dims: 4,
+            },
+            ChannelInfo {
+                id: ChannelId::new(ALTITUDE),
+                kind: None,
+                unit: "m".to_string(),
+                dims: 1,
+            },
+        ]
+    }
+
+    /// The current orientation estimate, once the filter has been initialised.
+    pub fn orientation(&self) -> Option<Quat> {
+        self.filter.as_ref().map(|f| f.q)
+    }
+
+    /// Feeds one sample taken at time `at` and returns the derived sample it produced, if any.
+    ///
+    /// `at` is what the filter uses to measure elapsed time: the local clock when running live, network time when
+    /// replaying a blob. The derived sample keeps the raw sample's `local_time`.
+    pub fn push(&mut self, sample: &Sample, at: Micros) -> Option<Sample> {
+        let channel = &sample.channel;
+        if *channel == self.config.barometer {
+            let pressure = *sample.values.first()?;
+            return Some(Sample {
+                channel: ChannelId::new(ALTITUDE),
+                local_time: sample.local_time,
+                values: vec![pressure_altitude(pressure, self.config.sea_level_pressure)],
+            });
+        }
+        if *channel == self.config.accelerometer {
+            self.accel = math::vec3(&sample.values);
+            return None;
+        }
+        if *channel == self.config.magnetometer {
+            self.mag = math::vec3(&sample.values);
+            return None;
+        }
+        if *channel != self.config.gyroscope {
+            return None;
+        }
+
+        let gyro = math::vec3(&sample.values)?;
+        let accel = self.accel?;
+        let dt = match self.last_gyro.replace(at) {
+            Some(last) if at > last && at - last <= Self::MAX_GAP => (at - last) as f64 / 1e6,
+            _ => {
+                self.filter = None;
+                return None;
+            }
+        };
+        let (kp, ki) = (self.config.kp, self.config.ki);
+        let filter = self.filter.get_or_insert_with(|| match self.mag {
+            Some(mag) => Mahony::from_vectors(accel, mag, kp, ki),
+            None => Mahony {
+                q: Quat::IDENTITY,
+                integral: [0.0; 3],
+                kp,
+                ki,
+            },
+        });
+        filter.update(gyro, accel, self.mag, dt);
+        let q = filter.q;
+
+        if self
+            .last_output
+            .is_some_and(|last| at - last < self.config.orientation_period)
+        {
+            return None;
+        }
+        self.last_output = Some(at);
+        Some(Sample {
+            channel: ChannelId::new(ORIENTATION),
+            local_time: sample.local_time,
+            values: vec![q.w, q.x, q.y, q.z],
+        })
+    }
+}
+
+/// Recomputes the derived channels for a stored hour blob.
+pub fn fuse_blob(blob: &SealedBlob, config: FusionConfig) -> Vec<TimedSample> {
+    let mut fusion = Fusion::new(config);
+    blob.samples
+        .iter()
+        .filter_map(|timed| {
+            let sample = fusion.push(&timed.sample, timed.time.network)?;
+            Some(TimedSample {
+                sample,
+                time: timed.time,
+            })
+        })
+        .collect()
+}
+
+/// Wraps a node's sensors and emits derived channels next to the raw ones.
+pub struct FusedSource<S> {
+    pub inner: S,
+    pub fusion: Fusion,
+}
+
+impl<S: SensorSource> FusedSource<S> {
+    pub fn new(inner: S, config: FusionConfig) -> Self {
+        FusedSource {
+            inner,
+            fusion: Fusion::new(config),
+        }
+    }
+}
+
+impl<S: SensorSource> SensorSource for FusedSource<S> {
+    fn channels(&self) -> Vec<ChannelInfo> {
+        let mut channels = self.inner.channels();
+        channels.extend(self.fusion.channels());
+        channels
+    }
+
+    fn poll(&mut self, now: Micros) -> Result<Vec<Sample>, SensorError> {
+        let mut samples = self.inner.poll(now)?;
+        samples.sort_by_key(|s| s.local_time);
+        let derived: Vec<Sample> = samples
+            .iter()
+            .filter_map(|s| self.fusion.push(s, s.local_time))
+            .collect();
+        samples.extend(derived);
+        Ok(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::NodeId;
+    use crate::time_sync::NetworkTime;
+
+    /// Samples per second of every simulated sensor.
+    const RATE: Micros = 100;
+    const GRAVITY: Vec3 = [0.0, 0.0, 9.81];
+    /// A field dipping 60° below north, in µT.
+    const FIELD: Vec3 = [25.0, 0.0, -43.3];
+    const GYRO_BIAS: Vec3 = [0.01, -0.02, 0.015];
+
+    /// Small deterministic noise, uniform in `[-amplitude, amplitude)`.
+    struct Noise(u64);
+
+    impl Noise {
+        fn next(&mut self, amplitude: f64) -> f64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            ((self.0 >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0) * amplitude
+        }
+
+        fn vec(&mut self, amplitude: f64) -> Vec3 {
+            [
+                self.next(amplitude),
+                self.next(amplitude),
+                self.next(amplitude),
+            ]
+        }
+    }
+
+    /// Body rates of the trajectory at `t` seconds: a steady turn with slow rolling and pitching on top.
+    fn rates(t: f64) -> Vec3 {
+        [0.3 * (0.5 * t).sin(), 0.2 * (0.3 * t).cos(), 0.4]
+    }
+
+    /// A phone tumbling through [`rates`] for `seconds`, starting tilted and facing north east: the raw samples its
+    /// sensors report, in time order, and its true orientation at every sample time.
+    fn trajectory(seconds: i64) -> (Vec<Sample>, Vec<(Micros, Quat)>) {
+        let step = 1_000_000 / RATE;
+        let mut q = Quat::from_axis_angle([0.0, 0.0, 1.0], -0.8)
+            * Quat::from_axis_angle([1.0, 0.0, 0.0], 0.3);
+        let mut noise = Noise(0x2545_f491_4f6c_dd1d);
+        let mut samples = Vec::new();
+        let mut truth = Vec::new();
+        for i in 0..seconds * RATE {
+            let at = i * step;
+            let t = at as f64 / 1e6;
+            let sample = |kind: SensorKind, values: Vec3| Sample {
+                channel: kind.channel(),
+                local_time: at,
+                values: values.to_vec(),
+            };
+            let body = q.conjugate();
+            samples.push(sample(
+                SensorKind::Accelerometer,
+                math::add(body.rotate(GRAVITY), noise.vec(0.05)),
+            ));
+            samples.push(sample(
+                SensorKind::Magnetometer,
+                math::add(body.rotate(FIELD), noise.vec(0.5)),
+            ));
+            samples.push(sample(
+                SensorKind::Gyroscope,
+                math::add(math::add(rates(t), GYRO_BIAS), noise.vec(0.005)),
+            ));
+            truth.push((at, q));
+
+            // Integrate the true attitude finely between samples.
+            for k in 0..10 {
+                let omega = rates(t + (k as f64 + 0.5) * step as f64 / 1e7);
+                let angle = math::norm(omega) * step as f64 / 1e7;
+                q = (q * Quat::from_axis_angle(omega, angle)).normalize();
+            }
+        }
+        (samples, truth)
+    }
+
+    /// Hands out samples recorded earlier, all on the first poll.
+    struct Replay(Vec<Sample>);
+
+    impl SensorSource for Replay {
+        fn channels(&self) -> Vec<ChannelInfo> {
+            Vec::new()
+        }
+
+        fn poll(&mut self, _now: Micros) -> Result<Vec<Sample>, SensorError> {
+            Ok(std::mem::take(&mut self.0))
+        }
+    }
+
+    fn orientations(samples: &[Sample]) -> Vec<(Micros, Quat)> {
+        samples
+            .iter()
+            .filter(|s| s.channel.as_str() == ORIENTATION)
+            .map(|s| {
+                let [w, x, y, z] = s.values[..] else {
+                    panic!("orientation has four values");
+                };
+                (s.local_time, Quat { w, x, y, z })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn orientation_follows_a_known_trajectory_and_learns_the_gyroscope_bias() {
+        let (samples, truth) = trajectory(120);
+        let mut fusion = Fusion::new(FusionConfig::default());
+        let derived: Vec<Sample> = samples
+            .iter()
+            .filter_map(|s| fusion.push(s, s.local_time))
+            .collect();
+        let estimated = orientations(&derived);
+        // An orientation sample every 100 ms, from the second gyroscope sample on.
+        assert_eq!(estimated.len(), 1_200);
+        assert_eq!(estimated[0].0, 10_000);
+
+        let error = |(at, q): &(Micros, Quat)| {
+            let (_, true_q) = truth.iter().find(|(t, _)| t == at).unwrap();
+            q.angle_to(*true_q).to_degrees()
+        };
+        // Starting from the accelerometer and magnetometer, the estimate is close from the first sample on, and the
+        // learned bias brings it closer still.
+        let early = estimated[..100].iter().map(error).fold(0.0, f64::max);
+        let late = estimated[600..].iter().map(error).fold(0.0, f64::max);
+        assert!(early < 3.0, "{early}° off in the first ten seconds");
+        assert!(late < 1.0, "{late}° off after a minute");
+
+        let learned = math::scale(fusion.filter.unwrap().integral, -1.0);
+        let off = math::norm(math::sub(learned, GYRO_BIAS));
+        assert!(off < 0.002, "learned gyroscope bias {learned:?}");
+    }
+
+    #[test]
+    fn altitude_comes_from_pressure() {
+        // The standard atmosphere: 898.75 hPa at 1000 m.
+        assert!(pressure_altitude(STANDARD_PRESSURE, STANDARD_PRESSURE).abs() < 1e-9);
+        assert!((pressure_altitude(898.75, STANDARD_PRESSURE) - 1000.0).abs() < 1.0);
+
+        let mut fusion = Fusion::new(FusionConfig {
+            sea_level_pressure: 1020.0,
+            ..FusionConfig::default()
+        });
+        let reading = Sample {
+            channel: SensorKind::Barometer.channel(),
+            local_time: 7,
+            values: vec![1020.0],
+        };
+        let altitude = fusion.push(&reading, 7).unwrap();
+        assert_eq!(altitude.channel.as_str(), ALTITUDE);
+        assert_eq!(altitude.local_time, 7);
+        assert!(altitude.values[0].abs() < 1e-9);
+    }
+
+    #[test]
+    fn stored_hours_fuse_like_the_live_node() {
+        let (samples, _) = trajectory(10);
+        let mut live = FusedSource::new(Replay(samples.clone()), FusionConfig::default());
+        let recorded = live.poll(Micros::MAX).unwrap();
+
+        // Network time runs a fixed offset ahead of the phone's clock.
+        let blob = SealedBlob {
+            node: NodeId(1),
+            hour: 0,
+            samples: samples
+                .iter()
+                .map(|sample| TimedSample {
+                    sample: sample.clone(),
+                    time: NetworkTime {
+                        local: sample.local_time,
+                        network: sample.local_time + 5_000,
+                        error: 0,
+                    },
+                })
+                .collect(),
+            stills: Vec::new(),
+        };
+        let replayed: Vec<Sample> = fuse_blob(&blob, FusionConfig::default())
+            .into_iter()
+            .map(|timed| timed.sample)
+            .collect();
+        let derived: Vec<Sample> = recorded
+            .into_iter()
+            .filter(|s| s.channel.as_str() == ORIENTATION)
+            .collect();
+        assert_eq!(replayed, derived);
+    }
+
+    #[test]
+    fn calibrated_inputs_are_read_where_there_are_any() {
+        let config = FusionConfig::prefer_calibrated(|raw| raw.as_str() == "magnetometer");
+        assert_eq!(config.magnetometer.as_str(), "magnetometer/calibrated");
+        assert_eq!(config.accelerometer.as_str(), "accelerometer");
+        assert_eq!(
+            config,
+            FusionConfig {
+                magnetometer: config.magnetometer.clone(),
+                ..FusionConfig::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,638 @@
+//! Hooks shared by the app's components.
+
+use std::collections::BTreeSet;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fmt;
+#[cfg(not(target_arch = "wasm32"))]
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use dioxus::prelude::*;
+use flumph::calibration::CalibrationStore;
+use flumph::config::AppConfig;
+#[cfg(not(target_arch = "wasm32"))]
+use flumph::control::Reply;
+use flumph::control::{CameraCommand, CommandClient, ControlMessage, QueuedCommand, Snapshot};
+use flumph::logs::LogBuffer;
+#[cfg(not(target_arch = "wasm32"))]
+use flumph::node::pipeline::PipelineNode;
+#[cfg(not(target_arch = "wasm32"))]
+use flumph::node::sim::SimulatedNode;
+#[cfg(not(target_arch = "wasm32"))]
+use flumph::node::{NodeBackend, NodeRole};
+use flumph::node::{NodeId, NodeStatus};
+use flumph::sensors::history::History;
+#[cfg(not(target_arch = "wasm32"))]
+use flumph::sensors::Micros;
+use flumph::sensors::{ChannelId, Sample};
+use flumph::server::PreviewFrame;
+#[cfg(not(target_arch = "wasm32"))]
+use flumph::server::{Server, ServerOptions, SharedBackend};
+#[cfg(not(target_arch = "wasm32"))]
+use flumph::storage::BlobStore;
+use flumph::storage::{BlobHash, BlobInfo, BlobQuery, SealedBlob, SeekPoint, Timeline};
+use flumph::time_sync::local_now;
+
+/// How often the app polls its node backend.
+#[cfg(not(target_arch = "wasm32"))]
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What the app reads from its node backend.
+#[derive(Clone, Copy, PartialEq)]
+pub struct NodeFeed {
+    pub status: ReadOnlySignal<NodeStatus>,
+    /// The samples from the most recent poll.
+    pub samples: ReadOnlySignal<Vec<Sample>>,
+    /// How the web build is getting on with the node that serves it.
+    #[cfg(target_arch = "wasm32")]
+    pub connection: ReadOnlySignal<crate::remote::Connection>,
+    /// Every stored blob, by node, then hour.
+    stored: ReadOnlySignal<Vec<BlobInfo>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    backend: Signal<SharedBackend>,
+}
+
+impl NodeFeed {
+    /// The stored blobs matching `query`. Reading them subscribes to the store, so the list stays current.
+    pub fn blobs(&self, query: &BlobQuery) -> Vec<BlobInfo> {
+        self.stored
+            .read()
+            .iter()
+            .filter(|info| query.matches(info))
+            .cloned()
+            .collect()
+    }
+
+    /// Opens a stored hour blob.
+    pub async fn hour(self, hash: BlobHash) -> Result<SealedBlob, String> {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.with_backend(|node| node.hour(&hash).map_err(|e| e.to_string()));
+        #[cfg(target_arch = "wasm32")]
+        return crate::remote::get(&format!("/api/hours/{hash}"))
+            .await
+            .map_err(|e| e.to_string());
+    }
+
+    /// The footage stored for UTC day `day` and what was detected in it. This opens every blob of the day, so callers
+    /// should only ask again when the store size in `status` changes.
+    pub async fn timeline(self, day: i64) -> Result<Timeline, String> {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.with_backend(|node| Ok(node.timeline(day)));
+        #[cfg(target_arch = "wasm32")]
+        return crate::remote::get(&format!("/api/timeline/{day}"))
+            .await
+            .map_err(|e| e.to_string());
+    }
+
+    /// Frames of footage from the keyframe at `point` to the end of its segment.
+    pub async fn preview(self, point: SeekPoint) -> Result<Vec<PreviewFrame>, String> {
+        #[cfg(not(target_arch = "wasm32"))]
+        return {
+            let video =
+                self.with_backend(|node| node.video(&point.video).map_err(|e| e.to_string()))?;
+            let frames =
+                flumph::encoder::preview(&video, &point.keyframe).map_err(|e| e.to_string())?;
+            Ok(frames
+                .into_iter()
+                .map(|(time, jpeg)| PreviewFrame {
+                    time,
+                    image: crate::components::format::data_url("image/jpeg", &jpeg),
+                })
+                .collect())
+        };
+        #[cfg(target_arch = "wasm32")]
+        return crate::remote::get(&format!(
+            "/api/footage/{}/{}/{}",
+            point.video, point.keyframe.segment, point.keyframe.offset
+        ))
+        .await
+        .map_err(|e| e.to_string());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn with_backend<T>(&self, f: impl FnOnce(&dyn NodeBackend) -> T) -> T {
+        let backend = self.backend.peek().clone();
+        let node = lock(&backend);
+        f(&*node)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn lock(backend: &SharedBackend) -> std::sync::MutexGuard<'_, dyn NodeBackend + Send + 'static> {
+    // The backend is only ever polled or read whole, so one left behind by a panic is still usable.
+    backend
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Runs the node `config` describes: the sensor pipeline, keeping its blobs in `blobs` beside the config, or a
+/// [`SimulatedNode`] in demo mode. Keeps its status, newest samples and stored blobs in signals, reconfigures it
+/// whenever `config` changes and starts the other kind of node when demo mode is switched.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn use_node(id: NodeId, config: ReadOnlySignal<AppConfig>) -> NodeFeed {
+    let mut demo = use_signal(|| config.peek().node.demo);
+    let mut backend = use_signal(|| start_node(id, &config.peek()));
+    use_effect(move || {
+        let config = config.read();
+        if config.node.demo != *demo.peek() {
+            demo.set(config.node.demo);
+            backend.set(start_node(id, &config));
+        }
+    });
+    use_node_feed(backend, config)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn start_node(id: NodeId, config: &AppConfig) -> SharedBackend {
+    if config.node.demo {
+        let period = 1_000_000 / Micros::from(config.sensors.rate_hz.max(1));
+        let mut node = SimulatedNode::new(id, config.node.role, period, local_now());
+        node.configure(config);
+        Arc::new(Mutex::new(node))
+    } else {
+        let node = PipelineNode::new(id, config);
+        let dir = flumph::config::default_path().with_file_name("blobs");
+        let node = match BlobStore::open(&dir) {
+            Ok(store) => node.with_store(store),
+            Err(e) => {
+                tracing::warn!(
+                    "cannot keep blobs in {}, so they are lost when the app closes: {e}",
+                    dir.display()
+                );
+                node
+            }
+        };
+        Arc::new(Mutex::new(node))
+    }
+}
+
+/// Polls whichever node `backend` holds in the background, keeping its status, newest samples and stored blobs in
+/// signals, and reconfigures it whenever `config` or the backend changes.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn use_node_feed(
+    backend: Signal<SharedBackend>,
+    config: ReadOnlySignal<AppConfig>,
+) -> NodeFeed {
+    let mut status = use_signal(|| lock(&backend.peek()).status(local_now()));
+    let mut samples = use_signal(Vec::new);
+    let mut stored = use_signal(|| lock(&backend.peek()).blobs(&BlobQuery::default()));
+    use_effect(move || {
+        let backend = backend.read().clone();
+        lock(&backend).configure(&config.read());
+    });
+    use_future(move || async move {
+        loop {
+            futures_timer::Delay::new(POLL_INTERVAL).await;
+            let now = local_now();
+            let (polled, now_status, blobs) = {
+                let backend = backend.peek().clone();
+                let mut node = lock(&backend);
+                let polled = node.poll(now);
+                (polled, node.status(now), node.blobs(&BlobQuery::default()))
+            };
+            status.set(now_status);
+            samples.set(polled);
+            if *stored.peek() != blobs {
+                stored.set(blobs);
+            }
+        }
+    });
+    NodeFeed {
+        status: status.into(),
+        samples: samples.into(),
+        stored: stored.into(),
+        backend,
+    }
+}
+
+/// Follows the compute node that served the web build over its live WebSocket, reconnecting whenever the connection
+/// drops. Until the browser is paired the node only reports that it needs to be.
+#[cfg(target_arch = "wasm32")]
+pub fn use_node(id: NodeId, config: ReadOnlySignal<AppConfig>) -> NodeFeed {
+    use crate::remote::{self, Connection, RemoteError};
+    use flumph::server::LiveUpdate;
+
+    let mut status = use_signal(|| NodeStatus::new(id, config.peek().node.role));
+    let mut samples = use_signal(Vec::new);
+    let mut stored = use_signal(Vec::new);
+    let mut connection = use_signal(|| Connection::Connecting);
+    use_future(move || async move {
+        loop {
+            match remote::get::<NodeStatus>("/api/status").await {
+                Ok(now) => status.set(now),
+                Err(e) => {
+                    connection.set(match e {
+                        RemoteError::Unpaired => Connection::Unpaired,
+                        RemoteError::Failed(e) => Connection::Lost(e),
+                    });
+                    futures_timer::Delay::new(remote::RETRY_INTERVAL).await;
+                    continue;
+                }
+            }
+            connection.set(Connection::Live);
+            let mut live = remote::live();
+            while let Ok(Some(message)) = live.recv::<Option<String>>().await {
+                let update: LiveUpdate = match serde_json::from_str(&message) {
+                    Ok(update) => update,
+                    Err(e) => {
+                        tracing::warn!("unreadable live update: {e}");
+                        continue;
+                    }
+                };
+                status.set(update.status);
+                samples.set(update.samples);
+                if let Some(blobs) = update.blobs {
+                    stored.set(blobs);
+                }
+            }
+            connection.set(Connection::Lost("the live connection closed".to_string()));
+            futures_timer::Delay::new(remote::RETRY_INTERVAL).await;
+        }
+    });
+    NodeFeed {
+        status: status.into(),
+        samples: samples.into(),
+        connection: connection.into(),
+        stored: stored.into(),
+    }
+}
+
+/// Whether the app is serving itself to other devices, and where.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerState {
+    Off,
+    Serving(SocketAddr),
+    Failed(String),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl fmt::Display for ServerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerState::Off => {
+                f.write_str("off; only a compute node with remote access enabled serves the app")
+            }
+            ServerState::Serving(addr) => write!(f, "serving on {addr}"),
+            ServerState::Failed(e) => write!(f, "not serving: {e}"),
+        }
+    }
+}
+
+/// Serves the app and an API over `node` to the local network while the config makes this a compute node with remote
+/// access enabled, restarting the server whenever its settings or the node's backend change. A config without a
+/// pairing token is given one.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn use_server(file: ConfigFile, node: NodeFeed) -> ReadOnlySignal<ServerState> {
+    let mut state = use_signal(|| ServerState::Off);
+    let mut running = use_signal(|| None::<(ServerOptions, SharedBackend, Server)>);
+    use_effect(move || {
+        let config = file.config().read().clone();
+        let backend = node.backend.read().clone();
+        if config.node.role != NodeRole::Compute || !config.server.enabled {
+            running.set(None);
+            state.set(ServerState::Off);
+            return;
+        }
+        let Some(token) = config.server.token.clone() else {
+            let mut config = config;
+            config.server.token = Some(flumph::server::new_token());
+            file.apply(config);
+            return;
+        };
+        let Ok(listen) = config.server.listen.parse() else {
+            state.set(ServerState::Failed(format!(
+                "{} is not an address",
+                config.server.listen
+            )));
+            return;
+        };
+        let options = ServerOptions {
+            listen,
+            web_root: config.server.web_root,
+            token,
+        };
+        if running
+            .peek()
+            .as_ref()
+            .is_some_and(|(running, serving, _)| {
+                *running == options && Arc::ptr_eq(serving, &backend)
+            })
+        {
+            return;
+        }
+        // The old server has to let go of its port before a new one can take it.
+        running.set(None);
+        match Server::start(options.clone(), backend.clone()) {
+            Ok(server) => {
+                state.set(ServerState::Serving(server.local_addr()));
+                running.set(Some((options, backend, server)));
+            }
+            Err(e) => {
+                tracing::warn!("cannot serve on {}: {e}", options.listen);
+                state.set(ServerState::Failed(format!(
+                    "cannot listen on {}: {e}",
+                    options.listen
+                )));
+            }
+        }
+    });
+    use_effect(move || {
+        let samples = node.samples.read();
+        if let Some((_, _, server)) = running.peek().as_ref() {
+            server.publish(&samples);
+        }
+    });
+    state.into()
+}
+
+/// The app's config, and the file it is kept in.
+#[derive(Clone, Copy, PartialEq)]
+pub struct ConfigFile {
+    config: Signal<AppConfig>,
+    path: Signal<Option<PathBuf>>,
+    problem: Signal<Option<String>>,
+}
+
+impl ConfigFile {
+    pub fn config(&self) -> ReadOnlySignal<AppConfig> {
+        self.config.into()
+    }
+
+    /// Where the config is saved. The browser has no files, so there it lasts until the page is closed.
+    pub fn path(&self) -> Option<PathBuf> {
+        self.path.read().clone()
+    }
+
+    /// Why the config could not be read or written the last time it was tried, if it could not.
+    pub fn problem(&self) -> Option<String> {
+        self.problem.read().clone()
+    }
+
+    /// Uses `config` from now on and saves it. It should already be valid.
+    pub fn apply(mut self, config: AppConfig) {
+        if let Some(path) = self.path.peek().as_ref() {
+            self.problem
+                .set(config.save(path).err().map(|e| e.to_string()));
+        }
+        self.config.set(config);
+    }
+}
+
+/// Loads the config from [`flumph::config::default_path`]. A file that cannot be used leaves the defaults in place
+/// until the user saves over it.
+pub fn use_config() -> ConfigFile {
+    let path = use_signal(|| cfg!(not(target_arch = "wasm32")).then(flumph::config::default_path));
+    let (loaded, failed) = use_hook(|| match path.peek().as_ref() {
+        Some(path) => match AppConfig::load(path) {
+            Ok(config) => (config, None),
+            Err(e) => (
+                AppConfig::default(),
+                Some(format!("{}: {e}; using the defaults", path.display())),
+            ),
+        },
+        None => (AppConfig::default(), None),
+    });
+    let config = use_signal(|| loaded);
+    let problem = use_signal(|| failed);
+    ConfigFile {
+        config,
+        path,
+        problem,
+    }
+}
+
+/// Folds every batch of samples into a [`History`] for the charts.
+pub fn use_sensor_history(samples: ReadOnlySignal<Vec<Sample>>) -> ReadOnlySignal<History> {
+    let mut history = use_signal(History::new);
+    use_effect(move || {
+        let samples = samples.read();
+        let mut history = history.write();
+        for sample in samples.iter() {
+            history.push(sample);
+        }
+    });
+    history.into()
+}
+
+/// How often unacknowledged camera commands are checked for resending.
+const RETRY_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The app's control link to the cameras its node can reach.
+#[derive(Clone, Copy, PartialEq)]
+pub struct CameraLink {
+    /// Cameras the node passes commands to, with the node each is on.
+    pub cameras: Memo<Vec<(NodeId, ChannelId)>>,
+    pub client: Signal<CommandClient>,
+    /// The most recent snapshot a camera sent back.
+    pub snapshot: Signal<Option<Snapshot>>,
+    node: NodeFeed,
+}
+
+impl CameraLink {
+    /// The node `camera` is on, if it can be reached.
+    pub fn target(&self, camera: &ChannelId) -> Option<NodeId> {
+        self.cameras
+            .read()
+            .iter()
+            .find(|(_, known)| known == camera)
+            .map(|(node, _)| *node)
+    }
+
+    /// Sends a command to the camera, or queues it while its node cannot be reached.
+    pub fn send(mut self, camera: ChannelId, command: CameraCommand) {
+        let Some(target) = self.target(&camera) else {
+            return;
+        };
+        let now = local_now();
+        let (_, message) = self.client.write().issue(target, camera, command, now);
+        if let Some(message) = message {
+            self.deliver(target, message);
+        }
+    }
+
+    /// How many commands are waiting for their camera node to be back.
+    pub fn queued(&self) -> usize {
+        self.client.read().queued().len()
+    }
+
+    /// Hands a message to the node to pass on to `target`.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn deliver(mut self, target: NodeId, message: ControlMessage) {
+        let now = local_now();
+        let backend = self.node.backend.peek().clone();
+        let Some(reply) = lock(&backend).control(target, message, now) else {
+            return;
+        };
+        if let Some((_, Ok(Reply::Snapshot(snapshot)))) = self.client.write().handle(reply, now) {
+            self.snapshot.set(Some(snapshot));
+        }
+    }
+
+    /// The browser lists no cameras, so it never has anything to send.
+    #[cfg(target_arch = "wasm32")]
+    fn deliver(self, target: NodeId, message: ControlMessage) {
+        tracing::debug!("the browser cannot pass {message:?} on to {target}");
+    }
+}
+
+/// The node's calibration profiles, kept in `calibration.json` beside the config and handed to `node` whenever they
+/// change. The browser has no files, so there they last until the page is closed.
+pub fn use_calibration(file: ConfigFile, node: NodeFeed) -> Signal<CalibrationStore> {
+    let path = use_hook(|| {
+        file.path
+            .peek()
+            .as_ref()
+            .map(|config| config.with_file_name("calibration.json"))
+    });
+    let loaded = use_hook(|| match &path {
+        Some(path) => CalibrationStore::load(path).unwrap_or_else(|e| {
+            tracing::warn!("cannot read calibration from {}: {e}", path.display());
+            CalibrationStore::default()
+        }),
+        None => CalibrationStore::default(),
+    });
+    let store = use_signal(|| loaded.clone());
+    use_effect(move || {
+        let store = store.read();
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let backend = node.backend.read().clone();
+            lock(&backend).calibrate(&store);
+        }
+        // The node serving the browser calibrates with the profiles it keeps itself.
+        #[cfg(target_arch = "wasm32")]
+        let _ = node;
+        // An unreadable file is left alone until there is something new to write over it.
+        if let Some(path) = path.as_ref().filter(|_| *store != loaded) {
+            if let Err(e) = store.save(path) {
+                tracing::warn!("cannot save calibration to {}: {e}", path.display());
+            }
+        }
+    });
+    store
+}
+
+/// Sends commands from node `id` to the cameras `node` reports, through the node, resending them in the background
+/// until they are acknowledged. The node takes commands for its own cameras and passes the rest on to its peers.
+/// While a camera's node is not connected, commands for it are queued instead, and the queue is kept in
+/// `queue.json` beside the config so that it survives the app being closed.
+///
+/// The server's API is read only, so in the browser the link lists no cameras and sends nothing.
+pub fn use_camera_link(id: NodeId, node: NodeFeed) -> CameraLink {
+    let queue_file = use_hook(|| {
+        cfg!(not(target_arch = "wasm32"))
+            .then(|| flumph::config::default_path().with_file_name("queue.json"))
+    });
+    let mut link = CameraLink {
+        cameras: use_memo(move || {
+            if cfg!(target_arch = "wasm32") {
+                return Vec::new();
+            }
+            node.status.read().cameras.clone()
+        }),
+        client: use_signal(|| {
+            let now = local_now();
+            let mut client = CommandClient::new(id, now as u64);
+            if let Some(path) = &queue_file {
+                client.restore(load_queue(path), now);
+            }
+            client
+        }),
+        snapshot: use_signal(|| None),
+        node,
+    };
+    use_future(move || async move {
+        loop {
+            futures_timer::Delay::new(RETRY_INTERVAL).await;
+            let now = local_now();
+            let status = node.status.peek().clone();
+            let mut client = link.client.write();
+            let targets: BTreeSet<NodeId> = (link.cameras.peek().iter().map(|(node, _)| *node))
+                .chain(client.queued().iter().map(|queued| queued.target))
+                .collect();
+            let mut outgoing = Vec::new();
+            for target in targets {
+                let connected = target == status.id
+                    || status
+                        .peers
+                        .iter()
+                        .any(|peer| peer.id == target && peer.connected);
+                outgoing.extend(client.set_reachable(target, connected, now));
+            }
+            outgoing.extend(client.retries(now));
+            drop(client);
+            for (target, message) in outgoing {
+                link.deliver(target, message);
+            }
+        }
+    });
+    let queued = use_memo(move || link.client.read().queued());
+    use_effect(move || {
+        let queued = queued.read();
+        if let Some(path) = &queue_file {
+            if let Err(e) = save_queue(path, &queued) {
+                tracing::warn!("cannot save queued commands to {}: {e}", path.display());
+            }
+        }
+    });
+    link
+}
+
+fn load_queue(path: &Path) -> Vec<QueuedCommand> {
+    let loaded = std::fs::read(path).and_then(|bytes| Ok(serde_json::from_slice(&bytes)?));
+    match loaded {
+        Ok(queued) => queued,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => {
+            tracing::warn!(
+                "dropping unreadable queued commands in {}: {e}",
+                path.display()
+            );
+            Vec::new()
+        }
+    }
+}
+
+/// Writes the queue beside its old copy and swaps it in, so that a crash leaves one or the other whole.
+fn save_queue(path: &Path, queued: &[QueuedCommand]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let temporary = path.with_extension("json.tmp");
+    std::fs::write(&temporary, serde_json::to_vec_pretty(queued)?)?;
+    std::fs::rename(&temporary, path)
+}
+
+/// How often the log is checked for new records.
+const LOG_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The app's log, as `main` sets it up before launching.
+#[derive(Clone)]
+pub struct AppLog {
+    pub buffer: LogBuffer,
+    /// Where records are also written, if the file could be opened.
+    pub file: Option<PathBuf>,
+}
+
+/// How many records the log has taken, which changes whenever there is a new one for readers of the buffer to pick
+/// up.
+pub fn use_log_updates(buffer: LogBuffer) -> ReadOnlySignal<u64> {
+    let mut pushed = use_signal(|| buffer.pushed());
+    use_future(move || {
+        let buffer = buffer.clone();
+        async move {
+            loop {
+                futures_timer::Delay::new(LOG_POLL_INTERVAL).await;
+                let now = buffer.pushed();
+                if now != *pushed.peek() {
+                    pushed.set(now);
+                }
+            }
+        }
+    });
+    pushed.into()
+}
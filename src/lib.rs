@@ -0,0 +1,20 @@
+//! Core library for flumph nodes.
+//!
+//! Everything that does not need a renderer lives here so that the Dioxus app in `main.rs` stays a thin shell, and so
+//! that the same code can eventually run on sensor nodes, compute nodes and embedded targets alike.
+
+pub mod analytics;
+pub mod calibration;
+pub mod camera;
+pub mod config;
+pub mod control;
+pub mod encoder;
+pub mod fusion;
+pub mod live;
+pub mod logs;
+pub mod math;
+pub mod node;
+pub mod sensors;
+pub mod server;
+pub mod storage;
+pub mod time_sync;
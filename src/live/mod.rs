@@ -0,0 +1,285 @@
+//! Live view of a camera over WebRTC.
+//!
+//! A camera node runs a [`LivePublisher`] that hands the packets its encoder already produces to every subscribed
+//! viewer as an AV1 RTP track, so watching costs no extra encoding. A viewer on another node opens a recvonly session
+//! with [`LiveViewer`]; any WebRTC peer that offers AV1 would do as well.
+//!
+//! Signalling is a handful of [`LiveSignal`] messages that the caller carries between the two nodes, the same way
+//! [`crate::time_sync`] leaves moving its messages to the caller. The media itself goes over UDP, along with a data
+//! channel for [`crate::control`] messages; both ends are sans-IO state machines that [`udp::pump`] can drive from a
+//! socket.
+//!
+//! This covers live view between two nodes, and only as a library: the app does not use either end yet. Nodes have no
+//! link between them to carry the signals, and a camera node only encodes while there is motion, so between events
+//! there would be nothing to watch. Watching from the app, and from a browser, is left to a follow-up request (see
+//! the README): a browser would subscribe through its own `RTCPeerConnection`, with the compute node that served it
+//! carrying the signals, so both ends are left out of the web build.
+
+#[cfg(not(target_arch = "wasm32"))]
+mod publisher;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod udp;
+#[cfg(not(target_arch = "wasm32"))]
+mod viewer;
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::node::NodeId;
+use crate::sensors::ChannelId;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use publisher::{LivePublisher, ViewerInfo};
+#[cfg(not(target_arch = "wasm32"))]
+pub use viewer::{LiveFrame, LiveViewer, ViewerState};
+
+/// Label of the data channel viewers open alongside the video to carry [`crate::control::ControlMessage`]s.
+pub const CONTROL_CHANNEL: &str = "control";
+
+/// Signalling between a viewer and a publisher. Session ids are picked by the viewer and only need to be unique
+/// among its own sessions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LiveSignal {
+    /// Viewer to publisher: an SDP offer to receive `camera`.
+    Subscribe {
+        session: u64,
+        camera: ChannelId,
+        offer: String,
+    },
+    /// Publisher to viewer: the SDP answer for an accepted subscription.
+    Accept { session: u64, answer: String },
+    /// Publisher to viewer.
+    Reject { session: u64, reason: RejectReason },
+    /// Either way: the session is over.
+    Close { session: u64 },
+}
+
+impl LiveSignal {
+    pub fn session(&self) -> u64 {
+        match self {
+            LiveSignal::Subscribe { session, .. }
+            | LiveSignal::Accept { session, .. }
+            | LiveSignal::Reject { session, .. }
+            | LiveSignal::Close { session } => *session,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        postcard::to_stdvec(self).expect("live signals always serialize")
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, postcard::Error> {
+        postcard::from_bytes(bytes)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectReason {
+    Unauthorized,
+    UnknownCamera,
+    TooManyViewers,
+    /// The offer could not be parsed or has nothing the publisher can send, such as no AV1.
+    BadOffer(String),
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::Unauthorized => f.write_str("not authorized to view this node"),
+            RejectReason::UnknownCamera => f.write_str("no such camera"),
+            RejectReason::TooManyViewers => f.write_str("too many viewers"),
+            RejectReason::BadOffer(e) => write!(f, "unusable offer: {e}"),
+        }
+    }
+}
+
+/// Which nodes may watch a publisher's cameras.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LiveAccess {
+    /// Nobody, the default.
+    #[default]
+    Nobody,
+    Nodes(BTreeSet<NodeId>),
+    /// Any node that can reach the publisher, for networks where every node is trusted.
+    Anyone,
+}
+
+impl LiveAccess {
+    pub fn allows(&self, node: NodeId) -> bool {
+        match self {
+            LiveAccess::Nobody => false,
+            LiveAccess::Nodes(nodes) => nodes.contains(&node),
+            LiveAccess::Anyone => true,
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::net::UdpSocket;
+    use std::time::{Duration, Instant};
+
+    use super::udp::pump;
+    use super::*;
+    use crate::camera::{CameraSource, FormatRequest, TestPattern};
+    use crate::control::sim::SimulatedCameras;
+    use crate::control::{CameraCommand, CommandHandler, CommandId, ControlMessage, Reply};
+    use crate::encoder::{
+        default_backends, Av1Decoder, EncoderSettings, FallbackEncoder, Packet, VideoCodec,
+        VideoEncoder,
+    };
+
+    const CAMERA_NODE: NodeId = NodeId(1);
+    const VIEWER: NodeId = NodeId(2);
+
+    /// A couple of seconds of the test pattern, as the camera node's encoder makes it for recording.
+    fn clip() -> Vec<Packet> {
+        let settings = EncoderSettings {
+            width: 96,
+            height: 72,
+            fps: 10,
+            bitrate_kbps: 64,
+            keyframe_interval: 10,
+            lookahead: 1,
+            ..EncoderSettings::default()
+        };
+        let mut pattern = TestPattern::open(FormatRequest::Any).unwrap();
+        let mut encoder =
+            FallbackEncoder::new(default_backends(), vec![VideoCodec::Av1], settings).unwrap();
+        let mut packets = Vec::new();
+        for i in 0..20 {
+            let frame = pattern.frame(i * 100_000).unwrap().unwrap();
+            packets.extend(encoder.encode(&frame.resized(96, 72)).unwrap());
+        }
+        packets.extend(encoder.flush().unwrap());
+        packets
+    }
+
+    fn socket() -> UdpSocket {
+        UdpSocket::bind("127.0.0.1:0").unwrap()
+    }
+
+    /// Signals go as bytes over whatever link the two nodes share.
+    fn carry(signal: LiveSignal) -> LiveSignal {
+        LiveSignal::decode(&signal.encode()).unwrap()
+    }
+
+    #[test]
+    fn a_viewer_on_another_node_watches_the_recorded_stream_and_sends_commands() {
+        let clip = clip();
+        let camera = ChannelId::new("camera0");
+        let (publishing, viewing) = (socket(), socket());
+        let mut publisher = LivePublisher::new(
+            vec![publishing.local_addr().unwrap()],
+            LiveAccess::Nodes([VIEWER].into()),
+        );
+        publisher.add_camera(camera.clone());
+        let (mut viewer, subscribe) = LiveViewer::new(
+            CAMERA_NODE,
+            camera.clone(),
+            1,
+            &[viewing.local_addr().unwrap()],
+            Instant::now(),
+        );
+        for reply in publisher.handle_signal(VIEWER, carry(subscribe), Instant::now()) {
+            viewer.handle_signal(carry(reply));
+        }
+        assert_eq!(viewer.state(), &ViewerState::Connecting);
+
+        let mut handler = CommandHandler::new(LiveAccess::Nodes([VIEWER].into()));
+        let mut cameras = SimulatedCameras::new([camera.clone()]);
+        let command = ControlMessage::Command {
+            id: CommandId {
+                issuer: VIEWER,
+                seq: 1,
+            },
+            camera: camera.clone(),
+            command: CameraCommand::SetFps(5),
+        };
+        let (mut received, mut replies) = (Vec::new(), Vec::new());
+        let (mut next, mut commanded) = (0, false);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while received.len() < clip.len() || replies.is_empty() {
+            assert!(
+                Instant::now() < deadline,
+                "{} of {} frames and {} replies arrived",
+                received.len(),
+                clip.len(),
+                replies.len()
+            );
+            pump(&publishing, &mut publisher).unwrap();
+            pump(&viewing, &mut viewer).unwrap();
+            if publisher.viewers().iter().any(|v| v.connected) && next < clip.len() {
+                publisher.push(&camera, &clip[next], Instant::now());
+                next += 1;
+            }
+            if !commanded {
+                commanded = viewer.send_control(&command);
+            }
+            for (from, message) in publisher.take_control() {
+                let ack = handler.handle(from, message, &mut cameras, 0).unwrap();
+                assert!(publisher.send_control(from, &ack));
+            }
+            received.extend(viewer.take_frames());
+            replies.extend(viewer.take_control());
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(viewer.state(), &ViewerState::Connected);
+
+        // The frames are the camera node's own packets, decodable as they come.
+        let mut decoder = Av1Decoder::new(1).unwrap();
+        let mut decoded = Vec::new();
+        for frame in &received {
+            assert!(frame.contiguous);
+            decoded.extend(decoder.decode(&frame.data, frame.captured).unwrap());
+        }
+        assert!(decoded.len() >= clip.len() - 1);
+        assert!(decoded.iter().all(|f| (f.width, f.height) == (96, 72)));
+        // Capture times come through on the 90 kHz RTP clock.
+        assert_eq!(received.len(), clip.len());
+        for (frame, packet) in received.iter().zip(&clip) {
+            assert!((frame.captured - packet.captured).abs() < 1_000);
+        }
+
+        assert!(matches!(
+            &replies[..],
+            [ControlMessage::Ack {
+                result: Ok(Reply::Done),
+                ..
+            }]
+        ));
+        assert_eq!(cameras.state(&camera).unwrap().encoder.fps, 5);
+
+        assert!(publisher
+            .handle_signal(VIEWER, carry(viewer.close()), Instant::now())
+            .is_empty());
+        assert!(publisher.viewers().is_empty());
+    }
+
+    #[test]
+    fn viewers_the_camera_node_does_not_allow_are_turned_away() {
+        let camera = ChannelId::new("camera0");
+        let mut publisher =
+            LivePublisher::new(vec![socket().local_addr().unwrap()], LiveAccess::Nobody);
+        publisher.add_camera(camera.clone());
+        let candidates = [socket().local_addr().unwrap()];
+        let (mut viewer, subscribe) =
+            LiveViewer::new(CAMERA_NODE, camera, 7, &candidates, Instant::now());
+        let replies = publisher.handle_signal(VIEWER, subscribe, Instant::now());
+        assert_eq!(
+            replies,
+            vec![LiveSignal::Reject {
+                session: 7,
+                reason: RejectReason::Unauthorized
+            }]
+        );
+        viewer.handle_signal(replies[0].clone());
+        assert_eq!(
+            viewer.state(),
+            &ViewerState::Rejected(RejectReason::Unauthorized)
+        );
+        assert!(publisher.viewers().is_empty());
+    }
+}
@@ -0,0 +1,342 @@
+//! The camera node's end of live view.
+
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use str0m::change::SdpOffer;
+use str0m::channel::ChannelId as DataChannelId;
+use str0m::format::Codec;
+use str0m::media::{MediaTime, Mid};
+use str0m::{Candidate, Event, IceConnectionState, Rtc};
+
+use super::udp::{self, Datagram, Endpoint};
+use super::{LiveAccess, LiveSignal, RejectReason, CONTROL_CHANNEL};
+use crate::control::ControlMessage;
+use crate::encoder::Packet;
+use crate::node::NodeId;
+use crate::sensors::ChannelId;
+
+struct Session {
+    viewer: NodeId,
+    id: u64,
+    camera: ChannelId,
+    rtc: Rtc,
+    mid: Option<Mid>,
+    control: Option<DataChannelId>,
+    connected: bool,
+    /// Whether the viewer has been sent a keyframe to start decoding from.
+    synced: bool,
+    timeout: Option<Instant>,
+}
+
+/// A viewer as shown in the camera node's UI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ViewerInfo {
+    pub viewer: NodeId,
+    pub session: u64,
+    pub camera: ChannelId,
+    pub connected: bool,
+}
+
+/// Serves live view of a node's cameras to any number of viewers, each over its own WebRTC session.
+pub struct LivePublisher {
+    pub access: LiveAccess,
+    pub max_viewers: usize,
+    candidates: Vec<SocketAddr>,
+    cameras: BTreeSet<ChannelId>,
+    sessions: Vec<Session>,
+    datagrams: Vec<Datagram>,
+    keyframe_requests: BTreeSet<ChannelId>,
+    control: Vec<(NodeId, ControlMessage)>,
+}
+
+impl LivePublisher {
+    /// `candidates` are the UDP addresses viewers can reach this node on, each with a socket bound to it.
+    pub fn new(candidates: Vec<SocketAddr>, access: LiveAccess) -> Self {
+        LivePublisher {
+            access,
+            max_viewers: 4,
+            candidates,
+            cameras: BTreeSet::new(),
+            sessions: Vec::new(),
+            datagrams: Vec::new(),
+            keyframe_requests: BTreeSet::new(),
+            control: Vec::new(),
+        }
+    }
+
+    pub fn add_camera(&mut self, camera: ChannelId) {
+        self.cameras.insert(camera);
+    }
+
+    /// Stops publishing `camera`, ending its sessions.
+    pub fn remove_camera(&mut self, camera: &ChannelId) -> Vec<(NodeId, LiveSignal)> {
+        self.cameras.remove(camera);
+        let mut closed = Vec::new();
+        for session in self.sessions.iter_mut().filter(|s| s.camera == *camera) {
+            session.rtc.disconnect();
+            closed.push((
+                session.viewer,
+                LiveSignal::Close {
+                    session: session.id,
+                },
+            ));
+        }
+        self.sessions.retain(|s| s.rtc.is_alive());
+        closed
+    }
+
+    pub fn viewers(&self) -> Vec<ViewerInfo> {
+        self.sessions
+            .iter()
+            .map(|s| ViewerInfo {
+                viewer: s.viewer,
+                session: s.id,
+                camera: s.camera.clone(),
+                connected: s.connected,
+            })
+            .collect()
+    }
+
+    /// Handles a signal from `from`, returning the replies to send back to it.
+    pub fn handle_signal(
+        &mut self,
+        from: NodeId,
+        signal: LiveSignal,
+        now: Instant,
+    ) -> Vec<LiveSignal> {
+        match signal {
+            LiveSignal::Subscribe {
+                session,
+                camera,
+                offer,
+            } => {
+                let reply = match self.subscribe(from, session, camera, &offer, now) {
+                    Ok(answer) => LiveSignal::Accept { session, answer },
+                    Err(reason) => {
+                        tracing::info!("rejected live view from {from}: {reason}");
+                        LiveSignal::Reject { session, reason }
+                    }
+                };
+                vec![reply]
+            }
+            LiveSignal::Close { session } => {
+                self.close(from, session);
+                Vec::new()
+            }
+            LiveSignal::Accept { .. } | LiveSignal::Reject { .. } => Vec::new(),
+        }
+    }
+
+    fn subscribe(
+        &mut self,
+        viewer: NodeId,
+        id: u64,
+        camera: ChannelId,
+        offer: &str,
+        now: Instant,
+    ) -> Result<String, RejectReason> {
+        if !self.access.allows(viewer) {
+            return Err(RejectReason::Unauthorized);
+        }
+        if !self.cameras.contains(&camera) {
+            return Err(RejectReason::UnknownCamera);
+        }
+        // A repeated subscription replaces the session it repeats.
+        self.close(viewer, id);
+        if self.sessions.len() >= self.max_viewers {
+            return Err(RejectReason::TooManyViewers);
+        }
+        // Sessions only learn which media line is theirs once connected, so check up front that there is one.
+        if !offer
+            .lines()
+            .any(|l| l.starts_with("a=rtpmap:") && l.contains(" AV1/90000"))
+        {
+            return Err(RejectReason::BadOffer("no AV1 video".to_string()));
+        }
+        let offer =
+            SdpOffer::from_sdp_string(offer).map_err(|e| RejectReason::BadOffer(e.to_string()))?;
+
+        let mut rtc = Rtc::builder().clear_codecs().enable_av1(true).build(now);
+        for &address in &self.candidates {
+            match Candidate::host(address, "udp") {
+                Ok(candidate) => {
+                    rtc.add_local_candidate(candidate);
+                }
+                Err(e) => tracing::warn!("cannot offer {address} to viewers: {e}"),
+            }
+        }
+        let answer = rtc
+            .sdp_api()
+            .accept_offer(offer)
+            .map_err(|e| RejectReason::BadOffer(e.to_string()))?;
+
+        let mut session = Session {
+            viewer,
+            id,
+            camera: camera.clone(),
+            rtc,
+            mid: None,
+            control: None,
+            connected: false,
+            synced: false,
+            timeout: None,
+        };
+        self.drain(&mut session);
+        tracing::info!("{viewer} is watching {camera}");
+        self.sessions.push(session);
+        Ok(answer.to_sdp_string())
+    }
+
+    fn close(&mut self, viewer: NodeId, id: u64) {
+        self.sessions.retain_mut(|s| {
+            if s.viewer == viewer && s.id == id {
+                s.rtc.disconnect();
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Sends a packet from `camera`'s encoder to everyone watching it. Viewers that have not had a keyframe yet skip
+    /// packets until the next one, which [`LivePublisher::take_keyframe_requests`] asks for.
+    pub fn push(&mut self, camera: &ChannelId, packet: &Packet, now: Instant) {
+        let mut sessions = std::mem::take(&mut self.sessions);
+        for session in sessions.iter_mut().filter(|s| s.camera == *camera) {
+            let Some(mid) = session.mid.filter(|_| session.connected) else {
+                continue;
+            };
+            if !session.synced && !packet.keyframe {
+                continue;
+            }
+            let Some(mut writer) = session.rtc.writer(mid) else {
+                continue;
+            };
+            let Some(pt) = writer
+                .payload_params()
+                .find(|p| p.spec().codec == Codec::Av1)
+                .map(|p| p.pt())
+            else {
+                continue;
+            };
+            // Capture times are only ever compared with each other, so their epoch does not matter.
+            let time = MediaTime::from_micros(packet.captured.max(0) as u64);
+            writer = writer.playout_delay(MediaTime::ZERO, MediaTime::from_millis(200));
+            match writer.write(pt, now, time, packet.data.clone()) {
+                Ok(()) => session.synced = true,
+                Err(e) => tracing::debug!("dropping live packet for {}: {e}", session.viewer),
+            }
+            self.drain(session);
+        }
+        self.sessions = sessions;
+        self.sessions.retain(|s| s.rtc.is_alive());
+    }
+
+    /// Cameras whose encoders should produce a keyframe as soon as they can, because a viewer joined or lost
+    /// packets.
+    pub fn take_keyframe_requests(&mut self) -> BTreeSet<ChannelId> {
+        std::mem::take(&mut self.keyframe_requests)
+    }
+
+    /// Control messages viewers sent over their sessions' data channels since the last call.
+    pub fn take_control(&mut self) -> Vec<(NodeId, ControlMessage)> {
+        std::mem::take(&mut self.control)
+    }
+
+    /// Sends a control message over the data channel of one of `viewer`'s sessions, returning whether there was one
+    /// with room for it.
+    pub fn send_control(&mut self, viewer: NodeId, message: &ControlMessage) -> bool {
+        let bytes = message.encode();
+        let mut sessions = std::mem::take(&mut self.sessions);
+        let mut sent = false;
+        for session in sessions.iter_mut().filter(|s| s.viewer == viewer) {
+            let Some(mut channel) = session.control.and_then(|id| session.rtc.channel(id)) else {
+                continue;
+            };
+            sent = channel.write(true, &bytes).unwrap_or(false);
+            if sent {
+                self.drain(session);
+                break;
+            }
+        }
+        self.sessions = sessions;
+        sent
+    }
+
+    fn drain(&mut self, session: &mut Session) {
+        let (datagrams, requests) = (&mut self.datagrams, &mut self.keyframe_requests);
+        let (mid, control, connected) = (
+            &mut session.mid,
+            &mut session.control,
+            &mut session.connected,
+        );
+        let (viewer, camera, incoming) = (session.viewer, &session.camera, &mut self.control);
+        session.timeout = udp::drain(&mut session.rtc, datagrams, |rtc, event| match event {
+            Event::MediaAdded(added) => *mid = Some(added.mid),
+            Event::ChannelOpen(id, label) if label == CONTROL_CHANNEL => *control = Some(id),
+            Event::ChannelData(data) if Some(data.id) == *control => {
+                match ControlMessage::decode(&data.data) {
+                    Ok(message) => incoming.push((viewer, message)),
+                    Err(e) => tracing::debug!("bad control message from {viewer}: {e}"),
+                }
+            }
+            Event::Connected => {
+                *connected = true;
+                requests.insert(camera.clone());
+            }
+            Event::KeyframeRequest(_) => {
+                requests.insert(camera.clone());
+            }
+            Event::IceConnectionStateChange(IceConnectionState::Disconnected) => rtc.disconnect(),
+            _ => {}
+        });
+    }
+}
+
+impl Endpoint for LivePublisher {
+    fn receive(&mut self, now: Instant, datagram: Datagram) {
+        let Some(input) = udp::input(now, &datagram) else {
+            return;
+        };
+        let mut sessions = std::mem::take(&mut self.sessions);
+        if let Some(session) = sessions.iter_mut().find(|s| s.rtc.accepts(&input)) {
+            if let Err(e) = session.rtc.handle_input(input) {
+                tracing::debug!("live session with {} failed: {e}", session.viewer);
+                session.rtc.disconnect();
+            }
+            self.drain(session);
+        }
+        self.sessions = sessions;
+        self.sessions.retain(|s| s.rtc.is_alive());
+    }
+
+    fn timeout(&mut self, now: Instant) {
+        let mut sessions = std::mem::take(&mut self.sessions);
+        for session in sessions.iter_mut() {
+            if session.timeout.is_some_and(|t| t <= now) {
+                if let Err(e) = session.rtc.handle_input(str0m::Input::Timeout(now)) {
+                    tracing::debug!("live session with {} failed: {e}", session.viewer);
+                    session.rtc.disconnect();
+                }
+                self.drain(session);
+            }
+        }
+        self.sessions = sessions;
+        self.sessions.retain(|s| {
+            if !s.rtc.is_alive() {
+                tracing::info!("{} stopped watching {}", s.viewer, s.camera);
+            }
+            s.rtc.is_alive()
+        });
+    }
+
+    fn next_timeout(&self) -> Option<Instant> {
+        self.sessions.iter().filter_map(|s| s.timeout).min()
+    }
+
+    fn take_datagrams(&mut self) -> Vec<Datagram> {
+        std::mem::take(&mut self.datagrams)
+    }
+}
@@ -0,0 +1,114 @@
+//! Moving the datagrams of a live session through a UDP socket.
+
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Instant;
+
+use str0m::net::{Protocol, Receive};
+use str0m::{Event, Input, Output, Rtc};
+
+/// A UDP datagram to or from a live session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub contents: Vec<u8>,
+}
+
+/// Either end of a live session, as seen by the code that owns its socket.
+pub trait Endpoint {
+    /// Feeds in a datagram that arrived at `destination`, one of the endpoint's candidate addresses.
+    fn receive(&mut self, now: Instant, datagram: Datagram);
+
+    /// Lets timers that are due fire.
+    fn timeout(&mut self, now: Instant);
+
+    /// When [`Endpoint::timeout`] next needs calling, if ever.
+    fn next_timeout(&self) -> Option<Instant>;
+
+    /// Datagrams waiting to be sent.
+    fn take_datagrams(&mut self) -> Vec<Datagram>;
+}
+
+/// Does everything `endpoint` can do without waiting: reads every datagram waiting on `socket`, fires due timers and
+/// sends what that produced. Returns when it next needs calling.
+///
+/// `socket` must be bound to the address the endpoint was given as its candidate, not to an unspecified address,
+/// since that is what sessions match incoming datagrams against.
+pub fn pump(socket: &UdpSocket, endpoint: &mut dyn Endpoint) -> io::Result<Option<Instant>> {
+    socket.set_nonblocking(true)?;
+    let local = socket.local_addr()?;
+    let mut buffer = vec![0; 2000];
+    loop {
+        match socket.recv_from(&mut buffer) {
+            Ok((n, source)) => endpoint.receive(
+                Instant::now(),
+                Datagram {
+                    source,
+                    destination: local,
+                    contents: buffer[..n].to_vec(),
+                },
+            ),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            // ICMP port unreachable from a viewer that went away shows up here on some platforms.
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    let now = Instant::now();
+    if endpoint.next_timeout().is_some_and(|t| t <= now) {
+        endpoint.timeout(now);
+    }
+    for datagram in endpoint.take_datagrams() {
+        if datagram.source != local {
+            tracing::warn!(
+                "dropping datagram from {} on socket bound to {local}",
+                datagram.source
+            );
+            continue;
+        }
+        if let Err(e) = socket.send_to(&datagram.contents, datagram.destination) {
+            if e.kind() != io::ErrorKind::WouldBlock {
+                tracing::debug!("sending to {} failed: {e}", datagram.destination);
+            }
+        }
+    }
+    Ok(endpoint.next_timeout())
+}
+
+/// Wraps a received datagram for str0m, or `None` if it is not something a session could want.
+pub(super) fn input(now: Instant, datagram: &Datagram) -> Option<Input<'_>> {
+    let receive = Receive::new(
+        Protocol::Udp,
+        datagram.source,
+        datagram.destination,
+        &datagram.contents,
+    )
+    .ok()?;
+    Some(Input::Receive(now, receive))
+}
+
+/// Drains `rtc` until it has nothing more to do right now, queueing its datagrams and handing its events to
+/// `event`. Returns when it next wants a timeout. str0m needs this after every input and every write.
+pub(super) fn drain(
+    rtc: &mut Rtc,
+    datagrams: &mut Vec<Datagram>,
+    mut event: impl FnMut(&mut Rtc, Event),
+) -> Option<Instant> {
+    loop {
+        match rtc.poll_output() {
+            Ok(Output::Timeout(at)) => return Some(at),
+            Ok(Output::Transmit(transmit)) => datagrams.push(Datagram {
+                source: transmit.source,
+                destination: transmit.destination,
+                contents: transmit.contents.into(),
+            }),
+            Ok(Output::Event(e)) => event(rtc, e),
+            Err(e) => {
+                tracing::warn!("live session failed: {e}");
+                rtc.disconnect();
+                return None;
+            }
+        }
+    }
+}
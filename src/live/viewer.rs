@@ -0,0 +1,260 @@
+//! The watching end of live view.
+
+use std::net::SocketAddr;
+use std::time::Instant;
+
+use str0m::change::{SdpAnswer, SdpPendingOffer};
+use str0m::channel::ChannelId as DataChannelId;
+use str0m::media::{Direction, KeyframeRequestKind, MediaKind, Mid};
+use str0m::{Candidate, Event, IceConnectionState, Rtc};
+
+use super::udp::{self, Datagram, Endpoint};
+use super::{LiveSignal, RejectReason, CONTROL_CHANNEL};
+use crate::control::ControlMessage;
+use crate::node::NodeId;
+use crate::sensors::{ChannelId, Micros};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ViewerState {
+    /// Waiting for the publisher to answer.
+    Offered,
+    /// Answered, setting up the connection.
+    Connecting,
+    Connected,
+    Rejected(RejectReason),
+    Closed,
+}
+
+/// A temporal unit of the publisher's AV1 stream, ready for [`crate::encoder::Av1Decoder`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiveFrame {
+    /// Capture time on the publisher's clock, modulo a little under 13 hours since RTP timestamps wrap.
+    pub captured: Micros,
+    pub data: Vec<u8>,
+    /// False if packets were lost since the previous frame, in which case decoding may fail until the next keyframe.
+    pub contiguous: bool,
+}
+
+/// One node's view of one camera on another node.
+pub struct LiveViewer {
+    pub publisher: NodeId,
+    pub camera: ChannelId,
+    session: u64,
+    rtc: Rtc,
+    mid: Mid,
+    control: DataChannelId,
+    control_open: bool,
+    pending: Option<SdpPendingOffer>,
+    state: ViewerState,
+    timeout: Option<Instant>,
+    datagrams: Vec<Datagram>,
+    frames: Vec<LiveFrame>,
+    replies: Vec<ControlMessage>,
+}
+
+impl LiveViewer {
+    /// Starts watching `camera` on `publisher`. `candidates` are the local UDP addresses the media can arrive on,
+    /// each with a socket bound to it. The returned signal has to reach the publisher.
+    pub fn new(
+        publisher: NodeId,
+        camera: ChannelId,
+        session: u64,
+        candidates: &[SocketAddr],
+        now: Instant,
+    ) -> (Self, LiveSignal) {
+        let mut rtc = Rtc::builder().clear_codecs().enable_av1(true).build(now);
+        for &address in candidates {
+            match Candidate::host(address, "udp") {
+                Ok(candidate) => {
+                    rtc.add_local_candidate(candidate);
+                }
+                Err(e) => tracing::warn!("cannot receive live view on {address}: {e}"),
+            }
+        }
+        let mut changes = rtc.sdp_api();
+        let mid = changes.add_media(MediaKind::Video, Direction::RecvOnly, None, None, None);
+        let control = changes.add_channel(CONTROL_CHANNEL.to_string());
+        let (offer, pending) = changes
+            .apply()
+            .expect("adding media always changes the session");
+
+        let mut viewer = LiveViewer {
+            publisher,
+            camera: camera.clone(),
+            session,
+            rtc,
+            mid,
+            control,
+            control_open: false,
+            pending: Some(pending),
+            state: ViewerState::Offered,
+            timeout: None,
+            datagrams: Vec::new(),
+            frames: Vec::new(),
+            replies: Vec::new(),
+        };
+        viewer.drain();
+        let signal = LiveSignal::Subscribe {
+            session,
+            camera,
+            offer: offer.to_sdp_string(),
+        };
+        (viewer, signal)
+    }
+
+    pub fn session(&self) -> u64 {
+        self.session
+    }
+
+    pub fn state(&self) -> &ViewerState {
+        &self.state
+    }
+
+    /// Handles a signal from the publisher that belongs to this session.
+    pub fn handle_signal(&mut self, signal: LiveSignal) {
+        if signal.session() != self.session {
+            return;
+        }
+        match signal {
+            LiveSignal::Accept { answer, .. } => {
+                let Some(pending) = self.pending.take() else {
+                    return;
+                };
+                let result = SdpAnswer::from_sdp_string(&answer)
+                    .map_err(|e| e.to_string())
+                    .and_then(|answer| {
+                        let changes = self.rtc.sdp_api();
+                        changes
+                            .accept_answer(pending, answer)
+                            .map_err(|e| e.to_string())
+                    });
+                match result {
+                    Ok(()) => self.state = ViewerState::Connecting,
+                    Err(e) => {
+                        tracing::warn!("unusable answer from {}: {e}", self.publisher);
+                        self.rtc.disconnect();
+                        self.state = ViewerState::Closed;
+                    }
+                }
+                self.drain();
+            }
+            LiveSignal::Reject { reason, .. } => {
+                self.rtc.disconnect();
+                self.state = ViewerState::Rejected(reason);
+            }
+            LiveSignal::Close { .. } => {
+                self.rtc.disconnect();
+                self.state = ViewerState::Closed;
+            }
+            LiveSignal::Subscribe { .. } => {}
+        }
+    }
+
+    /// Stops watching, returning the signal that tells the publisher.
+    pub fn close(&mut self) -> LiveSignal {
+        self.rtc.disconnect();
+        self.state = ViewerState::Closed;
+        LiveSignal::Close {
+            session: self.session,
+        }
+    }
+
+    /// Asks the publisher for a keyframe, for when decoding has gone wrong.
+    pub fn request_keyframe(&mut self) {
+        if let Some(mut writer) = self.rtc.writer(self.mid) {
+            if writer.is_request_keyframe_possible(KeyframeRequestKind::Pli) {
+                let _ = writer.request_keyframe(None, KeyframeRequestKind::Pli);
+            }
+        }
+        self.drain();
+    }
+
+    /// Frames received since the last call, in order.
+    pub fn take_frames(&mut self) -> Vec<LiveFrame> {
+        std::mem::take(&mut self.frames)
+    }
+
+    /// Sends a control message to the publisher over the session's data channel, returning whether it is open and
+    /// had room for it.
+    pub fn send_control(&mut self, message: &ControlMessage) -> bool {
+        if !self.control_open {
+            return false;
+        }
+        let Some(mut channel) = self.rtc.channel(self.control) else {
+            return false;
+        };
+        let sent = channel.write(true, &message.encode()).unwrap_or(false);
+        self.drain();
+        sent
+    }
+
+    /// Control messages from the publisher since the last call.
+    pub fn take_control(&mut self) -> Vec<ControlMessage> {
+        std::mem::take(&mut self.replies)
+    }
+
+    fn drain(&mut self) {
+        let (state, frames, mid) = (&mut self.state, &mut self.frames, self.mid);
+        let (control, control_open, replies) =
+            (self.control, &mut self.control_open, &mut self.replies);
+        self.timeout = udp::drain(
+            &mut self.rtc,
+            &mut self.datagrams,
+            |rtc, event| match event {
+                Event::Connected => *state = ViewerState::Connected,
+                Event::MediaData(data) if data.mid == mid => frames.push(LiveFrame {
+                    captured: data.time.as_micros() as Micros,
+                    data: data.data.to_vec(),
+                    contiguous: data.contiguous,
+                }),
+                Event::ChannelOpen(id, _) if id == control => *control_open = true,
+                Event::ChannelClose(id) if id == control => *control_open = false,
+                Event::ChannelData(data) if data.id == control => {
+                    match ControlMessage::decode(&data.data) {
+                        Ok(message) => replies.push(message),
+                        Err(e) => tracing::debug!("bad control message: {e}"),
+                    }
+                }
+                Event::IceConnectionStateChange(IceConnectionState::Disconnected) => {
+                    rtc.disconnect();
+                }
+                _ => {}
+            },
+        );
+        if !self.rtc.is_alive() && !matches!(self.state, ViewerState::Rejected(_)) {
+            self.state = ViewerState::Closed;
+        }
+    }
+}
+
+impl Endpoint for LiveViewer {
+    fn receive(&mut self, now: Instant, datagram: Datagram) {
+        let Some(input) = udp::input(now, &datagram) else {
+            return;
+        };
+        if !self.rtc.accepts(&input) {
+            return;
+        }
+        if let Err(e) = self.rtc.handle_input(input) {
+            tracing::debug!("live view of {} failed: {e}", self.camera);
+            self.rtc.disconnect();
+        }
+        self.drain();
+    }
+
+    fn timeout(&mut self, now: Instant) {
+        if let Err(e) = self.rtc.handle_input(str0m::Input::Timeout(now)) {
+            tracing::debug!("live view of {} failed: {e}", self.camera);
+            self.rtc.disconnect();
+        }
+        self.drain();
+    }
+
+    fn next_timeout(&self) -> Option<Instant> {
+        self.timeout.filter(|_| self.rtc.is_alive())
+    }
+
+    fn take_datagrams(&mut self) -> Vec<Datagram> {
+        std::mem::take(&mut self.datagrams)
+    }
+}
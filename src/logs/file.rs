@@ -0,0 +1,129 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A log file that moves aside once it grows too large: `app.log` becomes `app.log.1`, which becomes `app.log.2`, and
+/// so on, with the oldest beyond `keep` deleted. Each line is written whole, so files only ever end between records.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    written: u64,
+}
+
+impl RotatingFile {
+    /// Appends to the file at `path`, creating it and its directory if need be.
+    pub fn open(path: impl Into<PathBuf>, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            max_bytes,
+            keep,
+            file,
+            written,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends `line` and a newline, rotating first if it would take the file past its size.
+    pub fn write(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.written > 0 && self.written + len > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(format!("{line}\n").as_bytes())?;
+        self.written += len;
+        Ok(())
+    }
+
+    /// The file rotated out `n` times ago.
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            match std::fs::remove_file(self.rotated(self.keep)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            for n in (1..self.keep).rev() {
+                match std::fs::rename(self.rotated(n), self.rotated(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.written = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("flumph-log-{test}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn read(path: &Path) -> String {
+        std::fs::read_to_string(path).unwrap_or_default()
+    }
+
+    #[test]
+    fn files_rotate_before_they_outgrow_their_size_and_only_the_newest_are_kept() {
+        let dir = dir("rotate");
+        let mut file = RotatingFile::open(dir.join("app.log"), 10, 2).unwrap();
+        for line in ["one", "two", "three", "four", "five"] {
+            file.write(line).unwrap();
+        }
+        assert_eq!(read(file.path()), "four\nfive\n");
+        assert_eq!(read(&file.rotated(1)), "three\n");
+        assert_eq!(read(&file.rotated(2)), "one\ntwo\n");
+
+        // Reopening carries on from the size the file has, and the oldest file goes once there are too many.
+        drop(file);
+        let mut file = RotatingFile::open(dir.join("app.log"), 10, 2).unwrap();
+        file.write("six").unwrap();
+        assert_eq!(read(file.path()), "six\n");
+        assert_eq!(read(&file.rotated(1)), "four\nfive\n");
+        assert_eq!(read(&file.rotated(2)), "three\n");
+        assert!(!file.rotated(3).exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeping_nothing_starts_the_file_over_and_long_lines_are_written_whole() {
+        let dir = dir("keep-none");
+        let mut file = RotatingFile::open(dir.join("app.log"), 8, 0).unwrap();
+        file.write("a line longer than the limit").unwrap();
+        assert_eq!(read(file.path()), "a line longer than the limit\n");
+        file.write("next").unwrap();
+        assert_eq!(read(file.path()), "next\n");
+        assert!(!file.rotated(1).exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
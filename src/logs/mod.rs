@@ -0,0 +1,518 @@
+//! The app's own log, kept where it can be read without a laptop.
+//!
+//! [`init`] installs a `tracing` subscriber that, besides printing to the console, keeps the newest events in a
+//! [`LogBuffer`] the app can show and filter, and appends them to a [`RotatingFile`] on disk. Events are grouped by
+//! subsystem, which is the module of this crate they come from (`node`, `storage`, `live`, …) or the crate name for
+//! everything else.
+
+mod file;
+
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+use crate::sensors::Micros;
+use crate::time_sync::{local_now, rfc3339};
+
+pub use file::RotatingFile;
+
+/// How much an event matters, most severe first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub const ALL: [LogLevel; 5] = [
+        LogLevel::Error,
+        LogLevel::Warn,
+        LogLevel::Info,
+        LogLevel::Debug,
+        LogLevel::Trace,
+    ];
+}
+
+impl From<Level> for LogLevel {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::ERROR => LogLevel::Error,
+            Level::WARN => LogLevel::Warn,
+            Level::INFO => LogLevel::Info,
+            Level::DEBUG => LogLevel::Debug,
+            Level::TRACE => LogLevel::Trace,
+        }
+    }
+}
+
+impl From<LogLevel> for Level {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => Level::ERROR,
+            LogLevel::Warn => Level::WARN,
+            LogLevel::Info => Level::INFO,
+            LogLevel::Debug => Level::DEBUG,
+            LogLevel::Trace => Level::TRACE,
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Padded, so that records line up in the log file.
+        f.pad(match self {
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Trace => "TRACE",
+        })
+    }
+}
+
+/// One event, as it was logged.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogRecord {
+    /// Counts every record the buffer has taken, so it tells apart records with the same time.
+    pub seq: u64,
+    /// Local time the event was logged at.
+    pub time: Micros,
+    pub level: LogLevel,
+    /// Module path of the code that logged it.
+    pub target: String,
+    pub message: String,
+    /// Fields logged alongside the message, by name.
+    pub fields: Vec<(String, String)>,
+}
+
+impl LogRecord {
+    /// Which part of the app the event came from: the module of this crate under the root, or the crate's name.
+    pub fn subsystem(&self) -> &str {
+        let mut path = self.target.split("::");
+        let krate = path.next().unwrap_or_default();
+        match path.next() {
+            Some(module) if krate == env!("CARGO_CRATE_NAME") => module,
+            _ => krate,
+        }
+    }
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:5} {}: {}",
+            rfc3339(self.time),
+            self.level,
+            self.target,
+            self.message
+        )?;
+        for (name, value) in &self.fields {
+            write!(f, " {name}={value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Which records to show. The default shows everything.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogFilter {
+    /// Least severe level shown.
+    pub level: LogLevel,
+    pub subsystem: Option<String>,
+    /// Text the message, target or a field must contain, ignoring case. Empty matches everything.
+    pub search: String,
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        LogFilter {
+            level: LogLevel::Trace,
+            subsystem: None,
+            search: String::new(),
+        }
+    }
+}
+
+impl LogFilter {
+    pub fn matches(&self, record: &LogRecord) -> bool {
+        if record.level > self.level {
+            return false;
+        }
+        if self
+            .subsystem
+            .as_deref()
+            .is_some_and(|subsystem| subsystem != record.subsystem())
+        {
+            return false;
+        }
+        let search = self.search.trim().to_lowercase();
+        search.is_empty()
+            || [&record.message, &record.target]
+                .into_iter()
+                .chain(record.fields.iter().flat_map(|(name, value)| [name, value]))
+                .any(|text| text.to_lowercase().contains(&search))
+    }
+}
+
+/// The newest log records, up to a fixed count. Clones share the same records, so one can be handed to the
+/// subscriber and another to the app.
+#[derive(Debug, Clone)]
+pub struct LogBuffer {
+    ring: Arc<Mutex<Ring>>,
+}
+
+#[derive(Debug)]
+struct Ring {
+    records: VecDeque<LogRecord>,
+    capacity: usize,
+    next: u64,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        LogBuffer {
+            ring: Arc::new(Mutex::new(Ring {
+                records: VecDeque::with_capacity(capacity),
+                capacity: capacity.max(1),
+                next: 0,
+            })),
+        }
+    }
+
+    /// Adds a record, dropping the oldest if the buffer is full, and returns it.
+    pub fn push(
+        &self,
+        time: Micros,
+        level: LogLevel,
+        target: &str,
+        message: String,
+        fields: Vec<(String, String)>,
+    ) -> LogRecord {
+        let mut ring = self.lock();
+        let record = LogRecord {
+            seq: ring.next,
+            time,
+            level,
+            target: target.to_string(),
+            message,
+            fields,
+        };
+        ring.next += 1;
+        if ring.records.len() == ring.capacity {
+            ring.records.pop_front();
+        }
+        ring.records.push_back(record.clone());
+        record
+    }
+
+    /// How many records have ever been pushed. It changes whenever there is something new.
+    pub fn pushed(&self) -> u64 {
+        self.lock().next
+    }
+
+    /// The records `filter` lets through, oldest first.
+    pub fn query(&self, filter: &LogFilter) -> Vec<LogRecord> {
+        self.lock()
+            .records
+            .iter()
+            .filter(|record| filter.matches(record))
+            .cloned()
+            .collect()
+    }
+
+    /// Every subsystem with a record in the buffer.
+    pub fn subsystems(&self) -> BTreeSet<String> {
+        self.lock()
+            .records
+            .iter()
+            .map(|record| record.subsystem().to_string())
+            .collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Ring> {
+        // Records are only ever appended whole, so a panic elsewhere cannot leave the ring half written.
+        self.ring
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// `records` one per line, as the log file has them.
+pub fn export(records: &[LogRecord]) -> String {
+    records.iter().map(|record| format!("{record}\n")).collect()
+}
+
+/// Sends every event at `level` or more severe to the console, to `buffer` and, if there is one, to `file`. Other
+/// crates only log from `INFO` up, since renderers are chatty below that.
+///
+/// This installs the global subscriber, so call it before anything logs; it fails if one has already been set.
+pub fn init(
+    level: LogLevel,
+    buffer: LogBuffer,
+    file: Option<RotatingFile>,
+) -> Result<(), tracing::subscriber::SetGlobalDefaultError> {
+    let filter = Targets::new()
+        .with_target(env!("CARGO_CRATE_NAME"), Level::from(level))
+        .with_default(Level::from(level.min(LogLevel::Info)));
+    #[cfg(not(target_arch = "wasm32"))]
+    let console = tracing_subscriber::fmt::layer().with_ansi(false);
+    #[cfg(target_arch = "wasm32")]
+    let console = tracing_wasm::WASMLayer::new(
+        tracing_wasm::WASMLayerConfigBuilder::new()
+            .set_max_level(level.into())
+            .build(),
+    );
+    let subscriber = tracing_subscriber::registry()
+        .with(filter)
+        .with(console)
+        .with(RecordLayer {
+            buffer,
+            file: file.map(|file| Mutex::new(FileSink::new(file))),
+        });
+    tracing::subscriber::set_global_default(subscriber)
+}
+
+/// How long to leave a log file that could not be written before trying it again.
+const RETRY_FILE: Micros = 60_000_000;
+
+/// Keeps events in the buffer and the file.
+struct RecordLayer {
+    buffer: LogBuffer,
+    file: Option<Mutex<FileSink>>,
+}
+
+/// The log file, left alone for a while whenever writing to it fails.
+struct FileSink {
+    file: RotatingFile,
+    /// Local time of the last write that failed, while writes are failing.
+    failed: Option<Micros>,
+}
+
+impl FileSink {
+    fn new(file: RotatingFile) -> Self {
+        FileSink { file, failed: None }
+    }
+
+    /// Writes `line` unless the file failed less than [`RETRY_FILE`] ago, so a full disk costs one failed write a
+    /// minute rather than one per event. Returns what to tell the console when the file stops or starts working.
+    fn write(&mut self, line: &str, now: Micros) -> Option<String> {
+        if self.failed.is_some_and(|at| now - at < RETRY_FILE) {
+            return None;
+        }
+        let written = self.file.write(line);
+        let path = self.file.path().display();
+        match written {
+            Ok(()) => self
+                .failed
+                .take()
+                .map(|_| format!("writing to {path} again")),
+            Err(e) => {
+                let first = self.failed.is_none();
+                self.failed = Some(now);
+                first.then(|| format!("cannot write to {path}: {e}; trying again every minute"))
+            }
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for RecordLayer {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let record = self.buffer.push(
+            local_now(),
+            (*metadata.level()).into(),
+            metadata.target(),
+            fields.message,
+            fields.others,
+        );
+        if let Some(Ok(mut file)) = self.file.as_ref().map(Mutex::lock) {
+            if let Some(news) = file.write(&record.to_string(), record.time) {
+                // Logging this would only come back here, so it goes to the console alone.
+                eprintln!("{news}");
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Fields {
+    message: String,
+    others: Vec<(String, String)>,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.others
+                .push((field.name().to_string(), value.to_string()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+        } else {
+            self.others
+                .push((field.name().to_string(), format!("{value:?}")));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(buffer: &LogBuffer, level: LogLevel, target: &str, message: &str) -> LogRecord {
+        buffer.push(0, level, target, message.to_string(), Vec::new())
+    }
+
+    fn messages(records: &[LogRecord]) -> Vec<&str> {
+        records.iter().map(|r| r.message.as_str()).collect()
+    }
+
+    #[test]
+    fn a_full_buffer_drops_its_oldest_records() {
+        let buffer = LogBuffer::new(3);
+        for i in 0..5 {
+            push(
+                &buffer,
+                LogLevel::Info,
+                "flumph::node",
+                &format!("event {i}"),
+            );
+        }
+        let records = buffer.query(&LogFilter::default());
+        assert_eq!(messages(&records), ["event 2", "event 3", "event 4"]);
+        assert_eq!(records.iter().map(|r| r.seq).collect::<Vec<_>>(), [2, 3, 4]);
+        assert_eq!(buffer.pushed(), 5);
+        // Clones share the records.
+        push(&buffer.clone(), LogLevel::Info, "flumph::node", "event 5");
+        assert_eq!(buffer.query(&LogFilter::default()).len(), 3);
+        assert_eq!(buffer.pushed(), 6);
+    }
+
+    #[test]
+    fn filters_pick_by_level_subsystem_and_text() {
+        let buffer = LogBuffer::new(10);
+        push(
+            &buffer,
+            LogLevel::Warn,
+            "flumph::storage::store",
+            "cannot evict",
+        );
+        push(
+            &buffer,
+            LogLevel::Info,
+            "flumph::node::pipeline",
+            "sealed an hour",
+        );
+        push(&buffer, LogLevel::Debug, "flumph::node", "polled");
+        push(
+            &buffer,
+            LogLevel::Info,
+            "tracing_webrtc::agent",
+            "connected",
+        );
+        buffer.push(
+            0,
+            LogLevel::Info,
+            "flumph::storage",
+            "stored".to_string(),
+            vec![("blob".to_string(), "ABC123".to_string())],
+        );
+        assert_eq!(
+            buffer.subsystems(),
+            ["node", "storage", "tracing_webrtc"]
+                .map(str::to_string)
+                .into()
+        );
+
+        let only = |level: LogLevel, subsystem: Option<&str>, search: &str| {
+            let filter = LogFilter {
+                level,
+                subsystem: subsystem.map(str::to_string),
+                search: search.to_string(),
+            };
+            messages(&buffer.query(&filter))
+                .into_iter()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(only(LogLevel::Warn, None, ""), ["cannot evict"]);
+        assert_eq!(
+            only(LogLevel::Trace, Some("node"), ""),
+            ["sealed an hour", "polled"]
+        );
+        assert_eq!(only(LogLevel::Info, Some("node"), ""), ["sealed an hour"]);
+        assert_eq!(
+            only(LogLevel::Trace, Some("tracing_webrtc"), ""),
+            ["connected"]
+        );
+        // Searches look at targets and fields too, ignoring case.
+        assert_eq!(only(LogLevel::Trace, None, "abc"), ["stored"]);
+        assert!(only(LogLevel::Trace, Some("storage"), " PIPELINE ").is_empty());
+        assert_eq!(only(LogLevel::Trace, None, "pipeline"), ["sealed an hour"]);
+    }
+
+    #[test]
+    fn exports_have_a_line_per_record() {
+        let buffer = LogBuffer::new(10);
+        buffer.push(
+            0,
+            LogLevel::Warn,
+            "flumph::node",
+            "lost contact".to_string(),
+            vec![("peer".to_string(), "2".to_string())],
+        );
+        push(&buffer, LogLevel::Info, "flumph::node", "back");
+        let export = export(&buffer.query(&LogFilter::default()));
+        let lines: Vec<&str> = export.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(
+            lines[0].ends_with("WARN  flumph::node: lost contact peer=2"),
+            "{}",
+            lines[0]
+        );
+        assert!(
+            lines[1].ends_with("INFO  flumph::node: back"),
+            "{}",
+            lines[1]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn a_full_disk_is_reported_once_and_tried_again_now_and_then() {
+        let full = RotatingFile::open("/dev/full", 1 << 20, 0).unwrap();
+        let mut sink = FileSink::new(full);
+        let news = sink.write("one", 0).unwrap();
+        assert!(news.starts_with("cannot write to /dev/full"), "{news}");
+        // Nothing is written for a while, and a write that fails again is not reported again.
+        assert_eq!(sink.write("two", 1_000_000), None);
+        assert_eq!(sink.failed, Some(0));
+        assert_eq!(sink.write("three", RETRY_FILE), None);
+        assert_eq!(sink.failed, Some(RETRY_FILE));
+
+        let path = std::env::temp_dir().join(format!("flumph-logs-{}.log", std::process::id()));
+        sink.file = RotatingFile::open(&path, 1 << 20, 0).unwrap();
+        assert_eq!(sink.write("four", RETRY_FILE + 1), None);
+        let news = sink.write("five", 2 * RETRY_FILE).unwrap();
+        assert_eq!(news, format!("writing to {} again", path.display()));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "five\n");
+        std::fs::remove_file(path).unwrap();
+    }
+}
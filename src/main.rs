@@ -2,10 +2,60 @@
 // need dioxus
 use dioxus::prelude::*;
 
-use components::Hero;
+use flumph::logs::{LogBuffer, LogLevel, RotatingFile};
+use flumph::node::NodeId;
+use flumph::sensors::ChannelId;
+use flumph::storage::BlobHash;
+use views::{
+    Camera, Cameras, Dashboard, Logs, Navbar, PageNotFound, Peer, Peers, RecordingDay, Recordings,
+    Sensor, Sensors, Settings, Storage, StoredHour,
+};
 
 /// Define a components module that contains all shared components for our app.
 mod components;
+/// Hooks that connect components to the node's backend.
+mod hooks;
+/// The web build's connection to the node that serves it.
+#[cfg(target_arch = "wasm32")]
+mod remote;
+/// The screens the router switches between.
+mod views;
+
+/// Every screen of the app and the path it lives at, so any of them can be linked to directly.
+#[derive(Debug, Clone, Routable, PartialEq)]
+#[rustfmt::skip]
+enum Route {
+    #[layout(Navbar)]
+        #[route("/")]
+        Dashboard {},
+        #[route("/sensors")]
+        Sensors {},
+        #[route("/sensors/:channel")]
+        Sensor { channel: ChannelId },
+        #[route("/peers")]
+        Peers {},
+        #[route("/peers/:id")]
+        Peer { id: NodeId },
+        #[route("/storage")]
+        Storage {},
+        #[route("/storage/:hash")]
+        StoredHour { hash: BlobHash },
+        #[route("/cameras")]
+        Cameras {},
+        #[route("/cameras/:camera")]
+        Camera { camera: ChannelId },
+        #[route("/recordings")]
+        Recordings {},
+        #[route("/recordings/:day")]
+        RecordingDay { day: i64 },
+        #[route("/settings")]
+        Settings {},
+        #[route("/logs")]
+        Logs {},
+    #[end_layout]
+    #[route("/:..route")]
+    PageNotFound { route: Vec<String> },
+}
 
 // We can import assets in dioxus with the `asset!` macro. This macro takes a path to an asset relative to the crate root.
 // The macro returns an `Asset` type that will display as the path to the asset in the browser or a local path in desktop bundles.
@@ -14,10 +64,49 @@ const FAVICON: Asset = asset!("/assets/favicon.ico");
 const MAIN_CSS: Asset = asset!("/assets/styling/main.css");
 const TAILWIND_CSS: Asset = asset!("/assets/tailwind.css");
 
+/// Nodes are not provisioned with an identity yet, so the app always acts as node zero.
+const LOCAL_NODE: NodeId = NodeId(0);
+
+/// Log records kept in memory for the Logs view.
+const LOG_CAPACITY: usize = 2_000;
+/// Size the log file may grow to before it is rotated, and how many rotated files are kept.
+const LOG_FILE_BYTES: u64 = 1_000_000;
+const LOG_FILES_KEPT: usize = 4;
+
 fn main() {
+    let log = start_logging();
     // The `launch` function is the main entry point for a dioxus app. It takes a component and renders it with the platform feature
     // you have enabled
-    dioxus::launch(App);
+    dioxus::LaunchBuilder::new().with_context(log).launch(App);
+}
+
+/// Installs the app's logger, writing to `flumph.log` in a `logs` directory beside the config file.
+fn start_logging() -> hooks::AppLog {
+    let level = if cfg!(debug_assertions) {
+        LogLevel::Debug
+    } else {
+        LogLevel::Info
+    };
+    let buffer = LogBuffer::new(LOG_CAPACITY);
+    let file = cfg!(not(target_arch = "wasm32")).then(|| {
+        let path = flumph::config::default_path()
+            .with_file_name("logs")
+            .join("flumph.log");
+        RotatingFile::open(&path, LOG_FILE_BYTES, LOG_FILES_KEPT).map_err(|e| (path, e))
+    });
+    let (file, failed) = match file {
+        Some(Ok(file)) => (Some(file), None),
+        Some(Err(failed)) => (None, Some(failed)),
+        None => (None, None),
+    };
+    let path = file.as_ref().map(|file| file.path().to_path_buf());
+    if let Err(e) = flumph::logs::init(level, buffer.clone(), file) {
+        eprintln!("logging is already set up elsewhere: {e}");
+    }
+    if let Some((path, e)) = failed {
+        tracing::warn!("cannot open log file {}: {e}", path.display());
+    }
+    hooks::AppLog { buffer, file: path }
 }
 
 /// App is the main component of our app. Components are the building blocks of dioxus apps. Each component is a function
@@ -26,6 +115,30 @@ fn main() {
 /// Components should be annotated with `#[component]` to support props, better error messages, and autocomplete
 #[component]
 fn App() -> Element {
+    // The node's state lives here rather than in the views so that it keeps running while the user navigates.
+    let config = hooks::use_config();
+    let node = hooks::use_node(LOCAL_NODE, config.config());
+    #[cfg(not(target_arch = "wasm32"))]
+    let server = hooks::use_server(config, node);
+    let history = hooks::use_sensor_history(node.samples);
+    let calibration = hooks::use_calibration(config, node);
+    let camera_link = hooks::use_camera_link(LOCAL_NODE, node);
+    use_context_provider(|| config);
+    use_context_provider(|| node);
+    #[cfg(not(target_arch = "wasm32"))]
+    use_context_provider(|| server);
+    use_context_provider(|| history);
+    use_context_provider(|| calibration);
+    use_context_provider(|| camera_link);
+
+    let display = use_memo(move || config.config().read().display.clone());
+    let display = display.read();
+    let targets = if display.large_targets {
+        " large-targets"
+    } else {
+        ""
+    };
+
     // The `rsx!` macro lets us define HTML inside of rust. It expands to an Element with all of our HTML inside.
     rsx! {
         // In addition to element and text (which we will see later), rsx can contain other components. In this case,
@@ -34,7 +147,6 @@ fn App() -> Element {
         document::Link { rel: "stylesheet", href: MAIN_CSS }
         document::Link { rel: "stylesheet", href: TAILWIND_CSS }
 
-        Hero {}
-
+        div { class: "app theme-{display.theme}{targets}", Router::<Route> {} }
     }
 }
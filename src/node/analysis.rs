@@ -0,0 +1,80 @@
+//! Classifying stored footage on a compute node.
+//!
+//! [`analyse`] decodes a whole hour of footage, which takes far longer than a poll may, so [`AnalysisThread`] runs it
+//! on a thread of its own. The node hands it one video blob at a time, whenever it is idle, and picks up the
+//! detection blobs it makes. Videos waiting their turn stay in the store rather than in memory. Dropping the thread
+//! lets it finish the video it is on without waiting for it.
+
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use crate::analytics::{analyse, DetectionBlob, Detector};
+use crate::sensors::Micros;
+use crate::storage::VideoBlob;
+
+pub struct AnalysisThread {
+    model: String,
+    videos: Sender<VideoBlob>,
+    /// What came of each video, `None` for one that could not be analysed.
+    done: Receiver<Option<DetectionBlob>>,
+    /// Videos handed over and not done yet.
+    in_flight: usize,
+}
+
+impl AnalysisThread {
+    /// Starts classifying one frame every `interval` of the footage handed over with `detector`.
+    pub fn start(mut detector: Box<dyn Detector + Send>, interval: Micros) -> Self {
+        let model = detector.name();
+        let (videos, incoming) = mpsc::channel::<VideoBlob>();
+        let (made, done) = mpsc::channel();
+        let _ = std::thread::Builder::new()
+            .name(format!("analysis {model}"))
+            .spawn(move || {
+                for video in incoming {
+                    let blob = analyse(&video, &mut detector, interval)
+                        .inspect(|blob| {
+                            tracing::info!(
+                                hour = video.hour,
+                                detections = blob.detections.len(),
+                                "analysed an hour of {}",
+                                video.camera
+                            )
+                        })
+                        .inspect_err(|e| tracing::warn!("cannot analyse {}: {e}", video.hash()))
+                        .ok();
+                    let _ = made.send(blob);
+                }
+            })
+            .inspect_err(|e| tracing::warn!("cannot start analysis: {e}"));
+        AnalysisThread {
+            model,
+            videos,
+            done,
+            in_flight: 0,
+        }
+    }
+
+    /// The name of the model, as the detection blobs it makes give it.
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Whether everything handed over has been analysed.
+    pub fn is_idle(&self) -> bool {
+        self.in_flight == 0
+    }
+
+    /// Hands over `video` to be classified.
+    pub fn push(&mut self, video: VideoBlob) {
+        // A thread that failed to start has already said so.
+        if self.videos.send(video).is_ok() {
+            self.in_flight += 1;
+        }
+    }
+
+    /// Detection blobs made since the last call.
+    pub fn take_detections(&mut self) -> Vec<DetectionBlob> {
+        let done: Vec<Option<DetectionBlob>> = self.done.try_iter().collect();
+        self.in_flight -= done.len();
+        done.into_iter().flatten().collect()
+    }
+}
@@ -0,0 +1,253 @@
+//! Identity of the nodes that make up a flumph network, and the state of one as its app shows it.
+//!
+//! A [`NodeBackend`] is what the app reads [`NodeStatus`] from, and what it queries for the blobs the node stores.
+//! [`pipeline::PipelineNode`] is the node itself; [`sim::SimulatedNode`] stands in for it in demo mode.
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod analysis;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pipeline;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod recording;
+pub mod sim;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::analytics::DetectionBlob;
+use crate::calibration::CalibrationStore;
+use crate::config::AppConfig;
+use crate::control::ControlMessage;
+use crate::sensors::{ChannelId, Micros, Sample};
+use crate::storage::{
+    day_span, BlobHash, BlobInfo, BlobKind, BlobQuery, SealedBlob, StoreError, Timeline, VideoBlob,
+    HOUR,
+};
+
+/// A stable identifier for a single node in the network.
+///
+/// Ids are displayed and parsed as 16 lowercase hex digits so they can be used in file names and logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct NodeId(pub u64);
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl FromStr for NodeId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(s, 16).map(NodeId)
+    }
+}
+
+/// What a node is deployed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum NodeRole {
+    /// A phone (or later an ESP-32) that collects data from its own sensors.
+    Sensor,
+    /// A desktop that downloads, stores and analyses data from the sensor nodes.
+    Compute,
+}
+
+impl fmt::Display for NodeRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeRole::Sensor => f.write_str("sensor"),
+            NodeRole::Compute => f.write_str("compute"),
+        }
+    }
+}
+
+/// Everything the dashboard shows about a node, at one moment.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeStatus {
+    pub id: NodeId,
+    pub role: NodeRole,
+    /// Newest sample of every channel.
+    pub readings: BTreeMap<ChannelId, Sample>,
+    /// Hour of network time the buffer being filled covers.
+    pub hour: i64,
+    /// Samples in that buffer so far.
+    pub hour_samples: usize,
+    pub store: StoreUsage,
+    pub peers: Vec<PeerStatus>,
+    /// Links between other nodes, as the peers report them.
+    pub links: Vec<Link>,
+    pub sync: SyncProgress,
+    /// Cameras the node passes commands to, with the node each is on: its own and those of its peers.
+    pub cameras: Vec<(NodeId, ChannelId)>,
+}
+
+impl NodeStatus {
+    /// A node that has nothing to report yet.
+    pub fn new(id: NodeId, role: NodeRole) -> Self {
+        NodeStatus {
+            id,
+            role,
+            readings: BTreeMap::new(),
+            hour: 0,
+            hour_samples: 0,
+            store: StoreUsage::default(),
+            peers: Vec::new(),
+            links: Vec::new(),
+            sync: SyncProgress::default(),
+            cameras: Vec::new(),
+        }
+    }
+
+    /// Every link known to the node: its own to each peer, then those the peers report.
+    pub fn topology(&self) -> Vec<Link> {
+        self.peers
+            .iter()
+            .map(|peer| Link {
+                a: self.id,
+                b: peer.id,
+                kind: peer.link,
+                rtt: peer.rtt,
+                last_seen: peer.last_seen,
+            })
+            .chain(self.links.iter().cloned())
+            .collect()
+    }
+}
+
+/// Sealed blobs kept on the node.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoreUsage {
+    pub blobs: usize,
+    pub bytes: u64,
+    /// Space the store may grow to, if it is limited.
+    pub capacity: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerStatus {
+    pub id: NodeId,
+    pub role: NodeRole,
+    /// Where the peer was last reached.
+    pub address: String,
+    pub link: LinkKind,
+    /// Round trip time of the last exchange, if there has been one.
+    pub rtt: Option<Micros>,
+    /// Local time the peer was last heard from.
+    pub last_seen: Micros,
+    pub connected: bool,
+    /// Blobs from the peer that this node holds.
+    pub held: StoreUsage,
+    /// How far this node is with sending its blobs to the peer.
+    pub sync: SyncProgress,
+}
+
+/// How two nodes reach each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LinkKind {
+    /// The same local network: fast and free.
+    Lan,
+    /// Across the internet, often over a metered or satellite link.
+    Wan,
+}
+
+impl fmt::Display for LinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkKind::Lan => f.write_str("LAN"),
+            LinkKind::Wan => f.write_str("WAN"),
+        }
+    }
+}
+
+/// A link between two nodes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Link {
+    pub a: NodeId,
+    pub b: NodeId,
+    pub kind: LinkKind,
+    pub rtt: Option<Micros>,
+    /// Local time of this node's clock the link was last known to work.
+    pub last_seen: Micros,
+}
+
+/// How far the node is with sending its blobs to its peers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncProgress {
+    /// Blobs waiting to be sent, including a partly sent one.
+    pub queued_blobs: usize,
+    pub queued_bytes: u64,
+    pub sent_blobs: usize,
+    pub sent_bytes: u64,
+}
+
+impl SyncProgress {
+    /// Fraction of all bytes ever queued that have been sent, `1.0` when nothing is waiting.
+    pub fn fraction(&self) -> f64 {
+        let total = self.sent_bytes + self.queued_bytes;
+        if total == 0 {
+            return 1.0;
+        }
+        self.sent_bytes as f64 / total as f64
+    }
+}
+
+/// Whatever runs the node behind the app: the pipeline itself, or a simulation of it.
+pub trait NodeBackend {
+    /// Advances the backend to `now`, returning the samples taken since the last call.
+    fn poll(&mut self, now: Micros) -> Vec<Sample>;
+
+    fn status(&self, now: Micros) -> NodeStatus;
+
+    /// Applies the parts of `config` the backend can change while running.
+    fn configure(&mut self, config: &AppConfig);
+
+    /// Takes the node's calibration profiles, which are applied to samples as they come in when the config asks for
+    /// calibration at capture time.
+    fn calibrate(&mut self, profiles: &CalibrationStore);
+
+    /// The stored blobs matching `query`, by node, then hour.
+    fn blobs(&self, query: &BlobQuery) -> Vec<BlobInfo>;
+
+    /// Passes a control message from this node to the camera node `target`, which may be the node itself, and returns
+    /// the answer if one came back.
+    fn control(
+        &mut self,
+        target: NodeId,
+        message: ControlMessage,
+        now: Micros,
+    ) -> Option<ControlMessage>;
+
+    /// Opens a stored hour blob.
+    fn hour(&self, hash: &BlobHash) -> Result<SealedBlob, StoreError>;
+
+    /// Opens a stored hour of camera footage.
+    fn video(&self, hash: &BlobHash) -> Result<VideoBlob, StoreError>;
+
+    /// Opens a stored set of detections made in some footage.
+    fn detections(&self, hash: &BlobHash) -> Result<DetectionBlob, StoreError>;
+
+    /// The footage stored for UTC day `day` and what was detected in it. This opens every blob of the day.
+    fn timeline(&self, day: i64) -> Timeline {
+        let (start, end) = day_span(day).unwrap_or((0, 0));
+        let query = |kind| BlobQuery {
+            hours: Some(start / HOUR..end / HOUR),
+            kind: Some(kind),
+            ..Default::default()
+        };
+        let videos: Vec<VideoBlob> = self
+            .blobs(&query(BlobKind::Video))
+            .iter()
+            .filter_map(|info| self.video(&info.hash).ok())
+            .collect();
+        let detections: Vec<DetectionBlob> = self
+            .blobs(&query(BlobKind::Detections))
+            .iter()
+            .filter_map(|info| self.detections(&info.hash).ok())
+            .collect();
+        Timeline::day(day, &videos, &detections)
+    }
+}
@@ -0,0 +1,1089 @@
+//! The node itself: its instruments and camera, feeding hour blobs into the store.
+//!
+//! [`PipelineNode`] polls the station described in the config (see [`crate::sensors::station`]) and the camera on a
+//! [`CaptureThread`]. The station's readings are calibrated and fused (see [`crate::fusion`]) as they come in, and the
+//! camera's frames are recorded on a [`RecordingThread`], or in snapshot mode kept as stills (see
+//! [`crate::camera::snapshot`]). Every sample is stamped with network time from the node's [`ClockSync`] and collected
+//! in an [`HourBuffer`], and every hour it seals is kept in a [`BlobStore`] and queued in an [`Outbox`] for the node's
+//! peers. The store is kept on disk when the node is given one [`with_store`](PipelineNode::with_store), and the oldest
+//! hours are evicted to keep it within the configured quota. Hours a peer holds a copy of are removed once they are
+//! older than the configured retention. Nodes do not talk to each other yet, so the node has no peers, and the outbox
+//! only keeps the newest [`OUTBOX_LIMIT`] bytes of what it seals.
+//!
+//! A compute node given a model in its config classifies the footage it keeps on an [`AnalysisThread`] (see
+//! [`crate::analytics`]), catching up on what it stored before, and keeps the detections beside the footage.
+//!
+//! The node's [`CommandHandler`] takes [`crate::control`] commands for its camera. They change how it records until
+//! the camera's settings in the config next change.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+use super::analysis::AnalysisThread;
+use super::recording::RecordingThread;
+use super::{NodeBackend, NodeId, NodeRole, NodeStatus, StoreUsage, SyncProgress};
+use crate::analytics::{DetectionBlob, DetectionIndex, Detector, OnnxConfig, OnnxDetector};
+use crate::calibration::{ApplyAt, CalibratedSource, CalibrationStore};
+use crate::camera::{
+    snapshot, CameraSensor, CaptureThread, FormatRequest, Frame, MotionConfig, Resolution,
+    SnapshotCamera, SnapshotConfig,
+};
+use crate::config::{AnalyticsConfig, AppConfig};
+use crate::control::{
+    CameraCommand, CameraControl, CameraState, CommandError, CommandHandler, ControlMessage, Reply,
+    Snapshot,
+};
+use crate::encoder::EncoderSettings;
+use crate::fusion::{FusedSource, Fusion, FusionConfig};
+use crate::live::LiveAccess;
+use crate::sensors::station::{DriverRegistry, Station, StationConfig};
+use crate::sensors::{ChannelId, ChannelInfo, Micros, Sample, SensorSource};
+use crate::storage::{
+    hour_of, BlobHash, BlobInfo, BlobKind, BlobQuery, BlobStore, HourBuffer, Outbox, Priority,
+    SealedBlob, StoreError, VideoBlob,
+};
+use crate::time_sync::{local_now, ClockSync, SyncConfig};
+
+/// Most bytes waiting in the outbox. Nothing sends from it until nodes have peers, so it only holds what a peer
+/// would want first; everything it drops is still in the store.
+pub const OUTBOX_LIMIT: usize = 16 * 1024 * 1024;
+
+/// What the node's sources are built from. Each is only restarted when its part of this changes.
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Inputs {
+    station: Option<PathBuf>,
+    /// The camera's device and resolution, and how it keeps stills in snapshot mode.
+    camera: Option<(String, Resolution, Option<SnapshotConfig>)>,
+    /// How the camera's footage is encoded, and what counts as motion worth recording. `None` in snapshot mode.
+    recording: Option<(EncoderSettings, MotionConfig)>,
+    /// How footage is classified. Only compute nodes with a model do.
+    analytics: Option<AnalyticsConfig>,
+}
+
+impl Inputs {
+    fn of(config: &AppConfig) -> Self {
+        Inputs {
+            station: config.sensors.station.clone(),
+            camera: config
+                .camera
+                .device
+                .clone()
+                .map(|device| (device, config.camera.resolution, config.camera.snapshot())),
+            recording: config
+                .camera
+                .snapshot_minutes
+                .is_none()
+                .then(|| (config.encoder(), config.camera.motion())),
+            analytics: (config.node.role == NodeRole::Compute && config.analytics.model.is_some())
+                .then(|| config.analytics.clone()),
+        }
+    }
+
+    /// The inputs with a camera recording video set up as `state` says.
+    fn commanded(mut self, state: &CameraState) -> Self {
+        let Some((_, resolution, None)) = &mut self.camera else {
+            return self;
+        };
+        *resolution = Resolution::new(state.encoder.width, state.encoder.height);
+        self.recording = state
+            .recording
+            .then(|| (state.encoder.clone(), state.motion.clone()));
+        self
+    }
+}
+
+/// The camera, recording video or keeping stills.
+enum Camera {
+    Video(CameraSensor<CaptureThread>),
+    Snapshot(SnapshotCamera<CaptureThread>),
+}
+
+impl Camera {
+    fn channel(&self) -> ChannelId {
+        match self {
+            Camera::Video(camera) => camera.channel(),
+            Camera::Snapshot(camera) => camera.channel(),
+        }
+    }
+
+    fn channels(&self) -> Vec<ChannelInfo> {
+        match self {
+            Camera::Video(camera) => camera.channels(),
+            Camera::Snapshot(camera) => camera.channels(),
+        }
+    }
+
+    fn source(&mut self) -> &mut dyn SensorSource {
+        match self {
+            Camera::Video(camera) => camera,
+            Camera::Snapshot(camera) => camera,
+        }
+    }
+}
+
+pub struct PipelineNode {
+    pub id: NodeId,
+    pub role: NodeRole,
+    /// Most bytes the store may take up, as the config's quota sets it.
+    pub capacity: Option<u64>,
+    /// Hours kept once a peer holds a copy, as the config's retention sets it.
+    pub retention: Option<i64>,
+    pub drivers: DriverRegistry,
+    inputs: Inputs,
+    station: Option<FusedSource<CalibratedSource<Station>>>,
+    camera: Option<Camera>,
+    recording: Option<RecordingThread>,
+    /// The newest frame from the camera, for snapshots.
+    last_frame: Option<Frame>,
+    handler: CommandHandler,
+    /// The camera as the config sets it up, and as commands have left it since.
+    configured: CameraState,
+    commanded: CameraState,
+    /// Sources that failed on their last poll, so a failure is reported once rather than on every poll.
+    failing: Vec<ChannelId>,
+    /// Every calibration profile the node was given, and whether to record calibrated channels with them.
+    profiles: CalibrationStore,
+    calibrate_at: ApplyAt,
+    clock: ClockSync,
+    readings: BTreeMap<ChannelId, Sample>,
+    buffer: Option<HourBuffer>,
+    store: BlobStore,
+    outbox: Outbox,
+    analysis: Option<AnalysisThread>,
+    /// Footage waiting to be classified, oldest first.
+    unanalysed: VecDeque<BlobHash>,
+    /// The detections the node holds, to tell which footage has been classified.
+    analysed: DetectionIndex,
+}
+
+impl PipelineNode {
+    /// A node running what `config` describes, with the drivers that ship with flumph.
+    pub fn new(id: NodeId, config: &AppConfig) -> Self {
+        Self::with_drivers(id, config, DriverRegistry::default())
+    }
+
+    pub fn with_drivers(id: NodeId, config: &AppConfig, drivers: DriverRegistry) -> Self {
+        let mut node = PipelineNode {
+            id,
+            role: config.node.role,
+            capacity: None,
+            retention: None,
+            drivers,
+            inputs: Inputs::default(),
+            station: None,
+            camera: None,
+            recording: None,
+            last_frame: None,
+            // Nodes do not talk to each other yet, so commands only come from the node's own app.
+            handler: CommandHandler::new(LiveAccess::Nodes([id].into())),
+            configured: CameraState::new(EncoderSettings::default()),
+            commanded: CameraState::new(EncoderSettings::default()),
+            failing: Vec::new(),
+            profiles: CalibrationStore::default(),
+            calibrate_at: ApplyAt::default(),
+            clock: ClockSync::new(id, SyncConfig::default()),
+            readings: BTreeMap::new(),
+            buffer: None,
+            store: BlobStore::new(),
+            outbox: Outbox::with_limit(OUTBOX_LIMIT),
+            analysis: None,
+            unanalysed: VecDeque::new(),
+            analysed: DetectionIndex::default(),
+        };
+        node.configure(config);
+        node
+    }
+
+    /// The node, keeping its blobs in `store` rather than in memory. What is already in `store` is evicted down to
+    /// the quota like anything the node seals.
+    pub fn with_store(mut self, store: BlobStore) -> Self {
+        self.store = store;
+        self.fit();
+        self.analysed = DetectionIndex::default();
+        let stored = self.store.query(&BlobQuery {
+            kind: Some(BlobKind::Detections),
+            ..Default::default()
+        });
+        for info in stored {
+            match self.store.detections(&info.hash) {
+                Ok(blob) => self.analysed.insert(blob),
+                Err(e) => tracing::warn!("cannot read detections {}: {e}", info.hash),
+            }
+        }
+        self.catch_up();
+        self
+    }
+
+    /// Every channel the node's sources offer.
+    pub fn channels(&self) -> Vec<ChannelId> {
+        let station = self.station.iter().flat_map(|s| s.channels());
+        let camera = self.camera.iter().flat_map(|c| c.channels());
+        station.chain(camera).map(|info| info.id).collect()
+    }
+
+    /// Starts the station and camera `inputs` name, stopping whatever ran before, where they differ from what is
+    /// running. A source that cannot be started is logged and left out, so a camera that is unplugged does not keep
+    /// the instruments from recording.
+    fn start(&mut self, inputs: Inputs) {
+        if inputs.station != self.inputs.station {
+            self.start_station(inputs.station.as_deref());
+        }
+        let camera = inputs.camera != self.inputs.camera;
+        if camera || inputs.recording != self.inputs.recording {
+            self.stop_recording();
+        }
+        if camera {
+            // Drop the old camera first, since most cannot be opened twice.
+            self.camera = None;
+            self.last_frame = None;
+            self.camera = inputs
+                .camera
+                .as_ref()
+                .and_then(|(device, resolution, snapshots)| {
+                    let camera =
+                        CaptureThread::open(device, FormatRequest::HighestResolution(*resolution))
+                            .or_else(|_| CaptureThread::open(device, FormatRequest::Any))
+                            .inspect_err(|e| tracing::warn!("cannot open camera {device}: {e}"))
+                            .ok()?;
+                    Some(match snapshots {
+                        Some(config) => {
+                            Camera::Snapshot(SnapshotCamera::new(camera, config.clone()))
+                        }
+                        None => Camera::Video(CameraSensor::new(camera)),
+                    })
+                });
+        }
+        if inputs.analytics != self.inputs.analytics {
+            let detector = inputs.analytics.as_ref().and_then(|config| {
+                let model = config.model.clone()?;
+                let onnx = OnnxConfig {
+                    labels: config.labels.clone(),
+                    min_confidence: config.min_confidence,
+                    ..OnnxConfig::new(&model)
+                };
+                OnnxDetector::load(onnx)
+                    .inspect_err(|e| tracing::warn!("cannot load {}: {e}", model.display()))
+                    .ok()
+            });
+            match (detector, &inputs.analytics) {
+                (Some(detector), Some(config)) => {
+                    self.start_analysis(Box::new(detector), config.interval())
+                }
+                _ => self.stop_analysis(),
+            }
+        }
+        if self.recording.is_none() {
+            let video = self.camera.as_ref().and_then(|camera| match camera {
+                Camera::Video(camera) => Some(camera),
+                Camera::Snapshot(_) => None,
+            });
+            self.recording =
+                video
+                    .zip(inputs.recording.clone())
+                    .map(|(camera, (encoder, motion))| {
+                        RecordingThread::start(self.id, camera.channel(), encoder, motion)
+                    });
+        }
+        self.failing.clear();
+        self.inputs = inputs;
+    }
+
+    fn start_station(&mut self, path: Option<&Path>) {
+        self.station = path.and_then(|path| {
+            let station = StationConfig::load(path)
+                .and_then(|config| self.drivers.build_station(&config))
+                .inspect_err(|e| tracing::warn!("cannot start station {}: {e}", path.display()))
+                .ok()?;
+            tracing::info!(
+                "started station {} with {} instruments",
+                station.name,
+                station.instruments.len()
+            );
+            let calibrated = CalibratedSource {
+                inner: station,
+                node: self.id,
+                store: self.calibrate_at.capture_profiles(&self.profiles),
+            };
+            Some(FusedSource::new(calibrated, self.fusion_config()))
+        });
+    }
+
+    /// Classifies footage with `detector` from now on, starting with what is stored and has not been yet.
+    fn start_analysis(&mut self, detector: Box<dyn Detector + Send>, interval: Micros) {
+        let analysis = AnalysisThread::start(detector, interval);
+        tracing::info!("classifying footage with {}", analysis.model());
+        self.analysis = Some(analysis);
+        self.catch_up();
+    }
+
+    fn stop_analysis(&mut self) {
+        self.analysis = None;
+        self.unanalysed.clear();
+    }
+
+    /// Queues every stored video the model has not been run on.
+    fn catch_up(&mut self) {
+        let Some(analysis) = &self.analysis else {
+            return;
+        };
+        let videos = self.store.query(&BlobQuery {
+            kind: Some(BlobKind::Video),
+            ..Default::default()
+        });
+        self.unanalysed = videos
+            .into_iter()
+            .filter(|info| !self.analysed.contains(&info.hash, analysis.model()))
+            .map(|info| info.hash)
+            .collect();
+    }
+
+    /// Keeps the detections made since the last poll, and hands the next video over once the last is done.
+    fn analyse(&mut self) {
+        let Some(analysis) = &mut self.analysis else {
+            return;
+        };
+        for blob in analysis.take_detections() {
+            let stored = self.store.insert_detections(&blob);
+            self.stored(stored);
+            self.analysed.insert(blob);
+        }
+        let Some(analysis) = &mut self.analysis else {
+            return;
+        };
+        while analysis.is_idle() {
+            let Some(hash) = self.unanalysed.pop_front() else {
+                return;
+            };
+            // Footage evicted while it waited is gone for good.
+            if let Ok(video) = self.store.video(&hash) {
+                analysis.push(video);
+            }
+        }
+    }
+
+    /// Stops recording, keeping what was recorded up to now.
+    fn stop_recording(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            for video in recording.stop() {
+                self.keep_video(&video, local_now());
+            }
+        }
+    }
+
+    /// Polls one source, reporting a failure only when it starts.
+    fn poll_source(
+        source: &mut dyn SensorSource,
+        name: ChannelId,
+        failing: &mut Vec<ChannelId>,
+        now: Micros,
+    ) -> Vec<Sample> {
+        match source.poll(now) {
+            Ok(samples) => {
+                if let Some(i) = failing.iter().position(|f| *f == name) {
+                    failing.swap_remove(i);
+                    tracing::info!("{name} is working again");
+                }
+                samples
+            }
+            Err(e) => {
+                if !failing.contains(&name) {
+                    tracing::warn!("{name} failed: {e}");
+                    failing.push(name);
+                }
+                Vec::new()
+            }
+        }
+    }
+
+    /// Fusion reads the calibrated channels of the sensors that are calibrated as they are polled.
+    fn fusion_config(&self) -> FusionConfig {
+        FusionConfig::prefer_calibrated(|raw| {
+            self.calibrate_at == ApplyAt::Capture && self.profiles.current(self.id, raw).is_some()
+        })
+    }
+
+    /// Hands the station the profiles it should apply as it is polled, restarting fusion if that changes what it
+    /// reads.
+    fn recalibrate(&mut self) {
+        let fusion = self.fusion_config();
+        if let Some(station) = &mut self.station {
+            station.inner.store = self.calibrate_at.capture_profiles(&self.profiles);
+            if station.fusion.config != fusion {
+                station.fusion = Fusion::new(fusion);
+            }
+        }
+    }
+
+    /// Stores a sealed blob and queues it for sending.
+    fn keep(&mut self, blob: &SealedBlob, now: Micros) {
+        tracing::info!(
+            hour = blob.hour,
+            samples = blob.samples.len(),
+            "sealed an hour"
+        );
+        let stored = self.store.insert_hour(blob);
+        self.stored(stored);
+        self.outbox.push(blob.encode(), Priority::Normal, now);
+        self.expire(blob.hour);
+    }
+
+    /// Removes what peers hold copies of from before the retention, counted back from `hour`.
+    fn expire(&mut self, hour: i64) {
+        let Some(retention) = self.retention else {
+            return;
+        };
+        match self.store.expire(hour - retention) {
+            Ok(expired) => {
+                for info in expired {
+                    self.analysed.remove(&info.hash);
+                    tracing::info!(hour = info.hour, kind = %info.kind, "{} expired", info.hash);
+                }
+            }
+            Err(e) => tracing::warn!("cannot remove expired blobs: {e}"),
+        }
+    }
+
+    /// Reports a blob that could not be stored, and makes room for one that was.
+    fn stored(&mut self, stored: Result<BlobHash, StoreError>) {
+        match stored {
+            Ok(_) => self.fit(),
+            Err(e) => tracing::warn!("cannot store a blob: {e}"),
+        }
+    }
+
+    /// Evicts the oldest hours while the store takes up more than its capacity.
+    fn fit(&mut self) {
+        let Some(capacity) = self.capacity else {
+            return;
+        };
+        match self.store.evict_to(capacity) {
+            Ok(evicted) => {
+                for info in evicted {
+                    self.analysed.remove(&info.hash);
+                    tracing::info!(
+                        hour = info.hour,
+                        kind = %info.kind,
+                        "evicted {} to stay within the quota",
+                        info.hash
+                    );
+                }
+            }
+            Err(e) => tracing::warn!("cannot evict blobs to stay within the quota: {e}"),
+        }
+    }
+
+    /// Encodes a still kept by the camera in snapshot mode into the hour blob and stores the full image. The
+    /// thumbnail is queued ahead of everything else and the full image behind it all.
+    fn keep_still(&mut self, frame: &Frame, now: Micros) {
+        let Some(Camera::Snapshot(camera)) = &self.camera else {
+            return;
+        };
+        let time = self.clock.network_time(frame.captured);
+        let (still, full) = match snapshot(self.id, camera.channel(), frame, time, &camera.config) {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                tracing::warn!("cannot encode a still from {}: {e}", camera.channel());
+                return;
+            }
+        };
+        let stored = self.store.insert_image(&full);
+        self.stored(stored);
+        self.outbox.push(still.encode(), Priority::High, now);
+        self.outbox.push(full.encode(), Priority::Low, now);
+        let buffer = self
+            .buffer
+            .get_or_insert_with(|| HourBuffer::new(self.id, hour_of(time.network)));
+        if let Some(sealed) = buffer.push_still(still) {
+            self.keep(&sealed, now);
+        }
+    }
+
+    /// Stores an hour of footage and queues it for sending when the link has room to spare.
+    fn keep_video(&mut self, video: &VideoBlob, now: Micros) {
+        tracing::info!(
+            hour = video.hour,
+            segments = video.segments.len(),
+            "sealed an hour of footage"
+        );
+        let stored = self.store.insert_video(video);
+        if let (Ok(hash), Some(_)) = (&stored, &self.analysis) {
+            self.unanalysed.push_back(*hash);
+        }
+        self.stored(stored);
+        self.outbox.push(video.encode(), Priority::Low, now);
+    }
+}
+
+impl NodeBackend for PipelineNode {
+    fn poll(&mut self, now: Micros) -> Vec<Sample> {
+        self.clock.update(now);
+        let mut samples = Vec::new();
+        if let Some(station) = &mut self.station {
+            let name = ChannelId::new(format!("station {}", station.inner.inner.name));
+            samples.extend(Self::poll_source(station, name, &mut self.failing, now));
+        }
+        let mut stills = Vec::new();
+        if let Some(camera) = &mut self.camera {
+            let name = camera.channel();
+            samples.extend(Self::poll_source(
+                camera.source(),
+                name,
+                &mut self.failing,
+                now,
+            ));
+            match camera {
+                Camera::Video(camera) => {
+                    let frames = camera.take_frames();
+                    if let Some(frame) = frames.last() {
+                        self.last_frame = Some(frame.clone());
+                    }
+                    if let Some(recording) = &mut self.recording {
+                        for frame in frames {
+                            let time = self.clock.network_time(frame.captured);
+                            recording.push(frame, time);
+                        }
+                    }
+                }
+                Camera::Snapshot(camera) => {
+                    stills = camera.take_frames();
+                    if let Some(frame) = stills.last() {
+                        self.last_frame = Some(frame.clone());
+                    }
+                }
+            }
+        }
+        for frame in &stills {
+            self.keep_still(frame, now);
+        }
+        let videos = self.recording.as_mut().map(RecordingThread::take_videos);
+        for video in videos.unwrap_or_default() {
+            self.keep_video(&video, now);
+        }
+        self.analyse();
+
+        for sample in &samples {
+            let time = self.clock.network_time(sample.local_time);
+            let buffer = self
+                .buffer
+                .get_or_insert_with(|| HourBuffer::new(self.id, hour_of(time.network)));
+            if let Some(sealed) = buffer.push(sample.clone(), time) {
+                self.keep(&sealed, now);
+            }
+            self.readings.insert(sample.channel.clone(), sample.clone());
+        }
+        samples
+    }
+
+    fn status(&self, now: Micros) -> NodeStatus {
+        NodeStatus {
+            hour: self
+                .buffer
+                .as_ref()
+                .map_or(hour_of(self.clock.network_time(now).network), |buffer| {
+                    buffer.hour()
+                }),
+            hour_samples: self.buffer.as_ref().map_or(0, |buffer| buffer.len()),
+            readings: self.readings.clone(),
+            store: StoreUsage {
+                blobs: self.store.len(),
+                bytes: self.store.stored_bytes(),
+                capacity: self.capacity,
+            },
+            sync: SyncProgress {
+                queued_blobs: self.outbox.len(),
+                queued_bytes: self.outbox.bytes() as u64,
+                sent_blobs: 0,
+                sent_bytes: 0,
+            },
+            cameras: self
+                .camera
+                .iter()
+                .map(|camera| (self.id, camera.channel()))
+                .collect(),
+            ..NodeStatus::new(self.id, self.role)
+        }
+    }
+
+    fn configure(&mut self, config: &AppConfig) {
+        self.role = config.node.role;
+        self.capacity = config.storage.quota_mb.map(|mb| mb * 1024 * 1024);
+        self.retention = config.storage.retention_hours();
+        self.fit();
+        self.calibrate_at = config.sensors.calibrate;
+        let configured = CameraState {
+            recording: true,
+            encoder: config.encoder(),
+            motion: config.camera.motion(),
+        };
+        if configured != self.configured {
+            self.commanded = configured.clone();
+            self.configured = configured;
+        }
+        let inputs = Inputs::of(config).commanded(&self.commanded);
+        if inputs != self.inputs {
+            self.start(inputs);
+        }
+        self.recalibrate();
+    }
+
+    fn calibrate(&mut self, profiles: &CalibrationStore) {
+        self.profiles = profiles.clone();
+        self.recalibrate();
+    }
+
+    fn control(
+        &mut self,
+        target: NodeId,
+        message: ControlMessage,
+        now: Micros,
+    ) -> Option<ControlMessage> {
+        // Without peers, the only camera node this one reaches is itself.
+        if target != self.id {
+            return None;
+        }
+        let mut handler = std::mem::take(&mut self.handler);
+        let reply = handler.handle(self.id, message, self, now);
+        self.handler = handler;
+        reply
+    }
+
+    fn blobs(&self, query: &BlobQuery) -> Vec<BlobInfo> {
+        self.store.query(query)
+    }
+
+    fn hour(&self, hash: &BlobHash) -> Result<SealedBlob, StoreError> {
+        self.store.hour(hash)
+    }
+
+    fn video(&self, hash: &BlobHash) -> Result<VideoBlob, StoreError> {
+        self.store.video(hash)
+    }
+
+    fn detections(&self, hash: &BlobHash) -> Result<DetectionBlob, StoreError> {
+        self.store.detections(hash)
+    }
+}
+
+impl CameraControl for PipelineNode {
+    fn execute(
+        &mut self,
+        camera: &ChannelId,
+        command: &CameraCommand,
+    ) -> Result<Reply, CommandError> {
+        let Some(ours) = self
+            .camera
+            .as_ref()
+            .filter(|ours| ours.channel() == *camera)
+        else {
+            return Err(CommandError::UnknownCamera);
+        };
+        match command {
+            CameraCommand::Snapshot => {
+                let frame = self
+                    .last_frame
+                    .as_ref()
+                    .ok_or_else(|| CommandError::Failed("no frame captured yet".to_string()))?;
+                return Ok(Reply::Snapshot(Snapshot::of(frame)));
+            }
+            _ if matches!(ours, Camera::Snapshot(_)) => {
+                return Err(CommandError::Invalid(format!(
+                    "{camera} keeps stills instead of recording"
+                )));
+            }
+            _ => {}
+        }
+        let mut state = self.commanded.clone();
+        state.apply(command)?;
+        let inputs = self.inputs.clone().commanded(&state);
+        self.commanded = state;
+        if inputs != self.inputs {
+            self.start(inputs);
+        }
+        Ok(Reply::Done)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::storage::HOUR;
+    use crate::time_sync::local_now;
+
+    /// A station description in the temp directory, named after the test writing it.
+    fn station_file(test: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "flumph-pipeline-{test}-{}.toml",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            r#"
+            name = "hilltop"
+
+            [[instruments]]
+            name = "phone"
+            driver = "phone-sim"
+            "#,
+        )
+        .unwrap();
+        path
+    }
+
+    fn config(station: Option<PathBuf>, camera: Option<&str>) -> AppConfig {
+        let mut config = AppConfig::default();
+        config.sensors.station = station;
+        config.camera.device = camera.map(str::to_string);
+        config
+    }
+
+    #[test]
+    fn samples_fill_the_hour_and_sealed_hours_are_stored_and_queued() {
+        let path = station_file("seal");
+        let mut node = PipelineNode::new(NodeId(1), &config(Some(path.clone()), None));
+        assert!(node.channels().contains(&ChannelId::new("barometer")));
+
+        let start = 5 * HOUR - 2_000_000;
+        let polled = node.poll(start);
+        assert!(!polled.is_empty());
+        let status = node.status(start);
+        assert_eq!(status.hour, 4);
+        assert_eq!(status.hour_samples, polled.len());
+        assert_eq!(status.readings.len(), polled.len());
+        assert_eq!(status.store.blobs, 0);
+
+        // Crossing into the next hour seals the first one.
+        node.poll(start + 3_000_000);
+        let status = node.status(start + 3_000_000);
+        assert_eq!(status.hour, 5);
+        assert_eq!(status.store.blobs, 1);
+        assert_eq!(status.sync.queued_blobs, 1);
+        assert!(status.peers.is_empty());
+        // The phone's sensors are fused into orientation and altitude as they come in.
+        for derived in [crate::fusion::ORIENTATION, crate::fusion::ALTITUDE] {
+            assert!(status.readings.contains_key(&ChannelId::new(derived)));
+        }
+
+        let stored = node.blobs(&BlobQuery::default());
+        let hour = node.hour(&stored[0].hash).unwrap();
+        assert_eq!((hour.node, hour.hour), (NodeId(1), 4));
+        assert!(hour.samples.iter().all(|s| s.sample.local_time < 5 * HOUR));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn the_oldest_hours_are_evicted_to_stay_within_the_quota_and_the_rest_outlive_the_node() {
+        let path = station_file("quota");
+        let dir =
+            std::env::temp_dir().join(format!("flumph-pipeline-quota-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut node = PipelineNode::new(NodeId(1), &config(Some(path.clone()), None))
+            .with_store(BlobStore::open(&dir).unwrap());
+        for hour in 0..4 {
+            node.poll(hour * HOUR + 1_000_000);
+        }
+        let hours = |node: &PipelineNode| -> Vec<i64> {
+            node.blobs(&BlobQuery::default())
+                .iter()
+                .map(|info| info.hour)
+                .collect()
+        };
+        assert_eq!(hours(&node), vec![0, 1, 2]);
+
+        node.capacity = Some(node.store.stored_bytes() - 1);
+        node.fit();
+        assert_eq!(hours(&node), vec![1, 2]);
+        assert!(node.status(4 * HOUR).store.bytes <= node.capacity.unwrap());
+
+        drop(node);
+        let reopened = BlobStore::open(&dir).unwrap();
+        let kept = reopened.query(&BlobQuery::default());
+        assert_eq!(
+            kept.iter().map(|info| info.hour).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(reopened.hour(&kept[0].hash).unwrap().hour, 1);
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn compute_nodes_classify_kept_footage_and_what_they_stored_before() {
+        use crate::analytics::{AnalyticsError, BoundingBox, Object};
+        use crate::camera::Frame;
+
+        struct Birds;
+
+        impl Detector for Birds {
+            fn name(&self) -> String {
+                "birds".to_string()
+            }
+
+            fn detect(&mut self, _frame: &Frame) -> Result<Vec<Object>, AnalyticsError> {
+                Ok(vec![Object {
+                    class: "bird".to_string(),
+                    confidence: 0.9,
+                    bbox: BoundingBox {
+                        x: 0.4,
+                        y: 0.4,
+                        width: 0.2,
+                        height: 0.2,
+                    },
+                }])
+            }
+        }
+
+        let analysed = |node: &PipelineNode| -> Vec<BlobHash> {
+            let query = BlobQuery {
+                kind: Some(BlobKind::Detections),
+                ..Default::default()
+            };
+            node.blobs(&query)
+                .iter()
+                .map(|info| node.detections(&info.hash).unwrap().video)
+                .collect()
+        };
+        let wait_for = |node: &mut PipelineNode, count: usize| {
+            // Decoding is slow in debug builds.
+            let deadline = Instant::now() + Duration::from_secs(300);
+            while analysed(node).len() < count {
+                assert!(Instant::now() < deadline, "footage was never analysed");
+                node.poll(local_now());
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        };
+
+        let camera = ChannelId::new("camera");
+        let (videos, _) = crate::node::sim::footage(NodeId(1), &camera, 0..4).unwrap();
+        let mut config = config(None, None);
+        config.node.role = NodeRole::Compute;
+        let mut node = PipelineNode::new(NodeId(1), &config);
+        node.keep_video(&videos[0], 0);
+        node.start_analysis(Box::new(Birds), 1_000_000);
+        node.keep_video(&videos[1], 0);
+        wait_for(&mut node, 2);
+
+        let mut done = analysed(&node);
+        done.sort();
+        let mut kept = vec![videos[0].hash(), videos[1].hash()];
+        kept.sort();
+        assert_eq!(done, kept);
+        assert!(node.analysis.as_ref().unwrap().is_idle());
+        assert!(node.unanalysed.is_empty());
+        let found = node.analysed.query(&Default::default());
+        assert!(found
+            .iter()
+            .all(|(blob, d)| blob.model == "birds" && d.class == "bird"));
+
+        // Nothing is classified twice.
+        node.start_analysis(Box::new(Birds), 1_000_000);
+        assert!(node.unanalysed.is_empty());
+    }
+
+    #[test]
+    fn sources_are_only_restarted_when_their_settings_change() {
+        let path = station_file("restart");
+        let mut config = config(Some(path.clone()), None);
+        let mut node = PipelineNode::new(NodeId(1), &config);
+        node.poll(0);
+        node.poll(1_000_000);
+        let samples = node.status(1_000_000).hour_samples;
+
+        // A new role or quota keeps the running station, which carries on from where it was.
+        config.node.role = NodeRole::Compute;
+        config.storage.quota_mb = Some(2);
+        node.configure(&config);
+        let status = node.status(1_000_000);
+        assert_eq!(status.role, NodeRole::Compute);
+        assert_eq!(status.store.capacity, Some(2 * 1024 * 1024));
+        assert_eq!(node.poll(1_000_000), Vec::new());
+        assert_eq!(node.status(1_000_000).hour_samples, samples);
+
+        // A station that cannot be started leaves the node running without it.
+        config.sensors.station = Some(path.with_extension("missing"));
+        node.configure(&config);
+        assert!(node.channels().is_empty());
+        assert_eq!(node.poll(2_000_000), Vec::new());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn calibrated_channels_are_recorded_only_when_calibrating_at_capture() {
+        use crate::calibration::{Calibration, CalibrationMethod, CalibrationProfile};
+        use crate::sensors::SensorKind;
+
+        let path = station_file("calibrate");
+        let mut config = config(Some(path.clone()), None);
+        let mut node = PipelineNode::new(NodeId(1), &config);
+        let mut profiles = CalibrationStore::default();
+        profiles.insert(CalibrationProfile {
+            node: NodeId(1),
+            channel: ChannelId::new("barometer"),
+            kind: SensorKind::Barometer,
+            valid_from: 0,
+            method: CalibrationMethod::Manual,
+            residual: 0.0,
+            calibration: Calibration::Linear {
+                offset: 1.5,
+                scale: 1.0,
+            },
+        });
+        node.calibrate(&profiles);
+        let calibrated = ChannelId::new("barometer/calibrated");
+        let polled = node.poll(0);
+        assert!(polled.iter().all(|s| s.channel != calibrated));
+
+        config.sensors.calibrate = ApplyAt::Capture;
+        node.configure(&config);
+        let polled = node.poll(1_000_000);
+        let raw = polled
+            .iter()
+            .find(|s| s.channel.as_str() == "barometer")
+            .unwrap();
+        let corrected = polled.iter().find(|s| s.channel == calibrated).unwrap();
+        assert_eq!(corrected.values, vec![raw.values[0] - 1.5]);
+        assert!(node.channels().contains(&calibrated));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn the_camera_is_captured_and_recorded_on_threads_of_its_own() {
+        let mut camera = config(None, Some("test-pattern"));
+        camera.camera.resolution = Resolution::new(320, 240);
+        let mut node = PipelineNode::new(NodeId(1), &camera);
+        let channel = ChannelId::new("camera/test-pattern");
+        assert_eq!(node.channels(), vec![channel.clone()]);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !node.status(local_now()).readings.contains_key(&channel) {
+            assert!(Instant::now() < deadline, "no frame from the test pattern");
+            std::thread::sleep(Duration::from_millis(20));
+            node.poll(local_now());
+        }
+        assert_eq!(node.status(local_now()).store.blobs, 0);
+
+        // Closing the camera ends the recording, and what it made is kept like any other hour.
+        let missing = config(None, Some("no-such-camera"));
+        node.configure(&missing);
+        assert!(node.channels().is_empty());
+        let videos = node.blobs(&BlobQuery {
+            kind: Some(crate::storage::BlobKind::Video),
+            ..Default::default()
+        });
+        assert_eq!(videos.len(), 1);
+        let video = node.video(&videos[0].hash).unwrap();
+        assert_eq!((video.node, &video.camera), (NodeId(1), &channel));
+        assert_eq!(video.segments[0].format.width, 320);
+        assert!(!video.keyframes.is_empty());
+        assert_eq!(node.status(local_now()).sync.queued_blobs, 1);
+    }
+
+    #[test]
+    fn in_snapshot_mode_stills_go_in_the_hour_and_thumbnails_go_out_first() {
+        let mut camera = config(None, Some("test-pattern"));
+        camera.camera.resolution = Resolution::new(320, 240);
+        camera.camera.snapshot_minutes = Some(10);
+        camera.camera.snapshot_format = crate::storage::ImageFormat::Jpeg;
+        let mut node = PipelineNode::new(NodeId(1), &camera);
+        assert!(node.recording.is_none());
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while node.status(local_now()).store.blobs == 0 {
+            assert!(Instant::now() < deadline, "no still from the test pattern");
+            std::thread::sleep(Duration::from_millis(20));
+            node.poll(local_now());
+        }
+        let images = node.blobs(&BlobQuery {
+            kind: Some(crate::storage::BlobKind::Image),
+            ..Default::default()
+        });
+        assert_eq!(images.len(), 1);
+
+        // The next still is not due for ten minutes.
+        node.poll(local_now());
+        let outgoing: Vec<_> = std::iter::from_fn(|| node.outbox.pop()).collect();
+        assert_eq!(outgoing.len(), 2);
+        let still = crate::storage::Still::decode(&outgoing[0].bytes).unwrap();
+        assert_eq!(outgoing[0].priority, Priority::High);
+        assert_eq!(still.full.hash, images[0].hash);
+        assert_eq!(still.thumbnail.width, 160);
+        assert_eq!(outgoing[1].priority, Priority::Low);
+
+        let hour = node.buffer.take().unwrap().seal();
+        assert_eq!(hour.stills, vec![still]);
+    }
+
+    #[test]
+    fn commands_change_how_the_camera_records_until_the_config_does() {
+        use crate::control::CommandId;
+
+        let mut config = config(None, Some("test-pattern"));
+        config.camera.resolution = Resolution::new(320, 240);
+        let mut node = PipelineNode::new(NodeId(1), &config);
+        let channel = ChannelId::new("camera/test-pattern");
+        assert_eq!(node.status(0).cameras, vec![(NodeId(1), channel.clone())]);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while node.last_frame.is_none() {
+            assert!(Instant::now() < deadline, "no frame from the test pattern");
+            std::thread::sleep(Duration::from_millis(20));
+            node.poll(local_now());
+        }
+
+        let mut seq = 0;
+        let mut send = |node: &mut PipelineNode, target, camera: &ChannelId, command| {
+            seq += 1;
+            let message = ControlMessage::Command {
+                id: CommandId {
+                    issuer: NodeId(1),
+                    seq,
+                },
+                camera: camera.clone(),
+                command,
+            };
+            match node.control(target, message, local_now()) {
+                Some(ControlMessage::Ack { result, .. }) => Some(result),
+                _ => None,
+            }
+        };
+        let stop = send(&mut node, NodeId(1), &channel, CameraCommand::StopRecording);
+        assert_eq!(stop, Some(Ok(Reply::Done)));
+        assert!(node.recording.is_none());
+        let Some(Ok(Reply::Snapshot(snapshot))) =
+            send(&mut node, NodeId(1), &channel, CameraCommand::Snapshot)
+        else {
+            panic!("no snapshot");
+        };
+        assert_eq!((snapshot.width, snapshot.height), (160, 120));
+        let resize = CameraCommand::SetResolution(Resolution::new(160, 120));
+        assert_eq!(
+            send(&mut node, NodeId(1), &channel, resize),
+            Some(Ok(Reply::Done))
+        );
+        assert_eq!(
+            node.inputs.camera,
+            Some(("test-pattern".to_string(), Resolution::new(160, 120), None))
+        );
+        assert!(node.recording.is_none());
+
+        // There are no peers to pass commands on to, and no other cameras.
+        assert_eq!(
+            send(&mut node, NodeId(2), &channel, CameraCommand::Snapshot),
+            None
+        );
+        let elsewhere = ChannelId::new("camera/elsewhere");
+        assert_eq!(
+            send(&mut node, NodeId(1), &elsewhere, CameraCommand::Snapshot),
+            Some(Err(CommandError::UnknownCamera))
+        );
+
+        // New camera settings in the config start over from them.
+        config.camera.fps = 10;
+        node.configure(&config);
+        assert_eq!(
+            node.inputs.camera,
+            Some(("test-pattern".to_string(), Resolution::new(320, 240), None))
+        );
+        assert!(node.recording.is_some());
+    }
+}
@@ -0,0 +1,202 @@
+//! Recording a camera: motion gating, encoding and cutting the packets into hour blobs.
+//!
+//! Encoding takes far longer than anything else the node does, so [`RecordingThread`] does all of it on a thread of
+//! its own. The node hands it frames as they are captured, each with the network time it was taken at, and picks up
+//! the video blobs it seals. A frame arriving while the encoder is still busy with earlier ones is dropped.
+
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+
+use super::NodeId;
+use crate::camera::{Frame, MotionConfig, MotionEvent, MotionGate};
+use crate::encoder::{
+    default_backends, EncoderError, EncoderSettings, FallbackEncoder, Packet, VideoCodec,
+    VideoEncoder,
+};
+use crate::sensors::{ChannelId, Micros};
+use crate::storage::{VideoBlob, VideoFormat, VideoRecorder};
+use crate::time_sync::NetworkTime;
+
+/// Frames waiting for the encoder. Beyond these the node drops frames rather than fall further behind.
+const BACKLOG: usize = 4;
+
+pub struct RecordingThread {
+    frames: Option<SyncSender<(Frame, NetworkTime)>>,
+    videos: Receiver<VideoBlob>,
+    thread: Option<JoinHandle<()>>,
+    /// Frames dropped because the encoder was behind.
+    pub dropped: u64,
+}
+
+impl RecordingThread {
+    /// Starts recording `camera` on `node`. Frames are encoded at their own size with the rest of `settings`.
+    pub fn start(
+        node: NodeId,
+        camera: ChannelId,
+        settings: EncoderSettings,
+        motion: MotionConfig,
+    ) -> Self {
+        let (frames, incoming) = mpsc::sync_channel(BACKLOG);
+        let (sealed, videos) = mpsc::channel();
+        let name = format!("recording {camera}");
+        let thread = std::thread::Builder::new()
+            .name(name)
+            .spawn(move || {
+                let mut recording = Recording {
+                    node,
+                    camera,
+                    settings,
+                    gate: MotionGate::new(motion),
+                    encoder: None,
+                    recorder: None,
+                    times: BTreeMap::new(),
+                    offset: 0,
+                    sealed,
+                };
+                for (frame, time) in incoming {
+                    if let Err(e) = recording.push(frame, time) {
+                        tracing::warn!("stopped recording {}: {e}", recording.camera);
+                        break;
+                    }
+                }
+                recording.finish();
+            })
+            .inspect_err(|e| tracing::warn!("cannot start recording: {e}"))
+            .ok();
+        RecordingThread {
+            frames: Some(frames),
+            videos,
+            thread,
+            dropped: 0,
+        }
+    }
+
+    /// Hands over a frame captured at network time `time`.
+    pub fn push(&mut self, frame: Frame, time: NetworkTime) {
+        let Some(frames) = &self.frames else {
+            return;
+        };
+        match frames.try_send((frame, time)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => self.dropped += 1,
+            // The thread has already logged why it stopped.
+            Err(TrySendError::Disconnected(_)) => self.frames = None,
+        }
+    }
+
+    /// Video blobs sealed since the last call.
+    pub fn take_videos(&mut self) -> Vec<VideoBlob> {
+        self.videos.try_iter().collect()
+    }
+
+    /// Stops recording, waiting for the frames already handed over to be encoded, and returns what was recorded since
+    /// the last [`RecordingThread::take_videos`].
+    pub fn stop(mut self) -> Vec<VideoBlob> {
+        self.frames = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                tracing::warn!("recording thread panicked");
+            }
+        }
+        self.take_videos()
+    }
+}
+
+/// What runs on the recording thread.
+struct Recording {
+    node: NodeId,
+    camera: ChannelId,
+    settings: EncoderSettings,
+    gate: MotionGate,
+    /// Opened with the first frame, whose size it takes.
+    encoder: Option<FallbackEncoder>,
+    recorder: Option<VideoRecorder>,
+    /// Network times of the frames handed to the gate, by capture time, until their packets come out.
+    times: BTreeMap<Micros, NetworkTime>,
+    /// How far network time was ahead of the local clock at the newest frame.
+    offset: Micros,
+    sealed: mpsc::Sender<VideoBlob>,
+}
+
+impl Recording {
+    fn push(&mut self, frame: Frame, time: NetworkTime) -> Result<(), EncoderError> {
+        self.times.insert(frame.captured, time);
+        self.offset = time.network - time.local;
+        let gated = self.gate.push(frame);
+        for (i, frame) in gated.frames.into_iter().enumerate() {
+            let encoder = match &mut self.encoder {
+                Some(encoder) => encoder,
+                None => {
+                    let settings = self.settings.clone().for_frame(&frame);
+                    let encoder =
+                        FallbackEncoder::new(default_backends(), vec![VideoCodec::Av1], settings)?;
+                    tracing::info!("recording {} with {}", self.camera, encoder.backend());
+                    self.encoder.insert(encoder)
+                }
+            };
+            let format = VideoFormat {
+                codec: encoder.codec().fourcc(),
+                width: frame.width as u16,
+                height: frame.height as u16,
+            };
+            let recorder = self
+                .recorder
+                .get_or_insert_with(|| VideoRecorder::new(self.node, self.camera.clone(), format));
+            recorder.set_format(format);
+            if (i == 0 && gated.keyframe) || recorder.take_keyframe_request() {
+                encoder.force_keyframe();
+            }
+            let packets = encoder.encode(&frame)?;
+            self.record(packets);
+        }
+        if let Some(event) = gated.finished {
+            self.mark(event);
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, packets: Vec<Packet>) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        for packet in packets {
+            let Some(&time) = self.times.get(&packet.captured) else {
+                continue;
+            };
+            // Frames captured before this one have either been encoded already or were left out by the gate.
+            self.times = self.times.split_off(&packet.captured);
+            if let Some(video) = recorder.push(&packet, time) {
+                let _ = self.sealed.send(video);
+            }
+        }
+    }
+
+    /// Tags the footage with `event`, moving its capture times onto the network clock.
+    fn mark(&mut self, event: MotionEvent) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        recorder.mark_motion(MotionEvent {
+            start: event.start + self.offset,
+            end: event.end + self.offset,
+            ..event
+        });
+    }
+
+    /// Encodes the frames still inside the encoder and seals what was recorded.
+    fn finish(mut self) {
+        if let Some(encoder) = &mut self.encoder {
+            match encoder.flush() {
+                Ok(packets) => self.record(packets),
+                Err(e) => tracing::warn!("cannot flush the encoder of {}: {e}", self.camera),
+            }
+        }
+        if let Some(event) = self.gate.current() {
+            self.mark(event);
+        }
+        if let Some(video) = self.recorder.as_mut().and_then(VideoRecorder::seal) {
+            let _ = self.sealed.send(video);
+        }
+    }
+}
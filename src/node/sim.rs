@@ -0,0 +1,563 @@
+//! A simulated node, so the app has something to show before nodes talk to each other.
+//!
+//! [`SimulatedNode`] runs a [`SimulatedPhone`] through a real [`HourBuffer`], keeps the blobs it seals and sends them
+//! out of a real [`Outbox`] over an uplink of fixed speed, and within the daily budget of a metered one, keeping them
+//! in a real [`BlobStore`]. It starts with a day of history of which the last few hours have not been sent yet, and has
+//! a few peers: two sensor nodes on the LAN, one of which keeps dropping out, and a compute node across a satellite
+//! link that the blobs go to, which in turn reaches a second compute node. The store also holds some history of each
+//! sensor peer and, outside the browser, a few clips of footage from the first one's camera with what a detector found
+//! in them. Commands for that camera go to a [`SimulatedCameraNode`] standing in for the peer. Encoding even small
+//! clips takes a while in a debug build, so a thread makes them and they show up in the store once it is done.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use super::{
+    Link, LinkKind, NodeBackend, NodeId, NodeRole, NodeStatus, PeerStatus, StoreUsage, SyncProgress,
+};
+use crate::analytics::DetectionBlob;
+use crate::calibration::{ApplyAt, CalibratedSource, CalibrationStore};
+use crate::config::AppConfig;
+use crate::control::sim::SimulatedCameraNode;
+use crate::control::ControlMessage;
+use crate::sensors::sim::SimulatedPhone;
+use crate::sensors::{ChannelId, Micros, Sample, SensorSource};
+use crate::storage::{
+    day_of, hour_of, BlobHash, BlobInfo, BlobQuery, BlobStore, HourBuffer, Outbox, Priority,
+    SealedBlob, StoreError, VideoBlob, HOUR,
+};
+use crate::time_sync::NetworkTime;
+
+/// Hours of history a new node starts with.
+const HISTORY_HOURS: i64 = 24;
+/// How many of those have not been sent yet.
+const UNSENT_HOURS: i64 = 6;
+/// Peers not heard from for this long count as disconnected.
+const PEER_TIMEOUT: Micros = 30_000_000;
+/// The flaky peer is away for the first two minutes of every five. It is the camera node, so that commands to it
+/// queue up while it is away.
+const FLAKY_PERIOD: Micros = 5 * 60_000_000;
+const FLAKY_AWAY: Micros = 2 * 60_000_000;
+/// Index of the flaky peer, and of the compute node that blobs are sent to.
+const FLAKY: usize = 0;
+const UPSTREAM: usize = 1;
+/// The camera on the first peer, which the app's camera link controls.
+pub const CAMERA: &str = "camera0";
+/// Length and frame rate of the simulated clips, and how many hours apart they are.
+#[cfg(not(target_arch = "wasm32"))]
+const CLIP: Micros = 10_000_000;
+#[cfg(not(target_arch = "wasm32"))]
+const CLIP_FPS: u32 = 4;
+#[cfg(not(target_arch = "wasm32"))]
+const CLIP_EVERY: i64 = 3;
+/// The compute node behind the upstream one, which already holds copies of the blobs sent before the node started.
+const BEYOND_UPSTREAM: u64 = 4;
+
+pub struct SimulatedNode {
+    pub id: NodeId,
+    pub role: NodeRole,
+    /// Bytes per second the uplink manages.
+    pub uplink: u64,
+    /// Store capacity reported in the status.
+    pub capacity: Option<u64>,
+    /// Hours kept once the upstream peer holds a copy.
+    pub retention: Option<i64>,
+    /// Most bytes the uplink may send in a day.
+    pub daily_budget: Option<u64>,
+    /// The day bytes were last sent on, and how many.
+    sent_today: (i64, u64),
+    phone: CalibratedSource<SimulatedPhone>,
+    /// Every calibration profile the node was given, and whether to record calibrated channels with them.
+    profiles: CalibrationStore,
+    calibrate_at: ApplyAt,
+    readings: BTreeMap<ChannelId, Sample>,
+    buffer: Option<HourBuffer>,
+    store: BlobStore,
+    outbox: Outbox,
+    /// Bytes of the blob at the head of the outbox that have already gone out.
+    in_flight: u64,
+    sent_blobs: usize,
+    sent_bytes: u64,
+    peers: Vec<PeerStatus>,
+    /// The flaky peer's end of remote control.
+    camera: SimulatedCameraNode,
+    last_poll: Option<Micros>,
+    #[cfg(not(target_arch = "wasm32"))]
+    footage: Option<std::sync::mpsc::Receiver<Footage>>,
+}
+
+/// What [`footage`] makes.
+#[cfg(not(target_arch = "wasm32"))]
+pub type Footage = Result<(Vec<VideoBlob>, Vec<DetectionBlob>), crate::encoder::EncoderError>;
+
+impl SimulatedNode {
+    /// A node whose phone samples every `period` and whose history ends at `now`.
+    pub fn new(id: NodeId, role: NodeRole, period: Micros, now: Micros) -> Self {
+        let peer = |i: u64, role, address: &str, link, rtt| PeerStatus {
+            id: NodeId(id.0.wrapping_add(i)),
+            role,
+            address: address.to_string(),
+            link,
+            rtt: Some(rtt),
+            last_seen: now,
+            connected: true,
+            held: StoreUsage::default(),
+            sync: SyncProgress::default(),
+        };
+        let peers = vec![
+            peer(
+                1,
+                NodeRole::Sensor,
+                "192.168.4.21:7310",
+                LinkKind::Lan,
+                3_000,
+            ),
+            peer(
+                2,
+                NodeRole::Compute,
+                "203.0.113.7:7310",
+                LinkKind::Wan,
+                620_000,
+            ),
+            peer(
+                3,
+                NodeRole::Sensor,
+                "192.168.4.23:7310",
+                LinkKind::Lan,
+                5_000,
+            ),
+        ];
+        // A fifth of the messages to and from the camera are lost, so that commands are retried.
+        let camera = SimulatedCameraNode::new(peers[FLAKY].id, [ChannelId::new(CAMERA)], 0.2);
+        let mut node = SimulatedNode {
+            id,
+            role,
+            uplink: 4_000,
+            capacity: Some(512 * 1024 * 1024),
+            retention: None,
+            daily_budget: None,
+            sent_today: (0, 0),
+            phone: CalibratedSource {
+                inner: SimulatedPhone::new(period),
+                node: id,
+                store: CalibrationStore::default(),
+            },
+            profiles: CalibrationStore::default(),
+            calibrate_at: ApplyAt::default(),
+            readings: BTreeMap::new(),
+            buffer: None,
+            store: BlobStore::new(),
+            outbox: Outbox::new(),
+            in_flight: 0,
+            sent_blobs: 0,
+            sent_bytes: 0,
+            peers,
+            camera,
+            last_poll: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            footage: None,
+        };
+        node.fill_history(now);
+        node
+    }
+
+    /// Seals a day of history, queueing the last few hours as if the uplink had been down, and stores what the sensor
+    /// peers have sent of theirs.
+    fn fill_history(&mut self, now: Micros) {
+        let current = hour_of(now);
+        let beyond = NodeId(self.id.0.wrapping_add(BEYOND_UPSTREAM));
+        for blob in history(self.id, current - HISTORY_HOURS..current) {
+            if blob.hour >= current - UNSENT_HOURS {
+                self.keep(&blob, now);
+            } else {
+                // The store is in memory, where inserting cannot fail.
+                if let Ok(hash) = self.store.insert_hour(&blob) {
+                    self.store.add_replica(&hash, self.peers[UPSTREAM].id);
+                    self.store.add_replica(&hash, beyond);
+                }
+                self.sent_blobs += 1;
+                self.sent_bytes += blob.encode().len() as u64;
+            }
+        }
+        for (i, hours) in [(0, HISTORY_HOURS), (FLAKY, 17)] {
+            let peer = self.peers[i].id;
+            for blob in history(peer, current - hours..current) {
+                if let Ok(hash) = self.store.insert_hour(&blob) {
+                    self.store.add_replica(&hash, peer);
+                }
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            let camera = self.peers[0].id;
+            std::thread::spawn(move || {
+                let made = footage(
+                    camera,
+                    &ChannelId::new(CAMERA),
+                    current - HISTORY_HOURS..current,
+                );
+                let _ = sender.send(made);
+            });
+            self.footage = Some(receiver);
+        }
+    }
+
+    /// Stores the simulated footage once its thread has made it.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn take_footage(&mut self) {
+        let Some(made) = self.footage.as_ref().and_then(|r| r.try_recv().ok()) else {
+            return;
+        };
+        self.footage = None;
+        match made {
+            Ok((videos, detections)) => {
+                for video in &videos {
+                    if let Ok(hash) = self.store.insert_video(video) {
+                        self.store.add_replica(&hash, video.node);
+                    }
+                }
+                for blob in &detections {
+                    let _ = self.store.insert_detections(blob);
+                }
+            }
+            Err(e) => tracing::warn!("failed to make simulated footage: {e}"),
+        }
+    }
+
+    /// Stores a sealed blob and queues it for sending.
+    fn keep(&mut self, blob: &SealedBlob, now: Micros) {
+        tracing::info!(
+            hour = blob.hour,
+            samples = blob.samples.len(),
+            "sealed an hour"
+        );
+        let _ = self.store.insert_hour(blob);
+        self.outbox.push(blob.encode(), Priority::Normal, now);
+        if let Some(retention) = self.retention {
+            // The store is in memory, where removing cannot fail.
+            let _ = self.store.expire(blob.hour - retention);
+        }
+    }
+
+    /// What the store holds of `node`'s blobs.
+    fn held(&self, node: NodeId) -> StoreUsage {
+        let blobs = self.store.query(&BlobQuery {
+            node: Some(node),
+            ..Default::default()
+        });
+        StoreUsage {
+            blobs: blobs.len(),
+            bytes: blobs.iter().map(|info| info.stored).sum(),
+            capacity: None,
+        }
+    }
+
+    /// Sends up to `budget` bytes from the outbox.
+    fn send(&mut self, mut budget: u64) {
+        while budget > 0 {
+            let Some((_, _, bytes)) = self.outbox.peek() else {
+                return;
+            };
+            let size = bytes.len() as u64;
+            let remaining = size - self.in_flight;
+            if budget < remaining {
+                self.in_flight += budget;
+                return;
+            }
+            budget -= remaining;
+            if let Some(sent) = self.outbox.pop() {
+                let upstream = self.peers[UPSTREAM].id;
+                self.store.add_replica(&sent.hash, upstream);
+                tracing::debug!(blob = %sent.hash, bytes = size, "sent blob to {upstream}");
+            }
+            self.in_flight = 0;
+            self.sent_blobs += 1;
+            self.sent_bytes += size;
+        }
+    }
+}
+
+impl NodeBackend for SimulatedNode {
+    fn poll(&mut self, now: Micros) -> Vec<Sample> {
+        let elapsed = self
+            .last_poll
+            .map_or(0, |last| (now - last).clamp(0, 60_000_000));
+        self.last_poll = Some(now);
+        #[cfg(not(target_arch = "wasm32"))]
+        self.take_footage();
+
+        let samples = match self.phone.poll(now) {
+            Ok(samples) => samples,
+            Err(e) => {
+                tracing::warn!("simulated phone failed: {e}");
+                Vec::new()
+            }
+        };
+        for sample in &samples {
+            let time = network_time(sample.local_time);
+            let buffer = self
+                .buffer
+                .get_or_insert_with(|| HourBuffer::new(self.id, hour_of(time.network)));
+            if let Some(sealed) = buffer.push(sample.clone(), time) {
+                self.keep(&sealed, now);
+            }
+            self.readings.insert(sample.channel.clone(), sample.clone());
+        }
+
+        for (i, peer) in self.peers.iter_mut().enumerate() {
+            let away = i == FLAKY && now.rem_euclid(FLAKY_PERIOD) < FLAKY_AWAY;
+            let lost = now - peer.last_seen >= PEER_TIMEOUT;
+            if away {
+                // Reported once, on the poll the peer times out.
+                if lost && now - elapsed - peer.last_seen < PEER_TIMEOUT {
+                    tracing::warn!(peer = %peer.id, "lost contact with peer");
+                }
+            } else {
+                if lost {
+                    tracing::info!(peer = %peer.id, "peer is back");
+                }
+                peer.last_seen = now;
+            }
+        }
+        if now - self.peers[UPSTREAM].last_seen < PEER_TIMEOUT {
+            let mut budget = elapsed as u64 * self.uplink / 1_000_000;
+            if let Some(daily) = self.daily_budget {
+                let day = day_of(now);
+                if self.sent_today.0 != day {
+                    self.sent_today = (day, 0);
+                }
+                budget = budget.min(daily.saturating_sub(self.sent_today.1));
+            }
+            let before = self.sent_bytes + self.in_flight;
+            self.send(budget);
+            self.sent_today.1 += self.sent_bytes + self.in_flight - before;
+        }
+        samples
+    }
+
+    fn status(&self, now: Micros) -> NodeStatus {
+        let sync = SyncProgress {
+            queued_blobs: self.outbox.len(),
+            queued_bytes: self.outbox.bytes() as u64 - self.in_flight,
+            sent_blobs: self.sent_blobs,
+            sent_bytes: self.sent_bytes + self.in_flight,
+        };
+        let peer = |i: usize| self.peers[i].id;
+        NodeStatus {
+            id: self.id,
+            role: self.role,
+            readings: self.readings.clone(),
+            hour: self
+                .buffer
+                .as_ref()
+                .map_or(hour_of(now), |buffer| buffer.hour()),
+            hour_samples: self.buffer.as_ref().map_or(0, |buffer| buffer.len()),
+            store: StoreUsage {
+                blobs: self.store.len(),
+                bytes: self.store.stored_bytes(),
+                capacity: self.capacity,
+            },
+            peers: self
+                .peers
+                .iter()
+                .enumerate()
+                .map(|(i, peer)| PeerStatus {
+                    connected: now - peer.last_seen < PEER_TIMEOUT,
+                    held: self.held(peer.id),
+                    sync: if i == UPSTREAM { sync } else { peer.sync },
+                    ..peer.clone()
+                })
+                .collect(),
+            links: vec![
+                Link {
+                    a: peer(UPSTREAM),
+                    b: NodeId(self.id.0.wrapping_add(4)),
+                    kind: LinkKind::Wan,
+                    rtt: Some(40_000),
+                    last_seen: now,
+                },
+                Link {
+                    a: peer(0),
+                    b: peer(FLAKY),
+                    kind: LinkKind::Lan,
+                    rtt: Some(2_000),
+                    last_seen: self.peers[FLAKY].last_seen,
+                },
+            ],
+            sync,
+            cameras: vec![(peer(FLAKY), ChannelId::new(CAMERA))],
+        }
+    }
+
+    fn configure(&mut self, config: &AppConfig) {
+        self.role = config.node.role;
+        self.phone.inner.period = 1_000_000 / Micros::from(config.sensors.rate_hz.max(1));
+        self.capacity = config.storage.quota_mb.map(|mb| mb * 1024 * 1024);
+        self.uplink = u64::from(config.link.uplink_kbps) * 1_000 / 8;
+        self.retention = config.storage.retention_hours();
+        self.daily_budget = config.link.daily_budget(0).map(|budget| budget.remaining);
+        self.peers[UPSTREAM].address = config.link.server.clone();
+        self.calibrate_at = config.sensors.calibrate;
+        self.phone.store = self.calibrate_at.capture_profiles(&self.profiles);
+    }
+
+    fn calibrate(&mut self, profiles: &CalibrationStore) {
+        self.profiles = profiles.clone();
+        self.phone.store = self.calibrate_at.capture_profiles(&self.profiles);
+    }
+
+    fn control(
+        &mut self,
+        target: NodeId,
+        message: ControlMessage,
+        now: Micros,
+    ) -> Option<ControlMessage> {
+        // The camera is only reachable while the flaky peer is.
+        let peer = &self.peers[FLAKY];
+        if target != peer.id || now - peer.last_seen >= PEER_TIMEOUT {
+            return None;
+        }
+        self.camera.deliver(self.id, message, now)
+    }
+
+    fn blobs(&self, query: &BlobQuery) -> Vec<BlobInfo> {
+        self.store.query(query)
+    }
+
+    fn hour(&self, hash: &BlobHash) -> Result<SealedBlob, StoreError> {
+        self.store.hour(hash)
+    }
+
+    fn video(&self, hash: &BlobHash) -> Result<VideoBlob, StoreError> {
+        self.store.video(hash)
+    }
+
+    fn detections(&self, hash: &BlobHash) -> Result<DetectionBlob, StoreError> {
+        self.store.detections(hash)
+    }
+}
+
+/// Footage from `camera` on `node` and what a detector made of it: every few hours of `hours`, a short, small clip
+/// of the test pattern with a motion event while its box slides across and a person detected for part of that.
+/// Each clip is sealed into a blob of its own, cut into two segments so there is more than one to pick. The clip is
+/// encoded once and re-timed for every hour, since encoding is what takes the time.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn footage(node: NodeId, camera: &ChannelId, hours: Range<i64>) -> Footage {
+    use crate::analytics::{BoundingBox, Detection};
+    use crate::camera::{CameraSource, FormatRequest, MotionEvent, TestPattern};
+    use crate::encoder::{
+        default_backends, EncoderError, EncoderSettings, FallbackEncoder, VideoCodec, VideoEncoder,
+    };
+    use crate::storage::{VideoFormat, VideoRecorder};
+
+    const SECOND: Micros = 1_000_000;
+    let settings = EncoderSettings {
+        width: 96,
+        height: 72,
+        fps: CLIP_FPS,
+        bitrate_kbps: 64,
+        keyframe_interval: 2 * CLIP_FPS as u64,
+        lookahead: 1,
+        ..EncoderSettings::default()
+    };
+    // One clip with frames captured from 0, shifted to each hour below. Previews can only decode AV1.
+    let mut pattern = TestPattern::open(FormatRequest::Any)
+        .map_err(|e| EncoderError::Unavailable(e.to_string()))?;
+    let mut encoder =
+        FallbackEncoder::new(default_backends(), vec![VideoCodec::Av1], settings.clone())?;
+    let format = VideoFormat {
+        codec: encoder.codec().fourcc(),
+        width: settings.width as u16,
+        height: settings.height as u16,
+    };
+    let mut clip = Vec::new();
+    let mut t = 0;
+    while t < CLIP {
+        if let Ok(Some(frame)) = pattern.frame(t) {
+            clip.extend(encoder.encode(&frame.resized(settings.width, settings.height))?);
+        }
+        t += SECOND / CLIP_FPS as Micros;
+    }
+    clip.extend(encoder.flush()?);
+
+    let (mut videos, mut detections) = (Vec::new(), Vec::new());
+    for hour in hours.filter(|hour| hour.rem_euclid(CLIP_EVERY) == 0) {
+        let start = hour * HOUR + 17 * 60 * SECOND;
+        let mut recorder = VideoRecorder::new(node, camera.clone(), format);
+        recorder.max_segment = CLIP / 2;
+        for packet in &clip {
+            recorder.push(packet, network_time(start + packet.captured));
+        }
+        recorder.mark_motion(MotionEvent {
+            start: start + 2 * SECOND,
+            end: start + 8 * SECOND,
+            peak: 0.08,
+        });
+        let Some(video) = recorder.seal() else {
+            continue;
+        };
+        let found = (3..8)
+            .map(|s| start + s * SECOND)
+            .filter_map(|time| {
+                let segment = video
+                    .segments
+                    .iter()
+                    .find(|segment| segment.start <= time && time <= segment.end)?;
+                let along = (time - start) as f32 / CLIP as f32;
+                Some(Detection {
+                    time,
+                    segment: BlobHash::of(&segment.ivf),
+                    class: "person".to_string(),
+                    confidence: 0.6 + along * 0.3,
+                    bbox: BoundingBox {
+                        x: along,
+                        y: 0.3,
+                        width: 0.15,
+                        height: 0.4,
+                    },
+                })
+            })
+            .collect();
+        detections.push(DetectionBlob {
+            video: video.hash(),
+            node,
+            camera: camera.clone(),
+            hour,
+            model: "simulated".to_string(),
+            interval: SECOND,
+            detections: found,
+        });
+        videos.push(video);
+    }
+    Ok((videos, detections))
+}
+
+/// Hour blobs of coarse samples from a simulated phone on `node`.
+fn history(node: NodeId, hours: Range<i64>) -> Vec<SealedBlob> {
+    const PERIOD: Micros = 60_000_000;
+    let mut phone = SimulatedPhone::new(PERIOD);
+    hours
+        .map(|hour| {
+            let mut buffer = HourBuffer::new(node, hour);
+            let mut t = hour * HOUR;
+            while t < (hour + 1) * HOUR {
+                for sample in phone.poll(t).unwrap_or_default() {
+                    let time = network_time(sample.local_time);
+                    buffer.push(sample, time);
+                }
+                t += PERIOD;
+            }
+            buffer.seal()
+        })
+        .collect()
+}
+
+/// The simulation has no clock sync, so local time stands in for network time.
+fn network_time(local: Micros) -> NetworkTime {
+    NetworkTime {
+        local,
+        network: local,
+        error: 0,
+    }
+}
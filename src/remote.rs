@@ -0,0 +1,135 @@
+//! The web build's side of the compute node's API: requests and the live WebSocket go through the browser's own
+//! `fetch` and `WebSocket`, and pairing is offered to whoever opens the app before the browser is let in.
+
+use std::time::Duration;
+
+use dioxus::prelude::*;
+use flumph::server::{ApiError, PairRequest, LIVE_PATH};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// How long to wait before trying the node again after it refused or dropped the connection.
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How the browser is getting on with the node that served it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Connection {
+    Connecting,
+    Live,
+    /// The node wants the pairing token first.
+    Unpaired,
+    Lost(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum RemoteError {
+    #[error("this browser is not paired with the node")]
+    Unpaired,
+    #[error("{0}")]
+    Failed(String),
+}
+
+/// Sends a request to the node that served the page, returning its status and body.
+async fn fetch(path: &str, body: Option<String>) -> Result<(u16, String), RemoteError> {
+    let script = format!(
+        r#"
+        const body = {};
+        const response = await fetch({}, body === null
+            ? {{ credentials: "same-origin" }}
+            : {{ method: "POST", credentials: "same-origin", headers: {{ "Content-Type": "application/json" }}, body }});
+        return [response.status, await response.text()];
+        "#,
+        serde_json::to_string(&body).unwrap_or_default(),
+        serde_json::to_string(path).unwrap_or_default(),
+    );
+    document::eval(&script)
+        .join()
+        .await
+        .map_err(|e| RemoteError::Failed(format!("cannot reach the node: {e}")))
+}
+
+/// Reads `path` of the API as JSON.
+pub async fn get<T: DeserializeOwned>(path: &str) -> Result<T, RemoteError> {
+    let (status, text) = fetch(path, None).await?;
+    answer(status, &text)
+}
+
+/// Posts `request` as JSON to `path` of the API, reading the answer as JSON.
+pub async fn post<T: DeserializeOwned>(
+    path: &str,
+    request: &impl Serialize,
+) -> Result<T, RemoteError> {
+    let body = serde_json::to_string(request).map_err(|e| RemoteError::Failed(e.to_string()))?;
+    let (status, text) = fetch(path, Some(body)).await?;
+    answer(status, &text)
+}
+
+/// Trades the pairing token for a session, which the browser keeps as a cookie.
+pub async fn pair(token: &str) -> Result<(), RemoteError> {
+    let request = PairRequest {
+        token: token.trim().to_string(),
+    };
+    post("/api/pair", &request).await
+}
+
+fn answer<T: DeserializeOwned>(status: u16, text: &str) -> Result<T, RemoteError> {
+    match status {
+        200 => serde_json::from_str(text)
+            .map_err(|e| RemoteError::Failed(format!("unreadable answer: {e}"))),
+        401 => Err(RemoteError::Unpaired),
+        _ => Err(RemoteError::Failed(
+            serde_json::from_str::<ApiError>(text)
+                .map_or_else(|_| format!("the node answered {status}"), |e| e.error),
+        )),
+    }
+}
+
+/// Opens the live WebSocket. Each message arrives as `Some` text, and `None` once the socket has closed.
+pub fn live() -> document::Eval {
+    document::eval(&format!(
+        r#"
+        const scheme = location.protocol === "https:" ? "wss://" : "ws://";
+        const socket = new WebSocket(scheme + location.host + {});
+        socket.onmessage = (event) => dioxus.send(event.data);
+        await new Promise((resolve) => socket.addEventListener("close", resolve));
+        dioxus.send(null);
+        "#,
+        serde_json::to_string(LIVE_PATH).unwrap_or_default(),
+    ))
+}
+
+/// Asks for the pairing token while the node will not let the browser in, and says so when the node cannot be
+/// reached.
+#[component]
+pub fn PairingBanner(connection: ReadOnlySignal<Connection>) -> Element {
+    let mut token = use_signal(String::new);
+    let mut problem = use_signal(|| None::<String>);
+
+    match &*connection.read() {
+        Connection::Connecting | Connection::Live => rsx! {},
+        Connection::Lost(e) => rsx! {
+            p { class: "banner error", "Lost the node: {e}. Trying again…" }
+        },
+        Connection::Unpaired => rsx! {
+            div { class: "banner control-row",
+                span { "Enter the pairing token shown in the node's settings to see its data." }
+                input {
+                    placeholder: "Pairing token",
+                    value: "{token}",
+                    oninput: move |e| token.set(e.value()),
+                }
+                button {
+                    onclick: move |_| async move {
+                        let entered = token.peek().clone();
+                        let result = pair(&entered).await;
+                        problem.set(result.err().map(|e| e.to_string()));
+                    },
+                    "Pair"
+                }
+                if let Some(problem) = problem() {
+                    span { class: "error", "{problem}" }
+                }
+            }
+        },
+    }
+}
@@ -0,0 +1,297 @@
+//! Recent readings, kept at a few resolutions for charting.
+//!
+//! A day of 50 Hz samples is far more than a chart can draw or a phone should hold, so [`History`] folds samples
+//! into buckets as they arrive: quarter seconds for the last minute, ten seconds for the last hour and four minutes
+//! for the last day. Each bucket keeps the minimum, maximum and mean of every value, which is what a chart needs to
+//! show spikes that averaging would hide.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use super::{ChannelId, Micros, Sample};
+
+/// How far back a chart looks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Window {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Window {
+    pub const ALL: [Window; 3] = [Window::Minute, Window::Hour, Window::Day];
+
+    pub fn span(self) -> Micros {
+        match self {
+            Window::Minute => 60_000_000,
+            Window::Hour => 3_600_000_000,
+            Window::Day => 86_400_000_000,
+        }
+    }
+
+    /// Width of one bucket.
+    pub fn resolution(self) -> Micros {
+        match self {
+            Window::Minute => 250_000,
+            Window::Hour => 10_000_000,
+            Window::Day => 240_000_000,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Window::Minute => "1 min",
+            Window::Hour => "1 h",
+            Window::Day => "24 h",
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Window::Minute => 0,
+            Window::Hour => 1,
+            Window::Day => 2,
+        }
+    }
+}
+
+/// The samples of one channel that fell into one stretch of time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bucket {
+    pub start: Micros,
+    pub count: u32,
+    pub min: Vec<f64>,
+    pub max: Vec<f64>,
+    pub sum: Vec<f64>,
+}
+
+impl Bucket {
+    fn new(start: Micros, values: &[f64]) -> Self {
+        Bucket {
+            start,
+            count: 1,
+            min: values.to_vec(),
+            max: values.to_vec(),
+            sum: values.to_vec(),
+        }
+    }
+
+    fn add(&mut self, values: &[f64]) {
+        self.count += 1;
+        for (i, &v) in values.iter().enumerate().take(self.sum.len()) {
+            self.min[i] = self.min[i].min(v);
+            self.max[i] = self.max[i].max(v);
+            self.sum[i] += v;
+        }
+    }
+
+    pub fn mean(&self, dim: usize) -> f64 {
+        self.sum[dim] / self.count.max(1) as f64
+    }
+}
+
+/// Minimum, maximum and mean of one value over a whole series.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+/// One channel's buckets for one window, oldest first.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Series {
+    pub window: Window,
+    /// Values in each sample.
+    pub dims: usize,
+    pub buckets: Vec<Bucket>,
+}
+
+impl Series {
+    /// `None` for an empty series, or a `dim` its samples do not have.
+    pub fn summary(&self, dim: usize) -> Option<Summary> {
+        let first = self.buckets.first()?;
+        let mut summary = Summary {
+            min: *first.min.get(dim)?,
+            max: *first.max.get(dim)?,
+            mean: 0.0,
+        };
+        let (mut sum, mut count) = (0.0, 0u64);
+        for bucket in &self.buckets {
+            summary.min = summary.min.min(*bucket.min.get(dim)?);
+            summary.max = summary.max.max(*bucket.max.get(dim)?);
+            sum += bucket.sum.get(dim)?;
+            count += bucket.count as u64;
+        }
+        summary.mean = sum / count.max(1) as f64;
+        Some(summary)
+    }
+
+    /// Lowest minimum and highest maximum across every value.
+    pub fn range(&self) -> Option<(f64, f64)> {
+        (0..self.dims)
+            .filter_map(|dim| self.summary(dim))
+            .map(|s| (s.min, s.max))
+            .reduce(|(lo, hi), (min, max)| (lo.min(min), hi.max(max)))
+    }
+
+    /// The bucket covering `time`, or the nearest one.
+    pub fn at(&self, time: Micros) -> Option<&Bucket> {
+        self.buckets
+            .iter()
+            .min_by_key(|bucket| (bucket.start - time).abs())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct ChannelHistory {
+    dims: usize,
+    windows: [VecDeque<Bucket>; 3],
+}
+
+/// The recent history of every channel seen.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    channels: BTreeMap<ChannelId, ChannelHistory>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History::default()
+    }
+
+    /// Adds a sample, dropping buckets that have fallen out of their window. Samples should come roughly in time
+    /// order; one from before the newest bucket of a window is folded into that bucket.
+    pub fn push(&mut self, sample: &Sample) {
+        let channel = self
+            .channels
+            .entry(sample.channel.clone())
+            .or_insert_with(|| ChannelHistory {
+                dims: sample.values.len(),
+                ..Default::default()
+            });
+        if sample.values.len() != channel.dims {
+            return;
+        }
+        for window in Window::ALL {
+            let buckets = &mut channel.windows[window.index()];
+            let resolution = window.resolution();
+            let start = sample.local_time.div_euclid(resolution) * resolution;
+            match buckets.back_mut() {
+                Some(last) if last.start >= start => last.add(&sample.values),
+                _ => buckets.push_back(Bucket::new(start, &sample.values)),
+            }
+            while buckets
+                .front()
+                .is_some_and(|first| first.start <= start - window.span())
+            {
+                buckets.pop_front();
+            }
+        }
+    }
+
+    pub fn channels(&self) -> impl Iterator<Item = &ChannelId> {
+        self.channels.keys()
+    }
+
+    pub fn series(&self, channel: &ChannelId, window: Window) -> Option<Series> {
+        let channel = self.channels.get(channel)?;
+        Some(Series {
+            window,
+            dims: channel.dims,
+            buckets: channel.windows[window.index()].iter().cloned().collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(channel: &str, local_time: Micros, values: &[f64]) -> Sample {
+        Sample {
+            channel: ChannelId::new(channel),
+            local_time,
+            values: values.to_vec(),
+        }
+    }
+
+    #[test]
+    fn samples_are_folded_into_buckets_per_window() {
+        let mut history = History::new();
+        for (time, value) in [(0, 1.0), (100_000, 3.0), (300_000, 5.0), (10_100_000, 7.0)] {
+            history.push(&sample("barometer", time, &[value]));
+        }
+        let barometer = ChannelId::new("barometer");
+
+        let minute = history.series(&barometer, Window::Minute).unwrap();
+        let starts: Vec<Micros> = minute.buckets.iter().map(|b| b.start).collect();
+        assert_eq!(starts, [0, 250_000, 10_000_000]);
+        let first = &minute.buckets[0];
+        assert_eq!((first.count, first.min[0], first.max[0]), (2, 1.0, 3.0));
+        assert_eq!(first.mean(0), 2.0);
+        assert_eq!(minute.at(9_000_000).unwrap().start, 10_000_000);
+
+        let hour = history.series(&barometer, Window::Hour).unwrap();
+        assert_eq!(hour.buckets.len(), 2);
+        assert_eq!(hour.buckets[0].count, 3);
+        let day = history.series(&barometer, Window::Day).unwrap();
+        assert_eq!(day.buckets.len(), 1);
+        assert_eq!(
+            day.summary(0),
+            Some(Summary {
+                min: 1.0,
+                max: 7.0,
+                mean: 4.0
+            })
+        );
+        assert_eq!(history.series(&ChannelId::new("gps"), Window::Day), None);
+    }
+
+    #[test]
+    fn buckets_older_than_their_window_are_dropped() {
+        let mut history = History::new();
+        let barometer = ChannelId::new("barometer");
+        history.push(&sample("barometer", 0, &[1.0]));
+        history.push(&sample("barometer", 59_000_000, &[2.0]));
+        history.push(&sample("barometer", 61_000_000, &[3.0]));
+        let minute = history.series(&barometer, Window::Minute).unwrap();
+        assert_eq!(minute.buckets.len(), 2);
+        assert_eq!(minute.summary(0).unwrap().min, 2.0);
+        // The longer windows still hold the first sample.
+        let hour = history.series(&barometer, Window::Hour).unwrap();
+        assert_eq!(hour.summary(0).unwrap().min, 1.0);
+
+        // A late sample joins the newest bucket rather than reopening an old one.
+        history.push(&sample("barometer", 0, &[0.0]));
+        let minute = history.series(&barometer, Window::Minute).unwrap();
+        assert_eq!(minute.buckets.last().unwrap().min[0], 0.0);
+        assert_eq!(minute.buckets.len(), 2);
+    }
+
+    #[test]
+    fn summaries_cover_every_value_and_only_those() {
+        let mut history = History::new();
+        history.push(&sample("accelerometer", 0, &[1.0, -2.0, 9.0]));
+        history.push(&sample("accelerometer", 1_000_000, &[3.0, -4.0, 10.0]));
+        // A sample with a different number of values is dropped.
+        history.push(&sample("accelerometer", 2_000_000, &[100.0]));
+        let series = history
+            .series(&ChannelId::new("accelerometer"), Window::Minute)
+            .unwrap();
+        assert_eq!(series.dims, 3);
+        assert_eq!(series.buckets.len(), 2);
+        assert_eq!(series.summary(1).unwrap().mean, -3.0);
+        assert_eq!(series.summary(3), None);
+        assert_eq!(series.range(), Some((-4.0, 10.0)));
+
+        let empty = Series {
+            window: Window::Minute,
+            dims: 1,
+            buckets: Vec::new(),
+        };
+        assert_eq!(empty.summary(0), None);
+        assert_eq!(empty.range(), None);
+    }
+}
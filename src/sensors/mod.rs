@@ -0,0 +1,149 @@
+//! Sensor data as it comes off a node, before it is buffered into hour blobs.
+
+pub mod history;
+pub mod nmea;
+pub mod serial;
+pub mod sim;
+pub mod station;
+pub mod usb;
+
+use serde::{Deserialize, Serialize};
+
+/// Microseconds since the Unix epoch. Every timestamp in flumph uses this unit.
+pub type Micros = i64;
+
+/// The name of a single stream of readings, e.g. `"barometer"` or `"accelerometer"`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ChannelId(pub String);
+
+impl ChannelId {
+    pub fn new(name: impl Into<String>) -> Self {
+        ChannelId(name.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// A channel computed from this one, e.g. `magnetometer/calibrated`.
+    pub fn derived(&self, suffix: &str) -> ChannelId {
+        ChannelId(format!("{}/{suffix}", self.0))
+    }
+}
+
+impl std::fmt::Display for ChannelId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::str::FromStr for ChannelId {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(ChannelId::new(s))
+    }
+}
+
+/// One reading from one channel, stamped with the local clock of the node that took it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    pub channel: ChannelId,
+    /// When the reading was taken according to the node's own (unsynchronised) clock.
+    pub local_time: Micros,
+    pub values: Vec<f64>,
+}
+
+/// The physical quantity a channel measures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum SensorKind {
+    Barometer,
+    Magnetometer,
+    Accelerometer,
+    Gyroscope,
+}
+
+impl SensorKind {
+    pub const ALL: [SensorKind; 4] = [
+        SensorKind::Barometer,
+        SensorKind::Magnetometer,
+        SensorKind::Accelerometer,
+        SensorKind::Gyroscope,
+    ];
+
+    /// The channel a phone's built in sensor of this kind is recorded on.
+    pub fn channel(self) -> ChannelId {
+        ChannelId::new(self.name())
+    }
+
+    /// The kind of built in sensor recorded on `channel`, if it is one.
+    pub fn of_channel(channel: &ChannelId) -> Option<SensorKind> {
+        SensorKind::ALL
+            .into_iter()
+            .find(|kind| kind.channel() == *channel)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SensorKind::Barometer => "barometer",
+            SensorKind::Magnetometer => "magnetometer",
+            SensorKind::Accelerometer => "accelerometer",
+            SensorKind::Gyroscope => "gyroscope",
+        }
+    }
+
+    pub fn unit(self) -> &'static str {
+        match self {
+            SensorKind::Barometer => "hPa",
+            SensorKind::Magnetometer => "µT",
+            SensorKind::Accelerometer => "m/s²",
+            SensorKind::Gyroscope => "rad/s",
+        }
+    }
+
+    /// Number of values in each sample.
+    pub fn dims(self) -> usize {
+        match self {
+            SensorKind::Barometer => 1,
+            _ => 3,
+        }
+    }
+}
+
+/// Describes one channel a [`SensorSource`] produces.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChannelInfo {
+    pub id: ChannelId,
+    pub kind: Option<SensorKind>,
+    pub unit: String,
+    pub dims: usize,
+}
+
+impl ChannelInfo {
+    pub fn builtin(kind: SensorKind) -> Self {
+        ChannelInfo {
+            id: kind.channel(),
+            kind: Some(kind),
+            unit: kind.unit().to_string(),
+            dims: kind.dims(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SensorError {
+    #[error("sensor is not available: {0}")]
+    Unavailable(String),
+    #[error("sensor i/o failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("malformed reading: {0}")]
+    Malformed(String),
+}
+
+/// Anything that produces samples: the phone's own sensors, an external device on the usb-c port, a simulation.
+pub trait SensorSource {
+    fn channels(&self) -> Vec<ChannelInfo>;
+
+    /// Returns every sample that became available since the last poll. `now` is the node's local clock.
+    fn poll(&mut self, now: Micros) -> Result<Vec<Sample>, SensorError>;
+}
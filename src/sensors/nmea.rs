@@ -0,0 +1,374 @@
+//! A driver for weather instruments that talk NMEA 0183, the line based protocol most marine and ultrasonic
+//! anemometers and many compact weather stations speak over RS-232/RS-485.
+//!
+//! Recognised sentences, from any talker:
+//!
+//! | sentence | channel       | values                              |
+//! |----------|---------------|-------------------------------------|
+//! | `MWV`    | `wind`        | speed in m/s, direction in degrees  |
+//! | `MTA`    | `temperature` | °C                                  |
+//! | `MHU`    | `humidity`    | relative humidity in %              |
+//! | `MMB`    | `pressure`    | hPa                                 |
+//!
+//! Everything else is ignored, so instruments that also emit e.g. GPS sentences are fine.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+use super::{ChannelId, ChannelInfo, Micros, Sample, SensorError, SensorSource};
+
+/// Sentences longer than this are line noise; the standard caps them at 82 characters.
+const MAX_SENTENCE: usize = 128;
+
+/// A parsed reading, before it is given a channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    pub channel: &'static str,
+    pub unit: &'static str,
+    pub values: Vec<f64>,
+}
+
+/// XOR of every byte between `$` and `*`.
+pub fn checksum(body: &str) -> u8 {
+    body.bytes().fold(0, |acc, b| acc ^ b)
+}
+
+/// Formats a sentence body (without `$` and checksum) as a complete line.
+pub fn sentence(body: &str) -> String {
+    format!("${body}*{:02X}\r\n", checksum(body))
+}
+
+/// Parses one line. Returns `Ok(None)` for valid sentences the driver does not use, or for readings the instrument
+/// marked as invalid.
+pub fn parse(line: &str) -> Result<Option<Reading>, SensorError> {
+    let line = line.trim();
+    let body = line
+        .strip_prefix('$')
+        .ok_or_else(|| SensorError::Malformed(format!("not an NMEA sentence: {line:?}")))?;
+    let body = match body.split_once('*') {
+        Some((body, sum)) => {
+            let sum = u8::from_str_radix(sum, 16)
+                .map_err(|_| SensorError::Malformed(format!("bad checksum field: {line:?}")))?;
+            if checksum(body) != sum {
+                return Err(SensorError::Malformed(format!(
+                    "checksum mismatch: {line:?}"
+                )));
+            }
+            body
+        }
+        // The checksum is optional in the standard, though every instrument we know of sends it.
+        None => body,
+    };
+    let fields: Vec<&str> = body.split(',').collect();
+    // Two character talker id, then the three character sentence type.
+    let kind = fields[0].get(2..).unwrap_or_default();
+    let number = |i: usize| -> Result<f64, SensorError> {
+        fields
+            .get(i)
+            .and_then(|f| f.parse().ok())
+            .ok_or_else(|| SensorError::Malformed(format!("field {i} is not a number: {line:?}")))
+    };
+    let field = |i: usize| fields.get(i).copied().unwrap_or_default();
+
+    let reading = match kind {
+        "MWV" => {
+            if field(5) != "A" {
+                return Ok(None);
+            }
+            let speed = number(3)?
+                * match field(4) {
+                    "M" => 1.0,
+                    "K" => 1.0 / 3.6,
+                    "N" => 1852.0 / 3600.0,
+                    "S" => 1609.344 / 3600.0,
+                    unit => {
+                        return Err(SensorError::Malformed(format!(
+                            "unknown wind speed unit {unit:?}"
+                        )))
+                    }
+                };
+            Reading {
+                channel: "wind",
+                unit: "m/s, °",
+                values: vec![speed, number(1)?],
+            }
+        }
+        "MTA" => Reading {
+            channel: "temperature",
+            unit: "°C",
+            values: vec![number(1)?],
+        },
+        "MHU" => Reading {
+            channel: "humidity",
+            unit: "%",
+            values: vec![number(1)?],
+        },
+        "MMB" => Reading {
+            channel: "pressure",
+            unit: "hPa",
+            // Field 3 is in bars; field 1 in inches of mercury is often left empty.
+            values: vec![number(3)? * 1000.0],
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(reading))
+}
+
+/// An NMEA instrument on a serial link.
+pub struct NmeaSensor<T> {
+    /// Channels are named `<name>/<channel>`, e.g. `mast/wind`.
+    pub name: String,
+    link: T,
+    line: Vec<u8>,
+    channels: BTreeMap<&'static str, &'static str>,
+    /// Sentences dropped for bad checksums or malformed fields.
+    pub bad_sentences: u64,
+}
+
+impl<T: Read> NmeaSensor<T> {
+    /// `link` should be non-blocking.
+    pub fn new(name: impl Into<String>, link: T) -> Self {
+        NmeaSensor {
+            name: name.into(),
+            link,
+            line: Vec::new(),
+            channels: BTreeMap::new(),
+            bad_sentences: 0,
+        }
+    }
+
+    fn handle_line(&mut self, now: Micros) -> Option<Sample> {
+        let line = String::from_utf8_lossy(&self.line).into_owned();
+        self.line.clear();
+        if line.trim().is_empty() {
+            return None;
+        }
+        match parse(&line) {
+            Ok(Some(reading)) => {
+                self.channels.insert(reading.channel, reading.unit);
+                Some(Sample {
+                    channel: ChannelId::new(format!("{}/{}", self.name, reading.channel)),
+                    local_time: now,
+                    values: reading.values,
+                })
+            }
+            Ok(None) => None,
+            Err(e) => {
+                self.bad_sentences += 1;
+                tracing::debug!("dropping sentence from {}: {e}", self.name);
+                None
+            }
+        }
+    }
+}
+
+impl<T: Read> SensorSource for NmeaSensor<T> {
+    /// The channels seen so far. NMEA has no way to ask an instrument what it measures.
+    fn channels(&self) -> Vec<ChannelInfo> {
+        self.channels
+            .iter()
+            .map(|(channel, unit)| ChannelInfo {
+                id: ChannelId::new(format!("{}/{channel}", self.name)),
+                kind: None,
+                unit: unit.to_string(),
+                dims: if *channel == "wind" { 2 } else { 1 },
+            })
+            .collect()
+    }
+
+    /// Reads every complete sentence available. Sentences are stamped with `now`, which at 4800 baud is at most a
+    /// few hundred milliseconds late; none of these quantities change faster than that.
+    fn poll(&mut self, now: Micros) -> Result<Vec<Sample>, SensorError> {
+        let mut samples = Vec::new();
+        let mut buf = [0u8; 256];
+        loop {
+            let read = match self.link.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for &byte in &buf[..read] {
+                match byte {
+                    b'\n' => samples.extend(self.handle_line(now)),
+                    // Resynchronise on the next sentence start if a line ends up garbled.
+                    b'$' => {
+                        if !self.line.is_empty() {
+                            self.bad_sentences += 1;
+                        }
+                        self.line.clear();
+                        self.line.push(byte);
+                    }
+                    _ if self.line.len() < MAX_SENTENCE => self.line.push(byte),
+                    _ => {}
+                }
+            }
+        }
+        Ok(samples)
+    }
+}
+
+/// A simulated weather instrument that behaves like a non-blocking serial port: reads return the sentences it has
+/// "sent" since the last read, or `WouldBlock`. It has no clock of its own; [`SimulatedNmeaDevice::advance`] says
+/// what time it is, so runs are reproducible.
+pub struct SimulatedNmeaDevice {
+    /// Time between rounds of sentences.
+    pub period: Micros,
+    /// Corrupt the checksum of every `n`th sentence, to exercise error handling.
+    pub corrupt_every: Option<u64>,
+    start: Option<Micros>,
+    rounds: u64,
+    sent: u64,
+    pending: Vec<u8>,
+}
+
+impl SimulatedNmeaDevice {
+    pub fn new(period: Micros) -> Self {
+        SimulatedNmeaDevice {
+            period,
+            corrupt_every: None,
+            start: None,
+            rounds: 0,
+            sent: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// The sentences for the round at `t` seconds: gusty wind veering slowly, and a daily temperature swing.
+    pub fn round(t: f64) -> Vec<String> {
+        use std::f64::consts::TAU;
+        let day = (TAU * t / 86_400.0).sin();
+        let speed = 4.0 + 2.0 * (t / 7.0).sin() + (t / 1.3).sin();
+        let direction = (225.0 + 40.0 * (t / 600.0).sin()).rem_euclid(360.0);
+        vec![
+            format!("WIMWV,{direction:.1},T,{:.1},N,A", speed * 3600.0 / 1852.0),
+            format!("WIMTA,{:.1},C", 14.0 + 6.0 * day),
+            format!("WIMHU,{:.1},,,", 70.0 - 15.0 * day),
+            format!("WIMMB,,I,{:.4},B", 1.01325 - 0.002 * (t / 3_600.0).sin()),
+        ]
+    }
+
+    /// Sends every round due by `now`. The first call starts the instrument, with a round right away.
+    pub fn advance(&mut self, now: Micros) {
+        let start = *self.start.get_or_insert(now);
+        while start + (self.rounds as i64) * self.period.max(1) <= now {
+            let t = (self.rounds as i64 * self.period) as f64 / 1e6;
+            for body in Self::round(t) {
+                self.sent += 1;
+                let line = if self
+                    .corrupt_every
+                    .is_some_and(|n| n > 0 && self.sent.is_multiple_of(n))
+                {
+                    format!("${body}*{:02X}\r\n", checksum(&body) ^ 0x5a)
+                } else {
+                    sentence(&body)
+                };
+                self.pending.extend_from_slice(line.as_bytes());
+            }
+            self.rounds += 1;
+        }
+    }
+}
+
+/// An [`NmeaSensor`] on a [`SimulatedNmeaDevice`], advanced to the poll time on every poll.
+pub struct SimulatedNmeaInstrument {
+    pub sensor: NmeaSensor<SimulatedNmeaDevice>,
+}
+
+impl SimulatedNmeaInstrument {
+    pub fn new(name: impl Into<String>, device: SimulatedNmeaDevice) -> Self {
+        SimulatedNmeaInstrument {
+            sensor: NmeaSensor::new(name, device),
+        }
+    }
+}
+
+impl SensorSource for SimulatedNmeaInstrument {
+    fn channels(&self) -> Vec<ChannelInfo> {
+        self.sensor.channels()
+    }
+
+    fn poll(&mut self, now: Micros) -> Result<Vec<Sample>, SensorError> {
+        self.sensor.link.advance(now);
+        self.sensor.poll(now)
+    }
+}
+
+impl Read for SimulatedNmeaDevice {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+/// Instruments are configured by writing to them, which the simulation ignores.
+impl Write for SimulatedNmeaDevice {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_weather_sentences() {
+        let wind = parse(&sentence("WIMWV,270.0,T,10.0,M,A")).unwrap().unwrap();
+        assert_eq!(wind.channel, "wind");
+        assert_eq!(wind.values, vec![10.0, 270.0]);
+        let knots = parse(&sentence("WIMWV,90.0,R,1.0,N,A")).unwrap().unwrap();
+        assert!((knots.values[0] - 1852.0 / 3600.0).abs() < 1e-9);
+        let pressure = parse(&sentence("WIMMB,,I,1.0132,B")).unwrap().unwrap();
+        assert!((pressure.values[0] - 1013.2).abs() < 1e-9);
+        assert_eq!(
+            parse(&sentence("WIMTA,-3.5,C")).unwrap().unwrap().values,
+            vec![-3.5]
+        );
+
+        // Marked invalid, or not a sentence the driver uses.
+        assert_eq!(parse(&sentence("WIMWV,90.0,R,1.0,N,V")).unwrap(), None);
+        assert_eq!(parse(&sentence("GPGGA,123519,4807.038,N")).unwrap(), None);
+        assert!(parse("$WIMTA,12.0,C*00").is_err());
+        assert!(parse(&sentence("WIMTA,warm,C")).is_err());
+    }
+
+    fn run(device: SimulatedNmeaDevice) -> (Vec<Sample>, u64) {
+        let mut instrument = SimulatedNmeaInstrument::new("mast", device);
+        let mut samples = Vec::new();
+        for t in 0..10 {
+            samples.extend(instrument.poll(5_000_000 + t * 250_000).unwrap());
+        }
+        (samples, instrument.sensor.bad_sentences)
+    }
+
+    #[test]
+    fn the_simulated_instrument_follows_the_poll_time() {
+        let (samples, bad) = run(SimulatedNmeaDevice::new(1_000_000));
+        // Rounds at 0, 1 and 2 s after the first poll, four sentences each.
+        assert_eq!(samples.len(), 12);
+        assert_eq!(bad, 0);
+        assert_eq!(samples[0].channel, ChannelId::new("mast/wind"));
+        assert_eq!(samples[4].local_time, 6_000_000);
+        assert_eq!(run(SimulatedNmeaDevice::new(1_000_000)).0, samples);
+    }
+
+    #[test]
+    fn corrupt_sentences_are_counted_and_skipped() {
+        let mut device = SimulatedNmeaDevice::new(1_000_000);
+        device.corrupt_every = Some(3);
+        let (samples, bad) = run(device);
+        assert_eq!(samples.len(), 8);
+        assert_eq!(bad, 4);
+    }
+}
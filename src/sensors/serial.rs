@@ -0,0 +1,53 @@
+//! Opening serial ports for the instrument drivers.
+
+use std::io;
+use std::path::Path;
+
+/// Opens a serial port such as `/dev/ttyUSB0` in raw, non-blocking mode. `baud` is left alone when `None`, which is
+/// right for USB-CDC devices that ignore it.
+#[cfg(unix)]
+pub fn open_port(path: &Path, baud: Option<u32>) -> io::Result<std::fs::File> {
+    use nix::fcntl::OFlag;
+    use nix::sys::termios::{self, BaudRate};
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags((OFlag::O_NONBLOCK | OFlag::O_NOCTTY).bits())
+        .open(path)?;
+    let mut attrs = termios::tcgetattr(&file)?;
+    termios::cfmakeraw(&mut attrs);
+    if let Some(baud) = baud {
+        let rate = match baud {
+            1200 => BaudRate::B1200,
+            2400 => BaudRate::B2400,
+            4800 => BaudRate::B4800,
+            9600 => BaudRate::B9600,
+            19200 => BaudRate::B19200,
+            38400 => BaudRate::B38400,
+            57600 => BaudRate::B57600,
+            115200 => BaudRate::B115200,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unsupported baud rate {baud}"),
+                ))
+            }
+        };
+        termios::cfsetspeed(&mut attrs, rate)?;
+    }
+    termios::tcsetattr(&file, termios::SetArg::TCSANOW, &attrs)?;
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+pub fn open_port(path: &Path, _baud: Option<u32>) -> io::Result<std::fs::File> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "serial ports are not supported on this platform ({})",
+            path.display()
+        ),
+    ))
+}
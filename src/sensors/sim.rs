@@ -0,0 +1,199 @@
+//! A simulated phone, so the app and the pipeline can be exercised on a desktop without real sensors.
+//!
+//! The phone repeats a one minute routine: thirty seconds of tumbling through every orientation (what a user does
+//! for a figure-eight magnetometer calibration) followed by five seconds resting on each of its six faces (what the
+//! accelerometer calibration asks for). Its sensors carry fixed, known distortions so calibration has something to
+//! find.
+
+use crate::math::{self, Quat, Vec3};
+
+use super::{ChannelInfo, Micros, Sample, SensorError, SensorKind, SensorSource};
+
+const G: f64 = 9.80665;
+/// Earth's field somewhere mid latitude, in µT, with x pointing north and z pointing up.
+const EARTH_FIELD: Vec3 = [20.0, 0.0, -45.0];
+const CYCLE: f64 = 60.0;
+const TUMBLE: f64 = 30.0;
+
+/// The distortions the simulated sensors apply to the true values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimDistortion {
+    pub mag_hard_iron: Vec3,
+    pub mag_soft_iron: Vec3,
+    pub accel_bias: Vec3,
+    pub accel_scale: Vec3,
+    pub gyro_bias: Vec3,
+    /// Standard deviation of the noise added to every value, relative to the sensor's typical magnitude.
+    pub noise: f64,
+}
+
+impl Default for SimDistortion {
+    fn default() -> Self {
+        SimDistortion {
+            mag_hard_iron: [12.0, -7.0, 30.0],
+            mag_soft_iron: [1.1, 0.9, 1.05],
+            accel_bias: [0.15, -0.1, 0.2],
+            accel_scale: [1.02, 0.98, 1.01],
+            gyro_bias: [0.01, -0.004, 0.002],
+            noise: 0.002,
+        }
+    }
+}
+
+pub struct SimulatedPhone {
+    pub distortion: SimDistortion,
+    /// Sample period of every channel.
+    pub period: Micros,
+    /// Pressure at the start of the simulation, in hPa.
+    pub base_pressure: f64,
+    start: Option<Micros>,
+    next: Micros,
+    rng: u64,
+}
+
+impl SimulatedPhone {
+    pub fn new(period: Micros) -> Self {
+        SimulatedPhone {
+            distortion: SimDistortion::default(),
+            period,
+            base_pressure: 1013.25,
+            start: None,
+            next: 0,
+            rng: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    /// Orientation of the phone `t` seconds into the simulation.
+    pub fn orientation(t: f64) -> Quat {
+        use std::f64::consts::{FRAC_PI_2, PI};
+        let phase = t.rem_euclid(CYCLE);
+        if phase < TUMBLE {
+            // Spin around while rolling and pitching far enough to point every axis both up and down.
+            Quat::from_axis_angle([0.0, 0.0, 1.0], 0.7 * phase)
+                * Quat::from_axis_angle([1.0, 0.0, 0.0], 2.6 * (0.4 * phase).sin())
+                * Quat::from_axis_angle([0.0, 1.0, 0.0], 1.6 * (0.23 * phase).sin())
+        } else {
+            // Ends screen up, which is where the next tumble starts from.
+            let faces = [
+                Quat::from_axis_angle([1.0, 0.0, 0.0], PI),
+                Quat::from_axis_angle([0.0, 1.0, 0.0], -FRAC_PI_2),
+                Quat::from_axis_angle([0.0, 1.0, 0.0], FRAC_PI_2),
+                Quat::from_axis_angle([1.0, 0.0, 0.0], FRAC_PI_2),
+                Quat::from_axis_angle([1.0, 0.0, 0.0], -FRAC_PI_2),
+                Quat::IDENTITY,
+            ];
+            let index = (((phase - TUMBLE) / 5.0) as usize).min(5);
+            let previous = match index {
+                0 => Self::orientation(t - phase + TUMBLE - 1e-9),
+                i => faces[i - 1],
+            };
+            // The first second on each face is spent turning the phone over to it.
+            let turning = ((phase - TUMBLE) - 5.0 * index as f64).min(1.0);
+            let eased = 0.5 - 0.5 * (PI * turning).cos();
+            previous.slerp(faces[index], eased)
+        }
+    }
+
+    /// Height above the starting point `t` seconds in, in metres. The phone is carried slowly up and down a hill.
+    pub fn altitude(t: f64) -> f64 {
+        20.0 * (t / 300.0).sin()
+    }
+
+    /// True angular rate in the body frame, in rad/s, by differentiating the orientation.
+    pub fn angular_rate(t: f64) -> Vec3 {
+        let dt = 1e-3;
+        let delta = Self::orientation(t).conjugate() * Self::orientation(t + dt);
+        let delta = if delta.w < 0.0 {
+            Quat {
+                w: -delta.w,
+                x: -delta.x,
+                y: -delta.y,
+                z: -delta.z,
+            }
+        } else {
+            delta
+        };
+        let v = [delta.x, delta.y, delta.z];
+        let angle = 2.0 * math::norm(v).atan2(delta.w);
+        math::scale(math::normalize(v), angle / dt)
+    }
+
+    fn sample(&mut self, kind: SensorKind, local_time: Micros, t: f64) -> Sample {
+        let q = Self::orientation(t);
+        let d = self.distortion.clone();
+        let values = match kind {
+            SensorKind::Barometer => {
+                // International barometric formula, roughly 0.12 hPa per metre near sea level.
+                let pressure =
+                    self.base_pressure * (1.0 - 2.25577e-5 * Self::altitude(t)).powf(5.25588);
+                vec![pressure + self.noise(0.05)]
+            }
+            SensorKind::Magnetometer => {
+                let body = q.conjugate().rotate(EARTH_FIELD);
+                let noise = 50.0 * d.noise;
+                (0..3)
+                    .map(|i| body[i] * d.mag_soft_iron[i] + d.mag_hard_iron[i] + self.noise(noise))
+                    .collect()
+            }
+            SensorKind::Accelerometer => {
+                let body = q.conjugate().rotate([0.0, 0.0, G]);
+                let noise = G * d.noise;
+                (0..3)
+                    .map(|i| body[i] * d.accel_scale[i] + d.accel_bias[i] + self.noise(noise))
+                    .collect()
+            }
+            SensorKind::Gyroscope => {
+                let rate = Self::angular_rate(t);
+                (0..3)
+                    .map(|i| rate[i] + d.gyro_bias[i] + self.noise(d.noise))
+                    .collect()
+            }
+        };
+        Sample {
+            channel: kind.channel(),
+            local_time,
+            values,
+        }
+    }
+
+    /// Roughly normal noise with standard deviation `sigma`.
+    fn noise(&mut self, sigma: f64) -> f64 {
+        let mut sum = 0.0;
+        for _ in 0..4 {
+            self.rng ^= self.rng << 13;
+            self.rng ^= self.rng >> 7;
+            self.rng ^= self.rng << 17;
+            sum += (self.rng >> 11) as f64 / (1u64 << 53) as f64 - 0.5;
+        }
+        // The sum of four uniforms on [-0.5, 0.5] has a variance of 1/3.
+        sum * 3f64.sqrt() * sigma
+    }
+}
+
+impl SensorSource for SimulatedPhone {
+    fn channels(&self) -> Vec<ChannelInfo> {
+        SensorKind::ALL
+            .into_iter()
+            .map(ChannelInfo::builtin)
+            .collect()
+    }
+
+    fn poll(&mut self, now: Micros) -> Result<Vec<Sample>, SensorError> {
+        let start = *self.start.get_or_insert_with(|| {
+            self.next = now;
+            now
+        });
+        // Never produce more than a minute of backlog, e.g. after the app was suspended.
+        self.next = self.next.max(now - 60_000_000);
+        let mut samples = Vec::new();
+        while self.next <= now {
+            let local_time = self.next;
+            let t = (local_time - start) as f64 / 1e6;
+            for kind in SensorKind::ALL {
+                samples.push(self.sample(kind, local_time, t));
+            }
+            self.next += self.period.max(1);
+        }
+        Ok(samples)
+    }
+}
@@ -0,0 +1,284 @@
+//! Station descriptions and the driver registry.
+//!
+//! A weather station is a node plus whatever instruments are wired to it. The instruments are listed in a station
+//! description file, TOML or JSON, each naming the driver that talks to it:
+//!
+//! ```toml
+//! name = "hilltop"
+//!
+//! [[instruments]]
+//! name = "mast"
+//! driver = "nmea"
+//! port = "/dev/ttyUSB0"
+//! baud = 4800
+//!
+//! [[instruments]]
+//! name = "soil"
+//! driver = "usb"
+//! port = "/dev/ttyACM0"
+//! ```
+//!
+//! [`DriverRegistry::build_station`] turns the description into one [`SensorSource`]. New protocols (Modbus RTU,
+//! SDI-12, ...) plug in with [`DriverRegistry::register`] and never need changes outside their own module.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::nmea::{NmeaSensor, SimulatedNmeaDevice, SimulatedNmeaInstrument};
+use super::sim::SimulatedPhone;
+use super::{ChannelInfo, Micros, Sample, SensorError, SensorSource};
+
+#[derive(Debug, thiserror::Error)]
+pub enum StationError {
+    #[error("could not read station description: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("station description is malformed: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("station description is malformed: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("instrument {instrument} uses unknown driver {driver:?}")]
+    UnknownDriver { instrument: String, driver: String },
+    #[error("could not start instrument {instrument}: {source}")]
+    Driver {
+        instrument: String,
+        source: SensorError,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StationConfig {
+    pub name: String,
+    #[serde(default)]
+    pub instruments: Vec<InstrumentConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstrumentConfig {
+    /// Prefix of the instrument's channels, so two anemometers on one station do not collide.
+    pub name: String,
+    pub driver: String,
+    #[serde(default)]
+    pub port: Option<PathBuf>,
+    #[serde(default)]
+    pub baud: Option<u32>,
+    /// Driver specific settings, e.g. register maps for Modbus.
+    #[serde(default)]
+    pub options: serde_json::Value,
+}
+
+impl StationConfig {
+    /// Reads a description, as TOML unless the file name ends in `.json`.
+    pub fn load(path: &Path) -> Result<Self, StationError> {
+        let text = std::fs::read_to_string(path)?;
+        if path.extension().is_some_and(|e| e == "json") {
+            Ok(serde_json::from_str(&text)?)
+        } else {
+            Self::from_toml(&text)
+        }
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, StationError> {
+        Ok(toml::from_str(text)?)
+    }
+}
+
+pub type BoxedSource = Box<dyn SensorSource + Send>;
+
+/// Builds a source for one instrument.
+pub type DriverFactory = fn(&InstrumentConfig) -> Result<BoxedSource, SensorError>;
+
+/// Every driver a node can use, by name.
+#[derive(Clone)]
+pub struct DriverRegistry {
+    drivers: BTreeMap<String, DriverFactory>,
+}
+
+impl Default for DriverRegistry {
+    /// The drivers that ship with flumph:
+    ///
+    /// - `nmea`: NMEA 0183 instruments on a serial port ([`super::nmea`]).
+    /// - `usb`: self-describing usb-c sensors ([`super::usb`]).
+    /// - `nmea-sim` and `phone-sim`: simulations, for trying a station description without the hardware.
+    fn default() -> Self {
+        let mut registry = DriverRegistry::empty();
+        registry.register("nmea", nmea);
+        registry.register("usb", usb);
+        registry.register("nmea-sim", |instrument| {
+            let device = SimulatedNmeaDevice::new(1_000_000);
+            Ok(Box::new(SimulatedNmeaInstrument::new(
+                &instrument.name,
+                device,
+            )))
+        });
+        registry.register("phone-sim", |_| Ok(Box::new(SimulatedPhone::new(20_000))));
+        registry
+    }
+}
+
+impl DriverRegistry {
+    pub fn empty() -> Self {
+        DriverRegistry {
+            drivers: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a driver, replacing any existing driver of the same name.
+    pub fn register(&mut self, name: impl Into<String>, factory: DriverFactory) {
+        self.drivers.insert(name.into(), factory);
+    }
+
+    pub fn drivers(&self) -> impl Iterator<Item = &str> {
+        self.drivers.keys().map(String::as_str)
+    }
+
+    pub fn build(&self, instrument: &InstrumentConfig) -> Result<BoxedSource, StationError> {
+        let factory =
+            self.drivers
+                .get(&instrument.driver)
+                .ok_or_else(|| StationError::UnknownDriver {
+                    instrument: instrument.name.clone(),
+                    driver: instrument.driver.clone(),
+                })?;
+        factory(instrument).map_err(|source| StationError::Driver {
+            instrument: instrument.name.clone(),
+            source,
+        })
+    }
+
+    /// Starts every instrument in a description. Fails if any of them cannot be started, so a typo in the file is
+    /// noticed right away rather than as a missing channel days later.
+    pub fn build_station(&self, config: &StationConfig) -> Result<Station, StationError> {
+        let instruments = config
+            .instruments
+            .iter()
+            .map(|i| Ok((i.name.clone(), self.build(i)?)))
+            .collect::<Result<_, StationError>>()?;
+        Ok(Station {
+            name: config.name.clone(),
+            instruments,
+        })
+    }
+}
+
+fn port(instrument: &InstrumentConfig) -> Result<&Path, SensorError> {
+    instrument
+        .port
+        .as_deref()
+        .ok_or_else(|| SensorError::Unavailable(format!("{} has no port", instrument.name)))
+}
+
+fn nmea(instrument: &InstrumentConfig) -> Result<BoxedSource, SensorError> {
+    let link = super::serial::open_port(port(instrument)?, Some(instrument.baud.unwrap_or(4800)))?;
+    Ok(Box::new(NmeaSensor::new(&instrument.name, link)))
+}
+
+fn usb(instrument: &InstrumentConfig) -> Result<BoxedSource, SensorError> {
+    let link = super::serial::open_port(port(instrument)?, instrument.baud)?;
+    Ok(Box::new(super::usb::UsbSensor::new(link)?))
+}
+
+/// All of a station's instruments, polled together.
+pub struct Station {
+    pub name: String,
+    pub instruments: Vec<(String, BoxedSource)>,
+}
+
+impl SensorSource for Station {
+    fn channels(&self) -> Vec<ChannelInfo> {
+        self.instruments
+            .iter()
+            .flat_map(|(_, source)| source.channels())
+            .collect()
+    }
+
+    /// Polls every instrument. An instrument that fails is logged and skipped so one unplugged cable does not stop
+    /// the rest of the station from recording.
+    fn poll(&mut self, now: Micros) -> Result<Vec<Sample>, SensorError> {
+        let mut samples = Vec::new();
+        for (name, source) in &mut self.instruments {
+            match source.poll(now) {
+                Ok(s) => samples.extend(s),
+                Err(e) => tracing::warn!("instrument {name} on station {}: {e}", self.name),
+            }
+        }
+        Ok(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensors::ChannelId;
+
+    const STATION: &str = r#"
+        name = "hilltop"
+
+        [[instruments]]
+        name = "mast"
+        driver = "nmea-sim"
+
+        [[instruments]]
+        name = "phone"
+        driver = "phone-sim"
+    "#;
+
+    #[test]
+    fn a_station_description_builds_every_instrument() {
+        let config = StationConfig::from_toml(STATION).unwrap();
+        let mut station = DriverRegistry::default().build_station(&config).unwrap();
+        assert_eq!(station.name, "hilltop");
+
+        let samples = station.poll(0).unwrap();
+        let channels: std::collections::BTreeSet<ChannelId> =
+            samples.iter().map(|s| s.channel.clone()).collect();
+        assert!(channels.contains(&ChannelId::new("mast/wind")));
+        assert!(channels.contains(&ChannelId::new("barometer")));
+        assert_eq!(station.channels().len(), 4 + 4);
+    }
+
+    #[test]
+    fn unknown_drivers_and_missing_ports_are_reported_by_instrument() {
+        let config = StationConfig::from_toml(
+            r#"
+            name = "valley"
+            [[instruments]]
+            name = "gauge"
+            driver = "sdi-12"
+            "#,
+        )
+        .unwrap();
+        let registry = DriverRegistry::default();
+        assert!(matches!(
+            registry.build_station(&config),
+            Err(StationError::UnknownDriver { instrument, .. }) if instrument == "gauge"
+        ));
+
+        let config = StationConfig {
+            instruments: vec![InstrumentConfig {
+                driver: "nmea".to_string(),
+                ..config.instruments[0].clone()
+            }],
+            ..config
+        };
+        assert!(matches!(
+            registry.build_station(&config),
+            Err(StationError::Driver { instrument, .. }) if instrument == "gauge"
+        ));
+    }
+
+    #[test]
+    fn new_drivers_plug_into_the_registry() {
+        let mut registry = DriverRegistry::empty();
+        registry.register("phone", |_| {
+            Ok(Box::new(crate::sensors::sim::SimulatedPhone::new(
+                1_000_000,
+            )))
+        });
+        assert_eq!(registry.drivers().collect::<Vec<_>>(), vec!["phone"]);
+        let json = r#"{"name": "van", "instruments": [{"name": "p", "driver": "phone"}]}"#;
+        let config: StationConfig = serde_json::from_str(json).unwrap();
+        assert!(registry.build_station(&config).is_ok());
+    }
+}
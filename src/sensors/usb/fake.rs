@@ -0,0 +1,202 @@
+//! A fake usb sensor on a pseudo terminal, so the driver can be exercised on Linux without hardware.
+//!
+//! The device runs on its own thread behind the master side of a pty. Point [`super::open_tty`] at
+//! [`FakeDevice::path`] and it behaves like a real USB-CDC device on `/dev/ttyACM0`.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::OwnedFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::sys::termios;
+
+use super::frame::{encode_frame, FrameDecoder};
+use super::protocol::{Announcement, DeviceMessage, HostMessage, PROTOCOL_VERSION};
+
+/// Produces the values of a channel for the `tick`th reading.
+pub type Generator = Box<dyn FnMut(&Announcement, u64) -> Vec<f32> + Send>;
+
+pub struct FakeDeviceSpec {
+    pub name: String,
+    pub channels: Vec<Announcement>,
+    /// Time between rounds of readings.
+    pub period: Duration,
+    pub generator: Generator,
+    /// Flip a byte in every `n`th frame, to exercise resynchronisation.
+    pub corrupt_every: Option<u64>,
+}
+
+impl FakeDeviceSpec {
+    /// A temperature and humidity probe whose values follow a slow sine.
+    pub fn thermometer() -> Self {
+        FakeDeviceSpec {
+            name: "fake-thermometer".to_string(),
+            channels: vec![
+                Announcement {
+                    channel: 0,
+                    name: "temperature".to_string(),
+                    unit: "°C".to_string(),
+                    dims: 1,
+                    rate_hz: 10.0,
+                },
+                Announcement {
+                    channel: 1,
+                    name: "humidity".to_string(),
+                    unit: "%".to_string(),
+                    dims: 1,
+                    rate_hz: 10.0,
+                },
+            ],
+            period: Duration::from_millis(100),
+            generator: Box::new(|announcement, tick| {
+                let phase = (tick as f32 / 50.0).sin();
+                match announcement.channel {
+                    0 => vec![18.0 + 4.0 * phase],
+                    _ => vec![55.0 - 10.0 * phase],
+                }
+            }),
+            corrupt_every: None,
+        }
+    }
+}
+
+pub struct FakeDevice {
+    path: PathBuf,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    /// Held so the pty stays open even while no host has the slave side open.
+    _slave: OwnedFd,
+}
+
+impl FakeDevice {
+    pub fn spawn(spec: FakeDeviceSpec) -> io::Result<Self> {
+        let pty = nix::pty::openpty(None, None)?;
+        let mut attrs = termios::tcgetattr(&pty.slave)?;
+        termios::cfmakeraw(&mut attrs);
+        termios::tcsetattr(&pty.slave, termios::SetArg::TCSANOW, &attrs)?;
+        fcntl(&pty.master, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+        let path = nix::unistd::ttyname(&pty.slave)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let master = File::from(pty.master);
+        let thread = std::thread::spawn({
+            let stop = stop.clone();
+            move || {
+                if let Err(e) = run(master, spec, &stop) {
+                    tracing::debug!("fake usb device stopped: {e}");
+                }
+            }
+        });
+        Ok(FakeDevice {
+            path,
+            stop,
+            thread: Some(thread),
+            _slave: pty.slave,
+        })
+    }
+
+    /// The tty the host should open.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for FakeDevice {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Writes a frame the way a device with a small transmit buffer does: whatever does not fit while the host is not
+/// reading is dropped, and the host resynchronises at the next delimiter.
+fn write_frame(master: &mut File, frame: &[u8]) -> io::Result<()> {
+    let mut rest = frame;
+    while !rest.is_empty() {
+        match master.write(rest) {
+            Ok(0) => return Ok(()),
+            Ok(n) => rest = &rest[n..],
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            // EIO just means no host has the slave side open right now.
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.raw_os_error() == Some(5) => {
+                return Ok(())
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn run(mut master: File, mut spec: FakeDeviceSpec, stop: &AtomicBool) -> io::Result<()> {
+    let mut sent = 0u64;
+    let mut send = |master: &mut File, message: &DeviceMessage| -> io::Result<()> {
+        let payload = postcard::to_stdvec(message).map_err(io::Error::other)?;
+        let mut frame = encode_frame(&payload);
+        sent += 1;
+        if spec
+            .corrupt_every
+            .is_some_and(|n| n > 0 && sent.is_multiple_of(n))
+        {
+            frame[0] ^= 0x55;
+        }
+        write_frame(master, &frame)
+    };
+    let describe = |send: &mut dyn FnMut(&mut File, &DeviceMessage) -> io::Result<()>,
+                    master: &mut File,
+                    spec: &FakeDeviceSpec|
+     -> io::Result<()> {
+        send(
+            master,
+            &DeviceMessage::Hello {
+                device: spec.name.clone(),
+                firmware: "fake-1".to_string(),
+                protocol: PROTOCOL_VERSION,
+            },
+        )?;
+        for announcement in &spec.channels {
+            send(master, &DeviceMessage::Announce(announcement.clone()))?;
+        }
+        Ok(())
+    };
+
+    let mut decoder = FrameDecoder::default();
+    let mut buf = [0u8; 256];
+    let mut tick = 0u64;
+    describe(&mut send, &mut master, &spec)?;
+    while !stop.load(Ordering::Relaxed) {
+        match master.read(&mut buf) {
+            Ok(n) => {
+                for payload in decoder.push(&buf[..n]).into_iter().flatten() {
+                    match postcard::from_bytes::<HostMessage>(&payload) {
+                        Ok(HostMessage::Describe) => describe(&mut send, &mut master, &spec)?,
+                        Ok(HostMessage::SetRate { channel, rate_hz }) => {
+                            if let Some(a) = spec.channels.iter_mut().find(|a| a.channel == channel)
+                            {
+                                a.rate_hz = rate_hz;
+                            }
+                        }
+                        Err(_) => {}
+                    }
+                }
+            }
+            // EIO just means no host has the slave side open right now.
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.raw_os_error() == Some(5) => {}
+            Err(e) => return Err(e),
+        }
+        for index in 0..spec.channels.len() {
+            let values = (spec.generator)(&spec.channels[index], tick);
+            let channel = spec.channels[index].channel;
+            send(&mut master, &DeviceMessage::Reading { channel, values })?;
+        }
+        tick += 1;
+        std::thread::sleep(spec.period);
+    }
+    Ok(())
+}
@@ -0,0 +1,131 @@
+//! Framing for the serial link: `COBS(payload || crc16) 0x00`.
+//!
+//! COBS removes every zero byte from the frame so a single `0x00` can delimit frames, which lets the host resync
+//! after line noise or after attaching halfway through a frame simply by skipping to the next zero.
+
+/// Largest encoded frame accepted, to bound memory use when a device sends garbage without delimiters.
+pub const MAX_FRAME: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum FrameError {
+    #[error("frame is not valid COBS")]
+    Cobs,
+    #[error("frame checksum mismatch")]
+    Checksum,
+    #[error("frame is longer than {MAX_FRAME} bytes")]
+    TooLong,
+    #[error("frame payload does not decode: {0}")]
+    Payload(String),
+}
+
+/// CRC-16/CCITT-FALSE, small enough to compute bit by bit on a microcontroller.
+pub fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+    for &byte in bytes {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+pub fn cobs_encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 254 + 2);
+    let mut code_index = 0;
+    out.push(0);
+    let mut code = 1u8;
+    for &byte in data {
+        if byte == 0 {
+            out[code_index] = code;
+            code_index = out.len();
+            out.push(0);
+            code = 1;
+        } else {
+            out.push(byte);
+            code += 1;
+            if code == 0xff {
+                out[code_index] = code;
+                code_index = out.len();
+                out.push(0);
+                code = 1;
+            }
+        }
+    }
+    out[code_index] = code;
+    out
+}
+
+pub fn cobs_decode(data: &[u8]) -> Result<Vec<u8>, FrameError> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let code = data[i] as usize;
+        if code == 0 || i + code > data.len() {
+            return Err(FrameError::Cobs);
+        }
+        out.extend_from_slice(&data[i + 1..i + code]);
+        i += code;
+        if code < 0xff && i < data.len() {
+            out.push(0);
+        }
+    }
+    Ok(out)
+}
+
+/// Wraps a payload into a complete frame, delimiter included.
+pub fn encode_frame(payload: &[u8]) -> Vec<u8> {
+    let mut body = payload.to_vec();
+    body.extend_from_slice(&crc16(payload).to_le_bytes());
+    let mut frame = cobs_encode(&body);
+    frame.push(0);
+    frame
+}
+
+/// Splits a byte stream back into checked payloads.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    overflowed: bool,
+}
+
+impl FrameDecoder {
+    /// Feeds bytes read from the link and returns every frame they completed.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Result<Vec<u8>, FrameError>> {
+        let mut frames = Vec::new();
+        for &byte in bytes {
+            if byte != 0 {
+                if self.buffer.len() < MAX_FRAME {
+                    self.buffer.push(byte);
+                } else {
+                    self.overflowed = true;
+                }
+                continue;
+            }
+            let raw = std::mem::take(&mut self.buffer);
+            if std::mem::take(&mut self.overflowed) {
+                frames.push(Err(FrameError::TooLong));
+            } else if !raw.is_empty() {
+                frames.push(decode_frame(&raw));
+            }
+        }
+        frames
+    }
+}
+
+fn decode_frame(raw: &[u8]) -> Result<Vec<u8>, FrameError> {
+    let mut body = cobs_decode(raw)?;
+    if body.len() < 2 {
+        return Err(FrameError::Checksum);
+    }
+    let crc = u16::from_le_bytes([body[body.len() - 2], body[body.len() - 1]]);
+    body.truncate(body.len() - 2);
+    if crc16(&body) != crc {
+        return Err(FrameError::Checksum);
+    }
+    Ok(body)
+}
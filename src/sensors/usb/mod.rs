@@ -0,0 +1,412 @@
+//! External sensors attached to a phone's usb-c port as USB-CDC serial devices.
+//!
+//! Devices speak a small framed protocol (see [`frame`]) and describe their own channels when they connect, so an
+//! anemometer or soil probe built on any microcontroller shows up on the node without a dedicated host driver.
+//! [`UsbSensor`] is the host side: it turns announcements into channels and readings into samples.
+
+pub mod frame;
+pub mod protocol;
+
+#[cfg(unix)]
+pub mod fake;
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+use self::frame::{encode_frame, FrameDecoder};
+use self::protocol::{Announcement, DeviceMessage, HostMessage, PROTOCOL_VERSION};
+use super::{ChannelId, ChannelInfo, Micros, Sample, SensorError, SensorSource};
+
+/// Least time between two [`HostMessage::Describe`]s sent because of readings on channels that were never announced.
+const DESCRIBE_RETRY: Micros = 5_000_000;
+
+/// What a device said about itself in its hello.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub device: String,
+    pub firmware: String,
+    pub protocol: u8,
+}
+
+/// Host side driver for one device on a serial link.
+pub struct UsbSensor<T> {
+    link: T,
+    decoder: FrameDecoder,
+    device: Option<DeviceInfo>,
+    announcements: BTreeMap<u8, Announcement>,
+    /// Corrupt or undecodable frames seen so far.
+    pub bad_frames: u64,
+    /// Readings that did not match their channel's announcement.
+    pub bad_readings: u64,
+    /// When the device was last asked to describe itself because of a reading on an unknown channel.
+    described: Option<Micros>,
+}
+
+impl<T: Read + Write> UsbSensor<T> {
+    /// Takes over a link and asks the device to describe itself. `link` should be non-blocking.
+    pub fn new(link: T) -> io::Result<Self> {
+        let mut sensor = UsbSensor {
+            link,
+            decoder: FrameDecoder::default(),
+            device: None,
+            announcements: BTreeMap::new(),
+            bad_frames: 0,
+            bad_readings: 0,
+            described: None,
+        };
+        sensor.send(&HostMessage::Describe)?;
+        Ok(sensor)
+    }
+
+    pub fn device(&self) -> Option<&DeviceInfo> {
+        self.device.as_ref()
+    }
+
+    pub fn send(&mut self, message: &HostMessage) -> io::Result<()> {
+        let payload = postcard::to_stdvec(message).map_err(io::Error::other)?;
+        self.link.write_all(&encode_frame(&payload))?;
+        self.link.flush()
+    }
+
+    fn channel_id(&self, name: &str) -> ChannelId {
+        let device = self
+            .device
+            .as_ref()
+            .map_or("unknown", |d| d.device.as_str());
+        ChannelId::new(format!("usb/{device}/{name}"))
+    }
+
+    /// Handles one message, returning a sample if it was a reading on an announced channel. Only a device that cannot
+    /// be talked to at all is an error; a bad reading is counted and skipped.
+    fn handle(
+        &mut self,
+        message: DeviceMessage,
+        now: Micros,
+    ) -> Result<Option<Sample>, SensorError> {
+        match message {
+            DeviceMessage::Hello {
+                device,
+                firmware,
+                protocol,
+            } => {
+                if protocol != PROTOCOL_VERSION {
+                    return Err(SensorError::Unavailable(format!(
+                        "{device} speaks protocol {protocol}, expected {PROTOCOL_VERSION}"
+                    )));
+                }
+                tracing::info!("usb sensor {device} (firmware {firmware}) connected");
+                // A hello means the device (re)started, so whatever it announced before is void.
+                self.announcements.clear();
+                self.device = Some(DeviceInfo {
+                    device,
+                    firmware,
+                    protocol,
+                });
+            }
+            DeviceMessage::Announce(announcement) => {
+                self.announcements
+                    .insert(announcement.channel, announcement);
+            }
+            DeviceMessage::Reading { channel, values } => {
+                let Some(announcement) = self.announcements.get(&channel) else {
+                    // We attached mid stream and missed the announcements. The device keeps sending readings until
+                    // it has answered, so only ask again once an answer is overdue.
+                    if self.described.is_none_or(|at| now - at >= DESCRIBE_RETRY) {
+                        self.described = Some(now);
+                        if let Err(e) = self.send(&HostMessage::Describe) {
+                            tracing::warn!("cannot ask usb sensor to describe itself: {e}");
+                        }
+                    }
+                    return Ok(None);
+                };
+                if values.len() != announcement.dims as usize {
+                    self.bad_readings += 1;
+                    tracing::debug!(
+                        "dropping reading from usb sensor: {} sent {} values, announced {}",
+                        announcement.name,
+                        values.len(),
+                        announcement.dims
+                    );
+                    return Ok(None);
+                }
+                return Ok(Some(Sample {
+                    channel: self.channel_id(&announcement.name),
+                    local_time: now,
+                    values: values.into_iter().map(f64::from).collect(),
+                }));
+            }
+            DeviceMessage::Fault { message } => {
+                tracing::warn!("usb sensor reported a fault: {message}");
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<T: Read + Write> SensorSource for UsbSensor<T> {
+    fn channels(&self) -> Vec<ChannelInfo> {
+        self.announcements
+            .values()
+            .map(|a| ChannelInfo {
+                id: self.channel_id(&a.name),
+                kind: None,
+                unit: a.unit.clone(),
+                dims: a.dims as usize,
+            })
+            .collect()
+    }
+
+    /// Drains everything the device has sent. Readings are stamped with `now`: USB latency is far below the rates
+    /// these sensors report at, so device side timestamps are not worth the protocol complexity.
+    fn poll(&mut self, now: Micros) -> Result<Vec<Sample>, SensorError> {
+        let mut samples = Vec::new();
+        let mut buf = [0u8; 512];
+        loop {
+            let read = match self.link.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for frame in self.decoder.push(&buf[..read]) {
+                let message = frame.and_then(|payload| {
+                    postcard::from_bytes::<DeviceMessage>(&payload)
+                        .map_err(|e| frame::FrameError::Payload(e.to_string()))
+                });
+                match message {
+                    Ok(message) => samples.extend(self.handle(message, now)?),
+                    Err(e) => {
+                        self.bad_frames += 1;
+                        tracing::debug!("dropping bad frame from usb sensor: {e}");
+                    }
+                }
+            }
+        }
+        Ok(samples)
+    }
+}
+
+/// Opens a serial device such as `/dev/ttyACM0` in raw, non-blocking mode.
+#[cfg(unix)]
+pub fn open_tty(path: &std::path::Path) -> io::Result<std::fs::File> {
+    super::serial::open_port(path, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::time::{Duration, Instant};
+
+    use super::protocol::Announcement;
+    use super::*;
+
+    /// One end of a link whose other end is the test.
+    #[derive(Default)]
+    struct MemoryLink {
+        incoming: VecDeque<u8>,
+        written: Vec<u8>,
+    }
+
+    impl MemoryLink {
+        fn device_sends(&mut self, message: &DeviceMessage) {
+            let payload = postcard::to_stdvec(message).unwrap();
+            self.incoming.extend(encode_frame(&payload));
+        }
+
+        fn host_sent(&self) -> Vec<HostMessage> {
+            FrameDecoder::default()
+                .push(&self.written)
+                .into_iter()
+                .map(|frame| postcard::from_bytes(&frame.unwrap()).unwrap())
+                .collect()
+        }
+    }
+
+    impl Read for MemoryLink {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.incoming.is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let n = buf.len().min(self.incoming.len());
+            for (slot, byte) in buf.iter_mut().zip(self.incoming.drain(..n)) {
+                *slot = byte;
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for MemoryLink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn hello() -> DeviceMessage {
+        DeviceMessage::Hello {
+            device: "probe".to_string(),
+            firmware: "1".to_string(),
+            protocol: PROTOCOL_VERSION,
+        }
+    }
+
+    fn announce(channel: u8, name: &str, dims: u8) -> DeviceMessage {
+        DeviceMessage::Announce(Announcement {
+            channel,
+            name: name.to_string(),
+            unit: "x".to_string(),
+            dims,
+            rate_hz: 1.0,
+        })
+    }
+
+    fn reading(channel: u8, values: &[f32]) -> DeviceMessage {
+        DeviceMessage::Reading {
+            channel,
+            values: values.to_vec(),
+        }
+    }
+
+    #[test]
+    fn a_bad_reading_does_not_lose_the_rest_of_the_batch() {
+        let mut sensor = UsbSensor::new(MemoryLink::default()).unwrap();
+        sensor.link.device_sends(&hello());
+        sensor.link.device_sends(&announce(0, "wind", 2));
+        sensor.link.device_sends(&reading(0, &[1.0, 2.0]));
+        sensor.link.device_sends(&reading(0, &[3.0]));
+        sensor.link.device_sends(&reading(0, &[4.0, 5.0]));
+
+        let samples = sensor.poll(10).unwrap();
+        let values: Vec<Vec<f64>> = samples.iter().map(|s| s.values.clone()).collect();
+        assert_eq!(values, vec![vec![1.0, 2.0], vec![4.0, 5.0]]);
+        assert_eq!(samples[0].channel, ChannelId::new("usb/probe/wind"));
+        assert_eq!(sensor.bad_readings, 1);
+        assert_eq!(sensor.channels().len(), 1);
+    }
+
+    #[test]
+    fn readings_on_unknown_channels_ask_for_a_description_once_per_retry() {
+        let mut sensor = UsbSensor::new(MemoryLink::default()).unwrap();
+        for t in 0..10 {
+            sensor.link.device_sends(&reading(3, &[1.0]));
+            assert!(sensor.poll(t * 100_000).unwrap().is_empty());
+        }
+        // The one from `new`, and one for the first unknown reading.
+        assert_eq!(sensor.link.host_sent(), vec![HostMessage::Describe; 2]);
+        sensor.link.device_sends(&reading(3, &[1.0]));
+        sensor.poll(DESCRIBE_RETRY).unwrap();
+        assert_eq!(sensor.link.host_sent().len(), 3);
+    }
+
+    #[test]
+    fn a_device_on_another_protocol_is_refused() {
+        let mut sensor = UsbSensor::new(MemoryLink::default()).unwrap();
+        sensor.link.device_sends(&DeviceMessage::Hello {
+            device: "probe".to_string(),
+            firmware: "1".to_string(),
+            protocol: PROTOCOL_VERSION + 1,
+        });
+        assert!(matches!(sensor.poll(0), Err(SensorError::Unavailable(_))));
+    }
+
+    #[test]
+    fn frames_survive_corruption_and_partial_reads() {
+        let payloads: [&[u8]; 3] = [b"", &[0, 0, 1, 0], &[7; 600]];
+        // Attaching halfway through a frame loses that frame, and only that one.
+        let mut stream = vec![0x13, 0x37];
+        stream.extend(encode_frame(b"lost"));
+        for payload in payloads {
+            stream.extend(encode_frame(payload));
+        }
+        let mut corrupt = encode_frame(b"oops");
+        corrupt[1] ^= 0x01;
+        stream.extend(corrupt);
+        stream.extend(encode_frame(b"after"));
+
+        let mut decoder = FrameDecoder::default();
+        let frames: Vec<_> = stream
+            .chunks(5)
+            .flat_map(|chunk| decoder.push(chunk))
+            .collect();
+        assert!(frames[0].is_err());
+        let good: Vec<Vec<u8>> = frames[1..4].iter().map(|f| f.clone().unwrap()).collect();
+        assert_eq!(good, payloads.map(<[u8]>::to_vec));
+        assert_eq!(frames[4], Err(frame::FrameError::Checksum));
+        assert_eq!(frames[5], Ok(b"after".to_vec()));
+    }
+
+    /// Polls `sensor` until `done` says so, or fails after a few seconds.
+    #[cfg(unix)]
+    fn poll_until<T: Read + Write>(
+        sensor: &mut UsbSensor<T>,
+        mut done: impl FnMut(&[Sample]) -> bool,
+    ) -> Vec<Sample> {
+        let start = Instant::now();
+        let mut samples = Vec::new();
+        while start.elapsed() < Duration::from_secs(5) {
+            samples.extend(sensor.poll(start.elapsed().as_micros() as Micros).unwrap());
+            if done(&samples) {
+                return samples;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("gave up after {} samples", samples.len());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_fake_device_on_a_pty_shows_up_as_channels() {
+        let device = fake::FakeDevice::spawn(fake::FakeDeviceSpec {
+            corrupt_every: Some(7),
+            ..fake::FakeDeviceSpec::thermometer()
+        })
+        .unwrap();
+        let mut sensor = UsbSensor::new(open_tty(device.path()).unwrap()).unwrap();
+        let samples = poll_until(&mut sensor, |samples| samples.len() >= 20);
+
+        assert_eq!(sensor.device().unwrap().device, "fake-thermometer");
+        let temperature = ChannelId::new("usb/fake-thermometer/temperature");
+        let humidity = ChannelId::new("usb/fake-thermometer/humidity");
+        let channels: Vec<ChannelId> = sensor.channels().into_iter().map(|c| c.id).collect();
+        assert_eq!(channels, vec![temperature.clone(), humidity.clone()]);
+        for sample in &samples {
+            let range = if sample.channel == temperature {
+                14.0..=22.0
+            } else {
+                45.0..=65.0
+            };
+            assert!(range.contains(&sample.values[0]), "{sample:?}");
+        }
+        assert!(sensor.bad_frames > 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_fake_device_keeps_going_while_the_host_is_not_reading() {
+        let device = fake::FakeDevice::spawn(fake::FakeDeviceSpec {
+            name: "fake-spectrometer".to_string(),
+            channels: vec![Announcement {
+                channel: 0,
+                name: "spectrum".to_string(),
+                unit: "counts".to_string(),
+                dims: 100,
+                rate_hz: 1_000.0,
+            }],
+            period: Duration::from_millis(1),
+            generator: Box::new(|announcement, _| vec![0.5; announcement.dims as usize]),
+            corrupt_every: None,
+        })
+        .unwrap();
+        let mut sensor = UsbSensor::new(open_tty(device.path()).unwrap()).unwrap();
+        // Long enough to fill the pty's buffer several times over.
+        std::thread::sleep(Duration::from_millis(500));
+        let _ = sensor.poll(0).unwrap();
+        let samples = poll_until(&mut sensor, |samples| samples.len() >= 50);
+        assert!(samples.iter().all(|s| s.values.len() == 100));
+    }
+}
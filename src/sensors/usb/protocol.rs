@@ -0,0 +1,47 @@
+//! Messages exchanged over the link, encoded with postcard so a `no_std` microcontroller can produce them too.
+
+use serde::{Deserialize, Serialize};
+
+/// Bumped whenever a message changes incompatibly.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Describes one channel a device can report, so the host needs no per device driver.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Announcement {
+    /// Index used by readings on this channel. Only unique within one device.
+    pub channel: u8,
+    pub name: String,
+    pub unit: String,
+    pub dims: u8,
+    pub rate_hz: f32,
+}
+
+/// Sent by the device.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DeviceMessage {
+    /// Sent on power up and in reply to [`HostMessage::Describe`], followed by one announcement per channel.
+    Hello {
+        device: String,
+        firmware: String,
+        protocol: u8,
+    },
+    Announce(Announcement),
+    Reading {
+        channel: u8,
+        values: Vec<f32>,
+    },
+    Fault {
+        message: String,
+    },
+}
+
+/// Sent by the host.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum HostMessage {
+    /// Asks the device to repeat its hello and announcements.
+    Describe,
+    SetRate {
+        channel: u8,
+        rate_hz: f32,
+    },
+}
@@ -0,0 +1,43 @@
+//! What the server's API sends and takes, shared with the web build of the app that reads it.
+
+use serde::{Deserialize, Serialize};
+
+use crate::node::NodeStatus;
+use crate::sensors::{Micros, Sample};
+use crate::storage::BlobInfo;
+
+/// Path of the WebSocket that streams [`LiveUpdate`]s.
+pub const LIVE_PATH: &str = "/api/live";
+
+/// Cookie a browser is given once it has paired.
+pub const SESSION_COOKIE: &str = "flumph_session";
+
+/// What a browser posts to `/api/pair` to be let in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PairRequest {
+    pub token: String,
+}
+
+/// One message on the live WebSocket: the node's status, the samples it took since the last message and, when the
+/// store has changed, every blob in it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LiveUpdate {
+    pub status: NodeStatus,
+    pub samples: Vec<Sample>,
+    pub blobs: Option<Vec<BlobInfo>>,
+}
+
+/// A decoded frame of footage, ready to show.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreviewFrame {
+    /// Network time the frame was captured at.
+    pub time: Micros,
+    /// A `data:` URL of the frame as a JPEG.
+    pub image: String,
+}
+
+/// Body of every error the API answers with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiError {
+    pub error: String,
+}
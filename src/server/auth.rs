@@ -0,0 +1,140 @@
+//! Who may use the API.
+//!
+//! Nodes have no keys of their own yet, so the one credential is the pairing token the compute node shows in its
+//! settings. Scripts send it as a bearer token; browsers trade it once for a session cookie, either by posting it to
+//! `/api/pair` or by opening a link with it in the query.
+
+use std::collections::VecDeque;
+
+use super::api::SESSION_COOKIE;
+use super::http::Request;
+
+/// Most browser sessions kept at once. Pairing another drops the oldest.
+const MAX_SESSIONS: usize = 256;
+
+/// A new random token, as 32 hex digits.
+pub fn new_token() -> String {
+    let mut bytes = [0u8; 16];
+    // Without an OS random source nothing could be kept private anyway, so there is no point carrying on.
+    getrandom::fill(&mut bytes).expect("the OS has no random number source");
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[derive(Debug)]
+pub struct Auth {
+    token: String,
+    sessions: VecDeque<String>,
+}
+
+impl Auth {
+    pub fn new(token: String) -> Self {
+        Auth {
+            token,
+            sessions: VecDeque::new(),
+        }
+    }
+
+    /// Starts a session for whoever knows the token, returning its id.
+    pub fn pair(&mut self, token: &str) -> Option<String> {
+        if !same(token, &self.token) {
+            return None;
+        }
+        if self.sessions.len() == MAX_SESSIONS {
+            self.sessions.pop_front();
+        }
+        let session = new_token();
+        self.sessions.push_back(session.clone());
+        Some(session)
+    }
+
+    /// Whether `request` carries the token or the cookie of a session.
+    pub fn allows(&self, request: &Request) -> bool {
+        let bearer = request
+            .header("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if bearer.is_some_and(|token| same(token.trim(), &self.token)) {
+            return true;
+        }
+        request
+            .cookie(SESSION_COOKIE)
+            .is_some_and(|cookie| self.sessions.iter().any(|session| same(cookie, session)))
+    }
+}
+
+/// Compares secrets in time that does not depend on where they differ.
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// `Set-Cookie` value for a session. It is only sent back to this server, and scripts on the page cannot read it.
+pub fn session_cookie(session: &str) -> String {
+    format!("{SESSION_COOKIE}={session}; Path=/; HttpOnly; SameSite=Strict")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "0123456789abcdef0123456789abcdef";
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: "GET".to_string(),
+            path: "/api/status".to_string(),
+            query: Vec::new(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: Vec::new(),
+        }
+    }
+
+    fn with_session(session: &str) -> Request {
+        request(&[("cookie", &format!("theme=dark; {SESSION_COOKIE}={session}"))])
+    }
+
+    #[test]
+    fn the_token_pairs_a_browser_whose_cookie_then_lets_it_in() {
+        let mut auth = Auth::new(TOKEN.to_string());
+        let session = auth.pair(TOKEN).unwrap();
+        assert_ne!(session, TOKEN);
+        assert!(auth.allows(&with_session(&session)));
+        assert!(session_cookie(&session).starts_with(&format!("{SESSION_COOKIE}={session};")));
+    }
+
+    #[test]
+    fn the_token_lets_scripts_in_as_a_bearer() {
+        let auth = Auth::new(TOKEN.to_string());
+        assert!(auth.allows(&request(&[("authorization", &format!("Bearer {TOKEN}"))])));
+        assert!(!auth.allows(&request(&[("authorization", TOKEN)])));
+    }
+
+    #[test]
+    fn wrong_tokens_and_sessions_are_turned_away() {
+        let mut auth = Auth::new(TOKEN.to_string());
+        assert_eq!(auth.pair("0123456789abcdef0123456789abcdee"), None);
+        assert_eq!(auth.pair(""), None);
+        assert!(!auth.allows(&request(&[])));
+        assert!(!auth.allows(&request(&[("authorization", "Bearer nope")])));
+        assert!(!auth.allows(&with_session(TOKEN)));
+        let session = auth.pair(TOKEN).unwrap();
+        assert!(!auth.allows(&with_session(&session[1..])));
+    }
+
+    #[test]
+    fn pairing_past_the_limit_drops_the_oldest_session() {
+        let mut auth = Auth::new(TOKEN.to_string());
+        let sessions: Vec<String> = (0..=MAX_SESSIONS)
+            .map(|_| auth.pair(TOKEN).unwrap())
+            .collect();
+        assert!(!auth.allows(&with_session(&sessions[0])));
+        assert!(sessions[1..]
+            .iter()
+            .all(|session| auth.allows(&with_session(session))));
+    }
+}
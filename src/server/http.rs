@@ -0,0 +1,332 @@
+//! Just enough HTTP/1.1 for the server: one request per connection, no chunked bodies.
+
+use std::io::{self, BufRead, Read, Write};
+
+use serde::Serialize;
+
+use super::api::ApiError;
+
+/// Longest request head (request line and headers) the server reads.
+const MAX_HEAD: usize = 16 * 1024;
+/// Longest request body. The API only ever takes small JSON bodies.
+const MAX_BODY: usize = 64 * 1024;
+const MAX_HEADERS: usize = 64;
+
+#[derive(Debug, thiserror::Error)]
+pub enum HttpError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("malformed request: {0}")]
+    Malformed(String),
+    #[error("request is too large")]
+    TooLarge,
+}
+
+/// A request, with its path and query already percent-decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    /// Header names are lowercase.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Reads one request from `reader`.
+    pub fn read(reader: &mut impl BufRead) -> Result<Self, HttpError> {
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let read = reader
+                .by_ref()
+                .take(MAX_HEAD as u64)
+                .read_until(b'\n', &mut head)?;
+            if read == 0 {
+                return Err(HttpError::Malformed(
+                    "connection closed mid request".to_string(),
+                ));
+            }
+            if head.len() > MAX_HEAD {
+                return Err(HttpError::TooLarge);
+            }
+        }
+
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut parsed = httparse::Request::new(&mut headers);
+        parsed
+            .parse(&head)
+            .map_err(|e| HttpError::Malformed(e.to_string()))?;
+        let method = parsed.method.unwrap_or_default().to_string();
+        let target = parsed.path.unwrap_or("/");
+        let headers: Vec<(String, String)> = parsed
+            .headers
+            .iter()
+            .map(|h| {
+                (
+                    h.name.to_ascii_lowercase(),
+                    String::from_utf8_lossy(h.value).into_owned(),
+                )
+            })
+            .collect();
+
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let query = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(name, true), percent_decode(value, true))
+            })
+            .collect();
+
+        let path = percent_decode(path, false);
+        // The path goes back out in redirects, where a line break would start a header of the requester's choosing.
+        if path.chars().any(char::is_control) {
+            return Err(HttpError::Malformed(
+                "the path has control characters".to_string(),
+            ));
+        }
+        let mut request = Request {
+            method,
+            path,
+            query,
+            headers,
+            body: Vec::new(),
+        };
+        let length: usize = request
+            .header("content-length")
+            .map_or(Ok(0), str::parse)
+            .map_err(|_| HttpError::Malformed("bad content-length".to_string()))?;
+        if length > MAX_BODY {
+            return Err(HttpError::TooLarge);
+        }
+        request.body = vec![0; length];
+        reader.read_exact(&mut request.body)?;
+        Ok(request)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .filter(|(n, _)| n == "cookie")
+            .flat_map(|(_, value)| value.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value)
+    }
+
+    /// The path split at slashes, without empty segments.
+    pub fn segments(&self) -> Vec<&str> {
+        self.path.split('/').filter(|s| !s.is_empty()).collect()
+    }
+
+    /// Whether the request asks to become a WebSocket.
+    pub fn is_upgrade(&self) -> bool {
+        self.header("upgrade")
+            .is_some_and(|u| u.eq_ignore_ascii_case("websocket"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Response {
+            status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: body.into(),
+        }
+    }
+
+    pub fn json(value: &impl Serialize) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => Response::new(200, "application/json", body),
+            Err(e) => Response::error(500, &e.to_string()),
+        }
+    }
+
+    pub fn error(status: u16, message: &str) -> Self {
+        let body = ApiError {
+            error: message.to_string(),
+        };
+        Response {
+            status,
+            ..Response::json(&body)
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str(&format!(
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            self.body.len()
+        ));
+        writer.write_all(head.as_bytes())?;
+        writer.write_all(&self.body)?;
+        writer.flush()
+    }
+
+    /// Writes just the head, leaving the connection open for whatever protocol it switches to.
+    pub fn write_upgrade(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+        writer.write_all(head.as_bytes())?;
+        writer.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        101 => "Switching Protocols",
+        200 => "OK",
+        303 => "See Other",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        500 => "Internal Server Error",
+        _ => "",
+    }
+}
+
+/// Undoes `%XX` escapes and, in queries, turns `+` into spaces. Escapes that are not valid UTF-8 come out as U+FFFD.
+fn percent_decode(text: &str, query: bool) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) if query => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(raw: &[u8]) -> Result<Request, HttpError> {
+        Request::read(&mut &raw[..])
+    }
+
+    #[test]
+    fn requests_are_read_with_their_path_query_headers_and_body() {
+        let request = read(
+            b"POST /api/a%20b?from=1&text=a+b%26c HTTP/1.1\r\nHost: node\r\nCookie: x=1; flumph_session=abc\r\n\
+              Content-Length: 5\r\n\r\nhello",
+        )
+        .unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/api/a b");
+        assert_eq!(request.segments(), ["api", "a b"]);
+        assert_eq!(request.query("from"), Some("1"));
+        assert_eq!(request.query("text"), Some("a b&c"));
+        assert_eq!(request.header("host"), Some("node"));
+        assert_eq!(request.cookie("flumph_session"), Some("abc"));
+        assert_eq!(request.body, b"hello");
+    }
+
+    #[test]
+    fn malformed_requests_are_refused() {
+        for raw in [
+            &b"not http at all\r\n\r\n"[..],
+            b"GET / HTTP/1.1\r\nContent-Length: lots\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: node\r\n",
+            b"",
+        ] {
+            assert!(
+                matches!(read(raw), Err(HttpError::Malformed(_))),
+                "{}",
+                String::from_utf8_lossy(raw)
+            );
+        }
+    }
+
+    #[test]
+    fn paths_with_control_characters_are_refused() {
+        let raw = b"GET /app%0d%0aSet-Cookie:%20x=1?token=t HTTP/1.1\r\n\r\n";
+        assert!(matches!(read(raw), Err(HttpError::Malformed(_))));
+    }
+
+    #[test]
+    fn oversized_requests_are_refused() {
+        let mut head = b"GET / HTTP/1.1\r\nX-Padding: ".to_vec();
+        head.extend(std::iter::repeat_n(b'a', MAX_HEAD));
+        head.extend(b"\r\n\r\n");
+        assert!(matches!(read(&head), Err(HttpError::TooLarge)));
+
+        let body = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY + 1
+        );
+        assert!(matches!(read(body.as_bytes()), Err(HttpError::TooLarge)));
+    }
+
+    #[test]
+    fn a_body_cut_short_is_an_error() {
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort";
+        assert!(matches!(read(raw), Err(HttpError::Io(_))));
+    }
+
+    #[test]
+    fn responses_carry_their_length_and_close_the_connection() {
+        let mut written = Vec::new();
+        Response::error(404, "gone")
+            .with_header("X-Test", "1")
+            .write_to(&mut written)
+            .unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(written.contains("X-Test: 1\r\n"));
+        assert!(written.contains("Content-Length: 16\r\nConnection: close\r\n\r\n"));
+        assert!(written.ends_with("{\"error\":\"gone\"}"));
+    }
+}
@@ -0,0 +1,537 @@
+//! The compute node's HTTP server, so that anyone on its network can look at it from a browser.
+//!
+//! [`Server`] serves the `web` build of the app from a directory, falling back to its `index.html` for the app's own
+//! routes, alongside a JSON API over the node's [`NodeBackend`]:
+//!
+//! - `POST /api/pair` trades the pairing token for a session cookie.
+//! - `GET /api/status` is the node's [`NodeStatus`](crate::node::NodeStatus).
+//! - `GET /api/blobs` lists stored blobs, narrowed by the `node`, `kind`, `from` and `to` (hours) query parameters.
+//! - `GET /api/hours/{hash}` is an hour blob, and `GET /api/hours/{hash}/csv` its samples as CSV.
+//! - `GET /api/timeline/{day}` is the footage and detections of a UTC day.
+//! - `GET /api/footage/{hash}/{segment}/{offset}` decodes footage from a keyframe into [`PreviewFrame`]s.
+//! - [`LIVE_PATH`] is a WebSocket streaming [`LiveUpdate`]s.
+//!
+//! Everything under `/api` but pairing needs the token or a session. Each connection gets a thread and carries one
+//! request, which is plenty for a household of browsers.
+
+pub mod api;
+#[cfg(not(target_arch = "wasm32"))]
+mod auth;
+#[cfg(not(target_arch = "wasm32"))]
+mod http;
+
+pub use api::{ApiError, LiveUpdate, PairRequest, PreviewFrame, LIVE_PATH, SESSION_COOKIE};
+#[cfg(not(target_arch = "wasm32"))]
+pub use auth::new_token;
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::{Server, ServerOptions, SharedBackend};
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use std::io::{self, BufReader};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::path::{Component, Path, PathBuf};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+    use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+    use std::thread::JoinHandle;
+    use std::time::Duration;
+
+    use base64::Engine;
+    use tungstenite::handshake::derive_accept_key;
+    use tungstenite::protocol::Role;
+    use tungstenite::{Message, WebSocket};
+
+    use super::api::{LiveUpdate, PairRequest, PreviewFrame, LIVE_PATH};
+    use super::auth::{session_cookie, Auth};
+    use super::http::{HttpError, Request, Response};
+    use crate::node::{NodeBackend, NodeId};
+    use crate::sensors::Sample;
+    use crate::storage::{day_span, BlobHash, BlobKind, BlobQuery};
+    use crate::time_sync::local_now;
+
+    /// How often the listener checks whether the server is stopping.
+    const ACCEPT_POLL: Duration = Duration::from_millis(50);
+    /// How often live connections get an update.
+    const LIVE_INTERVAL: Duration = Duration::from_millis(250);
+    /// How long a connection may take to send its request or accept a response.
+    const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// A backend shared between the app, which polls it, and the server, which reads it.
+    pub type SharedBackend = Arc<Mutex<dyn NodeBackend + Send>>;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ServerOptions {
+        pub listen: SocketAddr,
+        /// Directory of the web build. Without one only the API is served.
+        pub web_root: Option<PathBuf>,
+        /// What browsers and scripts need to use the API.
+        pub token: String,
+    }
+
+    /// A running server. Dropping it stops it.
+    pub struct Server {
+        local: SocketAddr,
+        state: Arc<State>,
+        listener: Option<JoinHandle<()>>,
+    }
+
+    struct State {
+        node: SharedBackend,
+        auth: Mutex<Auth>,
+        web_root: Option<PathBuf>,
+        /// Live connections, each waiting for the samples the app polls.
+        subscribers: Mutex<Vec<Sender<Vec<Sample>>>>,
+        stopping: AtomicBool,
+    }
+
+    impl Server {
+        pub fn start(options: ServerOptions, node: SharedBackend) -> io::Result<Server> {
+            let listener = TcpListener::bind(options.listen)?;
+            listener.set_nonblocking(true)?;
+            let local = listener.local_addr()?;
+            let state = Arc::new(State {
+                node,
+                auth: Mutex::new(Auth::new(options.token)),
+                web_root: options.web_root,
+                subscribers: Mutex::new(Vec::new()),
+                stopping: AtomicBool::new(false),
+            });
+            let accepting = state.clone();
+            let listener = std::thread::Builder::new()
+                .name("http".to_string())
+                .spawn(move || accept(listener, accepting))?;
+            tracing::info!("serving the app on http://{local}");
+            Ok(Server {
+                local,
+                state,
+                listener: Some(listener),
+            })
+        }
+
+        pub fn local_addr(&self) -> SocketAddr {
+            self.local
+        }
+
+        /// Hands samples the app polled from the node to every live connection.
+        pub fn publish(&self, samples: &[Sample]) {
+            if samples.is_empty() {
+                return;
+            }
+            lock(&self.state.subscribers)
+                .retain(|subscriber| subscriber.send(samples.to_vec()).is_ok());
+        }
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            self.state.stopping.store(true, Ordering::Relaxed);
+            if let Some(listener) = self.listener.take() {
+                let _ = listener.join();
+            }
+            tracing::info!("stopped serving on {}", self.local);
+        }
+    }
+
+    fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+        // Whoever panicked holding the lock was only reading or replacing whole values, so what it guards is whole.
+        mutex.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn accept(listener: TcpListener, state: Arc<State>) {
+        while !state.stopping.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    let state = state.clone();
+                    let spawned = std::thread::Builder::new()
+                        .name(format!("http {peer}"))
+                        .spawn(move || {
+                            if let Err(e) = serve(&state, stream, peer) {
+                                tracing::debug!("connection from {peer} failed: {e}");
+                            }
+                        });
+                    if let Err(e) = spawned {
+                        tracing::warn!("cannot serve {peer}: {e}");
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::sleep(ACCEPT_POLL),
+                Err(e) => {
+                    tracing::warn!("accepting a connection failed: {e}");
+                    std::thread::sleep(ACCEPT_POLL);
+                }
+            }
+        }
+    }
+
+    fn serve(state: &State, mut stream: TcpStream, peer: SocketAddr) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+        let request = match Request::read(&mut BufReader::new(&mut stream)) {
+            Ok(request) => request,
+            Err(HttpError::Io(e)) => return Err(e),
+            Err(e @ HttpError::TooLarge) => {
+                return Response::error(413, &e.to_string()).write_to(&mut stream)
+            }
+            Err(e) => return Response::error(400, &e.to_string()).write_to(&mut stream),
+        };
+        if request.path == LIVE_PATH && request.is_upgrade() {
+            if !lock(&state.auth).allows(&request) {
+                return Response::error(401, "pair with this node first").write_to(&mut stream);
+            }
+            tracing::debug!("{peer} is watching live");
+            return live(state, stream, &request);
+        }
+        let response = route(state, &request);
+        tracing::debug!(
+            "{peer} {} {} {}",
+            request.method,
+            request.path,
+            response.status
+        );
+        response.write_to(&mut stream)
+    }
+
+    fn route(state: &State, request: &Request) -> Response {
+        let segments = request.segments();
+        let method = request.method.as_str();
+        if segments.first() != Some(&"api") {
+            return match method {
+                "GET" | "HEAD" => static_file(state, request),
+                _ => Response::error(405, "only GET is served outside the API"),
+            };
+        }
+        if (method, &segments[1..]) == ("POST", &["pair"][..]) {
+            return pair(state, request);
+        }
+        if !lock(&state.auth).allows(request) {
+            return Response::error(401, "pair with this node first");
+        }
+        if method != "GET" {
+            return Response::error(405, "the API is read only");
+        }
+
+        let node = || lock(&state.node);
+
+        match segments[1..] {
+            ["status"] => Response::json(&node().status(local_now())),
+            ["blobs"] => match blob_query(request) {
+                Ok(query) => Response::json(&node().blobs(&query)),
+                Err(e) => Response::error(400, &e),
+            },
+            ["hours", hash] => match parse_hash(hash)
+                .and_then(|hash| node().hour(&hash).map_err(|e| e.to_string()))
+            {
+                Ok(blob) => Response::json(&blob),
+                Err(e) => Response::error(404, &e),
+            },
+            ["hours", hash, "csv"] => match parse_hash(hash)
+                .and_then(|hash| node().hour(&hash).map_err(|e| e.to_string()))
+            {
+                Ok(blob) => Response::new(200, "text/csv; charset=utf-8", blob.to_csv())
+                    .with_header(
+                        "Content-Disposition",
+                        format!("attachment; filename=\"{}-{}.csv\"", blob.node, blob.hour),
+                    ),
+                Err(e) => Response::error(404, &e),
+            },
+            ["timeline", day] => match day.parse().ok().filter(|&day| day_span(day).is_some()) {
+                Some(day) => Response::json(&node().timeline(day)),
+                None => Response::error(400, "the day must be a whole number of days since 1970"),
+            },
+            ["footage", hash, segment, offset] => footage(state, hash, segment, offset),
+            _ => Response::error(404, "no such API"),
+        }
+    }
+
+    fn pair(state: &State, request: &Request) -> Response {
+        let Ok(PairRequest { token }) = serde_json::from_slice(&request.body) else {
+            return Response::error(400, "expected {\"token\": \"...\"}");
+        };
+        match lock(&state.auth).pair(&token) {
+            Some(session) => {
+                tracing::info!("a browser paired");
+                Response::json(&()).with_header("Set-Cookie", session_cookie(&session))
+            }
+            None => {
+                tracing::warn!("pairing with a wrong token");
+                Response::error(401, "that is not this node's pairing token")
+            }
+        }
+    }
+
+    fn parse_hash(text: &str) -> Result<BlobHash, String> {
+        text.parse().map_err(|e| format!("{text}: {e}"))
+    }
+
+    fn blob_query(request: &Request) -> Result<BlobQuery, String> {
+        let hour = |name| {
+            request
+                .query(name)
+                .map(|hour| {
+                    hour.parse::<i64>()
+                        .map_err(|_| format!("{name} must be an hour since 1970"))
+                })
+                .transpose()
+        };
+        let kind = match request.query("kind") {
+            None => None,
+            Some("hour") => Some(BlobKind::Hour),
+            Some("video") => Some(BlobKind::Video),
+            Some("image") => Some(BlobKind::Image),
+            Some("detections") => Some(BlobKind::Detections),
+            Some(other) => return Err(format!("no blobs are of kind {other}")),
+        };
+        let hours = match (hour("from")?, hour("to")?) {
+            (None, None) => None,
+            (from, to) => Some(from.unwrap_or(i64::MIN)..to.unwrap_or(i64::MAX)),
+        };
+        Ok(BlobQuery {
+            node: request
+                .query("node")
+                .map(|node| {
+                    node.parse::<NodeId>()
+                        .map_err(|_| format!("{node} is not a node id"))
+                })
+                .transpose()?,
+            hours,
+            kind,
+        })
+    }
+
+    /// Frames from one keyframe to the end of its segment, as JPEGs.
+    fn footage(state: &State, hash: &str, segment: &str, offset: &str) -> Response {
+        let (Ok(segment), Ok(offset)) = (segment.parse::<u32>(), offset.parse::<u64>()) else {
+            return Response::error(400, "segment and offset must be whole numbers");
+        };
+        // Decoding takes a while, so it happens on a copy rather than holding up the node.
+        let video = match parse_hash(hash)
+            .and_then(|hash| lock(&state.node).video(&hash).map_err(|e| e.to_string()))
+        {
+            Ok(video) => video,
+            Err(e) => return Response::error(404, &e),
+        };
+        let Some(keyframe) = video
+            .keyframes
+            .iter()
+            .find(|k| k.segment == segment && k.offset == offset)
+            .copied()
+        else {
+            return Response::error(404, "the footage has no keyframe there");
+        };
+        match crate::encoder::preview(&video, &keyframe) {
+            Ok(frames) => Response::json(
+                &frames
+                    .into_iter()
+                    .map(|(time, jpeg)| PreviewFrame {
+                        time,
+                        image: format!(
+                            "data:image/jpeg;base64,{}",
+                            base64::engine::general_purpose::STANDARD.encode(jpeg)
+                        ),
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            Err(e) => Response::error(500, &e.to_string()),
+        }
+    }
+
+    /// Serves a file of the web build. Paths without an extension are the app's own routes, which its `index.html`
+    /// handles. A `token` in the query pairs the browser on the way.
+    fn static_file(state: &State, request: &Request) -> Response {
+        if let Some(token) = request.query("token") {
+            let session = lock(&state.auth).pair(token);
+            return match session {
+                Some(session) => Response::new(303, "text/plain", "paired")
+                    .with_header("Location", request.path.clone())
+                    .with_header("Set-Cookie", session_cookie(&session)),
+                None => Response::error(401, "that is not this node's pairing token"),
+            };
+        }
+        let Some(root) = &state.web_root else {
+            return Response::new(
+                404,
+                "text/plain; charset=utf-8",
+                "This node serves its API but not the web app; set a web root in its settings.",
+            );
+        };
+        let Some(relative) = safe_path(&request.path) else {
+            return Response::error(404, "no such file");
+        };
+        let mut path = root.join(&relative);
+        if path.is_dir() {
+            path = path.join("index.html");
+        }
+        if !path.is_file() && relative.extension().is_none() {
+            path = root.join("index.html");
+        }
+        match std::fs::read(&path) {
+            Ok(body) => Response::new(200, content_type(&path), body),
+            Err(_) => Response::error(404, "no such file"),
+        }
+    }
+
+    /// `path` relative to the web root, unless it tries to leave it.
+    fn safe_path(path: &str) -> Option<PathBuf> {
+        let relative = PathBuf::from(path.trim_start_matches('/'));
+        relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(part) if !part.to_string_lossy().starts_with('.')))
+            .then_some(relative)
+    }
+
+    fn content_type(path: &Path) -> &'static str {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("html") => "text/html; charset=utf-8",
+            Some("js") => "text/javascript",
+            Some("wasm") => "application/wasm",
+            Some("css") => "text/css",
+            Some("json") => "application/json",
+            Some("svg") => "image/svg+xml",
+            Some("png") => "image/png",
+            Some("jpg" | "jpeg") => "image/jpeg",
+            Some("ico") => "image/x-icon",
+            Some("txt") => "text/plain; charset=utf-8",
+            _ => "application/octet-stream",
+        }
+    }
+
+    /// Streams [`LiveUpdate`]s over the connection until it closes or the server stops.
+    fn live(state: &State, mut stream: TcpStream, request: &Request) -> io::Result<()> {
+        let Some(key) = request.header("sec-websocket-key") else {
+            return Response::error(400, "missing Sec-WebSocket-Key").write_to(&mut stream);
+        };
+        let switching = Response {
+            status: 101,
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+        switching
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade")
+            .with_header("Sec-WebSocket-Accept", derive_accept_key(key.as_bytes()))
+            .write_upgrade(&mut stream)?;
+        let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+        let samples = subscribe(state);
+        // The blobs the connection was last sent, so the list only goes out again when it changes.
+        let mut sent = None;
+        while !state.stopping.load(Ordering::Relaxed) {
+            let mut batch = Vec::new();
+            loop {
+                match samples.try_recv() {
+                    Ok(polled) => batch.extend(polled),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+            let (status, blobs) = {
+                let node = lock(&state.node);
+                (node.status(local_now()), node.blobs(&BlobQuery::default()))
+            };
+            let changed = sent.as_ref() != Some(&blobs);
+            let update = LiveUpdate {
+                status,
+                samples: batch,
+                blobs: changed.then(|| blobs.clone()),
+            };
+            if changed {
+                sent = Some(blobs);
+            }
+            let text = serde_json::to_string(&update).map_err(io::Error::other)?;
+            if let Err(e) = socket.send(Message::Text(text)) {
+                tracing::debug!("live connection closed: {e}");
+                return Ok(());
+            }
+            std::thread::sleep(LIVE_INTERVAL);
+        }
+        let _ = socket.close(None);
+        Ok(())
+    }
+
+    fn subscribe(state: &State) -> Receiver<Vec<Sample>> {
+        let (sender, receiver) = mpsc::channel();
+        lock(&state.subscribers).push(sender);
+        receiver
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::node::sim::SimulatedNode;
+        use crate::node::NodeRole;
+
+        const TOKEN: &str = "secret";
+
+        fn state() -> State {
+            State {
+                node: Arc::new(Mutex::new(SimulatedNode::new(
+                    NodeId(1),
+                    NodeRole::Compute,
+                    100_000,
+                    0,
+                ))),
+                auth: Mutex::new(Auth::new(TOKEN.to_string())),
+                web_root: None,
+                subscribers: Mutex::new(Vec::new()),
+                stopping: AtomicBool::new(false),
+            }
+        }
+
+        fn get(target: &str) -> Request {
+            let raw = format!("GET {target} HTTP/1.1\r\nAuthorization: Bearer {TOKEN}\r\n\r\n");
+            Request::read(&mut raw.as_bytes()).unwrap()
+        }
+
+        #[test]
+        fn paths_that_leave_the_web_root_are_refused() {
+            assert_eq!(
+                safe_path("/assets/main.css"),
+                Some(PathBuf::from("assets/main.css"))
+            );
+            for path in ["/../secret", "/assets/../../secret", "/.git/config"] {
+                assert_eq!(safe_path(path), None, "{path}");
+            }
+            // Absolute paths are taken as relative to the root.
+            assert_eq!(safe_path("//etc/passwd"), Some(PathBuf::from("etc/passwd")));
+            assert_eq!(safe_path(&get("/%2e%2e/secret").path), None);
+            assert_eq!(safe_path(&get("/assets/%2E%2E/%2e%2e/secret").path), None);
+        }
+
+        #[test]
+        fn days_too_far_out_for_a_timeline_are_refused() {
+            let state = state();
+            assert_eq!(route(&state, &get("/api/timeline/20000")).status, 200);
+            assert_eq!(route(&state, &get("/api/timeline/-20000")).status, 200);
+            for day in [i64::MAX, i64::MIN, i64::MAX / (24 * 3_600 * 1_000_000)] {
+                assert_eq!(
+                    route(&state, &get(&format!("/api/timeline/{day}"))).status,
+                    400
+                );
+            }
+        }
+
+        #[test]
+        fn the_api_is_read_only_and_needs_pairing() {
+            let state = state();
+            let mut post = get("/api/status");
+            post.method = "POST".to_string();
+            assert_eq!(route(&state, &post).status, 405);
+            let mut unpaired = get("/api/status");
+            unpaired.headers.clear();
+            assert_eq!(route(&state, &unpaired).status, 401);
+            assert_eq!(route(&state, &get("/api/status")).status, 200);
+        }
+
+        #[test]
+        fn pairing_from_a_link_redirects_back_to_the_page() {
+            let state = state();
+            let response = route(&state, &get(&format!("/recordings?token={TOKEN}")));
+            assert_eq!(response.status, 303);
+            assert!(response
+                .headers
+                .contains(&("Location".to_string(), "/recordings".to_string())));
+            let wrong = route(&state, &get("/recordings?token=wrong"));
+            assert_eq!(wrong.status, 401);
+        }
+    }
+}
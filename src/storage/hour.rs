@@ -0,0 +1,229 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::Still;
+use crate::node::NodeId;
+use crate::sensors::{Micros, Sample};
+use crate::time_sync::NetworkTime;
+
+/// Length of one blob in µs.
+pub const HOUR: Micros = 3_600 * 1_000_000;
+
+/// The hour (counted from the Unix epoch) a network timestamp falls into.
+pub fn hour_of(network: Micros) -> i64 {
+    network.div_euclid(HOUR)
+}
+
+/// A sample together with the network time it was taken at.
+///
+/// The raw local timestamp stays in `sample.local_time` so that analytics can re-correct it later if a better clock
+/// estimate becomes available.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimedSample {
+    pub sample: Sample,
+    pub time: NetworkTime,
+}
+
+/// Collects one node's samples and snapshot stills for a single hour of network time.
+#[derive(Debug, Clone)]
+pub struct HourBuffer {
+    node: NodeId,
+    hour: i64,
+    samples: Vec<TimedSample>,
+    stills: Vec<Still>,
+}
+
+impl HourBuffer {
+    pub fn new(node: NodeId, hour: i64) -> Self {
+        HourBuffer {
+            node,
+            hour,
+            samples: Vec::new(),
+            stills: Vec::new(),
+        }
+    }
+
+    pub fn hour(&self) -> i64 {
+        self.hour
+    }
+
+    /// Samples collected so far; stills are not counted.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty() && self.stills.is_empty()
+    }
+
+    /// Adds a sample. If it belongs to a later hour the current buffer is sealed and returned, and a new one is
+    /// started for that hour. Late samples from an earlier hour are kept in the current buffer rather than dropped.
+    pub fn push(&mut self, sample: Sample, time: NetworkTime) -> Option<SealedBlob> {
+        let sealed = self.roll_over(time);
+        self.samples.push(TimedSample { sample, time });
+        sealed
+    }
+
+    /// Adds a snapshot still, sealing the buffer the same way [`HourBuffer::push`] does.
+    pub fn push_still(&mut self, still: Still) -> Option<SealedBlob> {
+        let sealed = self.roll_over(still.time);
+        self.stills.push(still);
+        sealed
+    }
+
+    fn roll_over(&mut self, time: NetworkTime) -> Option<SealedBlob> {
+        let hour = hour_of(time.network);
+        if hour <= self.hour {
+            return None;
+        }
+        let previous = std::mem::replace(self, HourBuffer::new(self.node, hour));
+        Some(previous.seal())
+    }
+
+    pub fn seal(mut self) -> SealedBlob {
+        self.samples.sort_by_key(|s| s.time.network);
+        self.stills.sort_by_key(|s| s.time.network);
+        SealedBlob {
+            node: self.node,
+            hour: self.hour,
+            samples: self.samples,
+            stills: self.stills,
+        }
+    }
+}
+
+/// An hour of samples from one node that will no longer change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SealedBlob {
+    pub node: NodeId,
+    pub hour: i64,
+    pub samples: Vec<TimedSample>,
+    /// Snapshot thumbnails from cameras in snapshot mode, each pointing at its full image.
+    pub stills: Vec<Still>,
+}
+
+impl SealedBlob {
+    pub fn encode(&self) -> Vec<u8> {
+        postcard::to_stdvec(self).expect("sealed blobs always serialize")
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, postcard::Error> {
+        postcard::from_bytes(bytes)
+    }
+
+    pub fn hash(&self) -> BlobHash {
+        BlobHash::of(&self.encode())
+    }
+
+    /// The samples as CSV, one row per sample in network time order. Rows have as many value columns as their
+    /// sample has values.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("network_time,local_time,time_error,channel,values\n");
+        for TimedSample { sample, time } in &self.samples {
+            let values: Vec<String> = sample.values.iter().map(f64::to_string).collect();
+            csv.push_str(&format!(
+                "{},{},{},{},{}\n",
+                time.network,
+                sample.local_time,
+                time.error,
+                sample.channel,
+                values.join(",")
+            ));
+        }
+        csv
+    }
+}
+
+/// Blake3 hash of a blob's encoded bytes, used as its address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct BlobHash(pub [u8; 32]);
+
+impl BlobHash {
+    pub fn of(bytes: &[u8]) -> Self {
+        BlobHash(*blake3::hash(bytes).as_bytes())
+    }
+}
+
+impl fmt::Display for BlobHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The hash in a [`BlobHash`]'s display form was not 64 hex digits.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("not a blob hash")]
+pub struct ParseBlobHashError;
+
+impl FromStr for BlobHash {
+    type Err = ParseBlobHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 64 || !s.is_ascii() {
+            return Err(ParseBlobHashError);
+        }
+        let mut hash = [0; 32];
+        for (i, byte) in hash.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).map_err(|_| ParseBlobHashError)?;
+        }
+        Ok(BlobHash(hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensors::ChannelId;
+
+    fn sample(channel: &str, local_time: Micros, values: Vec<f64>) -> (Sample, NetworkTime) {
+        let sample = Sample {
+            channel: ChannelId::new(channel),
+            local_time,
+            values,
+        };
+        let time = NetworkTime {
+            local: local_time,
+            network: local_time + 500,
+            error: 20,
+        };
+        (sample, time)
+    }
+
+    #[test]
+    fn csv_has_a_row_per_sample_in_network_time_order() {
+        let mut buffer = HourBuffer::new(NodeId(1), 0);
+        let (later, at) = sample("accelerometer", 2_000_000, vec![0.5, -1.0, 9.75]);
+        buffer.push(later, at);
+        let (earlier, at) = sample("barometer", 1_000_000, vec![1013.25]);
+        buffer.push(earlier, at);
+        assert_eq!(
+            buffer.seal().to_csv(),
+            "network_time,local_time,time_error,channel,values\n\
+             1000500,1000000,20,barometer,1013.25\n\
+             2000500,2000000,20,accelerometer,0.5,-1,9.75\n"
+        );
+    }
+
+    #[test]
+    fn a_sample_from_a_later_hour_seals_the_buffer() {
+        let mut buffer = HourBuffer::new(NodeId(1), 0);
+        let (first, at) = sample("barometer", 1_000_000, vec![1.0]);
+        assert_eq!(buffer.push(first, at), None);
+        let (next, at) = sample("barometer", HOUR, vec![2.0]);
+        let sealed = buffer.push(next, at).unwrap();
+        assert_eq!((sealed.hour, sealed.samples.len()), (0, 1));
+        assert_eq!(SealedBlob::decode(&sealed.encode()).unwrap(), sealed);
+    }
+
+    #[test]
+    fn hashes_read_back_from_their_display_form() {
+        let hash = BlobHash::of(b"hour");
+        assert_eq!(hash.to_string().parse::<BlobHash>(), Ok(hash));
+        assert_eq!("abc".parse::<BlobHash>(), Err(ParseBlobHashError));
+    }
+}
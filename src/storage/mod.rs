@@ -0,0 +1,22 @@
+//! Storage of sensor data. Samples are collected in memory for the current hour and sealed into an immutable,
+//! content addressed blob when the hour ends. Camera footage is kept the same way, in [`VideoBlob`]s, and cameras in
+//! snapshot mode add [`Still`]s to the hour blob with their full images in [`ImageBlob`]s. The [`Outbox`] orders blobs
+//! for sending so that the small, urgent ones go first, and the [`BlobStore`] keeps sealed blobs compressed, in memory
+//! or on disk, and answers queries about them. A [`Timeline`] lays a day of footage out for playback.
+
+mod hour;
+pub mod ivf;
+mod outbox;
+mod still;
+mod store;
+mod timeline;
+mod video;
+
+pub use hour::{hour_of, BlobHash, HourBuffer, ParseBlobHashError, SealedBlob, TimedSample, HOUR};
+pub use outbox::{Outbox, Outgoing, Priority};
+pub use still::{Image, ImageBlob, ImageFormat, ImageRef, Still};
+pub use store::{BlobInfo, BlobKind, BlobQuery, BlobStore, StoreError};
+pub use timeline::{
+    day_of, day_span, CameraTimeline, Marker, MarkerKind, Recording, SeekPoint, Timeline, DAY,
+};
+pub use video::{Keyframe, VideoBlob, VideoFormat, VideoRecorder, VideoSegment};
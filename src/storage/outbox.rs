@@ -0,0 +1,146 @@
+//! Blobs waiting to be sent to other nodes, in the order they should go.
+//!
+//! Sending is up to the caller; the outbox only decides what comes next. Higher priorities always go first, and
+//! within a priority the oldest blob does, so a backlog of full images can never hold back a thumbnail. An outbox
+//! with a limit drops whatever would go last once it holds more than that.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use super::BlobHash;
+use crate::sensors::Micros;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Priority {
+    /// Bulk data that can wait for spare bandwidth, like full size snapshots and footage.
+    Low,
+    /// Sealed hour blobs.
+    Normal,
+    /// Small things worth having as soon as possible, like snapshot thumbnails.
+    High,
+}
+
+/// A queued blob.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outgoing {
+    pub hash: BlobHash,
+    pub priority: Priority,
+    /// When it was queued.
+    pub queued: Micros,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Outbox {
+    /// Keyed so that iteration order is send order: priority descending, then oldest, then by hash.
+    queue: BTreeMap<(Reverse<Priority>, Micros, BlobHash), Vec<u8>>,
+    hashes: BTreeSet<BlobHash>,
+    bytes: usize,
+    /// Most bytes queued at once.
+    limit: Option<usize>,
+}
+
+impl Outbox {
+    pub fn new() -> Self {
+        Outbox::default()
+    }
+
+    /// An outbox that holds no more than `limit` bytes.
+    pub fn with_limit(limit: usize) -> Self {
+        Outbox {
+            limit: Some(limit),
+            ..Outbox::default()
+        }
+    }
+
+    /// Queues an encoded blob and returns its hash. Blobs already waiting are not queued twice. Past the limit, the
+    /// blobs that would be sent last are dropped, which may be this one.
+    pub fn push(&mut self, bytes: Vec<u8>, priority: Priority, now: Micros) -> BlobHash {
+        let hash = BlobHash::of(&bytes);
+        if self.hashes.insert(hash) {
+            self.bytes += bytes.len();
+            self.queue.insert((Reverse(priority), now, hash), bytes);
+        }
+        while self.limit.is_some_and(|limit| self.bytes > limit) {
+            let Some(((_, _, dropped), bytes)) = self.queue.pop_last() else {
+                break;
+            };
+            self.hashes.remove(&dropped);
+            self.bytes -= bytes.len();
+        }
+        hash
+    }
+
+    /// The blob that should be sent next.
+    pub fn peek(&self) -> Option<(BlobHash, Priority, &[u8])> {
+        self.queue
+            .iter()
+            .next()
+            .map(|(&(priority, _, hash), bytes)| (hash, priority.0, bytes.as_slice()))
+    }
+
+    /// Takes the blob that should be sent next. One that fails to send can be pushed again.
+    pub fn pop(&mut self) -> Option<Outgoing> {
+        let ((priority, queued, hash), bytes) = self.queue.pop_first()?;
+        self.hashes.remove(&hash);
+        self.bytes -= bytes.len();
+        Some(Outgoing {
+            hash,
+            priority: priority.0,
+            queued,
+            bytes,
+        })
+    }
+
+    pub fn contains(&self, hash: &BlobHash) -> bool {
+        self.hashes.contains(hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Total size of the queued blobs.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blobs_go_out_by_priority_then_age() {
+        let mut outbox = Outbox::new();
+        let footage = outbox.push(vec![1; 100], Priority::Low, 0);
+        let hour = outbox.push(vec![2; 10], Priority::Normal, 1);
+        let thumbnail = outbox.push(vec![3; 5], Priority::High, 2);
+        assert_eq!(outbox.push(vec![2; 10], Priority::Normal, 3), hour);
+        assert_eq!((outbox.len(), outbox.bytes()), (3, 115));
+        let order: Vec<BlobHash> = std::iter::from_fn(|| outbox.pop())
+            .map(|o| o.hash)
+            .collect();
+        assert_eq!(order, vec![thumbnail, hour, footage]);
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn a_full_outbox_drops_what_would_go_last() {
+        let mut outbox = Outbox::with_limit(100);
+        let old = outbox.push(vec![1; 60], Priority::Low, 0);
+        let new = outbox.push(vec![2; 60], Priority::Low, 1);
+        assert!(outbox.contains(&old) && !outbox.contains(&new));
+        let hour = outbox.push(vec![3; 30], Priority::Normal, 2);
+        assert!(outbox.contains(&hour) && outbox.contains(&old));
+        let urgent = outbox.push(vec![4; 20], Priority::High, 3);
+        assert!(outbox.contains(&urgent) && !outbox.contains(&old));
+        assert_eq!(outbox.bytes(), 50);
+    }
+}
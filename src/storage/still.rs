@@ -0,0 +1,115 @@
+//! Still images from cameras in snapshot mode.
+//!
+//! A snapshot is kept twice: a thumbnail small enough to ride inside the hour blob as a [`Still`], and the full
+//! image in an [`ImageBlob`] of its own that the still points to by hash. On a slow link the thumbnails arrive
+//! first and the full images follow when there is bandwidth to spare.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::BlobHash;
+use crate::node::NodeId;
+use crate::sensors::ChannelId;
+use crate::time_sync::NetworkTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ImageFormat {
+    /// A single AV1 keyframe in a HEIF container, about half the size of a JPEG of the same quality.
+    Avif,
+    Jpeg,
+}
+
+impl ImageFormat {
+    pub const ALL: [ImageFormat; 2] = [ImageFormat::Avif, ImageFormat::Jpeg];
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ImageFormat::Avif => "image/avif",
+            ImageFormat::Jpeg => "image/jpeg",
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageFormat::Avif => f.write_str("AVIF"),
+            ImageFormat::Jpeg => f.write_str("JPEG"),
+        }
+    }
+}
+
+/// An encoded image file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Image {
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// Where to find the full size version of a still.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageRef {
+    /// Hash of the [`ImageBlob`].
+    pub hash: BlobHash,
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Size of the encoded image, so a viewer can decide whether fetching it is worth it.
+    pub bytes: u64,
+}
+
+/// One snapshot as recorded in the hour blob.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Still {
+    pub camera: ChannelId,
+    pub time: NetworkTime,
+    pub thumbnail: Image,
+    pub full: ImageRef,
+}
+
+impl Still {
+    pub fn encode(&self) -> Vec<u8> {
+        postcard::to_stdvec(self).expect("stills always serialize")
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, postcard::Error> {
+        postcard::from_bytes(bytes)
+    }
+}
+
+/// A full size snapshot, stored and synced apart from the hour blob.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageBlob {
+    pub node: NodeId,
+    pub camera: ChannelId,
+    pub time: NetworkTime,
+    pub image: Image,
+}
+
+impl ImageBlob {
+    pub fn encode(&self) -> Vec<u8> {
+        postcard::to_stdvec(self).expect("image blobs always serialize")
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, postcard::Error> {
+        postcard::from_bytes(bytes)
+    }
+
+    pub fn hash(&self) -> BlobHash {
+        BlobHash::of(&self.encode())
+    }
+
+    /// What a [`Still`] records about this image.
+    pub fn reference(&self) -> ImageRef {
+        ImageRef {
+            hash: self.hash(),
+            format: self.image.format,
+            width: self.image.width,
+            height: self.image.height,
+            bytes: self.image.data.len() as u64,
+        }
+    }
+}
@@ -0,0 +1,603 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::{hour_of, BlobHash, ImageBlob, SealedBlob, VideoBlob};
+use crate::analytics::DetectionBlob;
+use crate::node::NodeId;
+
+/// Deflate level blobs are stored at. Blobs are written once and read rarely, but phones pay for every cycle.
+const LEVEL: u8 = 6;
+
+/// What a stored blob holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum BlobKind {
+    Hour,
+    Video,
+    Image,
+    Detections,
+}
+
+impl BlobKind {
+    /// Whether blobs of this kind are deflated in the store. Footage and images are already compressed as tightly as
+    /// their codecs manage, so deflating them would cost cycles for nothing.
+    pub fn deflated(self) -> bool {
+        match self {
+            BlobKind::Hour | BlobKind::Detections => true,
+            BlobKind::Video | BlobKind::Image => false,
+        }
+    }
+}
+
+impl fmt::Display for BlobKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlobKind::Hour => f.write_str("hour"),
+            BlobKind::Video => f.write_str("video"),
+            BlobKind::Image => f.write_str("image"),
+            BlobKind::Detections => f.write_str("detections"),
+        }
+    }
+}
+
+/// What the store knows about one blob without opening it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobInfo {
+    pub hash: BlobHash,
+    pub kind: BlobKind,
+    /// The node the blob came from, which is not necessarily the one storing it.
+    pub node: NodeId,
+    pub hour: i64,
+    /// Length of the encoded blob.
+    pub size: u64,
+    /// Bytes it takes up in the store.
+    pub stored: u64,
+    /// Other nodes known to hold a copy.
+    pub replicas: BTreeSet<NodeId>,
+    /// Whether the blob is stored encrypted. Nodes do not encrypt blobs yet, so this is always false.
+    pub encrypted: bool,
+}
+
+impl BlobInfo {
+    /// How many times larger the blob is than what it takes up in the store.
+    pub fn compression_ratio(&self) -> f64 {
+        self.size as f64 / self.stored.max(1) as f64
+    }
+}
+
+/// Which blobs [`BlobStore::query`] lists. Every field left `None` matches everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobQuery {
+    pub node: Option<NodeId>,
+    pub hours: Option<Range<i64>>,
+    pub kind: Option<BlobKind>,
+}
+
+impl BlobQuery {
+    pub fn matches(&self, info: &BlobInfo) -> bool {
+        self.node.is_none_or(|node| node == info.node)
+            && self
+                .hours
+                .as_ref()
+                .is_none_or(|hours| hours.contains(&info.hour))
+            && self.kind.is_none_or(|kind| kind == info.kind)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[error("no blob {0} in the store")]
+    NotFound(BlobHash),
+    #[error("blob {hash} is a {found} blob where a {expected} blob was expected")]
+    WrongKind {
+        hash: BlobHash,
+        expected: BlobKind,
+        found: BlobKind,
+    },
+    #[error("stored blob {0} is corrupt: {1}")]
+    Corrupt(BlobHash, String),
+    #[error("failed to decode blob: {0}")]
+    Decode(#[from] postcard::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("the store's index is unreadable: {0}")]
+    Index(#[from] serde_json::Error),
+}
+
+/// Where a store keeps the bytes of its blobs.
+#[derive(Debug, Clone)]
+enum Backing {
+    Memory(BTreeMap<BlobHash, Vec<u8>>),
+    /// A file per blob, named after its hash, beside [`INDEX`].
+    Disk(PathBuf),
+}
+
+impl Default for Backing {
+    fn default() -> Self {
+        Backing::Memory(BTreeMap::new())
+    }
+}
+
+/// File listing the [`BlobInfo`] of every blob in a store on disk.
+const INDEX: &str = "index.json";
+
+/// The blobs a node keeps, addressed by the hash of their encoding.
+///
+/// Hour and detection blobs are [deflated](BlobKind::deflated): hour blobs shrink to less than half because
+/// neighbouring samples share most of their bytes. The address does not
+/// depend on how the blob is stored, so it stays the same on every node that holds a copy.
+///
+/// A store made with [`BlobStore::new`] lives in memory and is gone with the app. One [`opened`](BlobStore::open) in
+/// a directory keeps a file per blob there, and only what it knows about them in memory.
+#[derive(Debug, Clone, Default)]
+pub struct BlobStore {
+    blobs: BTreeMap<BlobHash, BlobInfo>,
+    backing: Backing,
+}
+
+impl BlobStore {
+    pub fn new() -> Self {
+        BlobStore::default()
+    }
+
+    /// The store kept in `dir`, made empty if there is none yet. Blobs listed in its index whose files are gone are
+    /// forgotten.
+    pub fn open(dir: &Path) -> Result<Self, StoreError> {
+        std::fs::create_dir_all(dir)?;
+        let infos: Vec<BlobInfo> = match std::fs::read(dir.join(INDEX)) {
+            Ok(index) => serde_json::from_slice(&index)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let blobs = infos
+            .into_iter()
+            .filter(|info| dir.join(info.hash.to_string()).is_file())
+            .map(|info| (info.hash, info))
+            .collect();
+        Ok(BlobStore {
+            blobs,
+            backing: Backing::Disk(dir.to_path_buf()),
+        })
+    }
+
+    pub fn insert_hour(&mut self, blob: &SealedBlob) -> Result<BlobHash, StoreError> {
+        self.insert(BlobKind::Hour, blob.node, blob.hour, &blob.encode())
+    }
+
+    pub fn insert_video(&mut self, blob: &VideoBlob) -> Result<BlobHash, StoreError> {
+        self.insert(BlobKind::Video, blob.node, blob.hour, &blob.encode())
+    }
+
+    pub fn insert_image(&mut self, blob: &ImageBlob) -> Result<BlobHash, StoreError> {
+        self.insert(
+            BlobKind::Image,
+            blob.node,
+            hour_of(blob.time.network),
+            &blob.encode(),
+        )
+    }
+
+    pub fn insert_detections(&mut self, blob: &DetectionBlob) -> Result<BlobHash, StoreError> {
+        self.insert(BlobKind::Detections, blob.node, blob.hour, &blob.encode())
+    }
+
+    /// Stores an encoded blob unless it is already there.
+    fn insert(
+        &mut self,
+        kind: BlobKind,
+        node: NodeId,
+        hour: i64,
+        bytes: &[u8],
+    ) -> Result<BlobHash, StoreError> {
+        let hash = BlobHash::of(bytes);
+        if self.blobs.contains_key(&hash) {
+            return Ok(hash);
+        }
+        let compressed = if kind.deflated() {
+            miniz_oxide::deflate::compress_to_vec(bytes, LEVEL)
+        } else {
+            bytes.to_vec()
+        };
+        let info = BlobInfo {
+            hash,
+            kind,
+            node,
+            hour,
+            size: bytes.len() as u64,
+            stored: compressed.len() as u64,
+            replicas: BTreeSet::new(),
+            encrypted: false,
+        };
+        match &mut self.backing {
+            Backing::Memory(stored) => {
+                stored.insert(hash, compressed);
+            }
+            Backing::Disk(dir) => write_whole(&dir.join(hash.to_string()), &compressed)?,
+        }
+        self.blobs.insert(hash, info);
+        self.save_index()?;
+        Ok(hash)
+    }
+
+    /// Takes a blob out of the store, returning what it was.
+    pub fn remove(&mut self, hash: &BlobHash) -> Result<Option<BlobInfo>, StoreError> {
+        let Some(info) = self.blobs.remove(hash) else {
+            return Ok(None);
+        };
+        // The index goes first, so that a crash in between leaves a stray file rather than a missing blob.
+        self.save_index()?;
+        match &mut self.backing {
+            Backing::Memory(stored) => {
+                stored.remove(hash);
+            }
+            Backing::Disk(dir) => std::fs::remove_file(dir.join(hash.to_string()))?,
+        }
+        Ok(Some(info))
+    }
+
+    /// Removes the blobs of the oldest hours until the store takes up no more than `limit` bytes, returning what it
+    /// removed.
+    pub fn evict_to(&mut self, limit: u64) -> Result<Vec<BlobInfo>, StoreError> {
+        let mut oldest: Vec<(i64, BlobKind, BlobHash)> = self
+            .blobs
+            .values()
+            .map(|info| (info.hour, info.kind, info.hash))
+            .collect();
+        oldest.sort();
+        let mut stored = self.stored_bytes();
+        let mut evicted = Vec::new();
+        for (_, _, hash) in oldest {
+            if stored <= limit {
+                break;
+            }
+            if let Some(info) = self.remove(&hash)? {
+                stored -= info.stored;
+                evicted.push(info);
+            }
+        }
+        Ok(evicted)
+    }
+
+    /// Removes the blobs of hours before `hour` that another node holds a copy of, returning what it removed, oldest
+    /// first.
+    pub fn expire(&mut self, hour: i64) -> Result<Vec<BlobInfo>, StoreError> {
+        let mut expired: Vec<(i64, BlobKind, BlobHash)> = self
+            .blobs
+            .values()
+            .filter(|info| info.hour < hour && !info.replicas.is_empty())
+            .map(|info| (info.hour, info.kind, info.hash))
+            .collect();
+        expired.sort();
+        let mut removed = Vec::new();
+        for (_, _, hash) in expired {
+            removed.extend(self.remove(&hash)?);
+        }
+        Ok(removed)
+    }
+
+    /// Notes that `node` holds a copy of a blob. Returns false if the blob is not in the store.
+    pub fn add_replica(&mut self, hash: &BlobHash, node: NodeId) -> bool {
+        let Some(info) = self.blobs.get_mut(hash) else {
+            return false;
+        };
+        if info.replicas.insert(node) {
+            // Losing track of a copy only means it may be sent again.
+            if let Err(e) = self.save_index() {
+                tracing::warn!("cannot note that {node} holds {hash}: {e}");
+            }
+        }
+        true
+    }
+
+    /// Writes the index of a store on disk.
+    fn save_index(&self) -> Result<(), StoreError> {
+        let Backing::Disk(dir) = &self.backing else {
+            return Ok(());
+        };
+        let infos: Vec<&BlobInfo> = self.blobs.values().collect();
+        write_whole(&dir.join(INDEX), &serde_json::to_vec(&infos)?)?;
+        Ok(())
+    }
+
+    pub fn contains(&self, hash: &BlobHash) -> bool {
+        self.blobs.contains_key(hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.blobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blobs.is_empty()
+    }
+
+    /// Bytes the store takes up.
+    pub fn stored_bytes(&self) -> u64 {
+        self.blobs.values().map(|info| info.stored).sum()
+    }
+
+    pub fn info(&self, hash: &BlobHash) -> Option<&BlobInfo> {
+        self.blobs.get(hash)
+    }
+
+    /// Every blob matching `query`, by node, then hour, then kind.
+    pub fn query(&self, query: &BlobQuery) -> Vec<BlobInfo> {
+        let mut found: Vec<BlobInfo> = self
+            .blobs
+            .values()
+            .filter(|info| query.matches(info))
+            .cloned()
+            .collect();
+        found.sort_by_key(|info| (info.node, info.hour, info.kind, info.hash));
+        found
+    }
+
+    /// A blob's encoded bytes, checked against its hash.
+    pub fn get(&self, hash: &BlobHash) -> Result<Vec<u8>, StoreError> {
+        let info = self.info(hash).ok_or(StoreError::NotFound(*hash))?;
+        let stored = match &self.backing {
+            Backing::Memory(stored) => stored.get(hash).ok_or(StoreError::NotFound(*hash))?.clone(),
+            Backing::Disk(dir) => std::fs::read(dir.join(hash.to_string()))?,
+        };
+        let bytes = if info.kind.deflated() {
+            miniz_oxide::inflate::decompress_to_vec(&stored)
+                .map_err(|e| StoreError::Corrupt(*hash, e.to_string()))?
+        } else {
+            stored
+        };
+        if BlobHash::of(&bytes) != *hash {
+            return Err(StoreError::Corrupt(*hash, "hash mismatch".to_string()));
+        }
+        Ok(bytes)
+    }
+
+    pub fn hour(&self, hash: &BlobHash) -> Result<SealedBlob, StoreError> {
+        Ok(SealedBlob::decode(&self.get_kind(hash, BlobKind::Hour)?)?)
+    }
+
+    pub fn video(&self, hash: &BlobHash) -> Result<VideoBlob, StoreError> {
+        Ok(VideoBlob::decode(&self.get_kind(hash, BlobKind::Video)?)?)
+    }
+
+    pub fn detections(&self, hash: &BlobHash) -> Result<DetectionBlob, StoreError> {
+        Ok(DetectionBlob::decode(
+            &self.get_kind(hash, BlobKind::Detections)?,
+        )?)
+    }
+
+    /// A blob's bytes, if it is of the `expected` kind.
+    fn get_kind(&self, hash: &BlobHash, expected: BlobKind) -> Result<Vec<u8>, StoreError> {
+        let info = self.info(hash).ok_or(StoreError::NotFound(*hash))?;
+        if info.kind != expected {
+            return Err(StoreError::WrongKind {
+                hash: *hash,
+                expected,
+                found: info.kind,
+            });
+        }
+        self.get(hash)
+    }
+}
+
+/// Writes `bytes` beside `path` and swaps them in, so that a crash leaves the old file or the new one whole.
+fn write_whole(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let temporary = path.with_extension("new");
+    std::fs::write(&temporary, bytes)?;
+    std::fs::rename(&temporary, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensors::{ChannelId, Sample};
+    use crate::storage::{HourBuffer, HOUR};
+    use crate::time_sync::NetworkTime;
+
+    /// A sealed hour of `node` with a few samples in it.
+    fn hour(node: NodeId, hour: i64) -> SealedBlob {
+        let mut buffer = HourBuffer::new(node, hour);
+        for i in 0..20 {
+            let local = hour * HOUR + i * 1_000_000;
+            let sample = Sample {
+                channel: ChannelId::new("barometer"),
+                local_time: local,
+                values: vec![1013.0 + i as f64],
+            };
+            let time = NetworkTime {
+                local,
+                network: local,
+                error: 0,
+            };
+            buffer.push(sample, time);
+        }
+        buffer.seal()
+    }
+
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("flumph-store-{test}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn a_store_on_disk_is_there_when_opened_again() {
+        let dir = temp_dir("reopen");
+        let mut store = BlobStore::open(&dir).unwrap();
+        let first = store.insert_hour(&hour(NodeId(1), 4)).unwrap();
+        let second = store.insert_hour(&hour(NodeId(1), 5)).unwrap();
+        assert!(store.add_replica(&first, NodeId(2)));
+        assert!(store.remove(&second).unwrap().is_some());
+        assert_eq!(store.remove(&second).unwrap(), None);
+        drop(store);
+
+        let store = BlobStore::open(&dir).unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.info(&first).unwrap().replicas, [NodeId(2)].into());
+        assert_eq!(store.hour(&first).unwrap(), hour(NodeId(1), 4));
+        assert!(matches!(store.get(&second), Err(StoreError::NotFound(_))));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn blobs_whose_files_are_gone_are_forgotten() {
+        let dir = temp_dir("missing");
+        let mut store = BlobStore::open(&dir).unwrap();
+        let hash = store.insert_hour(&hour(NodeId(1), 4)).unwrap();
+        std::fs::remove_file(dir.join(hash.to_string())).unwrap();
+        assert!(BlobStore::open(&dir).unwrap().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn eviction_takes_the_oldest_hours_first() {
+        let mut store = BlobStore::new();
+        for h in [7, 5, 6] {
+            store.insert_hour(&hour(NodeId(1), h)).unwrap();
+        }
+        let hours = |store: &BlobStore| -> Vec<i64> {
+            store
+                .query(&BlobQuery::default())
+                .iter()
+                .map(|info| info.hour)
+                .collect()
+        };
+        assert!(store.evict_to(store.stored_bytes()).unwrap().is_empty());
+        let evicted = store.evict_to(store.stored_bytes() - 1).unwrap();
+        assert_eq!(
+            evicted.iter().map(|info| info.hour).collect::<Vec<_>>(),
+            vec![5]
+        );
+        assert_eq!(hours(&store), vec![6, 7]);
+        store.evict_to(0).unwrap();
+        assert!(store.is_empty());
+        assert_eq!(store.stored_bytes(), 0);
+    }
+
+    #[test]
+    fn only_old_hours_a_peer_holds_expire() {
+        let mut store = BlobStore::new();
+        let hashes: Vec<BlobHash> = (0..4)
+            .map(|h| store.insert_hour(&hour(NodeId(1), h)).unwrap())
+            .collect();
+        for hash in &hashes[1..] {
+            store.add_replica(hash, NodeId(2));
+        }
+        let expired = store.expire(3).unwrap();
+        assert_eq!(
+            expired.iter().map(|info| info.hour).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        let left: Vec<i64> = store
+            .query(&BlobQuery::default())
+            .iter()
+            .map(|info| info.hour)
+            .collect();
+        assert_eq!(left, vec![0, 3]);
+    }
+
+    #[test]
+    fn hours_are_deflated_and_come_back_whole() {
+        let mut store = BlobStore::new();
+        let blob = hour(NodeId(1), 4);
+        let hash = store.insert_hour(&blob).unwrap();
+        assert_eq!(hash, blob.hash());
+        assert_eq!(store.insert_hour(&blob).unwrap(), hash);
+        assert_eq!(store.len(), 1);
+
+        let info = store.info(&hash).unwrap();
+        assert_eq!(info.size, blob.encode().len() as u64);
+        assert!(info.compression_ratio() > 1.5, "{info:?}");
+        assert_eq!(store.stored_bytes(), info.stored);
+        assert_eq!(store.get(&hash).unwrap(), blob.encode());
+        assert_eq!(store.hour(&hash).unwrap(), blob);
+        assert!(matches!(
+            store.video(&hash),
+            Err(StoreError::WrongKind {
+                expected: BlobKind::Video,
+                found: BlobKind::Hour,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn footage_is_stored_as_it_is() {
+        let mut store = BlobStore::new();
+        let video = VideoBlob {
+            node: NodeId(1),
+            camera: ChannelId::new("camera"),
+            hour: 4,
+            segments: Vec::new(),
+            keyframes: Vec::new(),
+        };
+        let hash = store.insert_video(&video).unwrap();
+        let info = store.info(&hash).unwrap();
+        assert_eq!(info.stored, info.size);
+        let Backing::Memory(stored) = &store.backing else {
+            unreachable!("new stores live in memory");
+        };
+        assert_eq!(stored[&hash], video.encode());
+        assert_eq!(store.video(&hash).unwrap(), video);
+    }
+
+    #[test]
+    fn tampered_blobs_are_reported_corrupt() {
+        let mut store = BlobStore::new();
+        let hash = store.insert_hour(&hour(NodeId(1), 4)).unwrap();
+        let Backing::Memory(stored) = &mut store.backing else {
+            unreachable!("new stores live in memory");
+        };
+        let deflated = stored.get_mut(&hash).unwrap();
+        *deflated = miniz_oxide::deflate::compress_to_vec(b"something else", LEVEL);
+        assert!(matches!(store.get(&hash), Err(StoreError::Corrupt(..))));
+    }
+
+    #[test]
+    fn queries_filter_by_node_hours_and_kind_in_order() {
+        let mut store = BlobStore::new();
+        for (node, h) in [(2, 5), (1, 6), (1, 4), (2, 4), (1, 5)] {
+            store.insert_hour(&hour(NodeId(node), h)).unwrap();
+        }
+        let listed = |query: BlobQuery| -> Vec<(u64, i64)> {
+            store
+                .query(&query)
+                .iter()
+                .map(|info| (info.node.0, info.hour))
+                .collect()
+        };
+        assert_eq!(
+            listed(BlobQuery::default()),
+            [(1, 4), (1, 5), (1, 6), (2, 4), (2, 5)]
+        );
+        let query = BlobQuery {
+            node: Some(NodeId(1)),
+            hours: Some(5..7),
+            ..Default::default()
+        };
+        assert_eq!(listed(query), [(1, 5), (1, 6)]);
+        let videos = BlobQuery {
+            kind: Some(BlobKind::Video),
+            ..Default::default()
+        };
+        assert_eq!(listed(videos), []);
+    }
+
+    #[test]
+    fn replicas_are_tracked_per_blob() {
+        let mut store = BlobStore::new();
+        let hash = store.insert_hour(&hour(NodeId(1), 4)).unwrap();
+        let other = store.insert_hour(&hour(NodeId(1), 5)).unwrap();
+        assert!(store.add_replica(&hash, NodeId(2)));
+        assert!(store.add_replica(&hash, NodeId(3)));
+        assert!(store.add_replica(&hash, NodeId(2)));
+        assert!(!store.add_replica(&BlobHash::of(b"unknown"), NodeId(2)));
+        assert_eq!(
+            store.info(&hash).unwrap().replicas,
+            [NodeId(2), NodeId(3)].into()
+        );
+        assert!(store.info(&other).unwrap().replicas.is_empty());
+    }
+}
@@ -0,0 +1,453 @@
+//! What each camera recorded over a stretch of time, laid out for a player.
+//!
+//! [`Timeline::assemble`] takes the [`VideoBlob`]s and [`DetectionBlob`]s of a day (or any other range) and gives
+//! every camera its recorded segments, the motion events and detections worth jumping to, and the keyframes in order,
+//! which are the only places a player can start decoding and so the steps a scrub bar moves in.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{BlobHash, Keyframe, VideoBlob, HOUR};
+use crate::analytics::DetectionBlob;
+use crate::node::NodeId;
+use crate::sensors::{ChannelId, Micros};
+
+/// Length of a timeline day in µs.
+pub const DAY: Micros = 24 * HOUR;
+
+/// The UTC day (counted from the Unix epoch) a network timestamp falls into.
+pub fn day_of(network: Micros) -> i64 {
+    network.div_euclid(DAY)
+}
+
+/// The network times UTC day `day` starts and ends at, unless they do not fit in a [`Micros`].
+pub fn day_span(day: i64) -> Option<(Micros, Micros)> {
+    Some((day.checked_mul(DAY)?, day.checked_add(1)?.checked_mul(DAY)?))
+}
+
+/// One recorded segment, which plays on its own.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recording {
+    /// The [`VideoBlob`] holding the segment.
+    pub video: BlobHash,
+    pub segment: u32,
+    pub start: Micros,
+    pub end: Micros,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MarkerKind {
+    Motion {
+        /// Highest fraction of the picture that changed.
+        peak: f32,
+    },
+    Detection {
+        class: String,
+        /// Highest confidence among the detections merged into the marker.
+        confidence: f32,
+    },
+}
+
+/// Something worth looking at in the footage.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Marker {
+    pub start: Micros,
+    pub end: Micros,
+    pub kind: MarkerKind,
+}
+
+/// A place playback can start from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeekPoint {
+    pub video: BlobHash,
+    pub keyframe: Keyframe,
+}
+
+/// Everything one camera recorded within a timeline's range, each list sorted by time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraTimeline {
+    pub node: NodeId,
+    pub camera: ChannelId,
+    pub recordings: Vec<Recording>,
+    pub markers: Vec<Marker>,
+    pub keyframes: Vec<SeekPoint>,
+}
+
+impl CameraTimeline {
+    /// The recording covering `time`.
+    pub fn recording_at(&self, time: Micros) -> Option<&Recording> {
+        self.recordings
+            .iter()
+            .find(|r| r.start <= time && time <= r.end)
+    }
+
+    /// Index in `keyframes` of the last keyframe at or before `time`, or the first one if there is none before it.
+    pub fn keyframe_index(&self, time: Micros) -> Option<usize> {
+        if self.keyframes.is_empty() {
+            return None;
+        }
+        let after = self.keyframes.partition_point(|k| k.keyframe.time <= time);
+        Some(after.saturating_sub(1))
+    }
+
+    /// Index in `keyframes` to start playing from to show `time`, or the start of the nearest recording if nothing
+    /// was recorded at `time`. A day is too long a stretch to hit a clip of a few seconds on, so this snaps.
+    pub fn seek(&self, time: Micros) -> Option<usize> {
+        let nearest = self.recordings.iter().min_by_key(|r| {
+            if time < r.start {
+                r.start - time
+            } else {
+                (time - r.end).max(0)
+            }
+        })?;
+        self.keyframe_index(time.clamp(nearest.start, nearest.end))
+    }
+}
+
+/// The footage of every camera over `start..end`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Timeline {
+    pub start: Micros,
+    pub end: Micros,
+    /// By node, then camera.
+    pub cameras: Vec<CameraTimeline>,
+}
+
+impl Timeline {
+    /// Lays out the parts of `videos` and `detections` that fall within `start..end`. Detections of the same class
+    /// at most two analysis intervals apart are merged into one marker, since a person walking past should be
+    /// one thing to jump to rather than one per analysed frame.
+    pub fn assemble(
+        start: Micros,
+        end: Micros,
+        videos: &[VideoBlob],
+        detections: &[DetectionBlob],
+    ) -> Timeline {
+        let within = |from: Micros, to: Micros| from < end && start <= to;
+        let mut cameras: BTreeMap<(NodeId, ChannelId), CameraTimeline> = BTreeMap::new();
+
+        for video in videos {
+            let hash = video.hash();
+            let timeline = cameras
+                .entry((video.node, video.camera.clone()))
+                .or_insert_with(|| empty(video.node, &video.camera));
+            for (i, segment) in video.segments.iter().enumerate() {
+                if within(segment.start, segment.end) {
+                    timeline.recordings.push(Recording {
+                        video: hash,
+                        segment: i as u32,
+                        start: segment.start,
+                        end: segment.end,
+                    });
+                }
+            }
+            for event in video.motion() {
+                if within(event.start, event.end) {
+                    timeline.markers.push(Marker {
+                        start: event.start,
+                        end: event.end,
+                        kind: MarkerKind::Motion { peak: event.peak },
+                    });
+                }
+            }
+            for &keyframe in &video.keyframes {
+                if within(keyframe.time, keyframe.time) {
+                    timeline.keyframes.push(SeekPoint {
+                        video: hash,
+                        keyframe,
+                    });
+                }
+            }
+        }
+
+        for blob in detections {
+            let timeline = cameras
+                .entry((blob.node, blob.camera.clone()))
+                .or_insert_with(|| empty(blob.node, &blob.camera));
+            let mut open: BTreeMap<&str, Marker> = BTreeMap::new();
+            for detection in &blob.detections {
+                if !within(detection.time, detection.time) {
+                    continue;
+                }
+                let class = detection.class.as_str();
+                if let Some(marker) = open.get_mut(class) {
+                    if detection.time - marker.end <= 2 * blob.interval {
+                        marker.end = detection.time;
+                        if let MarkerKind::Detection { confidence, .. } = &mut marker.kind {
+                            *confidence = confidence.max(detection.confidence);
+                        }
+                        continue;
+                    }
+                    timeline.markers.extend(open.remove(class));
+                }
+                open.insert(
+                    class,
+                    Marker {
+                        start: detection.time,
+                        end: detection.time,
+                        kind: MarkerKind::Detection {
+                            class: detection.class.clone(),
+                            confidence: detection.confidence,
+                        },
+                    },
+                );
+            }
+            timeline.markers.extend(open.into_values());
+        }
+
+        let cameras = cameras
+            .into_values()
+            .map(|mut timeline| {
+                timeline.recordings.sort_by_key(|r| r.start);
+                timeline.markers.sort_by_key(|m| m.start);
+                timeline.keyframes.sort_by_key(|k| k.keyframe.time);
+                timeline
+            })
+            .collect();
+        Timeline {
+            start,
+            end,
+            cameras,
+        }
+    }
+
+    /// The timeline of UTC day `day`. A day outside [`day_span`] has nothing in it.
+    pub fn day(day: i64, videos: &[VideoBlob], detections: &[DetectionBlob]) -> Timeline {
+        let (start, end) = day_span(day).unwrap_or((0, 0));
+        Timeline::assemble(start, end, videos, detections)
+    }
+
+    pub fn camera(&self, node: NodeId, camera: &ChannelId) -> Option<&CameraTimeline> {
+        self.cameras
+            .iter()
+            .find(|c| c.node == node && c.camera == *camera)
+    }
+}
+
+fn empty(node: NodeId, camera: &ChannelId) -> CameraTimeline {
+    CameraTimeline {
+        node,
+        camera: camera.clone(),
+        recordings: Vec::new(),
+        markers: Vec::new(),
+        keyframes: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analytics::{BoundingBox, Detection};
+    use crate::camera::MotionEvent;
+    use crate::encoder::Packet;
+    use crate::storage::ivf::FourCc;
+    use crate::storage::{VideoFormat, VideoRecorder};
+    use crate::time_sync::NetworkTime;
+
+    const SECOND: Micros = 1_000_000;
+    const TODAY: i64 = 20_000;
+
+    fn at(hours: i64, seconds: i64) -> Micros {
+        TODAY * DAY + hours * HOUR + seconds * SECOND
+    }
+
+    /// Records five frames a second with a keyframe every two seconds from `start` to `end`, and returns every hour
+    /// blob that produced.
+    fn record(
+        node: u64,
+        camera: &str,
+        start: Micros,
+        end: Micros,
+        motion: &[MotionEvent],
+    ) -> Vec<VideoBlob> {
+        let format = VideoFormat {
+            codec: FourCc::AV1,
+            width: 320,
+            height: 240,
+        };
+        let mut recorder = VideoRecorder::new(NodeId(node), ChannelId::new(camera), format);
+        recorder.max_segment = 60 * SECOND;
+        let mut blobs = Vec::new();
+        for (frame, t) in (start..end).step_by(200_000).enumerate() {
+            let packet = Packet {
+                captured: t,
+                frame: frame as u64,
+                keyframe: frame % 10 == 0,
+                data: vec![0; 32],
+            };
+            let time = NetworkTime {
+                local: t,
+                network: t,
+                error: 0,
+            };
+            blobs.extend(recorder.push(&packet, time));
+        }
+        for event in motion {
+            recorder.mark_motion(*event);
+        }
+        blobs.extend(recorder.seal());
+        blobs
+    }
+
+    fn detection(time: Micros, class: &str, confidence: f32) -> Detection {
+        Detection {
+            time,
+            segment: BlobHash([0; 32]),
+            class: class.to_string(),
+            confidence,
+            bbox: BoundingBox {
+                x: 0.1,
+                y: 0.1,
+                width: 0.2,
+                height: 0.4,
+            },
+        }
+    }
+
+    fn fixture() -> (Vec<VideoBlob>, Vec<DetectionBlob>) {
+        let motion = MotionEvent {
+            start: at(8, 10),
+            end: at(8, 20),
+            peak: 0.12,
+        };
+        let mut videos = record(1, "porch", at(0, -10), at(0, 30), &[]);
+        let morning = record(1, "porch", at(8, 0), at(8, 90), &[motion]);
+        videos.extend(morning.iter().cloned());
+        videos.extend(record(2, "gate", at(12, 0), at(12, 10), &[]));
+        let detections = DetectionBlob {
+            video: morning[0].hash(),
+            node: NodeId(1),
+            camera: ChannelId::new("porch"),
+            hour: TODAY * 24 + 8,
+            model: "fixture".to_string(),
+            interval: SECOND,
+            detections: vec![
+                detection(at(8, 10), "person", 0.6),
+                detection(at(8, 11), "car", 0.7),
+                detection(at(8, 11), "person", 0.9),
+                detection(at(8, 12), "person", 0.8),
+                detection(at(8, 40), "person", 0.5),
+            ],
+        };
+        (videos, vec![detections])
+    }
+
+    #[test]
+    fn a_day_is_assembled_per_camera_from_its_blobs() {
+        let (videos, detections) = fixture();
+        // The footage from before midnight went into the previous day's last hour.
+        assert_eq!(videos.len(), 4);
+        let timeline = Timeline::day(TODAY, &videos, &detections);
+        let cameras: Vec<_> = timeline
+            .cameras
+            .iter()
+            .map(|c| (c.node, c.camera.to_string()))
+            .collect();
+        assert_eq!(
+            cameras,
+            vec![
+                (NodeId(1), "porch".to_string()),
+                (NodeId(2), "gate".to_string())
+            ]
+        );
+
+        let porch = timeline
+            .camera(NodeId(1), &ChannelId::new("porch"))
+            .unwrap();
+        let recordings: Vec<_> = porch.recordings.iter().map(|r| (r.start, r.end)).collect();
+        assert_eq!(
+            recordings,
+            vec![
+                (at(0, 0), at(0, 30) - 200_000),
+                (at(8, 0), at(8, 60) - 200_000),
+                (at(8, 60), at(8, 90) - 200_000),
+            ]
+        );
+        assert!(porch
+            .keyframes
+            .windows(2)
+            .all(|k| k[0].keyframe.time < k[1].keyframe.time));
+        assert_eq!(porch.keyframes.len(), 15 + 45);
+        assert!(porch.keyframes.iter().all(|k| k.keyframe.time >= at(0, 0)));
+    }
+
+    #[test]
+    fn motion_and_detections_become_markers() {
+        let (videos, detections) = fixture();
+        let timeline = Timeline::day(TODAY, &videos, &detections);
+        let porch = timeline
+            .camera(NodeId(1), &ChannelId::new("porch"))
+            .unwrap();
+        let markers: Vec<_> = porch
+            .markers
+            .iter()
+            .map(|m| {
+                let what = match &m.kind {
+                    MarkerKind::Motion { .. } => "motion".to_string(),
+                    MarkerKind::Detection { class, confidence } => format!("{class} {confidence}"),
+                };
+                (m.start, m.end, what)
+            })
+            .collect();
+        assert_eq!(
+            markers,
+            vec![
+                (at(8, 10), at(8, 20), "motion".to_string()),
+                (at(8, 10), at(8, 12), "person 0.9".to_string()),
+                (at(8, 11), at(8, 11), "car 0.7".to_string()),
+                (at(8, 40), at(8, 40), "person 0.5".to_string()),
+            ]
+        );
+        let gate = timeline.camera(NodeId(2), &ChannelId::new("gate")).unwrap();
+        assert!(gate.markers.is_empty());
+    }
+
+    #[test]
+    fn seeking_lands_on_keyframes_and_snaps_to_the_nearest_recording() {
+        let (videos, detections) = fixture();
+        let timeline = Timeline::day(TODAY, &videos, &detections);
+        let porch = timeline
+            .camera(NodeId(1), &ChannelId::new("porch"))
+            .unwrap();
+        let time = |index: usize| porch.keyframes[index].keyframe.time;
+
+        let inside = porch.seek(at(8, 5)).unwrap();
+        assert_eq!(time(inside), at(8, 4));
+        assert_eq!(porch.recording_at(at(8, 5)).unwrap().start, at(8, 0));
+        // Half past four is nearer the morning's footage than the few seconds after midnight.
+        assert_eq!(time(porch.seek(at(4, 1800)).unwrap()), at(8, 0));
+        assert_eq!(time(porch.seek(at(2, 0)).unwrap()), at(0, 28));
+        assert_eq!(porch.recording_at(at(4, 0)), None);
+        // The scrub bar steps through keyframes from one recording into the next.
+        assert_eq!(
+            time(porch.keyframe_index(at(8, 59)).unwrap() + 1),
+            at(8, 60)
+        );
+    }
+
+    #[test]
+    fn the_timeline_survives_the_blob_store() {
+        let (videos, detections) = fixture();
+        let mut store = crate::storage::BlobStore::new();
+        for video in &videos {
+            store.insert_video(video).unwrap();
+        }
+        store.insert_detections(&detections[0]).unwrap();
+        let stored: Vec<_> = store
+            .query(&Default::default())
+            .iter()
+            .filter_map(|info| store.video(&info.hash).ok())
+            .collect();
+        assert_eq!(stored.len(), videos.len());
+        assert_eq!(
+            Timeline::day(TODAY, &stored, &detections),
+            Timeline::day(TODAY, &videos, &detections)
+        );
+        assert_eq!(day_of(at(23, 3599)), TODAY);
+        assert_eq!(day_of(at(0, -1)), TODAY - 1);
+        assert_eq!(day_span(TODAY), Some((at(0, 0), at(24, 0))));
+        assert_eq!(day_span(i64::MAX / DAY), None);
+        assert_eq!(day_span(i64::MIN), None);
+    }
+}
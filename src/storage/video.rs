@@ -0,0 +1,504 @@
+//! Long term storage of camera footage.
+//!
+//! Encoded packets are written into IVF segments that always start at a keyframe, so every segment can be decoded
+//! on its own. Segments are collected per hour of network time, like sensor samples, and sealed into a
+//! [`VideoBlob`] together with an index of every keyframe, which is all a player needs to seek. Segments also carry the
+//! motion events that happened during them, so a player can jump straight to the interesting parts.
+
+use serde::{Deserialize, Serialize};
+
+use super::ivf::{FourCc, IvfError, IvfHeader, IvfReader, IvfWriter};
+use super::{hour_of, BlobHash};
+use crate::camera::MotionEvent;
+use crate::encoder::Packet;
+use crate::node::NodeId;
+use crate::sensors::{ChannelId, Micros};
+use crate::time_sync::NetworkTime;
+
+/// IVF timestamps are in milliseconds from the start of their segment.
+const TIMEBASE: u32 = 1_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VideoFormat {
+    pub codec: FourCc,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// A self contained stretch of video starting at a keyframe.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VideoSegment {
+    pub format: VideoFormat,
+    /// Network time of the first frame.
+    pub start: Micros,
+    /// Network time of the last frame.
+    pub end: Micros,
+    /// A complete IVF file.
+    pub ivf: Vec<u8>,
+    /// Motion events overlapping the segment, in network time.
+    pub motion: Vec<MotionEvent>,
+}
+
+impl VideoSegment {
+    /// Every frame with its network time.
+    pub fn frames(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<(Micros, &[u8]), IvfError>>, IvfError> {
+        self.frames_from(0)
+    }
+
+    /// Frames from the one whose header is at `offset`, which should be a keyframe from the index.
+    pub fn frames_from(
+        &self,
+        offset: u64,
+    ) -> Result<impl Iterator<Item = Result<(Micros, &[u8]), IvfError>>, IvfError> {
+        let mut reader = IvfReader::new(&self.ivf)?;
+        reader.seek(offset);
+        let start = self.start;
+        Ok(reader.map(move |frame| {
+            let frame = frame?;
+            Ok((
+                start + frame.timestamp as Micros * (1_000_000 / TIMEBASE as Micros),
+                frame.data,
+            ))
+        }))
+    }
+}
+
+/// Where a keyframe can be found in a [`VideoBlob`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub time: Micros,
+    pub segment: u32,
+    /// Offset of the keyframe's IVF frame header within the segment.
+    pub offset: u64,
+}
+
+/// An hour of footage from one camera on one node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VideoBlob {
+    pub node: NodeId,
+    pub camera: ChannelId,
+    pub hour: i64,
+    pub segments: Vec<VideoSegment>,
+    /// Sorted by time.
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl VideoBlob {
+    pub fn encode(&self) -> Vec<u8> {
+        postcard::to_stdvec(self).expect("video blobs always serialize")
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, postcard::Error> {
+        postcard::from_bytes(bytes)
+    }
+
+    pub fn hash(&self) -> BlobHash {
+        BlobHash::of(&self.encode())
+    }
+
+    /// Every motion event in the hour, once each even when it spans several segments.
+    pub fn motion(&self) -> Vec<MotionEvent> {
+        let mut events: Vec<MotionEvent> = Vec::new();
+        for event in self.segments.iter().flat_map(|s| &s.motion) {
+            if !events.iter().any(|e| e.start == event.start) {
+                events.push(*event);
+            }
+        }
+        events.sort_by_key(|e| e.start);
+        events
+    }
+
+    /// The last keyframe at or before `time`, which is where decoding has to start to show `time`.
+    pub fn seek(&self, time: Micros) -> Option<Keyframe> {
+        let after = self.keyframes.partition_point(|k| k.time <= time);
+        self.keyframes[..after].last().copied()
+    }
+
+    /// Frames with their network times, from the keyframe [`VideoBlob::seek`] finds for `time` to the end of the
+    /// hour.
+    pub fn frames_from(
+        &self,
+        time: Micros,
+    ) -> Result<impl Iterator<Item = Result<(Micros, &[u8]), IvfError>>, IvfError> {
+        let start = self.seek(time).unwrap_or(Keyframe {
+            time,
+            segment: 0,
+            offset: 0,
+        });
+        let mut iterators = Vec::new();
+        for (i, segment) in self
+            .segments
+            .iter()
+            .enumerate()
+            .skip(start.segment as usize)
+        {
+            let offset = if i == start.segment as usize {
+                start.offset
+            } else {
+                0
+            };
+            iterators.push(segment.frames_from(offset)?);
+        }
+        Ok(iterators.into_iter().flatten())
+    }
+}
+
+/// The segment currently being written.
+#[derive(Debug, Clone)]
+struct OpenSegment {
+    format: VideoFormat,
+    start: Micros,
+    end: Micros,
+    writer: IvfWriter,
+    motion: Vec<MotionEvent>,
+}
+
+impl OpenSegment {
+    fn close(self) -> VideoSegment {
+        VideoSegment {
+            format: self.format,
+            start: self.start,
+            end: self.end,
+            ivf: self.writer.finish(),
+            motion: self.motion,
+        }
+    }
+}
+
+/// Cuts a camera's packets into segments and hour blobs.
+///
+/// Segments and hours can only begin at a keyframe. When an hour ends the recorder asks for one (see
+/// [`VideoRecorder::take_keyframe_request`]) and, until it arrives, keeps appending to the old hour, so a blob may run a
+/// few frames past the end of its hour but never loses any.
+#[derive(Debug, Clone)]
+pub struct VideoRecorder {
+    pub node: NodeId,
+    pub camera: ChannelId,
+    format: VideoFormat,
+    /// Segments are cut at the first keyframe after they reach this length.
+    pub max_segment: Micros,
+    hour: Option<i64>,
+    segments: Vec<VideoSegment>,
+    keyframes: Vec<Keyframe>,
+    open: Option<OpenSegment>,
+    wants_keyframe: bool,
+    /// Whether the caller has been told about `wants_keyframe` already.
+    requested: bool,
+    /// Packets dropped because no keyframe had been seen yet to decode them from.
+    pub dropped: u64,
+}
+
+impl VideoRecorder {
+    pub fn new(node: NodeId, camera: ChannelId, format: VideoFormat) -> Self {
+        VideoRecorder {
+            node,
+            camera,
+            format,
+            max_segment: 10 * 60 * 1_000_000,
+            hour: None,
+            segments: Vec::new(),
+            keyframes: Vec::new(),
+            open: None,
+            wants_keyframe: true,
+            requested: false,
+            dropped: 0,
+        }
+    }
+
+    /// Whether the encoder should be told to make the next frame a keyframe. Returns `true` once per keyframe
+    /// needed: encoders hold frames back, so asking again on every frame until one shows up would force several.
+    pub fn take_keyframe_request(&mut self) -> bool {
+        let request = self.wants_keyframe && !self.requested;
+        self.requested |= request;
+        request
+    }
+
+    /// Switches to a new format, e.g. after the encoder was restarted at another resolution. The current segment
+    /// ends here, and recording resumes at the next keyframe.
+    pub fn set_format(&mut self, format: VideoFormat) {
+        if format != self.format {
+            self.close_segment();
+            self.format = format;
+        }
+    }
+
+    /// Records one packet. Returns the previous hour's blob when this packet starts a new hour.
+    pub fn push(&mut self, packet: &Packet, time: NetworkTime) -> Option<VideoBlob> {
+        let hour = hour_of(time.network);
+        let current = *self.hour.get_or_insert(hour);
+        let mut sealed = None;
+        if hour > current {
+            if packet.keyframe {
+                sealed = self.seal();
+                self.hour = Some(hour);
+            } else {
+                self.wants_keyframe = true;
+            }
+        }
+
+        if packet.keyframe {
+            let too_long = self
+                .open
+                .as_ref()
+                .is_some_and(|open| time.network - open.start >= self.max_segment);
+            if too_long {
+                self.close_segment();
+            }
+            if self.open.is_none() {
+                self.open = Some(OpenSegment {
+                    format: self.format,
+                    start: time.network,
+                    end: time.network,
+                    writer: IvfWriter::new(IvfHeader {
+                        fourcc: self.format.codec,
+                        width: self.format.width,
+                        height: self.format.height,
+                        timebase_den: TIMEBASE,
+                        timebase_num: 1,
+                        frames: 0,
+                    }),
+                    motion: Vec::new(),
+                });
+            }
+            self.wants_keyframe = false;
+            self.requested = false;
+        }
+
+        let Some(open) = &mut self.open else {
+            self.dropped += 1;
+            self.wants_keyframe = true;
+            return sealed;
+        };
+        let timestamp = (time.network - open.start).max(0) as u64 / (1_000_000 / TIMEBASE as u64);
+        let offset = open.writer.write_frame(timestamp, &packet.data);
+        open.end = open.end.max(time.network);
+        if packet.keyframe {
+            self.keyframes.push(Keyframe {
+                time: time.network,
+                segment: self.segments.len() as u32,
+                offset,
+            });
+        }
+        sealed
+    }
+
+    /// Tags the segments of the current hour that overlap `event`, whose times have to be network times like the
+    /// packets'. Events are usually marked once their post-roll ends, by which time the segment they started in may
+    /// be closed, so closed segments are tagged too; a part of the event in an hour that was already sealed is not.
+    pub fn mark_motion(&mut self, event: MotionEvent) {
+        let closed = self
+            .segments
+            .iter_mut()
+            .map(|s| (s.start, s.end, &mut s.motion));
+        let open = self
+            .open
+            .as_mut()
+            .map(|o| (o.start, Micros::MAX, &mut o.motion));
+        for (start, end, motion) in closed.chain(open) {
+            if !event.overlaps(start, end) {
+                continue;
+            }
+            match motion.iter_mut().find(|e| e.start == event.start) {
+                Some(existing) => *existing = event,
+                None => motion.push(event),
+            }
+        }
+    }
+
+    /// Seals whatever has been recorded for the current hour, e.g. when recording stops. Recording can carry on
+    /// afterwards, which gives the hour a second blob.
+    pub fn seal(&mut self) -> Option<VideoBlob> {
+        self.close_segment();
+        let hour = self.hour?;
+        if self.segments.is_empty() {
+            return None;
+        }
+        Some(VideoBlob {
+            node: self.node,
+            camera: self.camera.clone(),
+            hour,
+            segments: std::mem::take(&mut self.segments),
+            keyframes: std::mem::take(&mut self.keyframes),
+        })
+    }
+
+    fn close_segment(&mut self) {
+        if let Some(open) = self.open.take() {
+            self.segments.push(open.close());
+        }
+        self.wants_keyframe = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::HOUR;
+
+    const FORMAT: VideoFormat = VideoFormat {
+        codec: FourCc::AV1,
+        width: 64,
+        height: 48,
+    };
+
+    fn recorder() -> VideoRecorder {
+        VideoRecorder::new(NodeId(1), ChannelId::new("camera"), FORMAT)
+    }
+
+    /// Pushes frame `frame` at network time `network`, its data naming it.
+    fn push(
+        recorder: &mut VideoRecorder,
+        frame: u64,
+        network: Micros,
+        keyframe: bool,
+    ) -> Option<VideoBlob> {
+        let packet = Packet {
+            captured: network,
+            frame,
+            keyframe,
+            data: frame.to_le_bytes().to_vec(),
+        };
+        let time = NetworkTime {
+            local: network,
+            network,
+            error: 0,
+        };
+        recorder.push(&packet, time)
+    }
+
+    /// The frames of a blob, by number, with their network times.
+    fn frames(blob: &VideoBlob) -> Vec<(Micros, u64)> {
+        blob.frames_from(Micros::MIN)
+            .unwrap()
+            .map(|frame| {
+                let (time, data) = frame.unwrap();
+                (time, u64::from_le_bytes(data.try_into().unwrap()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn recording_starts_at_the_first_keyframe() {
+        let mut recorder = recorder();
+        assert!(recorder.take_keyframe_request());
+        assert!(!recorder.take_keyframe_request());
+        push(&mut recorder, 0, 0, false);
+        push(&mut recorder, 1, 100_000, false);
+        assert_eq!(recorder.dropped, 2);
+        // Still waiting for the one already asked for.
+        assert!(!recorder.take_keyframe_request());
+
+        push(&mut recorder, 2, 200_000, true);
+        push(&mut recorder, 3, 300_000, false);
+        assert!(!recorder.take_keyframe_request());
+        let blob = recorder.seal().unwrap();
+        assert_eq!(frames(&blob), [(200_000, 2), (300_000, 3)]);
+        assert_eq!(recorder.seal(), None);
+        // After sealing, the next recording needs a keyframe of its own.
+        assert!(recorder.take_keyframe_request());
+    }
+
+    #[test]
+    fn segments_are_cut_at_the_first_keyframe_past_their_length() {
+        let mut recorder = recorder();
+        recorder.max_segment = 1_000_000;
+        for frame in 0..25u64 {
+            let time = frame as Micros * 100_000;
+            // A keyframe every 0.8 s.
+            push(&mut recorder, frame, time, frame % 8 == 0);
+        }
+        let blob = recorder.seal().unwrap();
+        let bounds: Vec<(Micros, Micros)> =
+            blob.segments.iter().map(|s| (s.start, s.end)).collect();
+        assert_eq!(bounds, [(0, 1_500_000), (1_600_000, 2_400_000)]);
+        assert!(blob.segments.iter().all(|s| s.format == FORMAT));
+        assert_eq!(
+            blob.keyframes
+                .iter()
+                .map(|k| (k.time, k.segment))
+                .collect::<Vec<_>>(),
+            [(0, 0), (800_000, 0), (1_600_000, 1), (2_400_000, 1)]
+        );
+        assert_eq!(frames(&blob).len(), 25);
+
+        // Seeking starts decoding at the keyframe before the time asked for.
+        assert_eq!(blob.seek(2_000_000).unwrap().time, 1_600_000);
+        let from: Vec<u64> = blob
+            .frames_from(2_000_000)
+            .unwrap()
+            .map(|frame| u64::from_le_bytes(frame.unwrap().1.try_into().unwrap()))
+            .collect();
+        assert_eq!(from, (16..25).collect::<Vec<_>>());
+        assert_eq!(VideoBlob::decode(&blob.encode()).unwrap(), blob);
+    }
+
+    #[test]
+    fn hours_are_sealed_at_the_next_keyframe() {
+        let mut recorder = recorder();
+        let start = HOUR - 300_000;
+        push(&mut recorder, 0, start, true);
+        push(&mut recorder, 1, start + 100_000, false);
+        assert!(!recorder.take_keyframe_request());
+        // The hour is over, but frames carry on into it until a keyframe arrives.
+        assert_eq!(push(&mut recorder, 2, HOUR, false), None);
+        assert!(recorder.take_keyframe_request());
+        assert!(!recorder.take_keyframe_request());
+        assert_eq!(push(&mut recorder, 3, HOUR + 100_000, false), None);
+
+        let sealed = push(&mut recorder, 4, HOUR + 200_000, true).unwrap();
+        assert_eq!(sealed.hour, 0);
+        assert_eq!(
+            frames(&sealed),
+            [
+                (start, 0),
+                (start + 100_000, 1),
+                (HOUR, 2),
+                (HOUR + 100_000, 3)
+            ]
+        );
+        let next = recorder.seal().unwrap();
+        assert_eq!(next.hour, 1);
+        assert_eq!(frames(&next), [(HOUR + 200_000, 4)]);
+        assert_eq!(next.keyframes[0].segment, 0);
+    }
+
+    #[test]
+    fn motion_is_tagged_on_every_segment_it_overlaps_and_listed_once() {
+        let mut recorder = recorder();
+        recorder.max_segment = 1_000_000;
+        for frame in 0..20u64 {
+            let time = frame as Micros * 100_000;
+            push(&mut recorder, frame, time, frame % 10 == 0);
+        }
+        let event = MotionEvent {
+            start: 800_000,
+            end: 1_200_000,
+            peak: 0.1,
+        };
+        recorder.mark_motion(event);
+        recorder.mark_motion(MotionEvent { peak: 0.3, ..event });
+        let blob = recorder.seal().unwrap();
+        assert_eq!(blob.segments.len(), 2);
+        assert!(blob.segments.iter().all(|s| s.motion.len() == 1));
+        assert_eq!(blob.motion(), [MotionEvent { peak: 0.3, ..event }]);
+    }
+
+    #[test]
+    fn a_new_format_starts_a_new_segment() {
+        let mut recorder = recorder();
+        push(&mut recorder, 0, 0, true);
+        let wide = VideoFormat {
+            width: 96,
+            ..FORMAT
+        };
+        recorder.set_format(wide);
+        push(&mut recorder, 1, 100_000, false);
+        assert_eq!(recorder.dropped, 1);
+        push(&mut recorder, 2, 200_000, true);
+        let blob = recorder.seal().unwrap();
+        let formats: Vec<VideoFormat> = blob.segments.iter().map(|s| s.format).collect();
+        assert_eq!(formats, [FORMAT, wide]);
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+
+use crate::node::NodeId;
+use crate::sensors::Micros;
+
+use super::Reference;
+
+/// The result of one request/response exchange with a neighbour, in the classic NTP four timestamp form.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    /// Our local clock at the midpoint of the exchange.
+    pub at: Micros,
+    /// The neighbour's network clock minus our local clock at `at`.
+    pub offset: f64,
+    /// Round trip time with the neighbour's processing time removed.
+    pub delay: f64,
+    /// The error bound the neighbour reported for its own network clock.
+    pub peer_error: f64,
+}
+
+impl Measurement {
+    /// Builds a measurement from the timestamps of one exchange.
+    ///
+    /// `origin` and `arrival` are our local clock when the request left and the response arrived, `receive` and
+    /// `transmit` are the neighbour's network clock when it got the request and sent the response.
+    pub fn from_exchange(
+        origin: Micros,
+        receive: Micros,
+        transmit: Micros,
+        arrival: Micros,
+        peer_error: Micros,
+    ) -> Self {
+        let offset = ((receive - origin) + (transmit - arrival)) as f64 / 2.0;
+        let delay = ((arrival - origin) - (transmit - receive)).max(0) as f64;
+        Measurement {
+            at: origin + (arrival - origin) / 2,
+            offset,
+            delay,
+            peer_error: peer_error as f64,
+        }
+    }
+}
+
+/// A linear model of a neighbour's network clock relative to our local clock.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockModel {
+    /// Local time the model is anchored at.
+    pub anchor: Micros,
+    /// Offset (in µs) to add to our local clock at `anchor`.
+    pub offset: f64,
+    /// How much the offset changes per µs of local time, i.e. relative skew. `1e-6` is one ppm.
+    pub rate: f64,
+    /// Error bound (in µs) of the model at `anchor`.
+    pub error: f64,
+}
+
+impl ClockModel {
+    pub const IDENTITY: ClockModel = ClockModel {
+        anchor: 0,
+        offset: 0.0,
+        rate: 0.0,
+        error: 0.0,
+    };
+
+    pub fn offset_at(&self, local: Micros) -> f64 {
+        self.offset + self.rate * (local - self.anchor) as f64
+    }
+
+    /// The error bound at `local`, widened by `max_drift` (relative, e.g. `20e-6`) for every µs away from the anchor.
+    pub fn error_at(&self, local: Micros, max_drift: f64) -> f64 {
+        self.error + max_drift * (local - self.anchor).abs() as f64
+    }
+}
+
+/// Keeps a window of measurements against one neighbour and fits a clock model to them.
+#[derive(Debug, Clone)]
+pub struct PeerEstimator {
+    window: VecDeque<Measurement>,
+    capacity: usize,
+    /// The reference the neighbour last advertised.
+    pub reference: Reference,
+    /// The neighbour's own neighbour it follows that reference through.
+    pub parent: Option<NodeId>,
+}
+
+impl PeerEstimator {
+    pub fn new(capacity: usize, peer: NodeId) -> Self {
+        PeerEstimator {
+            window: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(2),
+            reference: Reference::own(peer, false),
+            parent: None,
+        }
+    }
+
+    pub fn push(&mut self, measurement: Measurement, reference: Reference, parent: Option<NodeId>) {
+        if self.window.len() == self.capacity {
+            self.window.pop_front();
+        }
+        self.window.push_back(measurement);
+        self.reference = reference;
+        self.parent = parent;
+    }
+
+    /// Local time of the most recent measurement.
+    pub fn last_seen(&self) -> Option<Micros> {
+        self.window.back().map(|m| m.at)
+    }
+
+    /// Fits a line through the offsets in the window.
+    ///
+    /// Exchanges that were delayed by more than twice the fastest one in the window are dropped first, since
+    /// queueing delay is almost never symmetric and would bias the offset. The remaining points are weighted by the
+    /// inverse square of their delay.
+    pub fn model(&self) -> Option<ClockModel> {
+        let last = *self.window.back()?;
+        let min_delay = self
+            .window
+            .iter()
+            .map(|m| m.delay)
+            .fold(f64::INFINITY, f64::min);
+        let cutoff = (2.0 * min_delay).max(min_delay + 100.0);
+        let points: Vec<&Measurement> = self.window.iter().filter(|m| m.delay <= cutoff).collect();
+
+        let weight = |m: &Measurement| 1.0 / (m.delay + 1.0).powi(2);
+        let total: f64 = points.iter().map(|m| weight(m)).sum();
+        let mean_x = points
+            .iter()
+            .map(|m| weight(m) * (m.at - last.at) as f64)
+            .sum::<f64>()
+            / total;
+        let mean_y = points.iter().map(|m| weight(m) * m.offset).sum::<f64>() / total;
+
+        let (mut sxy, mut sxx) = (0.0, 0.0);
+        for m in &points {
+            let dx = (m.at - last.at) as f64 - mean_x;
+            sxy += weight(m) * dx * (m.offset - mean_y);
+            sxx += weight(m) * dx * dx;
+        }
+        // With a single point, or points too close together, the slope is noise.
+        let rate = if points.len() >= 3 && sxx > 1e6 {
+            sxy / sxx
+        } else {
+            0.0
+        };
+        let offset = mean_y - rate * mean_x;
+
+        let residual = points
+            .iter()
+            .map(|m| {
+                let predicted = offset + rate * (m.at - last.at) as f64;
+                (m.offset - predicted).powi(2)
+            })
+            .sum::<f64>()
+            / points.len() as f64;
+        let peer_error = points.iter().map(|m| m.peer_error).fold(0.0, f64::max);
+
+        Some(ClockModel {
+            anchor: last.at,
+            offset,
+            rate,
+            error: min_delay / 2.0 + residual.sqrt() + peer_error,
+        })
+    }
+}
@@ -0,0 +1,497 @@
+//! Peer-to-peer clock synchronisation.
+//!
+//! Remote stations have no NTP server, yet hour blob boundaries and multi-node analytics need every node to agree on
+//! what time it is. Each node therefore runs a [`ClockSync`] that periodically exchanges timestamps with its
+//! neighbours (the same four timestamp exchange NTP uses), fits a model of each neighbour's clock, and steers its own
+//! "network time" towards them.
+//!
+//! Nodes with a trustworthy clock (GPS, NTP while on the internet) can be marked authoritative. The rest of the mesh
+//! follows the closest authority stratum by stratum. When no authority is reachable the node with the lowest id
+//! becomes a free running root, much like PTP's best master clock election, so the mesh still agrees on a common
+//! time even though nobody knows the true time. Error bounds are always relative to whichever root is being followed.
+//!
+//! Like a distance vector routing protocol, the election has to cope with a root that goes away while its former
+//! followers keep advertising it to each other. Each node tells its neighbours which of them it follows, and never
+//! follows a neighbour back through itself. Loops of three or more nodes still count up one stratum per exchange, so
+//! a reference more than [`MAX_STRATUM`] hops away counts as unreachable.
+
+mod estimator;
+pub mod sim;
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::node::NodeId;
+use crate::sensors::Micros;
+
+pub use estimator::{ClockModel, Measurement, PeerEstimator};
+
+/// The node's local clock right now. Works on wasm too, where `std::time::SystemTime` panics.
+pub fn local_now() -> Micros {
+    web_time::SystemTime::now()
+        .duration_since(web_time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_micros() as Micros)
+}
+
+/// Year, month and day of a count of days since the Unix epoch, in the proleptic Gregorian calendar.
+pub fn civil_date(days: i64) -> (i64, u32, u32) {
+    // Howard Hinnant's days_from_civil, inverted: count in 400 year eras that start on the 1st of March.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// A time in µs as an RFC 3339 UTC time stamp to the millisecond, for logs and files people read.
+pub fn rfc3339(micros: Micros) -> String {
+    let (year, month, day) = civil_date(micros.div_euclid(86_400_000_000));
+    let millis = micros.div_euclid(1_000).rem_euclid(86_400_000);
+    format!(
+        "{year}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1_000 % 60,
+        millis % 1_000
+    )
+}
+
+/// Hops from a root beyond which its reference is treated as unreachable.
+pub const MAX_STRATUM: u8 = 16;
+
+/// The clock a node ultimately follows, and how many hops away it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reference {
+    pub root: NodeId,
+    /// Whether the root's clock is trusted (GPS, NTP) rather than just elected.
+    pub authoritative: bool,
+    /// Hops from the root. The root itself is stratum 0.
+    pub stratum: u8,
+}
+
+impl Reference {
+    pub fn own(id: NodeId, authoritative: bool) -> Self {
+        Reference {
+            root: id,
+            authoritative,
+            stratum: 0,
+        }
+    }
+
+    /// Any authoritative root beats every elected one, and the nearest authority wins. Between elected roots the
+    /// lowest id wins, so that the whole mesh converges on the same one.
+    fn rank(&self) -> (u8, u64, u64) {
+        if self.authoritative {
+            (0, self.stratum as u64, self.root.0)
+        } else {
+            (1, self.root.0, self.stratum as u64)
+        }
+    }
+
+    /// The reference as seen one hop further from the root, or `None` if that is too far to be reachable.
+    fn next_hop(self) -> Option<Self> {
+        let stratum = self.stratum + 1;
+        (stratum < MAX_STRATUM).then_some(Reference { stratum, ..self })
+    }
+}
+
+impl PartialOrd for Reference {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Reference {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.rank().cmp(&other.rank())
+    }
+}
+
+/// A local timestamp together with the network time it corresponds to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkTime {
+    /// The raw reading of the node's own clock.
+    pub local: Micros,
+    /// The estimated network time at `local`.
+    pub network: Micros,
+    /// The estimate is believed to be within `network ± error`.
+    pub error: Micros,
+}
+
+/// Sent to a neighbour to start an exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncRequest {
+    /// Local clock of the requester when the request was sent.
+    pub origin: Micros,
+}
+
+/// A neighbour's answer to a [`SyncRequest`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncResponse {
+    /// Copied from the request.
+    pub origin: Micros,
+    /// Network time of the responder when the request arrived.
+    pub receive: Micros,
+    /// Network time of the responder when the response was sent.
+    pub transmit: Micros,
+    /// Error bound the responder has on its own network time.
+    pub error: Micros,
+    pub reference: Reference,
+    /// The neighbour the responder follows `reference` through, `None` if it is the root itself.
+    pub parent: Option<NodeId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncConfig {
+    /// Number of exchanges remembered per neighbour.
+    pub window: usize,
+    /// Neighbours that have not answered for this long are ignored.
+    pub stale_after: Micros,
+    /// Worst case relative drift of an uncorrected crystal, used to widen error bounds between updates.
+    pub max_drift: f64,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        SyncConfig {
+            window: 16,
+            stale_after: 15 * 60 * 1_000_000,
+            max_drift: 50e-6,
+        }
+    }
+}
+
+/// Estimates network time for one node from exchanges with its neighbours.
+#[derive(Debug, Clone)]
+pub struct ClockSync {
+    id: NodeId,
+    config: SyncConfig,
+    authoritative: bool,
+    reference: Reference,
+    parent: Option<NodeId>,
+    correction: ClockModel,
+    peers: BTreeMap<NodeId, PeerEstimator>,
+}
+
+impl ClockSync {
+    pub fn new(id: NodeId, config: SyncConfig) -> Self {
+        ClockSync {
+            id,
+            config,
+            authoritative: false,
+            reference: Reference::own(id, false),
+            parent: None,
+            correction: ClockModel::IDENTITY,
+            peers: BTreeMap::new(),
+        }
+    }
+
+    /// A node whose local clock is trusted as is, e.g. because it is disciplined by GPS.
+    pub fn authoritative(id: NodeId, config: SyncConfig) -> Self {
+        ClockSync {
+            authoritative: true,
+            reference: Reference::own(id, true),
+            ..ClockSync::new(id, config)
+        }
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// The clock that defines network time for us right now.
+    pub fn reference(&self) -> Reference {
+        self.reference
+    }
+
+    /// The neighbour whose clock we follow most closely, `None` while we are the root.
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn correction(&self) -> ClockModel {
+        self.correction
+    }
+
+    /// Converts a reading of the local clock into network time.
+    pub fn network_time(&self, local: Micros) -> NetworkTime {
+        NetworkTime {
+            local,
+            network: local + self.correction.offset_at(local).round() as Micros,
+            error: self.correction.error_at(local, self.max_drift()).ceil() as Micros,
+        }
+    }
+
+    pub fn request(&self, local_now: Micros) -> SyncRequest {
+        SyncRequest { origin: local_now }
+    }
+
+    /// Answers a neighbour's request. `received` and `sent` are our local clock when the request came in and when
+    /// the response goes out.
+    pub fn respond(&self, request: SyncRequest, received: Micros, sent: Micros) -> SyncResponse {
+        let sent = self.network_time(sent);
+        SyncResponse {
+            origin: request.origin,
+            receive: self.network_time(received).network,
+            transmit: sent.network,
+            error: sent.error,
+            reference: self.reference,
+            parent: self.parent,
+        }
+    }
+
+    /// Records the response to one of our requests, which arrived at local time `arrival`.
+    pub fn handle_response(&mut self, peer: NodeId, response: SyncResponse, arrival: Micros) {
+        let measurement = Measurement::from_exchange(
+            response.origin,
+            response.receive,
+            response.transmit,
+            arrival,
+            response.error,
+        );
+        let window = self.config.window;
+        self.peers
+            .entry(peer)
+            .or_insert_with(|| PeerEstimator::new(window, peer))
+            .push(measurement, response.reference, response.parent);
+    }
+
+    pub fn forget_peer(&mut self, peer: NodeId) {
+        self.peers.remove(&peer);
+    }
+
+    /// Recomputes the local correction from the current neighbour models.
+    ///
+    /// Every fresh neighbour advertises the [`Reference`] it follows, and we follow the best one, averaging the
+    /// neighbours that lead to it weighted by their error. Neighbours that follow us are skipped, as are references
+    /// that would be [`MAX_STRATUM`] or more hops away. If our own candidacy as an elected root beats all of them we
+    /// keep the current correction and become the root ourselves, so network time does not jump when a reference
+    /// disappears.
+    pub fn update(&mut self, local_now: Micros) {
+        if self.authoritative {
+            return;
+        }
+
+        let max_drift = self.config.max_drift;
+        let fresh: Vec<(NodeId, Reference, ClockModel)> = self
+            .peers
+            .iter()
+            .filter(|(_, p)| {
+                p.last_seen()
+                    .is_some_and(|seen| local_now - seen <= self.config.stale_after)
+            })
+            .filter(|(_, p)| p.reference.root != self.id && p.parent != Some(self.id))
+            .filter_map(|(&peer, p)| Some((peer, p.reference.next_hop()?, p.model()?)))
+            .collect();
+        let own = Reference::own(self.id, false);
+        let best = fresh
+            .iter()
+            .map(|(_, reference, _)| *reference)
+            .min()
+            .filter(|best| *best < own);
+
+        let Some(best) = best else {
+            self.correction = ClockModel {
+                anchor: local_now,
+                offset: self.correction.offset_at(local_now),
+                rate: self.correction.rate,
+                error: 0.0,
+            };
+            self.reference = own;
+            self.parent = None;
+            return;
+        };
+
+        let sources: Vec<(NodeId, f64, f64, f64)> = fresh
+            .iter()
+            .filter(|(_, reference, _)| *reference == best)
+            .map(|(peer, _, model)| {
+                (
+                    *peer,
+                    model.offset_at(local_now),
+                    model.rate,
+                    model.error_at(local_now, max_drift),
+                )
+            })
+            .collect();
+        let weights: Vec<f64> = sources
+            .iter()
+            .map(|(_, _, _, error)| 1.0 / (error + 1.0).powi(2))
+            .collect();
+        let total: f64 = weights.iter().sum();
+
+        let offset = sources
+            .iter()
+            .zip(&weights)
+            .map(|((_, o, _, _), w)| o * w)
+            .sum::<f64>()
+            / total;
+        let rate = sources
+            .iter()
+            .zip(&weights)
+            .map(|((_, _, r, _), w)| r * w)
+            .sum::<f64>()
+            / total;
+        let (parent, best_error) = sources
+            .iter()
+            .map(|(peer, _, _, e)| (*peer, *e))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .expect("the best reference comes from at least one neighbour");
+        let spread = sources
+            .iter()
+            .map(|(_, o, _, _)| (o - offset).abs())
+            .fold(0.0, f64::max);
+
+        self.correction = ClockModel {
+            anchor: local_now,
+            offset,
+            rate,
+            error: best_error + spread,
+        };
+        self.reference = best;
+        self.parent = Some(parent);
+    }
+
+    fn max_drift(&self) -> f64 {
+        // The root defines network time, so by construction it has no error relative to it.
+        if self.reference.root == self.id {
+            0.0
+        } else {
+            self.config.max_drift
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sim::{SimClock, SimMesh};
+    use super::*;
+
+    const MINUTE: Micros = 60_000_000;
+
+    fn clock(offset: f64, drift_ppm: f64) -> SimClock {
+        SimClock { offset, drift_ppm }
+    }
+
+    /// An authoritative root on a true clock and three followers whose clocks drift at different rates, each linked
+    /// to the root and in a ring to each other.
+    fn mesh() -> SimMesh {
+        let mut mesh = SimMesh::new(7);
+        let root = mesh.add_node(clock(0.0, 0.0), true);
+        let followers = [
+            mesh.add_node(clock(250_000.0, 40.0), false),
+            mesh.add_node(clock(-1_200_000.0, -25.0), false),
+            mesh.add_node(clock(3_000_000.0, 12.0), false),
+        ];
+        for (i, &a) in followers.iter().enumerate() {
+            mesh.link(root, a, 2_000, 1_000);
+            mesh.link(a, followers[(i + 1) % followers.len()], 3_000, 2_000);
+        }
+        mesh
+    }
+
+    #[test]
+    fn drifting_clocks_follow_the_authority_within_their_error_bounds() {
+        let mut mesh = mesh();
+        for _ in 0..60 {
+            mesh.round(MINUTE);
+        }
+        assert!(mesh.disagreement() < 5_000, "{}", mesh.disagreement());
+        for (node, (network, error)) in mesh.nodes.iter().zip(mesh.estimates()) {
+            assert_eq!(node.sync.reference().root, NodeId(1));
+            assert!(
+                (network - mesh.now).abs() <= error,
+                "{} is {} µs off with a bound of {error}",
+                node.id,
+                network - mesh.now
+            );
+        }
+        assert_eq!(mesh.nodes[2].sync.reference().stratum, 1);
+        assert_eq!(mesh.nodes[2].sync.parent(), Some(NodeId(1)));
+    }
+
+    #[test]
+    fn the_mesh_elects_a_new_root_when_the_authority_disappears() {
+        let mut mesh = mesh();
+        for _ in 0..30 {
+            mesh.round(MINUTE);
+        }
+        mesh.disconnect(0);
+        // Long enough for the root to go stale and for any loop to count up to the stratum cap.
+        for _ in 0..(15 + 2 * MAX_STRATUM as usize) {
+            mesh.round(MINUTE);
+            for node in &mesh.nodes[1..] {
+                assert!(node.sync.reference().stratum < MAX_STRATUM);
+            }
+        }
+        for node in &mesh.nodes[1..] {
+            let reference = node.sync.reference();
+            assert_eq!(
+                reference.root,
+                NodeId(2),
+                "{} follows {reference:?}",
+                node.id
+            );
+            assert!(!reference.authoritative);
+        }
+        assert_eq!(mesh.nodes[1].sync.parent(), None);
+        let followers: Vec<Micros> = mesh.estimates()[1..].iter().map(|(t, _)| *t).collect();
+        let spread = followers.iter().max().unwrap() - followers.iter().min().unwrap();
+        assert!(spread < 5_000, "{spread}");
+    }
+
+    #[test]
+    fn neighbours_that_follow_us_are_not_followed_back() {
+        let config = SyncConfig::default();
+        let mut sync = ClockSync::new(NodeId(5), config);
+        let response = SyncResponse {
+            origin: 0,
+            receive: 1_000,
+            transmit: 1_050,
+            error: 10,
+            reference: Reference {
+                root: NodeId(1),
+                authoritative: true,
+                stratum: 2,
+            },
+            parent: Some(NodeId(5)),
+        };
+        sync.handle_response(NodeId(6), response, 100);
+        sync.update(100);
+        assert_eq!(sync.reference(), Reference::own(NodeId(5), false));
+
+        let response = SyncResponse {
+            parent: Some(NodeId(7)),
+            ..response
+        };
+        sync.handle_response(NodeId(6), response, 200);
+        sync.update(200);
+        assert_eq!(sync.reference().stratum, 3);
+        assert_eq!(sync.parent(), Some(NodeId(6)));
+
+        let response = SyncResponse {
+            reference: Reference {
+                stratum: MAX_STRATUM - 1,
+                ..response.reference
+            },
+            ..response
+        };
+        sync.handle_response(NodeId(6), response, 300);
+        sync.update(300);
+        assert_eq!(sync.reference(), Reference::own(NodeId(5), false));
+    }
+
+    #[test]
+    fn civil_dates_and_timestamps() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(19_782), (2024, 2, 29));
+        assert_eq!(rfc3339(1_700_000_000_123_456), "2023-11-14T22:13:20.123Z");
+    }
+}
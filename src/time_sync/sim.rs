@@ -0,0 +1,155 @@
+//! A deterministic simulation of nodes with drifting clocks, for exercising [`ClockSync`] without hardware.
+
+use crate::node::NodeId;
+use crate::sensors::Micros;
+
+use super::{ClockSync, SyncConfig};
+
+/// True time at which every simulation starts.
+pub const SIM_START: Micros = 1_700_000_000_000_000;
+
+/// A crystal that was `offset` µs off at [`SIM_START`] and runs `drift_ppm` parts per million fast (or slow, if
+/// negative).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimClock {
+    pub offset: f64,
+    pub drift_ppm: f64,
+}
+
+impl SimClock {
+    pub fn local(&self, true_time: Micros) -> Micros {
+        true_time
+            + (self.offset + (true_time - SIM_START) as f64 * self.drift_ppm * 1e-6).round()
+                as Micros
+    }
+}
+
+pub struct SimNode {
+    pub id: NodeId,
+    pub clock: SimClock,
+    pub sync: ClockSync,
+}
+
+/// A bidirectional link with a base one way latency and uniformly distributed extra queueing delay.
+#[derive(Debug, Clone, Copy)]
+pub struct SimLink {
+    pub a: usize,
+    pub b: usize,
+    pub latency: Micros,
+    pub jitter: Micros,
+}
+
+pub struct SimMesh {
+    pub nodes: Vec<SimNode>,
+    pub links: Vec<SimLink>,
+    /// The true time of the simulation. Nodes never see this directly.
+    pub now: Micros,
+    rng: u64,
+}
+
+impl SimMesh {
+    pub fn new(seed: u64) -> Self {
+        SimMesh {
+            nodes: Vec::new(),
+            links: Vec::new(),
+            now: SIM_START,
+            rng: seed | 1,
+        }
+    }
+
+    /// Adds a node and returns its index.
+    pub fn add_node(&mut self, clock: SimClock, authoritative: bool) -> usize {
+        let index = self.nodes.len();
+        let config = SyncConfig::default();
+        let id = NodeId(index as u64 + 1);
+        self.nodes.push(SimNode {
+            id,
+            clock,
+            sync: if authoritative {
+                ClockSync::authoritative(id, config)
+            } else {
+                ClockSync::new(id, config)
+            },
+        });
+        index
+    }
+
+    pub fn link(&mut self, a: usize, b: usize, latency: Micros, jitter: Micros) {
+        self.links.push(SimLink {
+            a,
+            b,
+            latency,
+            jitter,
+        });
+    }
+
+    /// Cuts every link of node `index`, as if it had been switched off.
+    pub fn disconnect(&mut self, index: usize) {
+        self.links.retain(|link| link.a != index && link.b != index);
+    }
+
+    /// Advances true time by `interval` and runs one exchange in each direction over every link.
+    pub fn round(&mut self, interval: Micros) {
+        self.now += interval;
+        for link in self.links.clone() {
+            self.exchange(link.a, link.b, link);
+            self.exchange(link.b, link.a, link);
+        }
+        for node in &mut self.nodes {
+            let local = node.clock.local(self.now);
+            node.sync.update(local);
+        }
+    }
+
+    /// Network time every node currently reports, paired with its claimed error bound.
+    pub fn estimates(&self) -> Vec<(Micros, Micros)> {
+        self.nodes
+            .iter()
+            .map(|node| {
+                let estimate = node.sync.network_time(node.clock.local(self.now));
+                (estimate.network, estimate.error)
+            })
+            .collect()
+    }
+
+    /// The largest difference between any two nodes' network time.
+    pub fn disagreement(&self) -> Micros {
+        let estimates = self.estimates();
+        let max = estimates.iter().map(|(t, _)| *t).max().unwrap_or(0);
+        let min = estimates.iter().map(|(t, _)| *t).min().unwrap_or(0);
+        max - min
+    }
+
+    fn exchange(&mut self, from: usize, to: usize, link: SimLink) {
+        let sent = self.now;
+        let arrives = sent + link.latency + self.jitter(link.jitter);
+        let replies = arrives + 50;
+        let returns = replies + link.latency + self.jitter(link.jitter);
+
+        let request = self.nodes[from]
+            .sync
+            .request(self.nodes[from].clock.local(sent));
+        let responder = &self.nodes[to];
+        let response = responder.sync.respond(
+            request,
+            responder.clock.local(arrives),
+            responder.clock.local(replies),
+        );
+        let peer = responder.id;
+        let requester = &mut self.nodes[from];
+        let arrival = requester.clock.local(returns);
+        requester.sync.handle_response(peer, response, arrival);
+    }
+
+    fn jitter(&mut self, max: Micros) -> Micros {
+        // xorshift, so runs are reproducible from the seed.
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        if max <= 0 {
+            0
+        } else {
+            (self.rng % max as u64) as Micros
+        }
+    }
+}
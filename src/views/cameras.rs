@@ -0,0 +1,46 @@
+use dioxus::prelude::*;
+use flumph::sensors::ChannelId;
+
+use crate::components::CameraControlPanel;
+use crate::hooks::CameraLink;
+use crate::Route;
+
+/// The cameras the app can control.
+#[component]
+pub fn Cameras() -> Element {
+    let link = use_context::<CameraLink>();
+    rsx! {
+        div { id: "cameras",
+            h2 { "Cameras" }
+            for (node, camera) in link.cameras.read().iter() {
+                div { class: "control-row",
+                    Link { to: Route::Camera { camera: camera.clone() }, "{camera}" }
+                    span { class: "muted", "on {node}" }
+                }
+            }
+            if cfg!(target_arch = "wasm32") {
+                p { class: "muted", "Cameras are controlled from the node's own app." }
+            } else if link.cameras.read().is_empty() {
+                p { class: "muted", "no cameras" }
+            }
+        }
+    }
+}
+
+/// Remote control of one camera.
+#[component]
+pub fn Camera(camera: ChannelId) -> Element {
+    let link = use_context::<CameraLink>();
+    if link.target(&camera).is_none() {
+        return rsx! {
+            div { id: "cameras",
+                h2 { "{camera}" }
+                p { class: "error", "no reachable node has a camera {camera}" }
+                Link { to: Route::Cameras {}, "All cameras" }
+            }
+        };
+    }
+    rsx! {
+        CameraControlPanel { link, camera }
+    }
+}
@@ -0,0 +1,150 @@
+use dioxus::prelude::*;
+
+use crate::components::NodeOverview;
+use crate::hooks::NodeFeed;
+
+#[component]
+pub fn Dashboard() -> Element {
+    let node = use_context::<NodeFeed>();
+    rsx! {
+        NodeOverview { status: node.status }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use flumph::analytics::DetectionBlob;
+    use flumph::calibration::CalibrationStore;
+    use flumph::config::AppConfig;
+    use flumph::control::ControlMessage;
+    use flumph::node::{NodeBackend, NodeId, NodeRole, NodeStatus};
+    use flumph::sensors::{ChannelId, Micros, Sample};
+    use flumph::server::SharedBackend;
+    use flumph::storage::{BlobHash, BlobInfo, BlobQuery, SealedBlob, StoreError, VideoBlob};
+    use flumph::time_sync::local_now;
+
+    use super::*;
+    use crate::components::testing::{html, wait_until};
+    use crate::hooks::use_node_feed;
+
+    /// A backend that reports whatever the test puts in `status` and remembers how it was driven.
+    struct FakeNode {
+        status: NodeStatus,
+        polls: usize,
+        configured: Vec<AppConfig>,
+    }
+
+    impl NodeBackend for FakeNode {
+        fn poll(&mut self, _now: Micros) -> Vec<Sample> {
+            self.polls += 1;
+            self.status.readings.values().cloned().collect()
+        }
+
+        fn status(&self, _now: Micros) -> NodeStatus {
+            self.status.clone()
+        }
+
+        fn configure(&mut self, config: &AppConfig) {
+            self.status.role = config.node.role;
+            self.configured.push(config.clone());
+        }
+
+        fn calibrate(&mut self, _profiles: &CalibrationStore) {}
+
+        fn control(
+            &mut self,
+            _target: NodeId,
+            _message: ControlMessage,
+            _now: Micros,
+        ) -> Option<ControlMessage> {
+            None
+        }
+
+        fn blobs(&self, _query: &BlobQuery) -> Vec<BlobInfo> {
+            Vec::new()
+        }
+
+        fn hour(&self, hash: &BlobHash) -> Result<SealedBlob, StoreError> {
+            Err(StoreError::NotFound(*hash))
+        }
+
+        fn video(&self, hash: &BlobHash) -> Result<VideoBlob, StoreError> {
+            Err(StoreError::NotFound(*hash))
+        }
+
+        fn detections(&self, hash: &BlobHash) -> Result<DetectionBlob, StoreError> {
+            Err(StoreError::NotFound(*hash))
+        }
+    }
+
+    fn fake() -> Arc<Mutex<FakeNode>> {
+        Arc::new(Mutex::new(FakeNode {
+            status: NodeStatus::new(NodeId(0xfeed), NodeRole::Sensor),
+            polls: 0,
+            configured: Vec::new(),
+        }))
+    }
+
+    fn app(node: Arc<Mutex<FakeNode>>) -> Element {
+        let backend = use_signal(|| node.clone() as SharedBackend);
+        let config = use_signal(|| {
+            let mut config = AppConfig::default();
+            config.node.role = NodeRole::Compute;
+            config
+        });
+        let feed = use_node_feed(backend, config.into());
+        use_context_provider(|| feed);
+        rsx! {
+            Dashboard {}
+        }
+    }
+
+    fn reading(channel: &str, values: Vec<f64>) -> Sample {
+        Sample {
+            channel: ChannelId::new(channel),
+            local_time: local_now(),
+            values,
+        }
+    }
+
+    #[test]
+    fn the_dashboard_shows_what_the_backend_reports() {
+        let node = fake();
+        {
+            let mut node = node.lock().unwrap();
+            let sample = reading("baro", vec![1013.25]);
+            node.status.readings.insert(sample.channel.clone(), sample);
+            node.status.hour_samples = 42;
+            node.status.store.blobs = 3;
+            node.status.sync.queued_blobs = 2;
+        }
+        let mut dom = VirtualDom::new_with_props(app, node.clone());
+        dom.rebuild_in_place();
+        let first = html(&dom);
+        assert!(first.contains("Node 000000000000feed"), "{first}");
+        assert!(first.contains("baro"), "{first}");
+        assert!(first.contains("1013.25"), "{first}");
+        assert!(first.contains("42 samples"), "{first}");
+        assert!(first.contains("3 blobs"), "{first}");
+        assert!(first.contains("2 waiting"), "{first}");
+
+        // The config is applied once the effects run, and the status follows the backend from then on.
+        {
+            let mut node = node.lock().unwrap();
+            let sample = reading("hygro", vec![55.0]);
+            node.status.readings.insert(sample.channel.clone(), sample);
+            node.status.hour_samples = 43;
+        }
+        let after = wait_until(&mut dom, Duration::from_secs(5), |html| {
+            html.contains("hygro") && html.contains("43 samples")
+        });
+        assert!(after.contains("compute node"), "{after}");
+        let node = node.lock().unwrap();
+        assert!(node.polls > 0);
+        assert_eq!(node.configured.len(), 1);
+        assert_eq!(node.configured[0].node.role, NodeRole::Compute);
+    }
+}
@@ -0,0 +1,167 @@
+use std::path::Path;
+
+use dioxus::prelude::*;
+use flumph::logs::{export, LogFilter, LogLevel};
+
+use crate::components::format;
+use crate::hooks::{use_log_updates, AppLog};
+
+/// Most records listed at once. Filtering narrows the rest down, and an export has them all.
+const SHOWN: usize = 300;
+
+/// The app's recent log, newest first, filtered by level, subsystem and text, with an export of whatever the filter
+/// lets through. Exports are written next to the log file, or downloaded in the browser.
+#[component]
+pub fn Logs() -> Element {
+    let log = use_context::<AppLog>();
+    let pushed = use_log_updates(log.buffer.clone());
+    let mut filter = use_signal(|| LogFilter {
+        level: LogLevel::Info,
+        ..Default::default()
+    });
+    let records = use_memo({
+        let buffer = log.buffer.clone();
+        move || {
+            pushed();
+            buffer.query(&filter.read())
+        }
+    });
+    let subsystems = use_memo({
+        let buffer = log.buffer.clone();
+        move || {
+            pushed();
+            buffer.subsystems()
+        }
+    });
+
+    let mut exported = use_signal(|| None::<Result<String, String>>);
+    let file = log.file.clone();
+    let records_now = records.read();
+    let current = filter.read().clone();
+
+    rsx! {
+        div { id: "logs",
+            h2 { "Logs" }
+            match &log.file {
+                Some(path) => rsx! {
+                    p { class: "muted", "Also written to {path.display()}" }
+                },
+                None => rsx! {
+                    p { class: "muted", "Only kept in memory; there is no log file." }
+                },
+            }
+            div { class: "control-row",
+                label { "Level" }
+                select {
+                    onchange: move |e| {
+                        if let Some(level) = LogLevel::ALL.into_iter().find(|l| l.to_string() == e.value()) {
+                            filter.write().level = level;
+                        }
+                    },
+                    for level in LogLevel::ALL {
+                        option {
+                            value: "{level}",
+                            selected: level == current.level,
+                            "{level} and up"
+                        }
+                    }
+                }
+                input {
+                    placeholder: "Search",
+                    value: "{current.search}",
+                    oninput: move |e| filter.write().search = e.value(),
+                }
+            }
+            div { class: "control-row",
+                button {
+                    class: if current.subsystem.is_none() { "selected" },
+                    onclick: move |_| filter.write().subsystem = None,
+                    "Everything"
+                }
+                for subsystem in subsystems.read().iter().cloned() {
+                    button {
+                        key: "{subsystem}",
+                        class: if current.subsystem.as_ref() == Some(&subsystem) { "selected" },
+                        onclick: {
+                            let subsystem = subsystem.clone();
+                            move |_| filter.write().subsystem = Some(subsystem.clone())
+                        },
+                        "{subsystem}"
+                    }
+                }
+            }
+            div { class: "control-row",
+                span { class: "muted", "{records_now.len()} records" }
+                button {
+                    onclick: move |_| {
+                        let records = records.read();
+                        exported.set(Some(save_export(file.as_deref(), &export(&records), records.len())));
+                    },
+                    "Export"
+                }
+            }
+            match exported() {
+                Some(Ok(done)) => rsx! {
+                    p { class: "muted", "{done}" }
+                },
+                Some(Err(e)) => rsx! {
+                    p { class: "error", "{e}" }
+                },
+                None => rsx! {},
+            }
+            table { class: "overview-table log-table",
+                for record in records_now.iter().rev().take(SHOWN) {
+                    tr { key: "{record.seq}",
+                        td { class: "muted", "{format::clock(record.time)}" }
+                        td { class: level_class(record.level), "{record.level}" }
+                        td { class: "muted", "{record.subsystem()}" }
+                        td {
+                            "{record.message}"
+                            for (name, value) in record.fields.iter() {
+                                span { class: "muted", " {name}={value}" }
+                            }
+                        }
+                    }
+                }
+            }
+            if records_now.is_empty() {
+                p { class: "muted", "nothing logged that matches" }
+            }
+        }
+    }
+}
+
+/// Writes an export of `count` records beside `log_file`, or beside the config when there is no log file, and says
+/// where it went.
+#[cfg(not(target_arch = "wasm32"))]
+fn save_export(log_file: Option<&Path>, text: &str, count: usize) -> Result<String, String> {
+    let beside = log_file.map_or_else(flumph::config::default_path, Path::to_path_buf);
+    let seconds = flumph::time_sync::local_now() / 1_000_000;
+    let path = beside.with_file_name(format!("flumph-export-{seconds}.log"));
+    std::fs::write(&path, text).map_err(|e| format!("cannot write {}: {e}", path.display()))?;
+    Ok(format!("Exported {count} records to {}", path.display()))
+}
+
+/// Hands an export of `count` records to the browser to download.
+#[cfg(target_arch = "wasm32")]
+fn save_export(_log_file: Option<&Path>, text: &str, count: usize) -> Result<String, String> {
+    let text = serde_json::to_string(text).map_err(|e| e.to_string())?;
+    document::eval(&format!(
+        r#"
+        const link = document.createElement("a");
+        link.href = URL.createObjectURL(new Blob([{text}], {{ type: "text/plain;charset=utf-8" }}));
+        link.download = "flumph.log";
+        link.click();
+        URL.revokeObjectURL(link.href);
+        "#
+    ));
+    Ok(format!("Exported {count} records to your downloads"))
+}
+
+fn level_class(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Error => "error",
+        LogLevel::Warn => "warning",
+        _ => "",
+    }
+}
@@ -0,0 +1,32 @@
+//! The app's screens, one per [`crate::Route`]. Views pull the node's state out of the context `App` provides and lay
+//! out components from [`crate::components`]; anything reusable belongs in a component rather than a view.
+
+mod cameras;
+pub use cameras::{Camera, Cameras};
+
+mod dashboard;
+pub use dashboard::Dashboard;
+
+mod logs;
+pub use logs::Logs;
+
+mod navbar;
+pub use navbar::Navbar;
+
+mod not_found;
+pub use not_found::PageNotFound;
+
+mod peers;
+pub use peers::{Peer, Peers};
+
+mod recordings;
+pub use recordings::{RecordingDay, Recordings};
+
+mod sensors;
+pub use sensors::{Sensor, Sensors};
+
+mod settings;
+pub use settings::Settings;
+
+mod storage;
+pub use storage::{Storage, StoredHour};
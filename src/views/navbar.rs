@@ -0,0 +1,42 @@
+use dioxus::prelude::*;
+
+use crate::components::StatusBar;
+use crate::hooks::{CameraLink, NodeFeed};
+use crate::Route;
+
+/// The status bar and navigation bar shared by every view, which renders below them.
+#[component]
+pub fn Navbar() -> Element {
+    let node = use_context::<NodeFeed>();
+    let link = use_context::<CameraLink>();
+
+    rsx! {
+        StatusBar { status: node.status, queued: link.queued() }
+        {banner()}
+        nav { id: "navbar",
+            Link { to: Route::Dashboard {}, "Dashboard" }
+            Link { to: Route::Sensors {}, "Sensors" }
+            Link { to: Route::Peers {}, "Peers" }
+            Link { to: Route::Storage {}, "Storage" }
+            Link { to: Route::Cameras {}, "Cameras" }
+            Link { to: Route::Recordings {}, "Recordings" }
+            Link { to: Route::Settings {}, "Settings" }
+            Link { to: Route::Logs {}, "Logs" }
+        }
+        Outlet::<Route> {}
+    }
+}
+
+/// In the web build, what is keeping the dashboard from showing the node's data, if anything.
+#[cfg(target_arch = "wasm32")]
+fn banner() -> Element {
+    let node = use_context::<NodeFeed>();
+    rsx! {
+        crate::remote::PairingBanner { connection: node.connection }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn banner() -> Element {
+    rsx! {}
+}
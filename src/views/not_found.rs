@@ -0,0 +1,15 @@
+use dioxus::prelude::*;
+
+use crate::Route;
+
+/// Shown for links to screens that do not exist, e.g. from an older version of the app.
+#[component]
+pub fn PageNotFound(route: Vec<String>) -> Element {
+    rsx! {
+        div { id: "not-found",
+            h2 { "Nothing here" }
+            p { class: "muted", "/{route.join(\"/\")} is not a page of this app." }
+            Link { to: Route::Dashboard {}, "Back to the dashboard" }
+        }
+    }
+}
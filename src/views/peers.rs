@@ -0,0 +1,59 @@
+use dioxus::prelude::*;
+use flumph::node::NodeId;
+
+use crate::components::{format, LinkTable, PeerDetail, PeerList, TopologyMap};
+use crate::hooks::NodeFeed;
+use crate::Route;
+
+/// The mesh as this node sees it, and its peers.
+#[component]
+pub fn Peers() -> Element {
+    let node = use_context::<NodeFeed>();
+    let status = node.status.read().clone();
+    let local = status.id;
+    let select = move |id: NodeId| {
+        if id != local {
+            navigator().push(Route::Peer { id });
+        }
+    };
+
+    rsx! {
+        div { id: "peers",
+            h2 { "Peers" }
+            TopologyMap { status: status.clone(), on_select: select }
+            PeerList { peers: status.peers, on_select: select }
+        }
+    }
+}
+
+/// One node of the mesh: how it is reached, what of it this node holds and how far syncing to it has got.
+#[component]
+pub fn Peer(id: NodeId) -> Element {
+    let node = use_context::<NodeFeed>();
+    let status = node.status.read();
+    let peer = status.peers.iter().find(|peer| peer.id == id).cloned();
+    let links: Vec<_> = status
+        .topology()
+        .into_iter()
+        .filter(|link| link.a == id || link.b == id)
+        .collect();
+
+    rsx! {
+        div { id: "peers",
+            h2 { "Node {format::short_id(id)}" }
+            p { class: "muted", "{id}" }
+            if let Some(peer) = peer {
+                PeerDetail { peer }
+            } else if !links.is_empty() {
+                p { "Not a peer of this node; it is reached through one." }
+            } else {
+                p { class: "error", "This node knows nothing about {id}." }
+            }
+            if !links.is_empty() {
+                h3 { "Links" }
+                LinkTable { from: id, links }
+            }
+            Link { to: Route::Peers {}, "All peers" }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use dioxus::prelude::*;
+use flumph::storage::{day_of, DAY};
+use flumph::time_sync::local_now;
+
+use crate::components::{format, FootagePlayer, FootageTimeline};
+use crate::hooks::NodeFeed;
+use crate::Route;
+
+/// Today's footage.
+#[component]
+pub fn Recordings() -> Element {
+    rsx! {
+        RecordingDay { day: day_of(local_now()) }
+    }
+}
+
+/// Every camera's footage over one UTC day, with a player for whichever was picked.
+#[component]
+pub fn RecordingDay(day: i64) -> Element {
+    let node = use_context::<NodeFeed>();
+    // Reassembling the day opens every blob in it, so it is only done when the store changes.
+    let stored = use_memo(move || node.status.read().store.blobs);
+    let timeline = use_resource(use_reactive!(|day| {
+        stored();
+        node.timeline(day)
+    }));
+    // The camera being played, by index in the timeline, and the keyframe it is at.
+    let mut playing = use_signal(|| None::<usize>);
+    let mut position = use_signal(|| 0usize);
+
+    let timeline = timeline.read();
+    let cameras = match &*timeline {
+        Some(Ok(timeline)) => timeline.cameras.as_slice(),
+        _ => &[],
+    };
+    let start = day * DAY;
+
+    rsx! {
+        div { id: "recordings",
+            h2 { "Recordings" }
+            div { class: "control-row",
+                Link { to: Route::RecordingDay { day: day - 1 }, "‹ Previous day" }
+                span { "{format::date(start)}" }
+                Link { to: Route::RecordingDay { day: day + 1 }, "Next day ›" }
+            }
+            for (i, camera) in cameras.iter().enumerate() {
+                div { key: "{day}-{camera.node}-{camera.camera}",
+                    h3 { "{camera.camera} on {format::short_id(camera.node)}" }
+                    FootageTimeline {
+                        timeline: camera.clone(),
+                        start,
+                        end: start + DAY,
+                        on_seek: {
+                            let camera = camera.clone();
+                            move |time| {
+                                if let Some(index) = camera.seek(time) {
+                                    playing.set(Some(i));
+                                    position.set(index);
+                                }
+                            }
+                        },
+                    }
+                    if playing() == Some(i) {
+                        FootagePlayer { timeline: camera.clone(), position }
+                    }
+                }
+            }
+            match &*timeline {
+                Some(Ok(_)) if cameras.is_empty() => rsx! {
+                    p { class: "muted", "no footage on this day" }
+                },
+                Some(Err(e)) => rsx! {
+                    p { class: "error", "{e}" }
+                },
+                None => rsx! {
+                    p { class: "muted", "loading…" }
+                },
+                _ => rsx! {},
+            }
+        }
+    }
+}
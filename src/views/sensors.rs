@@ -0,0 +1,48 @@
+use dioxus::prelude::*;
+use flumph::calibration::CalibrationStore;
+use flumph::sensors::history::History;
+use flumph::sensors::ChannelId;
+
+use crate::components::{CalibrationPanel, SensorCharts};
+use crate::hooks::NodeFeed;
+use crate::{Route, LOCAL_NODE};
+
+/// Charts of every channel, and calibration of the sensors that need it.
+#[component]
+pub fn Sensors() -> Element {
+    let node = use_context::<NodeFeed>();
+    let history = use_context::<ReadOnlySignal<History>>();
+    let calibration = use_context::<Signal<CalibrationStore>>();
+    let channels: Vec<ChannelId> = history.read().channels().cloned().collect();
+
+    rsx! {
+        div { id: "sensors",
+            h2 { "Sensors" }
+            div { class: "control-row",
+                for channel in channels {
+                    Link { to: Route::Sensor { channel: channel.clone() }, "{channel}" }
+                }
+            }
+        }
+        SensorCharts { history }
+        CalibrationPanel { node: LOCAL_NODE, samples: node.samples, store: calibration }
+    }
+}
+
+/// One channel's chart on its own, for linking to.
+#[component]
+pub fn Sensor(channel: ChannelId) -> Element {
+    let history = use_context::<ReadOnlySignal<History>>();
+    let known = history.read().channels().any(|c| *c == channel);
+
+    rsx! {
+        div { id: "sensors",
+            h2 { "{channel}" }
+            Link { to: Route::Sensors {}, "All sensors" }
+            if !known {
+                p { class: "muted", "no readings on this channel yet" }
+            }
+        }
+        SensorCharts { history, only: channel }
+    }
+}
@@ -0,0 +1,592 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::num::{IntErrorKind, ParseIntError};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use dioxus::prelude::*;
+use flumph::calibration::ApplyAt;
+use flumph::camera::Resolution;
+use flumph::config::{AppConfig, Theme};
+use flumph::node::NodeRole;
+use flumph::storage::ImageFormat;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::hooks::ServerState;
+use crate::hooks::{ConfigFile, NodeFeed};
+
+/// One editable setting: where it lives in the config, and how to turn it into text and back.
+struct Field {
+    /// Path of the field in the config file, as [`flumph::config::FieldError`] names it.
+    key: &'static str,
+    label: &'static str,
+    /// Shown next to the field, to say what it is for or what leaving it blank does.
+    help: &'static str,
+    /// The values it may take, for a field picked from a list. Empty for free text.
+    choices: &'static [&'static str],
+    get: fn(&AppConfig) -> String,
+    set: fn(&mut AppConfig, &str) -> Result<(), String>,
+}
+
+/// Every setting the view edits, by section.
+const SECTIONS: [(&str, &[Field]); 8] = [
+    (
+        "Node",
+        &[
+            Field {
+                key: "node.name",
+                label: "Name",
+                help: "",
+                choices: &[],
+                get: |c| c.node.name.clone(),
+                set: |c, text| {
+                    c.node.name = text.trim().to_string();
+                    Ok(())
+                },
+            },
+            Field {
+                key: "node.role",
+                label: "Role",
+                help: "",
+                choices: &["sensor", "compute"],
+                get: |c| c.node.role.to_string(),
+                set: |c, text| {
+                    c.node.role = match text {
+                        "compute" => NodeRole::Compute,
+                        _ => NodeRole::Sensor,
+                    };
+                    Ok(())
+                },
+            },
+            Field {
+                key: "node.demo",
+                label: "Demo",
+                help: "simulated sensors, peers and footage instead of the real ones",
+                choices: &["off", "on"],
+                get: |c| if c.node.demo { "on" } else { "off" }.to_string(),
+                set: |c, text| {
+                    c.node.demo = text == "on";
+                    Ok(())
+                },
+            },
+        ],
+    ),
+    (
+        "Sensors",
+        &[
+            Field {
+                key: "sensors.rate_hz",
+                label: "Rate (Hz)",
+                help: "samples per second of every sensor",
+                choices: &[],
+                get: |c| c.sensors.rate_hz.to_string(),
+                set: |c, text| {
+                    c.sensors.rate_hz = number(text)?;
+                    Ok(())
+                },
+            },
+            Field {
+                key: "sensors.station",
+                label: "Station",
+                help: "station description file listing the instruments; blank for none",
+                choices: &[],
+                get: |c| path(&c.sensors.station),
+                set: |c, text| {
+                    c.sensors.station = parse_path(text);
+                    Ok(())
+                },
+            },
+            Field {
+                key: "sensors.calibrate",
+                label: "Calibrate",
+                help: "record calibrated channels as samples come in, or only when stored hours are read",
+                choices: &["on read", "on capture"],
+                get: |c| match c.sensors.calibrate {
+                    ApplyAt::Capture => "on capture",
+                    ApplyAt::Query => "on read",
+                }
+                .to_string(),
+                set: |c, text| {
+                    c.sensors.calibrate = match text {
+                        "on capture" => ApplyAt::Capture,
+                        _ => ApplyAt::Query,
+                    };
+                    Ok(())
+                },
+            },
+        ],
+    ),
+    (
+        "Storage",
+        &[
+            Field {
+                key: "storage.quota_mb",
+                label: "Quota (MB)",
+                help: "blank for no limit",
+                choices: &[],
+                get: |c| optional(c.storage.quota_mb),
+                set: |c, text| {
+                    c.storage.quota_mb = parse_optional(text)?;
+                    Ok(())
+                },
+            },
+            Field {
+                key: "storage.retention_days",
+                label: "Keep (days)",
+                help: "once a peer has a copy; blank to keep everything",
+                choices: &[],
+                get: |c| optional(c.storage.retention_days),
+                set: |c, text| {
+                    c.storage.retention_days = parse_optional(text)?;
+                    Ok(())
+                },
+            },
+        ],
+    ),
+    (
+        "Link",
+        &[
+            Field {
+                key: "link.server",
+                label: "Server",
+                help: "host:port blobs are sent to",
+                choices: &[],
+                get: |c| c.link.server.clone(),
+                set: |c, text| {
+                    c.link.server = text.trim().to_string();
+                    Ok(())
+                },
+            },
+            Field {
+                key: "link.uplink_kbps",
+                label: "Uplink (kbps)",
+                help: "",
+                choices: &[],
+                get: |c| c.link.uplink_kbps.to_string(),
+                set: |c, text| {
+                    c.link.uplink_kbps = number(text)?;
+                    Ok(())
+                },
+            },
+            Field {
+                key: "link.daily_budget_mb",
+                label: "Daily budget (MB)",
+                help: "caps the camera's bitrate; blank for an unmetered link",
+                choices: &[],
+                get: |c| optional(c.link.daily_budget_mb),
+                set: |c, text| {
+                    c.link.daily_budget_mb = parse_optional(text)?;
+                    Ok(())
+                },
+            },
+        ],
+    ),
+    (
+        "Camera",
+        &[
+            Field {
+                key: "camera.device",
+                label: "Device",
+                help: "camera id, or test-pattern; blank for no camera",
+                choices: &[],
+                get: |c| c.camera.device.clone().unwrap_or_default(),
+                set: |c, text| {
+                    let text = text.trim();
+                    c.camera.device = (!text.is_empty()).then(|| text.to_string());
+                    Ok(())
+                },
+            },
+            Field {
+                key: "camera.resolution",
+                label: "Resolution",
+                help: "width x height",
+                choices: &[],
+                get: |c| c.camera.resolution.to_string(),
+                set: |c, text| {
+                    let (width, height) = text
+                        .trim()
+                        .split_once('x')
+                        .ok_or("must look like 640x480")?;
+                    c.camera.resolution = Resolution::new(number(width)?, number(height)?);
+                    Ok(())
+                },
+            },
+            Field {
+                key: "camera.fps",
+                label: "Frame rate",
+                help: "",
+                choices: &[],
+                get: |c| c.camera.fps.to_string(),
+                set: |c, text| {
+                    c.camera.fps = number(text)?;
+                    Ok(())
+                },
+            },
+            Field {
+                key: "camera.bitrate_kbps",
+                label: "Bitrate (kbps)",
+                help: "",
+                choices: &[],
+                get: |c| c.camera.bitrate_kbps.to_string(),
+                set: |c, text| {
+                    c.camera.bitrate_kbps = number(text)?;
+                    Ok(())
+                },
+            },
+            Field {
+                key: "camera.keyframe_interval",
+                label: "Keyframes",
+                help: "at least one every this many frames",
+                choices: &[],
+                get: |c| c.camera.keyframe_interval.to_string(),
+                set: |c, text| {
+                    c.camera.keyframe_interval = number(text)?;
+                    Ok(())
+                },
+            },
+            Field {
+                key: "camera.motion_threshold",
+                label: "Motion",
+                help: "difference out of 255 that counts as motion",
+                choices: &[],
+                get: |c| c.camera.motion_threshold.to_string(),
+                set: |c, text| {
+                    c.camera.motion_threshold = number(text)?;
+                    Ok(())
+                },
+            },
+            Field {
+                key: "camera.snapshot_minutes",
+                label: "Snapshots",
+                help: "minutes between stills instead of video, for slow links; blank records video",
+                choices: &[],
+                get: |c| optional(c.camera.snapshot_minutes),
+                set: |c, text| {
+                    c.camera.snapshot_minutes = parse_optional(text)?;
+                    Ok(())
+                },
+            },
+            Field {
+                key: "camera.snapshot_format",
+                label: "Snapshot format",
+                help: "AVIF is about half the size of JPEG",
+                choices: &["AVIF", "JPEG"],
+                get: |c| c.camera.snapshot_format.to_string(),
+                set: |c, text| {
+                    c.camera.snapshot_format = ImageFormat::ALL
+                        .into_iter()
+                        .find(|format| format.to_string() == text)
+                        .unwrap_or(ImageFormat::Avif);
+                    Ok(())
+                },
+            },
+        ],
+    ),
+    (
+        "Analytics",
+        &[
+            Field {
+                key: "analytics.model",
+                label: "Model",
+                help: "ONNX object detector a compute node runs on its footage; blank for none",
+                choices: &[],
+                get: |c| path(&c.analytics.model),
+                set: |c, text| {
+                    c.analytics.model = parse_path(text);
+                    Ok(())
+                },
+            },
+            Field {
+                key: "analytics.interval_s",
+                label: "Every (s)",
+                help: "seconds of footage between the frames classified",
+                choices: &[],
+                get: |c| c.analytics.interval_s.to_string(),
+                set: |c, text| {
+                    c.analytics.interval_s = number(text)?;
+                    Ok(())
+                },
+            },
+        ],
+    ),
+    (
+        "Display",
+        &[
+            Field {
+                key: "display.theme",
+                label: "Theme",
+                help: "outdoor is high contrast, for reading in sunlight",
+                choices: &["dark", "outdoor"],
+                get: |c| c.display.theme.to_string(),
+                set: |c, text| {
+                    c.display.theme = Theme::ALL
+                        .into_iter()
+                        .find(|theme| theme.to_string() == text)
+                        .unwrap_or_default();
+                    Ok(())
+                },
+            },
+            Field {
+                key: "display.large_targets",
+                label: "Large buttons",
+                help: "bigger buttons and fields, for gloves",
+                choices: &["on", "off"],
+                get: |c| if c.display.large_targets { "on" } else { "off" }.to_string(),
+                set: |c, text| {
+                    c.display.large_targets = text == "on";
+                    Ok(())
+                },
+            },
+        ],
+    ),
+    (
+        "Remote access",
+        &[
+            Field {
+                key: "server.enabled",
+                label: "Serve",
+                help: "lets browsers on this network see the node, when it is a compute node",
+                choices: &["on", "off"],
+                get: |c| if c.server.enabled { "on" } else { "off" }.to_string(),
+                set: |c, text| {
+                    c.server.enabled = text == "on";
+                    Ok(())
+                },
+            },
+            Field {
+                key: "server.listen",
+                label: "Address",
+                help: "ip:port to listen on",
+                choices: &[],
+                get: |c| c.server.listen.clone(),
+                set: |c, text| {
+                    c.server.listen = text.trim().to_string();
+                    Ok(())
+                },
+            },
+            Field {
+                key: "server.web_root",
+                label: "Web build",
+                help: "directory of the app's web bundle; blank to serve only the API",
+                choices: &[],
+                get: |c| path(&c.server.web_root),
+                set: |c, text| {
+                    c.server.web_root = parse_path(text);
+                    Ok(())
+                },
+            },
+        ],
+    ),
+];
+
+fn number<T: FromStr<Err = ParseIntError>>(text: &str) -> Result<T, String> {
+    text.trim().parse().map_err(|e: ParseIntError| {
+        match e.kind() {
+            IntErrorKind::Empty => "must not be blank",
+            IntErrorKind::PosOverflow => "is too large",
+            _ => "must be a whole number",
+        }
+        .to_string()
+    })
+}
+
+fn parse_optional<T: FromStr<Err = ParseIntError>>(text: &str) -> Result<Option<T>, String> {
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
+    number(text).map(Some)
+}
+
+fn optional<T: Display>(value: Option<T>) -> String {
+    value.map_or_else(String::new, |value| value.to_string())
+}
+
+fn path(path: &Option<PathBuf>) -> String {
+    path.as_ref()
+        .map_or_else(String::new, |path| path.display().to_string())
+}
+
+fn parse_path(text: &str) -> Option<PathBuf> {
+    let text = text.trim();
+    (!text.is_empty()).then(|| PathBuf::from(text))
+}
+
+/// `config` with every edit applied, and what is wrong with each field, by key. A field that cannot be read keeps
+/// its old value, so it is only reported as unreadable.
+fn check(
+    config: &AppConfig,
+    edits: &BTreeMap<&'static str, String>,
+) -> (AppConfig, BTreeMap<String, Vec<String>>) {
+    let mut draft = config.clone();
+    let mut errors: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for field in SECTIONS.iter().flat_map(|(_, fields)| fields.iter()) {
+        if let Some(text) = edits.get(field.key) {
+            if let Err(e) = (field.set)(&mut draft, text) {
+                errors.entry(field.key.to_string()).or_default().push(e);
+            }
+        }
+    }
+    let unreadable: Vec<String> = errors.keys().cloned().collect();
+    for e in draft.validate() {
+        if !unreadable.contains(&e.field) {
+            errors.entry(e.field).or_default().push(e.message);
+        }
+    }
+    (draft, errors)
+}
+
+/// The app's config, edited in place. Changes take effect and are saved as soon as every field is valid; until then
+/// each field shows what is wrong with it and the node keeps the last valid config.
+#[component]
+pub fn Settings() -> Element {
+    if cfg!(target_arch = "wasm32") {
+        return rsx! {
+            div { id: "settings",
+                h2 { "Settings" }
+                p { class: "muted", "This dashboard is read only; settings are changed in the app on the node serving it." }
+            }
+        };
+    }
+    let node = use_context::<NodeFeed>();
+    let file = use_context::<ConfigFile>();
+    let config = file.config();
+    // What has been typed into each field, by key. Fields not in here show the config as it is.
+    let mut edits = use_signal(BTreeMap::<&'static str, String>::new);
+    let checked = use_memo(move || check(&config.read(), &edits.read()));
+    use_effect(move || {
+        let (draft, errors) = &*checked.read();
+        if errors.is_empty() && *draft != *config.peek() {
+            file.apply(draft.clone());
+        }
+    });
+
+    let status = node.status.read();
+    let (_, errors) = &*checked.read();
+    let current = config.read();
+    let edited = edits.read();
+    let text = |field: &Field| {
+        edited
+            .get(field.key)
+            .cloned()
+            .unwrap_or_else(|| (field.get)(&current))
+    };
+
+    rsx! {
+        div { id: "settings",
+            h2 { "Settings" }
+            div { class: "overview-row",
+                span { class: "overview-label", "Node id" }
+                span { "{status.id}" }
+            }
+            match file.path() {
+                Some(path) => rsx! {
+                    p { class: "muted", "Saved to {path.display()}" }
+                },
+                None => rsx! {
+                    p { class: "muted", "The browser cannot keep files, so settings last until the page is closed." }
+                },
+            }
+            if let Some(problem) = file.problem() {
+                p { class: "error", "{problem}" }
+            }
+            if !errors.is_empty() {
+                p { class: "error", "Fix the fields below to apply your changes; until then the last valid settings stay in use." }
+            }
+
+            for (section, fields) in SECTIONS {
+                h3 { key: "{section}", "{section}" }
+                for field in fields.iter() {
+                    div { key: "{field.key}", class: "control-row",
+                        label { "{field.label}" }
+                        if field.choices.is_empty() {
+                            input {
+                                value: "{text(field)}",
+                                oninput: move |e| {
+                                    edits.write().insert(field.key, e.value());
+                                },
+                            }
+                        } else {
+                            select {
+                                onchange: move |e| {
+                                    edits.write().insert(field.key, e.value());
+                                },
+                                for choice in field.choices.iter() {
+                                    option {
+                                        value: "{choice}",
+                                        selected: *choice == text(field),
+                                        "{choice}"
+                                    }
+                                }
+                            }
+                        }
+                        span { class: "muted", "{field.help}" }
+                    }
+                    for message in errors.get(field.key).into_iter().flatten() {
+                        p { class: "error", "{message}" }
+                    }
+                }
+                if section == "Remote access" {
+                    RemoteAccess {}
+                }
+            }
+
+            div { class: "control-row",
+                button {
+                    onclick: move |_| {
+                        edits.write().clear();
+                        file.apply(AppConfig::default());
+                    },
+                    "Reset to defaults"
+                }
+            }
+        }
+    }
+}
+
+/// Whether the node is serving, and the token other devices pair with.
+#[cfg(not(target_arch = "wasm32"))]
+#[component]
+fn RemoteAccess() -> Element {
+    let file = use_context::<ConfigFile>();
+    let server = use_context::<ReadOnlySignal<ServerState>>();
+    let config = file.config();
+    let token = config.read().server.token.clone().unwrap_or_default();
+    let link = match &*server.read() {
+        ServerState::Serving(addr) if addr.ip().is_unspecified() => Some(format!(
+            "http://<this computer's address>:{}/?token={token}",
+            addr.port()
+        )),
+        ServerState::Serving(addr) => Some(format!("http://{addr}/?token={token}")),
+        _ => None,
+    };
+
+    rsx! {
+        div { class: "overview-row",
+            span { class: "overview-label", "Status" }
+            span { "{server}" }
+        }
+        div { class: "control-row",
+            label { "Pairing token" }
+            span { class: "blob-hash", "{token}" }
+            button {
+                onclick: move |_| {
+                    let mut config = file.config().peek().clone();
+                    config.server.token = Some(flumph::server::new_token());
+                    file.apply(config);
+                },
+                "New token"
+            }
+            span { class: "muted", "a new token signs out every paired browser" }
+        }
+        if let Some(link) = link {
+            p { class: "muted", "Open {link} on another device on this network to pair it." }
+        }
+    }
+}
+
+/// The browser does not serve anything, and [`Settings`] says so before getting this far.
+#[cfg(target_arch = "wasm32")]
+#[component]
+fn RemoteAccess() -> Element {
+    rsx! {}
+}
@@ -0,0 +1,217 @@
+use std::collections::BTreeSet;
+
+use dioxus::prelude::*;
+use flumph::calibration::{CalibrationStore, CALIBRATED};
+use flumph::fusion::{fuse_blob, FusionConfig};
+use flumph::node::NodeId;
+use flumph::sensors::history::{History, Window};
+use flumph::sensors::{ChannelId, Sample};
+use flumph::storage::{
+    day_of, BlobHash, BlobInfo, BlobKind, BlobQuery, SealedBlob, TimedSample, HOUR,
+};
+
+use crate::components::{format, BlobTable, ReplicaList, SeriesChart, StorageSummary};
+use crate::hooks::NodeFeed;
+use crate::Route;
+
+/// The node's blob store, browsable by the node the blobs came from.
+#[component]
+pub fn Storage() -> Element {
+    let node = use_context::<NodeFeed>();
+    let mut only = use_signal(|| None::<NodeId>);
+    let status = node.status.read();
+    let nodes: BTreeSet<NodeId> = node
+        .blobs(&BlobQuery::default())
+        .iter()
+        .map(|blob| blob.node)
+        .collect();
+    let blobs = node.blobs(&BlobQuery {
+        node: only(),
+        ..Default::default()
+    });
+    let groups: Vec<(NodeId, Vec<_>)> = nodes
+        .iter()
+        .filter(|id| only().is_none_or(|only| only == **id))
+        .map(|&id| (id, blobs.iter().filter(|b| b.node == id).cloned().collect()))
+        .collect();
+
+    rsx! {
+        div { id: "storage",
+            h2 { "Storage" }
+            StorageSummary {
+                hour: status.hour,
+                hour_samples: status.hour_samples,
+                store: status.store,
+                sync: status.sync,
+            }
+
+            h3 { "Blobs" }
+            div { class: "control-row",
+                button {
+                    class: if only().is_none() { "selected" },
+                    onclick: move |_| only.set(None),
+                    "All nodes"
+                }
+                for id in nodes {
+                    button {
+                        class: if only() == Some(id) { "selected" },
+                        onclick: move |_| only.set(Some(id)),
+                        "{format::short_id(id)}"
+                    }
+                }
+            }
+            for (id, blobs) in groups {
+                h4 { key: "{id}",
+                    if id == status.id {
+                        "This node"
+                    } else {
+                        "From {format::short_id(id)}"
+                    }
+                }
+                BlobTable {
+                    key: "{id}-blobs",
+                    blobs,
+                    on_select: move |blob: BlobInfo| {
+                        if blob.kind == BlobKind::Video {
+                            navigator().push(Route::RecordingDay {
+                                day: day_of(blob.hour * HOUR),
+                            });
+                        } else {
+                            navigator().push(Route::StoredHour { hash: blob.hash });
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
+
+/// One stored blob: what the store knows about it and, for an hour of samples, a chart of each channel and a CSV
+/// export.
+#[component]
+pub fn StoredHour(hash: BlobHash) -> Element {
+    let node = use_context::<NodeFeed>();
+    let info = node
+        .blobs(&BlobQuery::default())
+        .into_iter()
+        .find(|blob| blob.hash == hash);
+    let opened = use_resource(use_reactive!(|hash| node.hour(hash)));
+
+    let Some(info) = info else {
+        return rsx! {
+            div { id: "storage",
+                h2 { "Blob not found" }
+                p { class: "muted", "This node does not hold blob {hash}." }
+                Link { to: Route::Storage {}, "All blobs" }
+            }
+        };
+    };
+    let start = info.hour * HOUR;
+
+    rsx! {
+        div { id: "storage",
+            h2 { "{format::date_time(start)}" }
+            p { class: "muted", "{info.kind} blob from node {info.node}" }
+            Link { to: Route::Storage {}, "All blobs" }
+
+            div { class: "overview-row",
+                span { class: "overview-label", "Hash" }
+                span { class: "blob-hash", "{info.hash}" }
+            }
+            div { class: "overview-row",
+                span { class: "overview-label", "Size" }
+                span { "{format::bytes(info.size)}" }
+                span { class: "muted",
+                    "{format::bytes(info.stored)} stored, {info.compression_ratio():.1}× compressed"
+                }
+            }
+            div { class: "overview-row",
+                span { class: "overview-label", "Copies on" }
+                ReplicaList { replicas: info.replicas.iter().copied().collect::<Vec<_>>() }
+            }
+            div { class: "overview-row",
+                span { class: "overview-label", "Encrypted" }
+                span { if info.encrypted { "yes" } else { "no" } }
+            }
+
+            match (info.kind, &*opened.read()) {
+                (BlobKind::Hour, Some(Ok(blob))) => rsx! {
+                    HourContents { blob: blob.clone() }
+                },
+                (BlobKind::Hour, Some(Err(e))) => rsx! {
+                    p { class: "error", "{e}" }
+                },
+                (BlobKind::Hour, None) => rsx! {
+                    p { class: "muted", "opening…" }
+                },
+                (BlobKind::Video, _) => rsx! {
+                    Link { to: Route::RecordingDay { day: day_of(start) }, "Watch on the timeline" }
+                },
+                _ => rsx! {},
+            }
+        }
+    }
+}
+
+/// Charts of every channel in an hour blob, and a link that downloads its samples.
+#[component]
+fn HourContents(blob: SealedBlob) -> Element {
+    let calibration = use_context::<Signal<CalibrationStore>>();
+    let end = (blob.hour + 1) * HOUR;
+    // Channels calibrated at capture time are already in the blob; the rest are calibrated now with the profiles in
+    // effect when their samples were taken.
+    let recorded: BTreeSet<&ChannelId> = blob.samples.iter().map(|s| &s.sample.channel).collect();
+    let calibrated: Vec<TimedSample> = calibration
+        .read()
+        .calibrate_blob(&blob)
+        .into_iter()
+        .filter(|timed| !recorded.contains(&timed.sample.channel))
+        .collect();
+    // Likewise orientation and altitude, for hours from nodes that did not fuse their sensors.
+    let mut readings = SealedBlob {
+        samples: blob.samples.iter().chain(&calibrated).cloned().collect(),
+        ..blob.clone()
+    };
+    readings.samples.sort_by_key(|timed| timed.time.network);
+    let channels: BTreeSet<&ChannelId> =
+        readings.samples.iter().map(|s| &s.sample.channel).collect();
+    let fusion = FusionConfig::prefer_calibrated(|raw| channels.contains(&raw.derived(CALIBRATED)));
+    let fused: Vec<TimedSample> = fuse_blob(&readings, fusion)
+        .into_iter()
+        .filter(|timed| !recorded.contains(&timed.sample.channel))
+        .collect();
+    let mut history = History::new();
+    for timed in readings.samples.iter().chain(&fused) {
+        // Charts place samples by their time stamp, and stored hours are laid out in network time.
+        history.push(&Sample {
+            local_time: timed.time.network,
+            ..timed.sample.clone()
+        });
+    }
+    let charts: Vec<(ChannelId, _)> = history
+        .channels()
+        .filter_map(|channel| Some((channel.clone(), history.series(channel, Window::Hour)?)))
+        .collect();
+    let csv = format::data_url("text/csv;charset=utf-8", blob.to_csv().as_bytes());
+
+    rsx! {
+        div { class: "control-row",
+            span { "{blob.samples.len()} samples, {blob.stills.len()} stills" }
+            a {
+                class: "button",
+                href: "{csv}",
+                download: "{blob.node}-{blob.hour}.csv",
+                "Export CSV"
+            }
+        }
+        for (channel, series) in charts {
+            SeriesChart {
+                key: "{channel}",
+                channel,
+                series,
+                end,
+                live: false,
+            }
+        }
+    }
+}
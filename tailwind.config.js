@@ -3,7 +3,22 @@ module.exports = {
   mode: "all",
   content: ["./src/**/*.{rs,html,css}", "./dist/**/*.html"],
   theme: {
-    extend: {},
+    extend: {
+      // The theme's colours, set on the app's root in assets/styling/main.css, so utilities follow the theme too.
+      colors: {
+        background: "var(--background)",
+        surface: "var(--surface)",
+        text: "var(--text)",
+        muted: "var(--muted)",
+        line: "var(--line)",
+        accent: "var(--accent)",
+        error: "var(--error)",
+        warning: "var(--warning)",
+      },
+      // Smallest comfortable target for a gloved finger.
+      minHeight: { touch: "48px" },
+      minWidth: { touch: "48px" },
+    },
   },
   plugins: [],
 };